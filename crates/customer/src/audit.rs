@@ -0,0 +1,164 @@
+use std::sync::Arc;
+
+use qm_entity::ids::InfraContext;
+use qm_keycloak::GroupRepresentation;
+use qm_role::{Access, AccessLevel};
+
+use crate::context::RelatedStorage;
+use crate::groups::GroupPath;
+use crate::model::GroupDetail;
+
+/// Difference between the Keycloak group memberships of a user and the memberships expected
+/// from the owner and access of the user, see [audit_user_consistency].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Discrepancy {
+    /// The user has no access role with a context, no group can be expected.
+    MissingOwner,
+    /// The user is not a member of any group.
+    MissingGroup,
+    /// The user is a member of a group belonging to a context outside of its owner.
+    ForeignGroup {
+        path: Arc<str>,
+        context: InfraContext,
+    },
+    /// The user is a member of a group which does not allow its access level.
+    AccessLevelNotAllowed {
+        path: Arc<str>,
+        access_level: AccessLevel,
+    },
+}
+
+/// Compares the Keycloak groups of the user with the groups expected from the owner and the
+/// access level of the cached user.
+pub async fn audit_user_consistency<Store>(
+    store: &Store,
+    realm: &str,
+    user_id: &str,
+) -> anyhow::Result<Vec<Discrepancy>>
+where
+    Store: RelatedStorage,
+{
+    let cache = store.cache_db();
+    let Some(details) = cache.user_details_by_id(user_id).await else {
+        anyhow::bail!("user '{user_id}' not found");
+    };
+    let keycloak_groups = store.keycloak().user_groups(realm, user_id).await?;
+    let mut groups = Vec::with_capacity(keycloak_groups.len());
+    for group in keycloak_groups {
+        let detail = match group.id.as_deref() {
+            Some(id) => cache.group_detail_by_id(id).await,
+            None => None,
+        };
+        groups.push((group, detail));
+    }
+    Ok(user_discrepancies(
+        details.context.as_ref(),
+        details.access.as_ref(),
+        &groups,
+    ))
+}
+
+fn user_discrepancies(
+    context: Option<&InfraContext>,
+    access: Option<&Access>,
+    groups: &[(GroupRepresentation, Option<Arc<GroupDetail>>)],
+) -> Vec<Discrepancy> {
+    let mut discrepancies = vec![];
+    if context.is_none() {
+        discrepancies.push(Discrepancy::MissingOwner);
+    }
+    if groups.is_empty() {
+        discrepancies.push(Discrepancy::MissingGroup);
+    }
+    for (group, detail) in groups {
+        let path: Arc<str> = Arc::from(
+            group
+                .path
+                .as_deref()
+                .or(group.name.as_deref())
+                .unwrap_or_default(),
+        );
+        let group_context = detail
+            .as_ref()
+            .and_then(|d| d.context)
+            .or_else(|| GroupPath::parse(&path).ok().map(|p| *p.context()));
+        if let Some((group_context, context)) = group_context.zip(context) {
+            if !group_context.contains(context) {
+                discrepancies.push(Discrepancy::ForeignGroup {
+                    path: path.clone(),
+                    context: group_context,
+                });
+            }
+        }
+        let allowed_access_levels = detail
+            .as_ref()
+            .and_then(|d| d.allowed_access_levels.as_ref());
+        if let Some((allowed, access)) = allowed_access_levels.zip(access) {
+            if !allowed.contains(access.ty()) {
+                discrepancies.push(Discrepancy::AccessLevelNotAllowed {
+                    path,
+                    access_level: *access.ty(),
+                });
+            }
+        }
+    }
+    discrepancies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group(path: &str) -> GroupRepresentation {
+        GroupRepresentation {
+            path: Some(path.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn detail(allowed_access_levels: &[AccessLevel]) -> Option<Arc<GroupDetail>> {
+        Some(Arc::new(GroupDetail {
+            built_in: true,
+            display_name: None,
+            allowed_access_levels: Some(Arc::from(allowed_access_levels)),
+            allowed_types: None,
+            context: None,
+        }))
+    }
+
+    #[test]
+    fn test_user_discrepancies() {
+        let context = InfraContext::Institution((1, 2, 3).into());
+        let access = Access::new(AccessLevel::Institution).with_fmt_id(Some(&context));
+        let groups = [
+            (
+                group("/institution_admin"),
+                detail(&[AccessLevel::Institution]),
+            ),
+            (group("/custom@T0102/staff"), None),
+        ];
+        assert!(user_discrepancies(Some(&context), Some(&access), &groups).is_empty());
+
+        let groups = [
+            (group("/customer_admin"), detail(&[AccessLevel::Customer])),
+            (group("/custom@R010204/staff"), None),
+        ];
+        assert_eq!(
+            user_discrepancies(Some(&context), Some(&access), &groups),
+            vec![
+                Discrepancy::AccessLevelNotAllowed {
+                    path: Arc::from("/customer_admin"),
+                    access_level: AccessLevel::Institution,
+                },
+                Discrepancy::ForeignGroup {
+                    path: Arc::from("/custom@R010204/staff"),
+                    context: InfraContext::Institution((1, 2, 4).into()),
+                },
+            ]
+        );
+        assert_eq!(
+            user_discrepancies(None, None, &[]),
+            vec![Discrepancy::MissingOwner, Discrepancy::MissingGroup]
+        );
+    }
+}
@@ -1,16 +1,24 @@
 use crate::model::*;
 use crate::query::fetch_customers;
+use crate::query::fetch_customers_by_ids;
+use crate::query::fetch_customers_modified_since;
+use crate::query::fetch_database_time;
+use crate::query::fetch_infra_ids;
 use crate::query::fetch_institutions;
+use crate::query::fetch_institutions_by_ids;
+use crate::query::fetch_institutions_modified_since;
 use crate::query::fetch_organizations;
+use crate::query::fetch_organizations_by_ids;
+use crate::query::fetch_organizations_modified_since;
 use prometheus_client::metrics::gauge::Gauge;
 use qm_entity::ids::InfraId;
 use qm_pg::DB;
 use sqlx::postgres::PgListener;
+use sqlx::types::time::PrimitiveDateTime;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::sync::atomic::AtomicI64;
 use std::sync::Arc;
-use time::macros::format_description;
-use time::PrimitiveDateTime;
 use tokio::sync::RwLock;
 
 use super::update::Op;
@@ -23,11 +31,7 @@ pub type OrganizationIdMap = HashMap<InfraId, Arc<QmOrganization>>;
 pub type InstitutionMap = HashMap<(Arc<str>, InfraId, InfraId), Arc<QmInstitution>>;
 pub type InstitutionIdMap = HashMap<InfraId, Arc<QmInstitution>>;
 
-fn parse_date_time(s: &str) -> Option<PrimitiveDateTime> {
-    let format = format_description!("[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond]");
-    PrimitiveDateTime::parse(s, format).ok()
-}
-
+#[cfg_attr(test, derive(Default))]
 pub struct InfraDB {
     pub customers: RwLock<CustomerMap>,
     pub customer_id_map: RwLock<CustomerIdMap>,
@@ -90,13 +94,158 @@ impl InfraDB {
         Ok(())
     }
 
+    /// Loads the given customers, organizations and institutions with one query per table.
+    pub async fn load_by_ids(
+        &self,
+        db: &DB,
+        cids: &[i64],
+        oids: &[i64],
+        iids: &[i64],
+    ) -> anyhow::Result<()> {
+        if !cids.is_empty() {
+            for v in fetch_customers_by_ids(db, cids).await? {
+                self.new_customer(Arc::new(v)).await;
+            }
+        }
+        if !oids.is_empty() {
+            for v in fetch_organizations_by_ids(db, oids).await? {
+                self.new_organization(Arc::new(v)).await;
+            }
+        }
+        if !iids.is_empty() {
+            for v in fetch_institutions_by_ids(db, iids).await? {
+                self.new_institution(Arc::new(v)).await;
+            }
+        }
+        Ok(())
+    }
+
     pub async fn reload(&self, db: &DB) -> anyhow::Result<()> {
-        self.load_customers(db).await?;
-        self.load_organizations(db).await?;
-        self.load_institutions(db).await?;
+        self.reload_since(db, None).await
+    }
+
+    /// Like [InfraDB::reload], with `since` only customers, organizations and institutions
+    /// created or updated after it are loaded.
+    ///
+    /// Deleted rows have no timestamp to filter on, so the cached ids are compared with the
+    /// ids in the database and entries that no longer exist are dropped.
+    pub async fn reload_since(
+        &self,
+        db: &DB,
+        since: Option<PrimitiveDateTime>,
+    ) -> anyhow::Result<()> {
+        let Some(since) = since else {
+            self.load_customers(db).await?;
+            self.load_organizations(db).await?;
+            self.load_institutions(db).await?;
+            return Ok(());
+        };
+        for v in fetch_customers_modified_since(db, since).await? {
+            self.refresh_customer(Arc::new(v)).await;
+        }
+        for v in fetch_organizations_modified_since(db, since).await? {
+            self.refresh_organization(Arc::new(v)).await;
+        }
+        for v in fetch_institutions_modified_since(db, since).await? {
+            self.refresh_institution(Arc::new(v)).await;
+        }
+        let (cids, oids, iids) = fetch_infra_ids(db).await?;
+        self.retain_ids(&cids, &oids, &iids).await;
         Ok(())
     }
 
+    /// Inserts the customer or replaces the cached customer with the same id, also if it was
+    /// renamed.
+    pub async fn refresh_customer(&self, customer: Arc<QmCustomer>) {
+        let customers_total = {
+            let mut customers = self.customers.write().await;
+            let mut customer_id_map = self.customer_id_map.write().await;
+            if let Some(old) = customer_id_map.insert(customer.id, customer.clone()) {
+                customers.remove(&old.name);
+            }
+            customers.insert(customer.name.clone(), customer);
+            customers.len()
+        };
+        self.customers_total.set(customers_total as i64);
+    }
+
+    /// Inserts the organization or replaces the cached organization with the same id, also if
+    /// it was renamed.
+    pub async fn refresh_organization(&self, organization: Arc<QmOrganization>) {
+        let organizations_total = {
+            let mut organizations = self.organizations.write().await;
+            let mut organization_id_map = self.organization_id_map.write().await;
+            if let Some(old) = organization_id_map.insert(organization.id, organization.clone()) {
+                organizations.remove(&(old.name.clone(), old.customer_id));
+            }
+            organizations.insert(
+                (organization.name.clone(), organization.customer_id),
+                organization,
+            );
+            organizations.len()
+        };
+        self.organizations_total.set(organizations_total as i64);
+    }
+
+    /// Inserts the institution or replaces the cached institution with the same id, also if
+    /// it was renamed.
+    pub async fn refresh_institution(&self, institution: Arc<QmInstitution>) {
+        let institutions_total = {
+            let mut institutions = self.institutions.write().await;
+            let mut institution_id_map = self.institution_id_map.write().await;
+            if let Some(old) = institution_id_map.insert(institution.id, institution.clone()) {
+                institutions.remove(&(old.name.clone(), old.customer_id, old.organization_id));
+            }
+            institutions.insert(
+                (
+                    institution.name.clone(),
+                    institution.customer_id,
+                    institution.organization_id,
+                ),
+                institution,
+            );
+            institutions.len()
+        };
+        self.institutions_total.set(institutions_total as i64);
+    }
+
+    /// Drops cached customers, organizations and institutions whose id is not in the given ids.
+    pub async fn retain_ids(&self, cids: &[i64], oids: &[i64], iids: &[i64]) {
+        let cids: HashSet<InfraId> = cids.iter().map(|&id| id.into()).collect();
+        let oids: HashSet<InfraId> = oids.iter().map(|&id| id.into()).collect();
+        let iids: HashSet<InfraId> = iids.iter().map(|&id| id.into()).collect();
+        let customers_total = {
+            let mut customers = self.customers.write().await;
+            customers.retain(|_, v| cids.contains(&v.id));
+            self.customer_id_map
+                .write()
+                .await
+                .retain(|id, _| cids.contains(id));
+            customers.len()
+        };
+        self.customers_total.set(customers_total as i64);
+        let organizations_total = {
+            let mut organizations = self.organizations.write().await;
+            organizations.retain(|_, v| oids.contains(&v.id));
+            self.organization_id_map
+                .write()
+                .await
+                .retain(|id, _| oids.contains(id));
+            organizations.len()
+        };
+        self.organizations_total.set(organizations_total as i64);
+        let institutions_total = {
+            let mut institutions = self.institutions.write().await;
+            institutions.retain(|_, v| iids.contains(&v.id));
+            self.institution_id_map
+                .write()
+                .await
+                .retain(|id, _| iids.contains(id));
+            institutions.len()
+        };
+        self.institutions_total.set(institutions_total as i64);
+    }
+
     pub async fn new_customer(&self, customer: Arc<QmCustomer>) {
         let customers_total = {
             let mut customers = self.customers.write().await;
@@ -146,6 +295,41 @@ impl InfraDB {
         self.institutions_total.set(institutions_total as i64);
     }
 
+    /// Looks up a customer by name.
+    ///
+    /// The name index is keyed like the unique constraint of the table, if duplicate names
+    /// are inserted anyway the last inserted customer wins.
+    pub async fn customer_by_name(&self, name: &str) -> Option<Arc<QmCustomer>> {
+        self.customers.read().await.get(name).cloned()
+    }
+
+    /// Looks up an organization by name, names are only unique within a customer.
+    ///
+    /// If duplicate names are inserted anyway the last inserted organization wins.
+    pub async fn organization_by_name(
+        &self,
+        cid: InfraId,
+        name: Arc<str>,
+    ) -> Option<Arc<QmOrganization>> {
+        self.organizations.read().await.get(&(name, cid)).cloned()
+    }
+
+    /// Looks up an institution by name, names are only unique within an organization.
+    ///
+    /// If duplicate names are inserted anyway the last inserted institution wins.
+    pub async fn institution_by_name(
+        &self,
+        cid: InfraId,
+        oid: InfraId,
+        name: Arc<str>,
+    ) -> Option<Arc<QmInstitution>> {
+        self.institutions
+            .read()
+            .await
+            .get(&(name, cid, oid))
+            .cloned()
+    }
+
     pub async fn remove_customer(&self, v: CustomerUpdate) {
         let customers_total = {
             let mut customers = self.customers.write().await;
@@ -232,39 +416,39 @@ impl InfraDB {
             ])
             .await?;
 
-        while let Some(notification) = listener.try_recv().await? {
-            match notification.channel() {
-                "customers_update" => {
-                    self.customers_update(notification.payload()).await?;
-                }
-                "organizations_update" => {
-                    self.organizations_update(notification.payload()).await?;
-                }
-                "institutions_update" => {
-                    self.institutions_update(notification.payload()).await?;
+        let mut connected_since = fetch_database_time(db).await?;
+        loop {
+            while let Some(notification) = listener.try_recv().await? {
+                match notification.channel() {
+                    "customers_update" => {
+                        self.customers_update(notification.payload()).await?;
+                    }
+                    "organizations_update" => {
+                        self.organizations_update(notification.payload()).await?;
+                    }
+                    "institutions_update" => {
+                        self.institutions_update(notification.payload()).await?;
+                    }
+                    _ => {}
                 }
-                _ => {}
             }
+            // `try_recv` has reconnected already, notifications sent while the connection was
+            // down are lost, so catch up with what changed since the previous connect.
+            tracing::warn!(
+                "postgresql listener reconnected, reloading changes since {connected_since}"
+            );
+            let now = fetch_database_time(db).await?;
+            self.reload_since(db, Some(connected_since)).await?;
+            connected_since = now;
         }
-        tracing::error!("postgresql listener disconnected");
-        std::process::exit(1);
     }
 
     async fn customers_update(&self, payload: &str) -> anyhow::Result<()> {
         let payload: Payload<CustomerUpdate> = serde_json::from_str(payload)?;
         match (payload.op, payload.new, payload.old) {
             (Op::Insert, Some(new), None) => {
-                if let Some(created_at) = parse_date_time(&new.created_at) {
-                    let customer = Arc::new(QmCustomer {
-                        id: new.id,
-                        name: new.name,
-                        ty: new.ty,
-                        created_at,
-                        created_by: new.created_by,
-                        updated_at: new.updated_at.and_then(|s| parse_date_time(&s)),
-                        updated_by: new.updated_by,
-                    });
-                    self.new_customer(customer).await;
+                if let Ok(customer) = QmCustomer::try_from(new) {
+                    self.new_customer(Arc::new(customer)).await;
                 }
             }
             (Op::Delete, None, Some(old)) => {
@@ -279,18 +463,8 @@ impl InfraDB {
         let payload: Payload<OrganizationUpdate> = serde_json::from_str(payload)?;
         match (payload.op, payload.new, payload.old) {
             (Op::Insert, Some(new), None) => {
-                if let Some(created_at) = parse_date_time(&new.created_at) {
-                    let organization = Arc::new(QmOrganization {
-                        id: new.id,
-                        customer_id: new.customer_id,
-                        name: new.name,
-                        ty: new.ty,
-                        created_at,
-                        created_by: new.created_by,
-                        updated_at: new.updated_at.and_then(|s| parse_date_time(&s)),
-                        updated_by: new.updated_by,
-                    });
-                    self.new_organization(organization).await;
+                if let Ok(organization) = QmOrganization::try_from(new) {
+                    self.new_organization(Arc::new(organization)).await;
                 }
             }
             (Op::Delete, None, Some(old)) => {
@@ -305,19 +479,8 @@ impl InfraDB {
         let payload: Payload<InstitutionUpdate> = serde_json::from_str(payload)?;
         match (payload.op, payload.new, payload.old) {
             (Op::Insert, Some(new), None) => {
-                if let Some(created_at) = parse_date_time(&new.created_at) {
-                    let organization = Arc::new(QmInstitution {
-                        id: new.id,
-                        customer_id: new.customer_id,
-                        organization_id: new.organization_id,
-                        name: new.name,
-                        ty: new.ty,
-                        created_at,
-                        created_by: new.created_by,
-                        updated_at: new.updated_at.and_then(|s| parse_date_time(&s)),
-                        updated_by: new.updated_by,
-                    });
-                    self.new_institution(organization).await;
+                if let Ok(institution) = QmInstitution::try_from(new) {
+                    self.new_institution(Arc::new(institution)).await;
                 }
             }
             (Op::Delete, None, Some(old)) => {
@@ -328,3 +491,187 @@ impl InfraDB {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{customer, institution, organization};
+    use sqlx::types::Uuid;
+
+    fn infra_db() -> InfraDB {
+        InfraDB {
+            customers: Default::default(),
+            customer_id_map: Default::default(),
+            customers_total: Gauge::default(),
+            organizations: Default::default(),
+            organization_id_map: Default::default(),
+            organizations_total: Gauge::default(),
+            institutions: Default::default(),
+            institution_id_map: Default::default(),
+            institutions_total: Gauge::default(),
+        }
+    }
+
+    fn named_customer(id: i64, name: &str) -> Arc<QmCustomer> {
+        Arc::new(QmCustomer {
+            name: Arc::from(name),
+            ..customer(id)
+        })
+    }
+
+    #[tokio::test]
+    async fn test_refresh_customer() {
+        let db = infra_db();
+        db.new_customer(named_customer(1, "acme")).await;
+        db.new_customer(named_customer(2, "globex")).await;
+        db.refresh_customer(named_customer(1, "acme corp")).await;
+        db.refresh_customer(named_customer(3, "initech")).await;
+        assert!(db.customer_by_name("acme").await.is_none());
+        assert_eq!(db.customer_by_name("acme corp").await.unwrap().id, 1.into());
+        assert_eq!(db.customer_by_name("globex").await.unwrap().id, 2.into());
+        assert_eq!(db.customer_id_map.read().await.len(), 3);
+        assert_eq!(db.customers_total.get(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_retain_ids() {
+        let db = infra_db();
+        db.new_customer(named_customer(1, "acme")).await;
+        db.new_customer(named_customer(2, "globex")).await;
+        for (cid, oid) in [(1, 3), (2, 4)] {
+            db.new_organization(Arc::new(QmOrganization {
+                name: Arc::from("sales"),
+                ..organization(cid, oid)
+            }))
+            .await;
+        }
+        db.retain_ids(&[1], &[3], &[]).await;
+        assert!(db.customer_by_name("globex").await.is_none());
+        assert!(db.customer_id_map.read().await.get(&2.into()).is_none());
+        assert_eq!(db.customers_total.get(), 1);
+        assert!(db
+            .organization_by_name(2.into(), Arc::from("sales"))
+            .await
+            .is_none());
+        assert_eq!(db.organization_id_map.read().await.len(), 1);
+        assert_eq!(db.organizations_total.get(), 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a postgres database configured by PG_*"]
+    async fn test_reload_since() {
+        use crate::mutation::{create_customer, create_organization};
+        use crate::mutation::{remove_customer, update_customer};
+
+        let db = crate::testing::customer_db().await;
+        let pool = db.pool();
+        let user = Uuid::nil();
+        let prefix = Uuid::new_v4().to_string();
+        let name = |suffix: &str| format!("{prefix} {suffix}");
+        let kept = create_customer(pool, None, &name("kept"), None, &user)
+            .await
+            .unwrap();
+        let renamed = create_customer(pool, None, &name("renamed"), None, &user)
+            .await
+            .unwrap();
+        let removed = create_customer(pool, None, &name("removed"), None, &user)
+            .await
+            .unwrap();
+        let cache = infra_db();
+        cache.reload(&db).await.unwrap();
+
+        let since = fetch_database_time(&db).await.unwrap();
+        update_customer(pool, renamed.id, &name("new name"), &user)
+            .await
+            .unwrap();
+        let created = create_customer(pool, None, &name("created"), None, &user)
+            .await
+            .unwrap();
+        let organization = create_organization(pool, None, &name("sales"), None, kept.id, &user)
+            .await
+            .unwrap();
+        remove_customer(pool, removed.id).await.unwrap();
+
+        let mut changed: Vec<InfraId> = fetch_customers_modified_since(&db, since)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|v| v.name.starts_with(&prefix))
+            .map(|v| v.id)
+            .collect();
+        changed.sort();
+        assert_eq!(changed, [renamed.id, created.id]);
+
+        cache.reload_since(&db, Some(since)).await.unwrap();
+        assert!(cache.customer_by_name(&name("kept")).await.is_some());
+        assert!(cache.customer_by_name(&name("renamed")).await.is_none());
+        assert_eq!(
+            cache.customer_by_name(&name("new name")).await.unwrap().id,
+            renamed.id
+        );
+        assert!(cache.customer_by_name(&name("created")).await.is_some());
+        assert!(cache.customer_by_name(&name("removed")).await.is_none());
+        assert!(cache
+            .customer_id_map
+            .read()
+            .await
+            .get(&removed.id)
+            .is_none());
+        assert!(cache
+            .organization_id_map
+            .read()
+            .await
+            .contains_key(&organization.id));
+        remove_customer(pool, kept.id).await.unwrap();
+        remove_customer(pool, renamed.id).await.unwrap();
+        remove_customer(pool, created.id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_lookup_by_name() {
+        let db = infra_db();
+        db.new_customer(named_customer(1, "acme")).await;
+        for (cid, oid) in [(1, 2), (7, 3)] {
+            db.new_organization(Arc::new(QmOrganization {
+                name: Arc::from("sales"),
+                ..organization(cid, oid)
+            }))
+            .await;
+        }
+        for (oid, iid) in [(2, 4), (3, 5)] {
+            db.new_institution(Arc::new(QmInstitution {
+                name: Arc::from("main"),
+                ..institution(1, oid, iid)
+            }))
+            .await;
+        }
+
+        assert_eq!(db.customer_by_name("acme").await.unwrap().id, 1.into());
+        assert!(db.customer_by_name("other").await.is_none());
+
+        // names are scoped to the customer
+        let name: Arc<str> = Arc::from("sales");
+        for (cid, oid) in [(1, 2), (7, 3)] {
+            let organization = db
+                .organization_by_name(cid.into(), name.clone())
+                .await
+                .unwrap();
+            assert_eq!(organization.id, oid.into());
+        }
+        assert!(db.organization_by_name(8.into(), name).await.is_none());
+
+        // names are scoped to the organization
+        let name: Arc<str> = Arc::from("main");
+        for (oid, iid) in [(2, 4), (3, 5)] {
+            let institution = db
+                .institution_by_name(1.into(), oid.into(), name.clone())
+                .await
+                .unwrap();
+            assert_eq!(institution.id, iid.into());
+        }
+        assert!(db
+            .institution_by_name(1.into(), 6.into(), name)
+            .await
+            .is_none());
+    }
+}
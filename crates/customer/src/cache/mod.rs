@@ -1,9 +1,13 @@
 use prometheus_client::metrics::gauge::Gauge;
 
 use qm_entity::ids::PartialEqual;
-use qm_entity::ids::{CustomerId, CustomerOrOrganization, InfraContext, InfraId};
+use qm_entity::ids::{
+    CustomerId, CustomerOrOrganization, InfraContext, InfraId, InstitutionId, OrganizationId,
+};
 use qm_entity::model::ListFilter;
+use qm_entity::model::DEFAULT_PER_PAGE;
 
+use std::collections::{BTreeSet, HashMap};
 use std::str::FromStr;
 use std::sync::atomic::AtomicI64;
 use std::sync::Arc;
@@ -41,6 +45,13 @@ impl CacheDB {
         })
     }
 
+    #[cfg(test)]
+    pub(crate) fn from_parts(infra: InfraDB, user: UserDB) -> Self {
+        Self {
+            inner: Arc::new(Inner { infra, user }),
+        }
+    }
+
     pub fn user(&self) -> &UserDB {
         &self.inner.user
     }
@@ -49,6 +60,36 @@ impl CacheDB {
         &self.inner.infra
     }
 
+    /// Loads the owners of all cached users into the infra cache, so resolving
+    /// `customer`, `organization` and `institution` of a user does not miss.
+    pub async fn warm_owner_cache(&self, customer_db: &qm_pg::DB) -> anyhow::Result<()> {
+        let contexts = self.owner_contexts().await;
+        let mut cids = BTreeSet::new();
+        let mut oids = BTreeSet::new();
+        let mut iids = BTreeSet::new();
+        for context in contexts.iter() {
+            cids.insert(*context.customer_id());
+            if let Some(oid) = context.organization_id() {
+                oids.insert(*oid);
+            }
+            if let Some(iid) = context.institution_id() {
+                iids.insert(*iid);
+            }
+        }
+        let cids: Vec<i64> = cids.into_iter().collect();
+        let oids: Vec<i64> = oids.into_iter().collect();
+        let iids: Vec<i64> = iids.into_iter().collect();
+        self.inner
+            .infra
+            .load_by_ids(customer_db, &cids, &oids, &iids)
+            .await
+    }
+
+    /// Distinct owner contexts of the cached users.
+    async fn owner_contexts(&self) -> BTreeSet<InfraContext> {
+        self.user_contexts().await.into_iter().collect()
+    }
+
     pub fn customers_total(&self) -> &Gauge<i64, AtomicI64> {
         &self.inner.infra.customers_total
     }
@@ -73,15 +114,16 @@ impl CacheDB {
             itertools::Either::Left(customers.values())
         };
         if let Some(filter) = filter {
-            let page = filter.page.unwrap_or(0);
-            let limit = filter.limit.unwrap_or(100);
-            let offset = page * limit;
+            let pagination = filter.pagination(DEFAULT_PER_PAGE);
+            let page = pagination.page;
+            let limit = pagination.limit() as usize;
+            let offset = pagination.offset() as usize;
             let items: Arc<[Arc<QmCustomer>]> = iter.skip(offset).take(limit).cloned().collect();
             QmCustomerList {
                 items,
                 limit: Some(limit as i64),
                 total: Some(self.inner.infra.customers_total.get()),
-                page: Some(page as i64),
+                page: Some(page),
             }
         } else {
             let items: Arc<[Arc<QmCustomer>]> = iter.cloned().collect();
@@ -117,16 +159,17 @@ impl CacheDB {
         };
         let total = iter.clone().count();
         if let Some(filter) = filter {
-            let page = filter.page.unwrap_or(0);
-            let limit = filter.limit.unwrap_or(100);
-            let offset = page * limit;
+            let pagination = filter.pagination(DEFAULT_PER_PAGE);
+            let page = pagination.page;
+            let limit = pagination.limit() as usize;
+            let offset = pagination.offset() as usize;
             let items: Arc<[Arc<QmOrganization>]> =
                 iter.skip(offset).take(limit).cloned().collect();
             QmOrganizationList {
                 items,
                 limit: Some(limit as i64),
                 total: Some(total as i64),
-                page: Some(page as i64),
+                page: Some(page),
             }
         } else {
             let items: Arc<[Arc<QmOrganization>]> = iter.cloned().collect();
@@ -167,15 +210,16 @@ impl CacheDB {
             _ => itertools::Either::Right(iter),
         };
         if let Some(filter) = filter {
-            let page = filter.page.unwrap_or(0);
-            let limit = filter.limit.unwrap_or(100);
-            let offset = page * limit;
+            let pagination = filter.pagination(DEFAULT_PER_PAGE);
+            let page = pagination.page;
+            let limit = pagination.limit() as usize;
+            let offset = pagination.offset() as usize;
             let items: Arc<[Arc<QmInstitution>]> = iter.skip(offset).take(limit).cloned().collect();
             QmInstitutionList {
                 items,
                 limit: Some(limit as i64),
                 total: Some(self.inner.infra.institutions_total.get()),
-                page: Some(page as i64),
+                page: Some(page),
             }
         } else {
             let items: Arc<[Arc<QmInstitution>]> = iter.cloned().collect();
@@ -188,6 +232,51 @@ impl CacheDB {
         }
     }
 
+    /// Institutions of the organization together with the number of users in their context.
+    ///
+    /// Institutions without users are listed with a count of zero.
+    pub async fn institution_list_with_user_counts(
+        &self,
+        organization_id: OrganizationId,
+        filter: Option<ListFilter>,
+    ) -> Vec<(Arc<QmInstitution>, u64)> {
+        let institutions = self
+            .institution_list(
+                Some(CustomerOrOrganization::Organization(organization_id)),
+                filter,
+                None,
+            )
+            .await
+            .items;
+        institution_user_counts(&institutions, self.user_contexts().await)
+    }
+
+    /// Contexts of all cached users which have one.
+    pub async fn user_contexts(&self) -> Vec<InfraContext> {
+        self.users_with_context()
+            .await
+            .into_iter()
+            .map(|(_, context)| context)
+            .collect()
+    }
+
+    /// All cached users which have a context, together with that context.
+    pub async fn users_with_context(&self) -> Vec<(Arc<QmUser>, InfraContext)> {
+        let users = self.inner.user.users.read().await;
+        let user_roles = self.inner.user.user_roles.read().await;
+        let roles = self.inner.user.roles.read().await;
+        users
+            .list()
+            .iter()
+            .filter_map(|u| {
+                user_roles
+                    .by_user_id(&u.id)
+                    .and_then(|r| r.iter().find_map(|r| roles.get(r).and_then(|r| r.context)))
+                    .map(|context| (u.clone(), context))
+            })
+            .collect()
+    }
+
     pub async fn user_list(
         &self,
         context: Option<InfraContext>,
@@ -226,9 +315,10 @@ impl CacheDB {
             }
         });
         if let Some(filter) = filter {
-            let page = filter.page.unwrap_or(0);
-            let limit = filter.limit.unwrap_or(100);
-            let offset = page * limit;
+            let pagination = filter.pagination(DEFAULT_PER_PAGE);
+            let page = pagination.page;
+            let limit = pagination.limit() as usize;
+            let offset = pagination.offset() as usize;
             let items: Vec<QmUserDetails> = if let Some(context) = context {
                 iter.filter(|v| v.partial_equal(&context))
                     .skip(offset)
@@ -243,7 +333,7 @@ impl CacheDB {
                 items: Arc::from(items),
                 limit: Some(limit as i64),
                 total: Some(self.inner.user.users_total.get()),
-                page: Some(page as i64),
+                page: Some(page),
             }
         } else {
             let items: Vec<QmUserDetails> = if let Some(context) = context {
@@ -271,8 +361,9 @@ impl CacheDB {
             .cloned()
     }
 
+    /// Returns the customer with the given name, see [`InfraDB::customer_by_name`].
     pub async fn customer_by_name(&self, name: &str) -> Option<Arc<QmCustomer>> {
-        self.inner.infra.customers.read().await.get(name).cloned()
+        self.inner.infra.customer_by_name(name).await
     }
 
     pub async fn organization_by_id(&self, id: &InfraId) -> Option<Arc<QmOrganization>> {
@@ -285,33 +376,23 @@ impl CacheDB {
             .cloned()
     }
 
+    /// Returns the organization with the given name within the customer, see [`InfraDB::organization_by_name`].
     pub async fn organization_by_name(
         &self,
         cid: InfraId,
         name: Arc<str>,
     ) -> Option<Arc<QmOrganization>> {
-        self.inner
-            .infra
-            .organizations
-            .read()
-            .await
-            .get(&(name, cid))
-            .cloned()
+        self.inner.infra.organization_by_name(cid, name).await
     }
 
+    /// Returns the institution with the given name within the organization, see [`InfraDB::institution_by_name`].
     pub async fn institution_by_name(
         &self,
         cid: InfraId,
         oid: InfraId,
         name: Arc<str>,
     ) -> Option<Arc<QmInstitution>> {
-        self.inner
-            .infra
-            .institutions
-            .read()
-            .await
-            .get(&(name, cid, oid))
-            .cloned()
+        self.inner.infra.institution_by_name(cid, oid, name).await
     }
 
     pub async fn institution_by_id(&self, id: &InfraId) -> Option<Arc<QmInstitution>> {
@@ -324,6 +405,21 @@ impl CacheDB {
             .cloned()
     }
 
+    /// Returns `true` if the entity of the context is cached with the same parents.
+    pub async fn context_exists(&self, context: &InfraContext) -> bool {
+        match context {
+            InfraContext::Customer(id) => self.customer_by_id(&id.unzip().into()).await.is_some(),
+            InfraContext::Organization(id) => self
+                .organization_by_id(&id.id().into())
+                .await
+                .is_some_and(|v| OrganizationId::from(v.as_ref()) == *id),
+            InfraContext::Institution(id) => self
+                .institution_by_id(&id.id().into())
+                .await
+                .is_some_and(|v| InstitutionId::from(v.as_ref()) == *id),
+        }
+    }
+
     pub fn users_total(&self) -> &Gauge<i64, AtomicI64> {
         &self.inner.user.users_total
     }
@@ -350,6 +446,11 @@ impl CacheDB {
         self.inner.user.users.read().await.get(id).cloned()
     }
 
+    /// Details of the user with the id stored in `created_by` / `updated_by` fields.
+    pub async fn user_details_by_uuid(&self, id: &sqlx::types::Uuid) -> Option<QmUserDetails> {
+        self.user_details_by_id(&id.to_string()).await
+    }
+
     pub async fn user_details_by_id(&self, id: &str) -> Option<QmUserDetails> {
         let users = self.inner.user.users.read().await;
         let user_roles = self.inner.user.user_roles.read().await;
@@ -476,6 +577,28 @@ impl CacheDB {
     }
 }
 
+fn institution_user_counts(
+    institutions: &[Arc<QmInstitution>],
+    contexts: impl IntoIterator<Item = InfraContext>,
+) -> Vec<(Arc<QmInstitution>, u64)> {
+    let mut counts: HashMap<InstitutionId, u64> = HashMap::new();
+    for context in contexts {
+        if let InfraContext::Institution(id) = context {
+            *counts.entry(id).or_default() += 1;
+        }
+    }
+    institutions
+        .iter()
+        .map(|i| {
+            let count = counts
+                .get(&InstitutionId::from(i.as_ref()))
+                .copied()
+                .unwrap_or_default();
+            (i.clone(), count)
+        })
+        .collect()
+}
+
 pub fn subscribe(keycloak_db: qm_pg::DB, customer_db: qm_pg::DB, listener_instance: CacheDB) {
     let keycloak_listener_instance = listener_instance.clone();
     std::thread::spawn(move || {
@@ -516,3 +639,123 @@ pub fn subscribe(keycloak_db: qm_pg::DB, customer_db: qm_pg::DB, listener_instan
         rt.block_on(local);
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::institution;
+
+    #[test]
+    fn test_institution_user_counts() {
+        let institutions = [
+            Arc::new(institution(1, 2, 3)),
+            Arc::new(institution(1, 2, 4)),
+        ];
+        let contexts = [
+            InfraContext::Institution((1, 2, 3).into()),
+            InfraContext::Institution((1, 2, 3).into()),
+            InfraContext::Institution((1, 5, 6).into()),
+            InfraContext::Organization((1, 2).into()),
+        ];
+        let counts = institution_user_counts(&institutions, contexts)
+            .into_iter()
+            .map(|(i, count)| (i.id, count))
+            .collect::<Vec<_>>();
+        assert_eq!(counts, vec![(3.into(), 2), (4.into(), 0)]);
+    }
+
+    /// Cache with one user per context, each having the access role of its context.
+    async fn cache_with_owners(contexts: &[InfraContext]) -> CacheDB {
+        let cache = CacheDB::from_parts(InfraDB::default(), UserDB::default());
+        let roles = contexts
+            .iter()
+            .enumerate()
+            .map(|(i, context)| qm_keycloak::RoleRepresentation {
+                id: Some(format!("r{i}")),
+                name: Some(crate::roles::context_access_string(context)),
+                ..Default::default()
+            })
+            .collect();
+        cache.user().new_roles(roles).await;
+        for i in 0..contexts.len() {
+            let id = format!("u{i}");
+            cache
+                .user()
+                .new_user(Arc::new(QmUser {
+                    id: Arc::from(id.as_str()),
+                    username: Arc::from(id.as_str()),
+                    email: Arc::from(format!("{id}@test.local")),
+                    firstname: Arc::from(id.as_str()),
+                    lastname: Arc::from(id.as_str()),
+                    enabled: true,
+                }))
+                .await;
+            cache
+                .user()
+                .user_roles
+                .write()
+                .await
+                .insert(Arc::from(id.as_str()), Arc::from(format!("r{i}")));
+        }
+        cache
+    }
+
+    #[tokio::test]
+    async fn test_owner_contexts() {
+        let contexts = [
+            InfraContext::Institution((1, 2, 3).into()),
+            InfraContext::Institution((1, 2, 3).into()),
+            InfraContext::Organization((1, 4).into()),
+            InfraContext::Customer(5.into()),
+        ];
+        let cache = cache_with_owners(&contexts).await;
+        assert_eq!(
+            cache.owner_contexts().await,
+            BTreeSet::from([contexts[0], contexts[2], contexts[3]])
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a postgres database configured by PG_*"]
+    async fn test_warm_owner_cache() {
+        let db = crate::testing::customer_db().await;
+        let user = sqlx::types::Uuid::nil();
+        let name = sqlx::types::Uuid::new_v4().to_string();
+        let pool = db.pool();
+        let customer = crate::mutation::create_customer(pool, None, &name, None, &user)
+            .await
+            .unwrap();
+        let organization =
+            crate::mutation::create_organization(pool, None, &name, None, customer.id, &user)
+                .await
+                .unwrap();
+        let institution = crate::mutation::create_institution(
+            pool,
+            None,
+            &name,
+            None,
+            customer.id,
+            organization.id,
+            &user,
+        )
+        .await
+        .unwrap();
+        let contexts = [
+            InfraContext::Institution((*customer.id, *organization.id, *institution.id).into()),
+            InfraContext::Organization((*customer.id, *organization.id).into()),
+        ];
+        let cache = cache_with_owners(&contexts).await;
+        assert!(cache.customer_by_id(&customer.id).await.is_none());
+
+        cache.warm_owner_cache(&db).await.unwrap();
+        // owner lookups are served from the cache without the database
+        pool.close().await;
+        for (i, context) in contexts.iter().enumerate() {
+            let details = cache.user_details_by_id(&format!("u{i}")).await.unwrap();
+            assert_eq!(details.context, Some(*context));
+        }
+        assert!(cache.customer_by_id(&customer.id).await.is_some());
+        assert!(cache.organization_by_id(&organization.id).await.is_some());
+        assert!(cache.institution_by_id(&institution.id).await.is_some());
+    }
+}
@@ -17,6 +17,7 @@ fn parse_type(s: &str) -> Arc<[Arc<str>]> {
     s.split(',').map(|s| s.trim().into()).collect()
 }
 
+#[cfg_attr(test, derive(Default))]
 pub struct GroupAttributes {
     group_attribute_map: GroupDetailsMap,
 }
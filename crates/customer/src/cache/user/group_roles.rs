@@ -11,6 +11,7 @@ use crate::{
     query::fetch_group_roles,
 };
 
+#[cfg_attr(test, derive(Default))]
 pub struct GroupRoles {
     group_id_role_map: UserRoleMap,
     role_id_group_map: UserRoleMap,
@@ -23,6 +23,7 @@ pub mod user_groups;
 pub mod user_roles;
 pub mod users;
 
+#[cfg_attr(test, derive(Default))]
 pub struct UserDB {
     pub realm: RwLock<Realm>,
     pub roles: RwLock<Roles>,
@@ -99,6 +100,15 @@ impl UserDB {
         self.users_total.set(self.users.read().await.total());
     }
 
+    /// Replaces the realm role `old_role_id` of the user with `new_role_id` before the change
+    /// arrives from the database listener.
+    pub async fn replace_user_role(&self, user_id: &str, old_role_id: &str, new_role_id: &str) {
+        self.user_roles
+            .write()
+            .await
+            .replace(user_id, old_role_id, Arc::from(new_role_id));
+    }
+
     pub async fn cleanup(db: &DB) -> anyhow::Result<()> {
         let mut migrator = sqlx::migrate!("./migrations/keycloak");
         migrator.set_ignore_missing(true);
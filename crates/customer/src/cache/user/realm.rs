@@ -10,6 +10,7 @@ use crate::{
     query::fetch_realm_info,
 };
 
+#[cfg_attr(test, derive(Default))]
 pub struct Realm {
     name: Arc<str>,
     id: Option<Arc<str>>,
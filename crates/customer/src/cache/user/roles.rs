@@ -13,13 +13,17 @@ use crate::{
     query::fetch_roles,
 };
 
-fn parse_context(name: &str) -> Option<InfraContext> {
-    if let Some((_, id)) = name.rsplit_once("access@") {
-        return id.parse().ok();
-    }
-    None
+/// Id part of an access role name, e.g. `R010203` of `institution:access@R010203`.
+pub(crate) fn access_id(name: &str) -> Option<&str> {
+    name.rsplit_once("access@").map(|(_, id)| id)
+}
+
+/// Context of an access role name.
+pub(crate) fn parse_context(name: &str) -> Option<InfraContext> {
+    access_id(name).and_then(|id| id.parse().ok())
 }
 
+#[cfg_attr(test, derive(Default))]
 pub struct Roles {
     role_name_map: RoleMap,
     role_id_map: RoleIdMap,
@@ -10,6 +10,7 @@ use crate::query::fetch_user_groups;
 
 use super::{groups::Groups, users::Users};
 
+#[cfg_attr(test, derive(Default))]
 pub struct UserGroups {
     user_id_group_map: UserGroupMap,
     group_id_user_map: UserGroupMap,
@@ -10,6 +10,7 @@ use crate::query::fetch_user_roles;
 
 use super::{roles::Roles, users::Users};
 
+#[cfg_attr(test, derive(Default))]
 pub struct UserRoles {
     user_id_role_map: UserRoleMap,
     role_id_user_map: UserRoleMap,
@@ -44,6 +45,28 @@ impl UserRoles {
         self.user_id_role_map.get(user_id)
     }
 
+    pub fn insert(&mut self, user_id: Arc<str>, role_id: Arc<str>) {
+        self.user_id_role_map
+            .entry(user_id.clone())
+            .or_default()
+            .insert(role_id.clone());
+        self.role_id_user_map
+            .entry(role_id)
+            .or_default()
+            .insert(user_id);
+    }
+
+    /// Replaces the role `old_role_id` of the user with `new_role_id`.
+    pub fn replace(&mut self, user_id: &str, old_role_id: &str, new_role_id: Arc<str>) {
+        if let Some(role_ids) = self.user_id_role_map.get_mut(user_id) {
+            role_ids.remove(old_role_id);
+        }
+        if let Some(user_ids) = self.role_id_user_map.get_mut(old_role_id) {
+            user_ids.remove(user_id);
+        }
+        self.insert(Arc::from(user_id), new_role_id);
+    }
+
     pub fn update(&mut self, users: &Users, roles: &Roles, payload: &str) -> anyhow::Result<bool> {
         let payload: Payload<UserRoleMappingUpdate> = serde_json::from_str(payload)?;
         match (payload.op, payload.new, payload.old) {
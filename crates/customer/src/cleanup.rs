@@ -1,7 +1,9 @@
 use futures::stream::FuturesUnordered;
+use futures::StreamExt;
 
 use qm_entity::ids::InfraContext;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::collections::BTreeSet;
 use std::collections::HashSet;
 use std::sync::Arc;
@@ -10,12 +12,27 @@ use tokio::sync::Semaphore;
 use tracing::error;
 
 use qm_entity::ids::CustomerIds;
+use qm_entity::ids::CustomerOrOrganization;
+use qm_entity::ids::InfraId;
 use qm_entity::ids::InstitutionIds;
+use qm_entity::ids::OrganizationId;
 use qm_entity::ids::OrganizationIds;
+use qm_entity::ids::Owner;
+use qm_keycloak::GroupRepresentation;
 use qm_keycloak::Keycloak;
 use qm_keycloak::KeycloakError;
+use qm_keycloak::UserRepresentation;
+use qm_mongodb::bson::doc;
+use qm_mongodb::bson::Document;
 use sqlx::types::Uuid;
 
+use crate::context::RelatedStorage;
+use crate::groups::GroupPath;
+use crate::model::OrganizationUnitNode;
+use crate::mutation;
+use crate::query;
+use crate::schema::user::KeycloakClient;
+
 #[derive(
     Default, AsRefStr, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize,
 )]
@@ -31,10 +48,96 @@ pub enum CleanupTaskType {
     None,
 }
 
+/// Defines what happens with the users of removed customers, organizations and institutions.
+#[derive(
+    Default, AsRefStr, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize,
+)]
+pub enum UserCleanupMode {
+    #[default]
+    #[strum(serialize = "delete")]
+    Delete,
+    /// Keeps the users but disables them, e.g. for a retention period before deletion
+    #[strum(serialize = "disable")]
+    Disable,
+}
+
+/// What happens with organization units left without members after an organization cleanup.
+#[derive(
+    Default, AsRefStr, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize,
+)]
+pub enum EmptyUnitPolicy {
+    /// Keeps the units and logs a warning for each of them
+    #[default]
+    #[strum(serialize = "flag")]
+    Flag,
+    #[strum(serialize = "delete")]
+    Delete,
+}
+
+lazy_static::lazy_static! {
+    static ref MAX_IDS_PER_TASK: usize = {
+        std::env::var("CUSTOMER_CLEANUP_TASK_MAX_IDS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(500)
+    };
+}
+
+/// Maximum number of ids in a single cleanup task, configured via `CUSTOMER_CLEANUP_TASK_MAX_IDS`.
+pub fn max_ids_per_task() -> usize {
+    *MAX_IDS_PER_TASK
+}
+
+impl CleanupTaskType {
+    /// Number of ids affected by this task type.
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Customers(ids) => ids.len(),
+            Self::Organizations(ids) => ids.len(),
+            Self::Institutions(ids) => ids.len(),
+            Self::None => 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Splits the ids into task types of at most `max` ids each.
+    pub fn split(self, max: usize) -> Vec<CleanupTaskType> {
+        let max = max.max(1);
+        match self {
+            Self::Customers(ids) => ids.chunks(max).map(|c| Self::Customers(c.into())).collect(),
+            Self::Organizations(ids) => ids
+                .chunks(max)
+                .map(|c| Self::Organizations(c.into()))
+                .collect(),
+            Self::Institutions(ids) => ids
+                .chunks(max)
+                .map(|c| Self::Institutions(c.into()))
+                .collect(),
+            Self::None => vec![Self::None],
+        }
+    }
+}
+
+pub const USER_DISABLED_REASON_ATTRIBUTE: &str = "disabledReason";
+pub(crate) const USER_DISABLED_REASON_CLEANUP: &str = "cleanup";
+const USER_DISABLED_REASON_DANGLING_OWNER: &str = "dangling_owner";
+const USER_DISABLED_REASON_OWNER: &str = "owner";
+
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct CleanupTask {
     pub id: Uuid,
     pub ty: CleanupTaskType,
+    #[serde(default)]
+    pub user_cleanup_mode: UserCleanupMode,
+    #[serde(default)]
+    pub empty_unit_policy: EmptyUnitPolicy,
+    /// Organization units with members of the removed organizations, recorded before the
+    /// removal. Only these units are checked with the [EmptyUnitPolicy].
+    #[serde(default)]
+    pub organization_unit_ids: Vec<i64>,
 }
 
 impl CleanupTask {
@@ -42,22 +145,90 @@ impl CleanupTask {
         Self {
             id: Uuid::new_v4(),
             ty,
+            user_cleanup_mode: UserCleanupMode::default(),
+            empty_unit_policy: EmptyUnitPolicy::default(),
+            organization_unit_ids: vec![],
         }
     }
+
+    pub fn with_user_cleanup_mode(mut self, user_cleanup_mode: UserCleanupMode) -> Self {
+        self.user_cleanup_mode = user_cleanup_mode;
+        self
+    }
+
+    pub fn with_empty_unit_policy(mut self, empty_unit_policy: EmptyUnitPolicy) -> Self {
+        self.empty_unit_policy = empty_unit_policy;
+        self
+    }
+
+    pub fn with_organization_unit_ids(mut self, organization_unit_ids: Vec<i64>) -> Self {
+        self.organization_unit_ids = organization_unit_ids;
+        self
+    }
+
+    /// Splits the task into sub-tasks of at most `max` ids, each processed independently.
+    ///
+    /// The first sub-task keeps the id and the organization unit ids of the original task.
+    pub fn split(self, max: usize) -> Vec<CleanupTask> {
+        let user_cleanup_mode = self.user_cleanup_mode;
+        let empty_unit_policy = self.empty_unit_policy;
+        let mut organization_unit_ids = Some(self.organization_unit_ids);
+        self.ty
+            .split(max)
+            .into_iter()
+            .enumerate()
+            .map(|(i, ty)| CleanupTask {
+                id: if i == 0 { self.id } else { Uuid::new_v4() },
+                ty,
+                user_cleanup_mode,
+                empty_unit_policy,
+                organization_unit_ids: organization_unit_ids.take().unwrap_or_default(),
+            })
+            .collect()
+    }
+}
+
+fn enable_user(user: &mut UserRepresentation) {
+    user.enabled = Some(true);
+    if let Some(attributes) = user.attributes.as_mut() {
+        attributes.remove(USER_DISABLED_REASON_ATTRIBUTE);
+    }
+}
+
+fn disable_user(user: &mut UserRepresentation, reason: &str) {
+    user.enabled = Some(false);
+    user.attributes.get_or_insert_with(Default::default).insert(
+        USER_DISABLED_REASON_ATTRIBUTE.to_string(),
+        vec![reason.to_string()],
+    );
 }
 
 async fn remove_users_by_access(
     realm: &str,
     keycloak: &Keycloak,
     role_name: &str,
-) -> anyhow::Result<()> {
-    let result = keycloak.role_members(realm, role_name).await;
+    mode: UserCleanupMode,
+) -> anyhow::Result<Vec<String>> {
+    let mut user_ids = vec![];
+    let result = keycloak.all_role_members(realm, role_name).await;
     match result {
         Ok(users) => {
-            for user in users {
-                keycloak
-                    .remove_user(realm, user.id.as_deref().unwrap())
-                    .await?;
+            for user_id in users.into_iter().filter_map(|user| user.id) {
+                match mode {
+                    UserCleanupMode::Delete => {
+                        keycloak.remove_user(realm, &user_id).await?;
+                    }
+                    UserCleanupMode::Disable => {
+                        // the members are brief representations, updating them would drop
+                        // attributes, required actions and federation links of the user
+                        let Some(mut user) = keycloak.user_by_id(realm, &user_id).await? else {
+                            continue;
+                        };
+                        disable_user(&mut user, USER_DISABLED_REASON_CLEANUP);
+                        keycloak.update_user(realm, &user_id, &user).await?;
+                    }
+                }
+                user_ids.push(user_id);
             }
         }
         Err(err) => match err {
@@ -65,7 +236,7 @@ async fn remove_users_by_access(
             _ => Err(err)?,
         },
     }
-    Ok(())
+    Ok(user_ids)
 }
 
 pub async fn cleanup_api_clients(
@@ -115,24 +286,32 @@ pub async fn cleanup_api_clients(
     Ok(())
 }
 
-pub async fn cleanup_roles(keycloak: &Keycloak, roles: BTreeSet<String>) -> anyhow::Result<()> {
+/// Removes the given roles and deletes or disables all users assigned to them.
+///
+/// Returns the ids of the affected users grouped by the role they were assigned to.
+pub async fn cleanup_roles(
+    keycloak: &Keycloak,
+    roles: BTreeSet<String>,
+    mode: UserCleanupMode,
+) -> anyhow::Result<BTreeMap<String, Vec<String>>> {
+    let mut removed_users = BTreeMap::new();
     if !roles.is_empty() {
         let semaphore = Arc::new(Semaphore::new(4));
-        let role_remove_tasks = FuturesUnordered::new();
-        for role in roles.clone().into_iter() {
+        let mut role_remove_tasks = FuturesUnordered::new();
+        for role in roles {
             let keycloak = keycloak.clone();
             let permit = semaphore.clone().acquire_owned().await.unwrap();
 
             role_remove_tasks.push(tokio::spawn(async move {
                 let realm = keycloak.config().realm();
-                tracing::debug!("remove users with role from keycloak {role}");
-                if remove_users_by_access(realm, &keycloak, &role)
-                    .await
-                    .is_err()
-                {
-                    drop(permit);
-                    return anyhow::Ok(role);
-                }
+                tracing::debug!("{} users with role from keycloak {role}", mode.as_ref());
+                let user_ids = match remove_users_by_access(realm, &keycloak, &role, mode).await {
+                    Ok(user_ids) => user_ids,
+                    Err(_) => {
+                        drop(permit);
+                        return anyhow::Ok((role, vec![]));
+                    }
+                };
                 tracing::debug!("remove role from keycloak {role}");
                 let result = keycloak.remove_role(realm, &role).await;
                 drop(permit);
@@ -146,9 +325,761 @@ pub async fn cleanup_roles(keycloak: &Keycloak, roles: BTreeSet<String>) -> anyh
                         }
                     },
                 }
-                anyhow::Ok(role)
+                anyhow::Ok((role, user_ids))
             }));
         }
+        while let Some(result) = role_remove_tasks.next().await {
+            match result {
+                Ok(Ok((role, user_ids))) => {
+                    if !user_ids.is_empty() {
+                        removed_users.insert(role, user_ids);
+                    }
+                }
+                Ok(Err(err)) => tracing::error!("{err:#?}"),
+                Err(err) => tracing::error!("{err:#?}"),
+            }
+        }
+    }
+    Ok(removed_users)
+}
+
+/// Lists the users of the realm which are no member of any managed access group.
+///
+/// Managed access groups are the `built_in_groups` (e.g. `RelatedGroups::built_in_groups`)
+/// and all custom groups, see [GroupPath].
+pub async fn orphaned_users<Store>(
+    store: &Store,
+    realm: &str,
+    built_in_groups: &[&str],
+) -> anyhow::Result<Vec<UserRepresentation>>
+where
+    Store: KeycloakClient,
+{
+    let keycloak = store.keycloak();
+    let mut groups = vec![];
+    for group in keycloak.all_groups_sorted(realm).await? {
+        if let Some(group_id) = group.id.as_deref() {
+            collect_groups(keycloak.group_subtree(realm, group_id).await?, &mut groups);
+        }
+    }
+    let mut members = HashSet::new();
+    for group in groups {
+        let (Some(group_id), Some(path)) = (group.id.as_deref(), group.path.as_deref()) else {
+            continue;
+        };
+        if is_managed_group_path(path, built_in_groups) {
+            members.extend(
+                keycloak
+                    .all_group_members(realm, group_id)
+                    .await?
+                    .into_iter()
+                    .filter_map(|user| user.id),
+            );
+        }
+    }
+    let page_size = 1000;
+    let mut offset = 0;
+    let mut users = vec![];
+    loop {
+        let result = keycloak
+            .users(realm, Some(offset), Some(page_size), None)
+            .await?;
+        if result.is_empty() {
+            break;
+        }
+        offset += page_size;
+        users.extend(result);
+    }
+    Ok(filter_orphans(users, &members))
+}
+
+fn collect_groups(mut group: GroupRepresentation, groups: &mut Vec<GroupRepresentation>) {
+    for sub_group in group.sub_groups.take().into_iter().flatten() {
+        collect_groups(sub_group, groups);
+    }
+    groups.push(group);
+}
+
+fn is_managed_group_path(path: &str, built_in_groups: &[&str]) -> bool {
+    built_in_groups.contains(&path) || GroupPath::parse(path).is_ok()
+}
+
+fn filter_orphans(
+    users: Vec<UserRepresentation>,
+    members: &HashSet<String>,
+) -> Vec<UserRepresentation> {
+    users
+        .into_iter()
+        .filter(|user| user.id.as_ref().is_some_and(|id| !members.contains(id)))
+        .collect()
+}
+
+/// Ids of the customers, organizations and institutions which exist in the database.
+#[derive(Debug, Default)]
+struct ExistingEntities {
+    customers: HashSet<i64>,
+    organizations: HashSet<i64>,
+    institutions: HashSet<i64>,
+}
+
+impl ExistingEntities {
+    fn contains(&self, context: &InfraContext) -> bool {
+        self.customers.contains(context.customer_id().as_ref())
+            && context
+                .organization_id()
+                .into_iter()
+                .all(|id| self.organizations.contains(id.as_ref()))
+            && context
+                .institution_id()
+                .into_iter()
+                .all(|id| self.institutions.contains(id.as_ref()))
+    }
+}
+
+/// Users whose context references a customer, organization or institution which does not
+/// exist in the database anymore, together with the owner of that context.
+pub async fn find_dangling_owners<Store>(store: &Store) -> anyhow::Result<Vec<(Arc<Uuid>, Owner)>>
+where
+    Store: RelatedStorage,
+{
+    let users: Vec<(Arc<str>, InfraContext)> = store
+        .cache_db()
+        .users_with_context()
+        .await
+        .into_iter()
+        .map(|(user, context)| (user.id.clone(), context))
+        .collect();
+    let ids = |id: fn(&InfraContext) -> Option<InfraId>| -> Vec<i64> {
+        let ids: BTreeSet<i64> = users
+            .iter()
+            .filter_map(|(_, c)| id(c))
+            .map(|id| *id.as_ref())
+            .collect();
+        ids.into_iter().collect()
+    };
+    let db = store.customer_db();
+    let existing = ExistingEntities {
+        customers: query::fetch_customers_by_ids(db, &ids(|c| Some(c.customer_id())))
+            .await?
+            .iter()
+            .map(|v| *v.id.as_ref())
+            .collect(),
+        organizations: query::fetch_organizations_by_ids(db, &ids(InfraContext::organization_id))
+            .await?
+            .iter()
+            .map(|v| *v.id.as_ref())
+            .collect(),
+        institutions: query::fetch_institutions_by_ids(db, &ids(InfraContext::institution_id))
+            .await?
+            .iter()
+            .map(|v| *v.id.as_ref())
+            .collect(),
+    };
+    Ok(dangling_owners(users, &existing))
+}
+
+fn dangling_owners(
+    users: impl IntoIterator<Item = (Arc<str>, InfraContext)>,
+    existing: &ExistingEntities,
+) -> Vec<(Arc<Uuid>, Owner)> {
+    users
+        .into_iter()
+        .filter(|(_, context)| !existing.contains(context))
+        .filter_map(|(user_id, context)| {
+            Some((Arc::new(Uuid::parse_str(&user_id).ok()?), context.into()))
+        })
+        .collect()
+}
+
+/// Disables the users returned by [find_dangling_owners], returns the number of disabled users.
+pub async fn disable_dangling_owners<Store>(
+    store: &Store,
+    users: &[(Arc<Uuid>, Owner)],
+) -> anyhow::Result<usize>
+where
+    Store: KeycloakClient,
+{
+    let keycloak = store.keycloak();
+    let realm = keycloak.config().realm();
+    let mut disabled = 0;
+    for (user_id, _) in users {
+        let user_id = user_id.to_string();
+        let Some(mut user) = keycloak.user_by_id(realm, &user_id).await? else {
+            continue;
+        };
+        disable_user(&mut user, USER_DISABLED_REASON_DANGLING_OWNER);
+        keycloak.update_user(realm, &user_id, &user).await?;
+        disabled += 1;
+    }
+    Ok(disabled)
+}
+
+/// Enables or disables all users in the context of `owner` and its descendants, e.g. to freeze
+/// the accounts of an institution during an investigation.
+///
+/// Users which already are in the requested state are skipped. Failures to update single
+/// users are logged and do not abort the operation. Returns the number of updated users.
+pub async fn set_users_enabled_by_owner<Store>(
+    store: &Store,
+    owner: &Owner,
+    enabled: bool,
+) -> anyhow::Result<u64>
+where
+    Store: RelatedStorage,
+{
+    let owner_context = owner
+        .as_owner_id()
+        .ok_or_else(|| anyhow::anyhow!("owner must not be empty"))
+        .and_then(InfraContext::try_from)?;
+    let users = users_by_owner(
+        &owner_context,
+        store
+            .cache_db()
+            .users_with_context()
+            .await
+            .into_iter()
+            .map(|(user, context)| (user.id.clone(), context)),
+    );
+    let keycloak = store.keycloak();
+    let realm = keycloak.config().realm();
+    let mut updated = 0;
+    let mut failed = 0;
+    for user_id in users {
+        let result = async {
+            let Some(mut user) = keycloak.user_by_id(realm, &user_id).await? else {
+                return anyhow::Ok(false);
+            };
+            if user.enabled == Some(enabled) {
+                return Ok(false);
+            }
+            if enabled {
+                enable_user(&mut user);
+            } else {
+                disable_user(&mut user, USER_DISABLED_REASON_OWNER);
+            }
+            keycloak.update_user(realm, &user_id, &user).await?;
+            Ok(true)
+        }
+        .await;
+        match result {
+            Ok(true) => updated += 1,
+            Ok(false) => {}
+            Err(err) => {
+                failed += 1;
+                error!("unable to set enabled={enabled} on user '{user_id}': {err:#}");
+            }
+        }
+    }
+    if failed > 0 {
+        error!("failed to set enabled={enabled} on {failed} users of '{owner_context}'");
+    }
+    Ok(updated)
+}
+
+fn users_by_owner(
+    owner: &InfraContext,
+    users: impl IntoIterator<Item = (Arc<str>, InfraContext)>,
+) -> Vec<Arc<str>> {
+    users
+        .into_iter()
+        .filter(|(_, context)| owner.contains(context))
+        .map(|(user_id, _)| user_id)
+        .collect()
+}
+
+/// Finds the organization units of the customers which have no members anymore, e.g. because
+/// the institutions of a removed organization were their only members, and applies `policy`.
+///
+/// Checks the organization units recorded in [CleanupTask::organization_unit_ids] for units
+/// left without members and applies `policy`. Returns the empty units.
+pub async fn apply_empty_unit_policy<Store>(
+    store: &Store,
+    unit_ids: &[i64],
+    policy: EmptyUnitPolicy,
+) -> anyhow::Result<Vec<OrganizationUnitNode>>
+where
+    Store: RelatedStorage,
+{
+    if unit_ids.is_empty() {
+        return Ok(vec![]);
+    }
+    let units = query::fetch_empty_organization_units(store.customer_db(), unit_ids).await?;
+    let remove = units_to_remove(policy, &units);
+    if !remove.is_empty() {
+        mutation::remove_organization_units(store.customer_db().pool(), &remove).await?;
+    }
+    Ok(units)
+}
+
+fn units_to_remove(policy: EmptyUnitPolicy, units: &[OrganizationUnitNode]) -> Vec<i64> {
+    match policy {
+        EmptyUnitPolicy::Flag => {
+            for unit in units {
+                tracing::warn!(
+                    "organization unit '{}' ({}) has no members",
+                    unit.name,
+                    unit.id
+                );
+            }
+            vec![]
+        }
+        EmptyUnitPolicy::Delete => units.iter().map(|unit| *unit.id.as_ref()).collect(),
+    }
+}
+
+/// Filter for the documents owned by the given organizations.
+pub fn organization_owner_query<'a>(ids: impl IntoIterator<Item = &'a OrganizationId>) -> Document {
+    let (cids, oids): (Vec<i64>, Vec<i64>) = ids.into_iter().map(OrganizationId::unzip).unzip();
+    doc! {
+        "owner.cid": {
+            "$in": &cids
+        },
+        "owner.oid": {
+            "$in": &oids
+        }
+    }
+}
+
+/// Number of entities which would be removed together with an organization.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CascadeCounts {
+    pub institutions: usize,
+    pub organization_units: i64,
+    pub users: usize,
+    /// Documents owned by the organization over all collections
+    pub documents: u64,
+}
+
+/// Counts what the removal of the organization would cascade to, without removing anything.
+pub async fn cascade_preview<Store>(
+    store: &Store,
+    org_id: OrganizationId,
+) -> anyhow::Result<CascadeCounts>
+where
+    Store: RelatedStorage,
+{
+    let cache = store.cache_db();
+    let institutions = cache
+        .institution_list(
+            Some(CustomerOrOrganization::Organization(org_id)),
+            None,
+            None,
+        )
+        .await
+        .items
+        .len();
+    let users = count_organization_users(&org_id, cache.user_contexts().await);
+    let organization_units =
+        query::count_organization_units(store.customer_db(), org_id.id()).await?;
+    let db: &qm_mongodb::DB = store.as_ref();
+    let query = organization_owner_query([&org_id]);
+    let mut documents = 0;
+    for collection in db.get().list_collection_names().await? {
+        documents += db
+            .get()
+            .collection::<Document>(&collection)
+            .count_documents(query.clone())
+            .await?;
+    }
+    Ok(CascadeCounts {
+        institutions,
+        organization_units,
+        users,
+        documents,
+    })
+}
+
+/// Users in the context of the organization or one of its institutions.
+fn count_organization_users(
+    org_id: &OrganizationId,
+    contexts: impl IntoIterator<Item = InfraContext>,
+) -> usize {
+    let (cid, oid) = org_id.unzip();
+    contexts
+        .into_iter()
+        .filter(|context| {
+            *context.customer_id().as_ref() == cid
+                && context.organization_id().map(|id| *id.as_ref()) == Some(oid)
+        })
+        .count()
+}
+
+pub const ROLE_CLEANUP_BATCH_SIZE: usize = 500;
+
+/// Collects roles which should be removed and flushes them as soon as `batch_size` roles are
+/// pending, so the roles of large cleanups are not buffered all at once.
+///
+/// Roles are passed to `flush` only once per batch instance, even when they are extended again
+/// after an earlier flush.
+pub struct RoleCleanupBatch<F> {
+    batch_size: usize,
+    seen: HashSet<String>,
+    roles: BTreeSet<String>,
+    removed_users: BTreeMap<String, Vec<String>>,
+    flush: F,
+}
+
+impl<F, Fut> RoleCleanupBatch<F>
+where
+    F: FnMut(BTreeSet<String>) -> Fut,
+    Fut: std::future::Future<Output = anyhow::Result<BTreeMap<String, Vec<String>>>>,
+{
+    pub fn new(batch_size: usize, flush: F) -> Self {
+        Self {
+            batch_size: batch_size.max(1),
+            seen: HashSet::new(),
+            roles: BTreeSet::new(),
+            removed_users: BTreeMap::new(),
+            flush,
+        }
+    }
+
+    pub async fn extend(&mut self, roles: impl IntoIterator<Item = String>) -> anyhow::Result<()> {
+        for role in roles {
+            if self.seen.contains(&role) {
+                continue;
+            }
+            self.seen.insert(role.clone());
+            self.roles.insert(role);
+            if self.roles.len() >= self.batch_size {
+                self.flush().await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> anyhow::Result<()> {
+        if !self.roles.is_empty() {
+            let roles = std::mem::take(&mut self.roles);
+            self.removed_users.extend((self.flush)(roles).await?);
+        }
+        Ok(())
+    }
+
+    /// Flushes the remaining roles and returns the removed users grouped by role.
+    pub async fn finish(mut self) -> anyhow::Result<BTreeMap<String, Vec<String>>> {
+        self.flush().await?;
+        Ok(self.removed_users)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::infra::InfraDB;
+    use crate::cache::user::UserDB;
+    use crate::cache::CacheDB;
+    use crate::testing::institution;
+    use qm_entity::ids::InstitutionId;
+    use qm_entity::ids::OwnerId;
+
+    #[test]
+    fn test_dangling_owners() {
+        let existing = ExistingEntities {
+            customers: HashSet::from([1]),
+            organizations: HashSet::from([2]),
+            institutions: HashSet::from([3]),
+        };
+        let user = |n: u128| Arc::<str>::from(Uuid::from_u128(n).to_string());
+        let users = vec![
+            (user(1), InfraContext::Institution((1, 2, 3).into())),
+            (user(2), InfraContext::Institution((1, 2, 4).into())),
+            (user(3), InfraContext::Organization((1, 5).into())),
+            (user(4), InfraContext::Customer(6.into())),
+            (user(5), InfraContext::Customer(1.into())),
+            (Arc::from("no-uuid"), InfraContext::Customer(7.into())),
+        ];
+        let dangling = dangling_owners(users, &existing);
+        let ids: Vec<_> = dangling.iter().map(|(id, _)| **id).collect();
+        assert_eq!(
+            ids,
+            vec![Uuid::from_u128(2), Uuid::from_u128(3), Uuid::from_u128(4)]
+        );
+        assert_eq!(
+            dangling[0].1.as_owner_id(),
+            Some(&OwnerId::from(InstitutionId::from((1, 2, 4))))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_remove_users_by_access_disable() {
+        let (keycloak, requests) = qm_keycloak::testing::mock_keycloak_recorded(
+            std::collections::HashMap::from([
+                (
+                    "GET /admin/realms/rmp/roles/access/users?briefRepresentation=true&first=0&max=1000",
+                    r#"[{"id":"u1","username":"u1"}]"#,
+                ),
+                (
+                    "GET /admin/realms/rmp/roles/access/users?briefRepresentation=true&first=1000&max=1000",
+                    "[]",
+                ),
+                (
+                    "GET /admin/realms/rmp/users/u1?userProfileMetadata=true",
+                    r#"{"id":"u1","username":"u1","enabled":true,"attributes":{"locale":["de"]},"requiredActions":["VERIFY_EMAIL"]}"#,
+                ),
+                ("PUT /admin/realms/rmp/users/u1", ""),
+            ]),
+        )
+        .await;
+        let user_ids = remove_users_by_access("rmp", &keycloak, "access", UserCleanupMode::Disable)
+            .await
+            .unwrap();
+        assert_eq!(user_ids, vec!["u1".to_string()]);
+        let requests = requests.lock().unwrap();
+        let put = requests
+            .iter()
+            .find_map(|r| r.strip_prefix("PUT /admin/realms/rmp/users/u1 "))
+            .unwrap();
+        let user: UserRepresentation = serde_json::from_str(put).unwrap();
+        assert_eq!(user.enabled, Some(false));
+        assert_eq!(
+            user.required_actions,
+            Some(vec!["VERIFY_EMAIL".to_string()])
+        );
+        let attributes = user.attributes.unwrap();
+        assert_eq!(attributes["locale"], vec!["de".to_string()]);
+        assert_eq!(
+            attributes[USER_DISABLED_REASON_ATTRIBUTE],
+            vec![USER_DISABLED_REASON_CLEANUP.to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cascade_preview_counts() {
+        let org_id: OrganizationId = (1, 2).into();
+        let infra = InfraDB::default();
+        for (cid, oid, iid) in [(1, 2, 3), (1, 2, 4), (1, 5, 6)] {
+            infra
+                .new_institution(Arc::new(institution(cid, oid, iid)))
+                .await;
+        }
+        let cache = CacheDB::from_parts(infra, UserDB::default());
+        let institutions = cache
+            .institution_list(
+                Some(CustomerOrOrganization::Organization(org_id)),
+                None,
+                None,
+            )
+            .await;
+        assert_eq!(institutions.items.len(), 2);
+
+        let contexts = [
+            InfraContext::Institution((1, 2, 3).into()),
+            InfraContext::Institution((1, 2, 4).into()),
+            InfraContext::Institution((1, 5, 6).into()),
+            InfraContext::Organization((1, 2).into()),
+            InfraContext::Organization((7, 2).into()),
+            InfraContext::Customer(1.into()),
+        ];
+        assert_eq!(count_organization_users(&org_id, contexts), 3);
+
+        assert_eq!(
+            organization_owner_query([&org_id]),
+            doc! {
+                "owner.cid": { "$in": [1_i64] },
+                "owner.oid": { "$in": [2_i64] },
+            }
+        );
+    }
+
+    #[test]
+    fn test_disable_user() {
+        let mut user = UserRepresentation {
+            enabled: Some(true),
+            attributes: Some(
+                [("locale".to_string(), vec!["de".to_string()])]
+                    .into_iter()
+                    .collect(),
+            ),
+            ..Default::default()
+        };
+        disable_user(&mut user, USER_DISABLED_REASON_CLEANUP);
+        assert_eq!(user.enabled, Some(false));
+        let attributes = user.attributes.unwrap();
+        assert_eq!(attributes["locale"], vec!["de".to_string()]);
+        assert_eq!(
+            attributes[USER_DISABLED_REASON_ATTRIBUTE],
+            vec![USER_DISABLED_REASON_CLEANUP.to_string()]
+        );
+    }
+
+    #[test]
+    fn test_users_by_owner() {
+        let users = vec![
+            (Arc::from("u1"), InfraContext::Institution((1, 2, 3).into())),
+            (Arc::from("u2"), InfraContext::Institution((1, 2, 4).into())),
+            (Arc::from("u3"), InfraContext::Organization((1, 2).into())),
+            (Arc::from("u4"), InfraContext::Institution((1, 2, 3).into())),
+        ];
+        let institution = InfraContext::Institution((1, 2, 3).into());
+        let ids = users_by_owner(&institution, users.clone());
+        assert_eq!(ids, vec![Arc::<str>::from("u1"), Arc::from("u4")]);
+        let organization = InfraContext::Organization((1, 2).into());
+        assert_eq!(users_by_owner(&organization, users).len(), 4);
+
+        let mut user = UserRepresentation {
+            enabled: Some(true),
+            ..Default::default()
+        };
+        disable_user(&mut user, USER_DISABLED_REASON_OWNER);
+        assert_eq!(user.enabled, Some(false));
+        enable_user(&mut user);
+        assert_eq!(user.enabled, Some(true));
+        assert!(!user
+            .attributes
+            .unwrap()
+            .contains_key(USER_DISABLED_REASON_ATTRIBUTE));
+    }
+
+    #[test]
+    fn test_cleanup_task_user_cleanup_mode_default() {
+        let task: CleanupTask =
+            serde_json::from_str(r#"{"id":"67e55044-10b1-426f-9247-bb680e5fe0c8","ty":"None"}"#)
+                .unwrap();
+        assert_eq!(task.user_cleanup_mode, UserCleanupMode::Delete);
+        assert_eq!(task.empty_unit_policy, EmptyUnitPolicy::Flag);
+    }
+
+    #[test]
+    fn test_units_to_remove() {
+        let unit = |id: i64| OrganizationUnitNode {
+            id: id.into(),
+            name: Arc::from(format!("unit {id}")),
+            ty: Arc::from("none"),
+        };
+        // the units left empty after the institutions of a removed organization cascaded away
+        let units = [unit(2), unit(5)];
+        assert!(units_to_remove(EmptyUnitPolicy::Flag, &units).is_empty());
+        assert_eq!(units_to_remove(EmptyUnitPolicy::Delete, &units), vec![2, 5]);
+
+        let task = CleanupTask::new(CleanupTaskType::Organizations(Arc::from([(1, 2).into()])))
+            .with_empty_unit_policy(EmptyUnitPolicy::Delete);
+        let task = task.with_organization_unit_ids(vec![7, 8]);
+        let task = CleanupTask {
+            ty: CleanupTaskType::Organizations(Arc::from([(1, 2).into(), (1, 3).into()])),
+            ..task
+        };
+        let tasks = task.split(1);
+        assert_eq!(tasks[0].empty_unit_policy, EmptyUnitPolicy::Delete);
+        assert_eq!(tasks[1].empty_unit_policy, EmptyUnitPolicy::Delete);
+        // only the first sub-task checks the recorded units
+        assert_eq!(tasks[0].organization_unit_ids, vec![7, 8]);
+        assert!(tasks[1].organization_unit_ids.is_empty());
+        let task: CleanupTask =
+            serde_json::from_str(&serde_json::to_string(&tasks[0]).unwrap()).unwrap();
+        assert_eq!(task.empty_unit_policy, EmptyUnitPolicy::Delete);
+        assert_eq!(task.organization_unit_ids, vec![7, 8]);
+        let task: CleanupTask =
+            serde_json::from_str(&format!(r#"{{"id":"{}","ty":"None"}}"#, Uuid::nil())).unwrap();
+        assert!(task.organization_unit_ids.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_role_cleanup_batch() {
+        let mut batches: Vec<BTreeSet<String>> = vec![];
+        let mut batch = RoleCleanupBatch::new(64, |roles: BTreeSet<String>| {
+            let removed = roles
+                .iter()
+                .map(|role| (role.clone(), vec![format!("user-{role}")]))
+                .collect();
+            batches.push(roles);
+            async move { anyhow::Ok(removed) }
+        });
+        for chunk in (0..10_000).collect::<Vec<_>>().chunks(100) {
+            batch
+                .extend(chunk.iter().map(|i| format!("customer:access@V{i:X}")))
+                .await
+                .unwrap();
+        }
+        let removed_users = batch.finish().await.unwrap();
+        assert_eq!(removed_users.len(), 10_000);
+        assert!(batches.iter().all(|b| b.len() <= 64));
+        assert_eq!(batches.iter().map(BTreeSet::len).sum::<usize>(), 10_000);
+    }
+
+    #[tokio::test]
+    async fn test_role_cleanup_batch_dedup_across_flushes() {
+        let mut flushed: Vec<String> = vec![];
+        let mut batch = RoleCleanupBatch::new(2, |roles: BTreeSet<String>| {
+            flushed.extend(roles);
+            async { anyhow::Ok(BTreeMap::new()) }
+        });
+        let role = |i: i32| format!("customer:access@V{i:X}");
+        batch.extend([role(1), role(2)]).await.unwrap();
+        batch.extend([role(1), role(3), role(2)]).await.unwrap();
+        batch.finish().await.unwrap();
+        assert_eq!(flushed, vec![role(1), role(2), role(3)]);
+    }
+
+    #[test]
+    fn test_cleanup_task_split() {
+        use qm_entity::ids::CustomerId;
+        let ids: CustomerIds = (1..=10_000).map(CustomerId::from).collect();
+        let task = CleanupTask::new(CleanupTaskType::Customers(ids.clone()))
+            .with_user_cleanup_mode(UserCleanupMode::Disable);
+        let task_id = task.id;
+        let tasks = task.split(500);
+        assert_eq!(tasks.len(), 20);
+        assert_eq!(tasks[0].id, task_id);
+        assert_eq!(tasks.iter().map(|t| t.id).collect::<HashSet<_>>().len(), 20);
+        let mut covered = vec![];
+        for task in tasks {
+            assert_eq!(task.user_cleanup_mode, UserCleanupMode::Disable);
+            assert!(task.ty.len() <= 500);
+            match task.ty {
+                CleanupTaskType::Customers(ids) => covered.extend_from_slice(&ids),
+                _ => panic!("unexpected task type"),
+            }
+        }
+        assert_eq!(covered.as_slice(), ids.as_ref());
+        assert_eq!(
+            CleanupTaskType::None.split(500),
+            vec![CleanupTaskType::None]
+        );
+    }
+
+    #[test]
+    fn test_orphaned_users() {
+        let built_in_groups = ["/app/admin", "/app/customer_owner"];
+        let custom = GroupPath::for_customer(1.into(), "editors").to_string();
+        let groups = [
+            ("1", "/app"),
+            ("2", "/app/admin"),
+            ("3", custom.as_str()),
+            ("4", "/other"),
+        ];
+        let group = GroupRepresentation {
+            id: Some("1".to_string()),
+            path: Some("/app".to_string()),
+            sub_groups: Some(vec![GroupRepresentation {
+                id: Some("2".to_string()),
+                path: Some("/app/admin".to_string()),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        };
+        let mut collected = vec![];
+        collect_groups(group, &mut collected);
+        let paths: Vec<_> = collected.iter().filter_map(|g| g.path.as_deref()).collect();
+        assert_eq!(paths, vec!["/app/admin", "/app"]);
+
+        let managed: Vec<_> = groups
+            .iter()
+            .filter(|(_, path)| is_managed_group_path(path, &built_in_groups))
+            .map(|(id, _)| *id)
+            .collect();
+        assert_eq!(managed, vec!["2", "3"]);
+
+        let user = |id: &str| UserRepresentation {
+            id: Some(id.to_string()),
+            ..Default::default()
+        };
+        let members = HashSet::from(["admin".to_string(), "editor".to_string()]);
+        let orphans = filter_orphans(
+            vec![user("admin"), user("orphan"), user("editor"), user("other")],
+            &members,
+        );
+        let ids: Vec<_> = orphans.iter().filter_map(|u| u.id.as_deref()).collect();
+        assert_eq!(ids, vec!["orphan", "other"]);
     }
-    Ok(())
 }
@@ -0,0 +1,150 @@
+use std::sync::Arc;
+
+use qm_entity::ids::{CustomerId, InfraContext, Owner};
+use qm_keycloak::{CredentialRepresentation, UserRepresentation};
+use serde::{Deserialize, Serialize};
+
+use crate::context::RelatedStorage;
+
+/// Self-contained snapshot of a user of a customer, see [export_users].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserExport {
+    pub id: Arc<str>,
+    /// Owner derived from the context of the user's access role
+    pub owner: Owner,
+    pub roles: Vec<Arc<str>>,
+    pub user: UserRepresentation,
+    /// Only exported with `include_credentials`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credentials: Option<Vec<CredentialRepresentation>>,
+}
+
+/// Exports all users of the customer, including the users of its organizations and
+/// institutions.
+///
+/// Credentials are left out unless `include_credentials` is set. Users which are cached but
+/// no longer exist in Keycloak are skipped.
+pub async fn export_users<Store>(
+    store: &Store,
+    customer_id: CustomerId,
+    include_credentials: bool,
+) -> anyhow::Result<Vec<UserExport>>
+where
+    Store: RelatedStorage,
+{
+    let keycloak = store.keycloak();
+    let realm = keycloak.config().realm();
+    let cache = store.cache_db();
+    let users = customer_users(
+        customer_id,
+        cache
+            .users_with_context()
+            .await
+            .into_iter()
+            .map(|(user, context)| (user.id.clone(), context)),
+    );
+    let mut exports = Vec::with_capacity(users.len());
+    for (user_id, context) in users {
+        let Some(user) = keycloak.user_by_id(realm, &user_id).await? else {
+            tracing::warn!("skip export of user '{user_id}': not found in keycloak");
+            continue;
+        };
+        let roles = cache
+            .roles_by_user_id(&user_id)
+            .await
+            .map(|roles| roles.iter().map(|role| role.name.clone()).collect())
+            .unwrap_or_default();
+        let credentials = if include_credentials {
+            Some(keycloak.user_credentials(realm, &user_id).await?)
+        } else {
+            None
+        };
+        exports.push(user_export(user_id, context, roles, user, credentials));
+    }
+    Ok(exports)
+}
+
+fn customer_users(
+    customer_id: CustomerId,
+    users: impl IntoIterator<Item = (Arc<str>, InfraContext)>,
+) -> Vec<(Arc<str>, InfraContext)> {
+    let cid = customer_id.unzip();
+    users
+        .into_iter()
+        .filter(|(_, context)| *context.customer_id().as_ref() == cid)
+        .collect()
+}
+
+fn user_export(
+    id: Arc<str>,
+    context: InfraContext,
+    roles: Vec<Arc<str>>,
+    mut user: UserRepresentation,
+    credentials: Option<Vec<CredentialRepresentation>>,
+) -> UserExport {
+    if credentials.is_none() {
+        user.credentials = None;
+    }
+    UserExport {
+        id,
+        owner: context.into(),
+        roles,
+        user,
+        credentials,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use qm_entity::ids::{InstitutionId, OwnerId};
+
+    #[test]
+    fn test_user_export() {
+        let users = vec![
+            (Arc::from("u1"), InfraContext::Customer(1.into())),
+            (Arc::from("u2"), InfraContext::Institution((1, 2, 3).into())),
+            (Arc::from("u3"), InfraContext::Organization((4, 5).into())),
+        ];
+        let users = customer_users(1.into(), users);
+        let ids: Vec<&str> = users.iter().map(|(id, _)| id.as_ref()).collect();
+        assert_eq!(ids, vec!["u1", "u2"]);
+
+        let (id, context) = users[1].clone();
+        let user = UserRepresentation {
+            id: Some("u2".to_string()),
+            username: Some("jane".to_string()),
+            email: Some("jane@test.local".to_string()),
+            credentials: Some(vec![CredentialRepresentation {
+                type_: Some("password".to_string()),
+                secret_data: Some("hash".to_string()),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        };
+        let export = user_export(
+            id,
+            context,
+            vec![Arc::from("institution_access@I123")],
+            user,
+            None,
+        );
+        assert_eq!(
+            export.owner.as_owner_id(),
+            Some(&OwnerId::from(InstitutionId::from((1, 2, 3))))
+        );
+        assert_eq!(export.user.username.as_deref(), Some("jane"));
+        assert_eq!(export.user.email.as_deref(), Some("jane@test.local"));
+        assert!(export.user.credentials.is_none());
+        assert!(export.credentials.is_none());
+
+        let json = serde_json::to_value(&export).unwrap();
+        assert!(!json.to_string().contains("hash"));
+        assert_eq!(
+            json["roles"],
+            serde_json::json!(["institution_access@I123"])
+        );
+        let export: UserExport = serde_json::from_value(json).unwrap();
+        assert_eq!(export.id.as_ref(), "u2");
+    }
+}
@@ -1,5 +1,7 @@
 use std::str::FromStr;
 
+use qm_entity::ids::{CustomerId, InfraContext, InstitutionId, OrganizationId};
+
 pub trait CustomerOwnerGroup<R, P>
 where
     R: std::fmt::Debug,
@@ -67,3 +69,109 @@ pub trait RelatedBuiltInGroup:
     AsRef<str> + async_graphql::InputType + FromStr + Eq + PartialEq + Send + Sync + 'static
 {
 }
+
+const CUSTOM_GROUP_PREFIX: &str = "custom@";
+
+/// Path of a custom group: `/custom@{context}/{name}`.
+///
+/// The name is stored in snake case without slashes, as Keycloak uses `/` as path separator.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GroupPath {
+    context: InfraContext,
+    name: String,
+}
+
+impl GroupPath {
+    pub fn new(context: InfraContext, name: &str) -> Self {
+        Self {
+            context,
+            name: inflector::cases::snakecase::to_snake_case(name.replace('/', "").trim()),
+        }
+    }
+
+    pub fn for_customer(id: CustomerId, name: &str) -> Self {
+        Self::new(InfraContext::Customer(id), name)
+    }
+
+    pub fn for_organization(id: OrganizationId, name: &str) -> Self {
+        Self::new(InfraContext::Organization(id), name)
+    }
+
+    pub fn for_institution(id: InstitutionId, name: &str) -> Self {
+        Self::new(InfraContext::Institution(id), name)
+    }
+
+    /// Name of the parent group holding all custom groups of `context`.
+    pub fn parent_name(context: &InfraContext) -> String {
+        format!("{CUSTOM_GROUP_PREFIX}{context}")
+    }
+
+    pub fn parse(path: &str) -> anyhow::Result<Self> {
+        let Some((parent, name)) = path.strip_prefix('/').and_then(|path| path.split_once('/'))
+        else {
+            anyhow::bail!("invalid group path '{path}'");
+        };
+        let Some(context) = parent.strip_prefix(CUSTOM_GROUP_PREFIX) else {
+            anyhow::bail!("group path '{path}' is not a custom group");
+        };
+        if name.is_empty() || name.contains('/') {
+            anyhow::bail!("invalid group name in path '{path}'");
+        }
+        Ok(Self {
+            context: InfraContext::parse(context)?,
+            name: name.to_string(),
+        })
+    }
+
+    pub fn context(&self) -> &InfraContext {
+        &self.context
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl std::fmt::Display for GroupPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "/{}/{}", Self::parent_name(&self.context), self.name)
+    }
+}
+
+impl FromStr for GroupPath {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_group_path_round_trip() {
+        let paths = [
+            (
+                GroupPath::for_customer(CustomerId::from(1), "Team Leads"),
+                "/custom@V01/team_leads",
+            ),
+            (
+                GroupPath::for_organization(OrganizationId::from((1, 2)), "a/b"),
+                "/custom@T0102/ab",
+            ),
+            (
+                GroupPath::for_institution(InstitutionId::from((1, 2, 3)), "Staff"),
+                "/custom@R010203/staff",
+            ),
+        ];
+        for (path, expected) in paths {
+            assert_eq!(path.to_string(), expected);
+            assert_eq!(GroupPath::parse(expected).unwrap(), path);
+        }
+        assert!(GroupPath::parse("/app/admin").is_err());
+        assert!(GroupPath::parse("/custom@V01").is_err());
+        assert!(GroupPath::parse("/custom@V01/a/b").is_err());
+    }
+}
@@ -1,7 +1,9 @@
+pub mod audit;
 pub mod cache;
 pub mod cleanup;
 pub mod config;
 pub mod context;
+pub mod export;
 pub mod groups;
 pub mod marker;
 pub mod model;
@@ -9,6 +11,9 @@ pub mod mutation;
 pub mod query;
 pub mod roles;
 pub mod schema;
+#[cfg(test)]
+mod testing;
+pub mod users;
 pub mod worker;
 
 #[macro_export]
@@ -1,5 +1,6 @@
 use async_graphql::{InputObject, SimpleObject};
 use qm_entity::ids::{CustomerId, InfraId};
+use qm_entity::list::NewList;
 use serde::{Deserialize, Serialize};
 use sqlx::types::uuid::Uuid;
 use sqlx::FromRow;
@@ -46,6 +47,37 @@ pub struct CustomerUpdate {
     pub updated_at: Option<String>,
 }
 
+/// Converts the notification row, which carries timestamps as strings, into the cached model.
+impl TryFrom<CustomerUpdate> for QmCustomer {
+    type Error = anyhow::Error;
+
+    fn try_from(value: CustomerUpdate) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: value.id,
+            name: value.name,
+            ty: value.ty,
+            created_by: value.created_by,
+            created_at: super::parse_created_at(&value.created_at)?,
+            updated_by: value.updated_by,
+            updated_at: value.updated_at.and_then(|s| super::parse_date_time(&s)),
+        })
+    }
+}
+
+impl<'a> From<&'a QmCustomer> for CustomerUpdate {
+    fn from(value: &'a QmCustomer) -> Self {
+        Self {
+            id: value.id,
+            name: value.name.clone(),
+            ty: value.ty.clone(),
+            created_by: value.created_by,
+            created_at: super::format_date_time(&value.created_at),
+            updated_by: value.updated_by,
+            updated_at: value.updated_at.as_ref().map(super::format_date_time),
+        }
+    }
+}
+
 pub struct RemoveCustomerPayload {
     pub id: InfraId,
     pub name: Arc<str>,
@@ -77,8 +109,61 @@ pub struct QmCustomerList {
     pub page: Option<i64>,
 }
 
+impl NewList<QmCustomer> for QmCustomerList {
+    fn new(
+        items: Vec<QmCustomer>,
+        limit: Option<i64>,
+        total: Option<i64>,
+        page: Option<i64>,
+    ) -> Self {
+        Self {
+            items: items.into_iter().map(Arc::new).collect(),
+            limit,
+            total,
+            page,
+        }
+    }
+}
+
 impl<'a> From<&'a QmCustomer> for CustomerId {
     fn from(val: &'a QmCustomer) -> Self {
         (*val.id.as_ref()).into()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::customer;
+    use qm_entity::list::paginate_query;
+    use qm_entity::model::Pagination;
+    use time::macros::datetime;
+
+    #[test]
+    fn test_customer_list_paginate_query() {
+        let pagination = Pagination::new(2, 2);
+        let list: QmCustomerList =
+            paginate_query(vec![customer(5), customer(6)], Some(&pagination), 6);
+        assert_eq!(list.items.len(), 2);
+        assert_eq!(list.items[0].id, 5.into());
+        assert_eq!(list.limit, Some(2));
+        assert_eq!(list.total, Some(6));
+        assert_eq!(list.page, Some(2));
+    }
+
+    #[test]
+    fn test_update_round_trip() {
+        let created_at = datetime!(2024-03-25 15:40:03.123456);
+        let customer = QmCustomer {
+            created_at,
+            updated_by: Some(Uuid::nil()),
+            updated_at: Some(datetime!(2024-03-26 08:00:00.5)),
+            ..customer(1)
+        };
+        let update = CustomerUpdate::from(&customer);
+        assert_eq!(update.created_at, "2024-03-25T15:40:03.123456");
+        let result = QmCustomer::try_from(update).unwrap();
+        assert_eq!(result.created_at, customer.created_at);
+        assert_eq!(result.updated_at, customer.updated_at);
+    }
+}
@@ -53,6 +53,41 @@ pub struct InstitutionUpdate {
     pub updated_at: Option<String>,
 }
 
+/// Converts the notification row, which carries timestamps as strings, into the cached model.
+impl TryFrom<InstitutionUpdate> for QmInstitution {
+    type Error = anyhow::Error;
+
+    fn try_from(value: InstitutionUpdate) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: value.id,
+            customer_id: value.customer_id,
+            organization_id: value.organization_id,
+            name: value.name,
+            ty: value.ty,
+            created_by: value.created_by,
+            created_at: super::parse_created_at(&value.created_at)?,
+            updated_by: value.updated_by,
+            updated_at: value.updated_at.and_then(|s| super::parse_date_time(&s)),
+        })
+    }
+}
+
+impl<'a> From<&'a QmInstitution> for InstitutionUpdate {
+    fn from(value: &'a QmInstitution) -> Self {
+        Self {
+            id: value.id,
+            customer_id: value.customer_id,
+            organization_id: value.organization_id,
+            name: value.name.clone(),
+            ty: value.ty.clone(),
+            created_by: value.created_by,
+            created_at: super::format_date_time(&value.created_at),
+            updated_by: value.updated_by,
+            updated_at: value.updated_at.as_ref().map(super::format_date_time),
+        }
+    }
+}
+
 pub struct RemoveInstitutionPayload {
     pub id: InfraId,
     pub customer_id: InfraId,
@@ -117,3 +152,27 @@ impl<'a> From<&'a QmInstitution> for CustomerId {
         cid.into()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::institution;
+    use time::macros::datetime;
+
+    #[test]
+    fn test_update_round_trip() {
+        let created_at = datetime!(2024-03-25 15:40:03.123456);
+        let institution = QmInstitution {
+            created_at,
+            ..institution(1, 2, 3)
+        };
+        let mut update = InstitutionUpdate::from(&institution);
+        let result = QmInstitution::try_from(update.clone()).unwrap();
+        assert_eq!(
+            InstitutionId::from(&result),
+            InstitutionId::from(&institution)
+        );
+        update.created_at = "yesterday".to_string();
+        assert!(QmInstitution::try_from(update).is_err());
+    }
+}
@@ -6,11 +6,17 @@ mod institution;
 pub use institution::*;
 mod organization;
 pub use organization::*;
+mod organization_unit;
+pub use organization_unit::*;
 mod group;
 pub use group::*;
 mod realm;
 pub use realm::*;
+mod resolved;
+pub use resolved::*;
 mod role;
 pub use role::*;
+mod tree;
+pub use tree::*;
 mod user;
 pub use user::*;
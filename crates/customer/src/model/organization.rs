@@ -48,6 +48,39 @@ pub struct OrganizationUpdate {
     pub updated_at: Option<String>,
 }
 
+/// Converts the notification row, which carries timestamps as strings, into the cached model.
+impl TryFrom<OrganizationUpdate> for QmOrganization {
+    type Error = anyhow::Error;
+
+    fn try_from(value: OrganizationUpdate) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: value.id,
+            customer_id: value.customer_id,
+            name: value.name,
+            ty: value.ty,
+            created_by: value.created_by,
+            created_at: super::parse_created_at(&value.created_at)?,
+            updated_by: value.updated_by,
+            updated_at: value.updated_at.and_then(|s| super::parse_date_time(&s)),
+        })
+    }
+}
+
+impl<'a> From<&'a QmOrganization> for OrganizationUpdate {
+    fn from(value: &'a QmOrganization) -> Self {
+        Self {
+            id: value.id,
+            customer_id: value.customer_id,
+            name: value.name.clone(),
+            ty: value.ty.clone(),
+            created_by: value.created_by,
+            created_at: super::format_date_time(&value.created_at),
+            updated_by: value.updated_by,
+            updated_at: value.updated_at.as_ref().map(super::format_date_time),
+        }
+    }
+}
+
 pub struct RemoveOrganizationPayload {
     pub id: InfraId,
     pub customer_id: InfraId,
@@ -96,3 +129,23 @@ impl<'a> From<&'a QmOrganization> for CustomerId {
         cid.into()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::organization;
+    use time::macros::datetime;
+
+    #[test]
+    fn test_update_round_trip() {
+        let created_at = datetime!(2024-03-25 15:40:03.123456);
+        let organization = QmOrganization {
+            created_at,
+            ..organization(1, 2)
+        };
+        let result = QmOrganization::try_from(OrganizationUpdate::from(&organization)).unwrap();
+        assert_eq!(result.customer_id, organization.customer_id);
+        assert_eq!(result.created_at, organization.created_at);
+        assert_eq!(result.updated_at, None);
+    }
+}
@@ -0,0 +1,172 @@
+use qm_entity::ids::{InfraId, InstitutionId, InstitutionIds};
+use serde::{Deserialize, Serialize};
+use sqlx::types::time::PrimitiveDateTime;
+use sqlx::types::uuid::Uuid;
+
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use super::QmInstitution;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QmOrganizationUnit {
+    pub id: InfraId,
+    pub customer_id: InfraId,
+    pub organization_id: Option<InfraId>,
+    pub name: Arc<str>,
+    pub ty: Arc<str>,
+    pub created_by: Uuid,
+    pub created_at: PrimitiveDateTime,
+    pub updated_by: Option<Uuid>,
+    pub updated_at: Option<PrimitiveDateTime>,
+    pub members: InstitutionIds,
+}
+
+impl QmOrganizationUnit {
+    /// New organization unit of the organization of `institution` with the institution as its
+    /// only member.
+    pub fn for_institution(
+        id: InfraId,
+        name: Arc<str>,
+        ty: Arc<str>,
+        created_by: Uuid,
+        created_at: PrimitiveDateTime,
+        institution: &QmInstitution,
+    ) -> Self {
+        Self {
+            id,
+            customer_id: institution.customer_id,
+            organization_id: Some(institution.organization_id),
+            name,
+            ty,
+            created_by,
+            created_at,
+            updated_by: None,
+            updated_at: None,
+            members: Arc::from([InstitutionId::from(institution)]),
+        }
+    }
+
+    /// Collects rows of a unit and one of its members into units with all their members, the
+    /// rows of a unit have to be adjacent.
+    pub fn collect_with_members(
+        rows: impl IntoIterator<Item = (Self, InstitutionId)>,
+    ) -> Vec<Self> {
+        let mut units: Vec<(Self, Vec<InstitutionId>)> = vec![];
+        for (unit, member) in rows {
+            match units.last_mut() {
+                Some((last, members)) if last.id == unit.id => members.push(member),
+                _ => units.push((unit, vec![member])),
+            }
+        }
+        units
+            .into_iter()
+            .map(|(unit, members)| Self {
+                members: members.into(),
+                ..unit
+            })
+            .collect()
+    }
+}
+
+/// Members added to and removed from an organization unit, see
+/// [crate::mutation::set_organization_unit_members].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OrganizationUnitMemberChanges {
+    pub added: Vec<InstitutionId>,
+    pub removed: Vec<InstitutionId>,
+}
+
+impl OrganizationUnitMemberChanges {
+    /// Changes turning the `current` members into `members`, both sorted.
+    pub fn diff(current: &[InstitutionId], members: &[InstitutionId]) -> Self {
+        let current: BTreeSet<_> = current.iter().copied().collect();
+        let members: BTreeSet<_> = members.iter().copied().collect();
+        Self {
+            added: members.difference(&current).copied().collect(),
+            removed: current.difference(&members).copied().collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::institution;
+
+    #[test]
+    fn test_organization_unit_for_institution() {
+        let institution = institution(1, 2, 3);
+        let unit = QmOrganizationUnit::for_institution(
+            4.into(),
+            Arc::from("default"),
+            Arc::from("none"),
+            Uuid::nil(),
+            time::PrimitiveDateTime::MIN,
+            &institution,
+        );
+        assert_eq!(unit.customer_id, institution.customer_id);
+        assert_eq!(unit.organization_id, Some(institution.organization_id));
+        assert_eq!(unit.members.as_ref(), &[InstitutionId::from(&institution)]);
+        assert_eq!(unit.members[0], InstitutionId::from((1, 2, 3)));
+    }
+
+    #[test]
+    fn test_organization_unit_collect_with_members() {
+        let institution = institution(1, 2, 3);
+        let unit = |id: i64| {
+            QmOrganizationUnit::for_institution(
+                id.into(),
+                Arc::from(format!("unit {id}")),
+                Arc::from("none"),
+                Uuid::nil(),
+                time::PrimitiveDateTime::MIN,
+                &institution,
+            )
+        };
+        let a = InstitutionId::from((1, 2, 3));
+        let b = InstitutionId::from((1, 2, 4));
+
+        assert!(QmOrganizationUnit::collect_with_members([]).is_empty());
+
+        let units = QmOrganizationUnit::collect_with_members([(unit(7), a)]);
+        assert_eq!(units.len(), 1);
+        assert_eq!(units[0].members.as_ref(), &[a]);
+
+        let units =
+            QmOrganizationUnit::collect_with_members([(unit(7), a), (unit(8), a), (unit(8), b)]);
+        assert_eq!(
+            units.iter().map(|u| *u.id.as_ref()).collect::<Vec<_>>(),
+            vec![7, 8]
+        );
+        assert_eq!(units[0].members.as_ref(), &[a]);
+        assert_eq!(units[1].members.as_ref(), &[a, b]);
+        assert_eq!(units[1].name.as_ref(), "unit 8");
+    }
+
+    #[test]
+    fn test_organization_unit_member_changes() {
+        let a = InstitutionId::from((1, 2, 3));
+        let b = InstitutionId::from((1, 2, 4));
+        let c = InstitutionId::from((1, 5, 6));
+
+        let added = OrganizationUnitMemberChanges::diff(&[a], &[c, a, b]);
+        assert_eq!(added.added, vec![b, c]);
+        assert!(added.removed.is_empty());
+
+        let removed = OrganizationUnitMemberChanges::diff(&[a, b, c], &[b]);
+        assert!(removed.added.is_empty());
+        assert_eq!(removed.removed, vec![a, c]);
+
+        let mixed = OrganizationUnitMemberChanges::diff(&[a, b], &[b, c]);
+        assert_eq!(mixed.added, vec![c]);
+        assert_eq!(mixed.removed, vec![a]);
+
+        assert!(OrganizationUnitMemberChanges::diff(&[a, b], &[b, a, b]).is_empty());
+        assert!(OrganizationUnitMemberChanges::diff(&[], &[]).is_empty());
+    }
+}
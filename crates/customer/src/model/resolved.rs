@@ -0,0 +1,140 @@
+use qm_entity::ids::{CustomerId, InfraContext, InfraId, InstitutionId, OrganizationId};
+
+/// Entity type and owner of an [InfraId], see [crate::query::resolve_infra_id].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResolvedEntity {
+    Customer(CustomerId),
+    Organization(OrganizationId),
+    Institution(InstitutionId),
+    OrganizationUnit {
+        id: InfraId,
+        customer_id: CustomerId,
+        organization_id: Option<OrganizationId>,
+    },
+}
+
+impl ResolvedEntity {
+    pub const CUSTOMER: i32 = 0;
+    pub const ORGANIZATION: i32 = 1;
+    pub const INSTITUTION: i32 = 2;
+    pub const ORGANIZATION_UNIT: i32 = 3;
+
+    /// Builds the entity from a row of [crate::query::resolve_infra_id], `kind` is one of the
+    /// associated constants.
+    pub fn from_row(
+        kind: i32,
+        id: i64,
+        customer_id: i64,
+        organization_id: Option<i64>,
+    ) -> anyhow::Result<Self> {
+        let organization = |organization_id: Option<i64>| {
+            organization_id
+                .map(|oid| OrganizationId::from((customer_id, oid)))
+                .ok_or_else(|| anyhow::anyhow!("entity {id} has no organization"))
+        };
+        Ok(match kind {
+            Self::CUSTOMER => Self::Customer(customer_id.into()),
+            Self::ORGANIZATION => Self::Organization(organization(Some(id))?),
+            Self::INSTITUTION => Self::Institution(InstitutionId::from((
+                customer_id,
+                organization(organization_id)?.id(),
+                id,
+            ))),
+            Self::ORGANIZATION_UNIT => Self::OrganizationUnit {
+                id: id.into(),
+                customer_id: customer_id.into(),
+                organization_id: organization_id.map(|oid| (customer_id, oid).into()),
+            },
+            _ => anyhow::bail!("invalid entity kind {kind}"),
+        })
+    }
+
+    /// Customer owning the entity.
+    pub fn customer_id(&self) -> CustomerId {
+        match self {
+            Self::Customer(v) => *v,
+            Self::Organization(v) => v.root(),
+            Self::Institution(v) => v.root(),
+            Self::OrganizationUnit { customer_id, .. } => *customer_id,
+        }
+    }
+    /// Context of the entity, `None` for organization units.
+    pub fn context(&self) -> Option<InfraContext> {
+        match self {
+            Self::Customer(v) => Some(InfraContext::Customer(*v)),
+            Self::Organization(v) => Some(InfraContext::Organization(*v)),
+            Self::Institution(v) => Some(InfraContext::Institution(*v)),
+            Self::OrganizationUnit { .. } => None,
+        }
+    }
+
+    /// The entity of `candidates` with exactly the given context.
+    pub fn find_context(
+        candidates: impl IntoIterator<Item = Self>,
+        context: &InfraContext,
+    ) -> Option<Self> {
+        candidates
+            .into_iter()
+            .find(|entity| entity.context().as_ref() == Some(context))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolved_entity_from_row() {
+        let customer = ResolvedEntity::from_row(ResolvedEntity::CUSTOMER, 1, 1, None).unwrap();
+        assert_eq!(customer, ResolvedEntity::Customer(1.into()));
+        let organization =
+            ResolvedEntity::from_row(ResolvedEntity::ORGANIZATION, 2, 1, Some(2)).unwrap();
+        assert_eq!(organization, ResolvedEntity::Organization((1, 2).into()));
+        let institution =
+            ResolvedEntity::from_row(ResolvedEntity::INSTITUTION, 3, 1, Some(2)).unwrap();
+        assert_eq!(institution, ResolvedEntity::Institution((1, 2, 3).into()));
+        let unit = ResolvedEntity::from_row(ResolvedEntity::ORGANIZATION_UNIT, 4, 1, None).unwrap();
+        assert_eq!(
+            unit,
+            ResolvedEntity::OrganizationUnit {
+                id: 4.into(),
+                customer_id: 1.into(),
+                organization_id: None,
+            }
+        );
+        for entity in [customer, organization, institution, unit] {
+            assert_eq!(entity.customer_id(), 1.into());
+        }
+        assert!(ResolvedEntity::from_row(ResolvedEntity::INSTITUTION, 3, 1, None).is_err());
+        assert!(ResolvedEntity::from_row(4, 5, 1, None).is_err());
+    }
+
+    #[test]
+    fn test_resolved_entity_id_collision() {
+        // every table has its own sequence, so the same id exists in all of them
+        let candidates = vec![
+            ResolvedEntity::from_row(ResolvedEntity::CUSTOMER, 2, 2, None).unwrap(),
+            ResolvedEntity::from_row(ResolvedEntity::ORGANIZATION, 2, 1, Some(2)).unwrap(),
+            ResolvedEntity::from_row(ResolvedEntity::INSTITUTION, 2, 1, Some(3)).unwrap(),
+            ResolvedEntity::from_row(ResolvedEntity::ORGANIZATION_UNIT, 2, 1, Some(3)).unwrap(),
+        ];
+        let resolve = |context: InfraContext| {
+            let context = InfraContext::parse(&context.to_string()).unwrap();
+            ResolvedEntity::find_context(candidates.clone(), &context)
+        };
+        assert_eq!(
+            resolve(InfraContext::Customer(2.into())),
+            Some(ResolvedEntity::Customer(2.into()))
+        );
+        assert_eq!(
+            resolve(InfraContext::Organization((1, 2).into())),
+            Some(ResolvedEntity::Organization((1, 2).into()))
+        );
+        assert_eq!(
+            resolve(InfraContext::Institution((1, 3, 2).into())),
+            Some(ResolvedEntity::Institution((1, 3, 2).into()))
+        );
+        assert_eq!(resolve(InfraContext::Organization((5, 2).into())), None);
+        assert_eq!(resolve(InfraContext::Institution((1, 2, 2).into())), None);
+    }
+}
@@ -1,8 +1,29 @@
 use std::sync::Arc;
 
+use time::format_description::FormatItem;
+use time::macros::format_description;
+use time::PrimitiveDateTime;
+
 #[derive(Debug, serde::Deserialize)]
 pub struct GroupAttributeUpdate {
     pub group_id: Arc<str>,
     pub name: Option<String>,
     pub value: Option<String>,
 }
+
+/// Timestamp format of the rows sent with Postgres notifications.
+const NOTIFY_DATE_TIME_FORMAT: &[FormatItem<'static>] =
+    format_description!("[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond]");
+
+pub(crate) fn parse_date_time(s: &str) -> Option<PrimitiveDateTime> {
+    PrimitiveDateTime::parse(s, NOTIFY_DATE_TIME_FORMAT).ok()
+}
+
+pub(crate) fn format_date_time(v: &PrimitiveDateTime) -> String {
+    v.format(NOTIFY_DATE_TIME_FORMAT)
+        .expect("notification date time format is valid")
+}
+
+pub(crate) fn parse_created_at(s: &str) -> anyhow::Result<PrimitiveDateTime> {
+    parse_date_time(s).ok_or_else(|| anyhow::anyhow!("invalid created_at '{s}'"))
+}
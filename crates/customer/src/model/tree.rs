@@ -0,0 +1,133 @@
+use qm_entity::ids::InfraId;
+use serde::Serialize;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::{QmCustomer, QmInstitution, QmOrganization};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OrganizationUnitNode {
+    pub id: InfraId,
+    pub name: Arc<str>,
+    pub ty: Arc<str>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OrganizationTree {
+    pub organization: QmOrganization,
+    pub institutions: Vec<QmInstitution>,
+    pub organization_units: Vec<OrganizationUnitNode>,
+}
+
+/// Customer with all of its organizations, institutions and organization units.
+#[derive(Debug, Clone, Serialize)]
+pub struct CustomerTree {
+    pub customer: QmCustomer,
+    pub organizations: Vec<OrganizationTree>,
+    /// Organization units which are not assigned to an organization
+    pub organization_units: Vec<OrganizationUnitNode>,
+}
+
+impl CustomerTree {
+    /// Nests the entities of a customer below their parents, keeping their order.
+    ///
+    /// Institutions and units of an organization which is not part of `organizations` are
+    /// dropped.
+    pub fn assemble(
+        customer: QmCustomer,
+        organizations: Vec<QmOrganization>,
+        institutions: Vec<QmInstitution>,
+        organization_units: Vec<(Option<InfraId>, OrganizationUnitNode)>,
+    ) -> Self {
+        let mut organizations: Vec<OrganizationTree> = organizations
+            .into_iter()
+            .map(|organization| OrganizationTree {
+                organization,
+                institutions: vec![],
+                organization_units: vec![],
+            })
+            .collect();
+        let index: HashMap<InfraId, usize> = organizations
+            .iter()
+            .enumerate()
+            .map(|(i, tree)| (tree.organization.id, i))
+            .collect();
+        for institution in institutions {
+            if let Some(&i) = index.get(&institution.organization_id) {
+                organizations[i].institutions.push(institution);
+            }
+        }
+        let mut customer_units = vec![];
+        for (organization_id, unit) in organization_units {
+            match organization_id {
+                Some(id) => {
+                    if let Some(&i) = index.get(&id) {
+                        organizations[i].organization_units.push(unit);
+                    }
+                }
+                None => customer_units.push(unit),
+            }
+        }
+        Self {
+            customer,
+            organizations,
+            organization_units: customer_units,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{customer, institution, organization};
+
+    fn unit(organization_id: Option<i64>, id: i64) -> (Option<InfraId>, OrganizationUnitNode) {
+        (
+            organization_id.map(InfraId::from),
+            OrganizationUnitNode {
+                id: id.into(),
+                name: Arc::from(format!("unit {id}")),
+                ty: Arc::from("none"),
+            },
+        )
+    }
+
+    #[test]
+    fn test_customer_tree_assemble() {
+        let tree = CustomerTree::assemble(
+            customer(1),
+            vec![organization(1, 2), organization(1, 3)],
+            vec![
+                institution(1, 2, 4),
+                institution(1, 3, 5),
+                institution(1, 2, 6),
+            ],
+            vec![unit(Some(3), 7), unit(None, 8), unit(Some(9), 10)],
+        );
+        let ids = |tree: &OrganizationTree| {
+            (
+                *tree.organization.id.as_ref(),
+                tree.institutions
+                    .iter()
+                    .map(|v| *v.id.as_ref())
+                    .collect::<Vec<_>>(),
+                tree.organization_units
+                    .iter()
+                    .map(|v| *v.id.as_ref())
+                    .collect::<Vec<_>>(),
+            )
+        };
+        assert_eq!(*tree.customer.id.as_ref(), 1);
+        assert_eq!(
+            tree.organizations.iter().map(ids).collect::<Vec<_>>(),
+            vec![(2, vec![4, 6], vec![]), (3, vec![5], vec![7])]
+        );
+        assert_eq!(tree.organization_units.len(), 1);
+        assert_eq!(*tree.organization_units[0].id.as_ref(), 8);
+
+        let empty = CustomerTree::assemble(customer(1), vec![], vec![], vec![]);
+        assert!(empty.organizations.is_empty());
+        assert!(empty.organization_units.is_empty());
+    }
+}
@@ -1,8 +1,9 @@
 use async_graphql::{Enum, InputObject, SimpleObject};
 use qm_entity::ids::{InfraContext, InstitutionId, PartialEqual};
+use qm_entity::list::NewList;
 use sqlx::types::Uuid;
 use sqlx::FromRow;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::Arc;
 
 use super::GroupDetail;
@@ -140,6 +141,36 @@ pub struct CreateUserPayload {
     pub context: Option<InfraContext>,
 }
 
+/// Payload of the `user` namespace events, `context` is the owner of the users.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct QmUserEvent {
+    pub ids: Vec<String>,
+    pub context: Option<String>,
+}
+
+impl QmUserEvent {
+    pub fn new(ids: Vec<String>, context: Option<&InfraContext>) -> Self {
+        Self {
+            ids,
+            context: context.map(InfraContext::to_string),
+        }
+    }
+
+    /// One event per owner context for the given users and their contexts.
+    pub fn by_context(
+        users: impl IntoIterator<Item = (String, Option<InfraContext>)>,
+    ) -> Vec<Self> {
+        let mut ids_by_context: BTreeMap<Option<InfraContext>, Vec<String>> = BTreeMap::new();
+        for (id, context) in users {
+            ids_by_context.entry(context).or_default().push(id);
+        }
+        ids_by_context
+            .into_iter()
+            .map(|(context, ids)| Self::new(ids, context.as_ref()))
+            .collect()
+    }
+}
+
 #[derive(Debug, Clone, SimpleObject)]
 pub struct QmUser {
     pub id: Arc<str>,
@@ -181,6 +212,22 @@ pub struct QmUserList {
     pub page: Option<i64>,
 }
 
+impl NewList<QmUserDetails> for QmUserList {
+    fn new(
+        items: Vec<QmUserDetails>,
+        limit: Option<i64>,
+        total: Option<i64>,
+        page: Option<i64>,
+    ) -> Self {
+        Self {
+            items: items.into(),
+            limit,
+            total,
+            page,
+        }
+    }
+}
+
 #[derive(Debug, Clone, SimpleObject)]
 #[graphql(complex)]
 pub struct QmUserDetails {
@@ -223,3 +270,55 @@ pub struct UserGroup {
     pub group_id: Arc<str>,
     pub group_detail: Arc<GroupDetail>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use qm_entity::list::paginate_query;
+
+    #[test]
+    fn test_user_list_paginate_query() {
+        let user = QmUserDetails {
+            user: Arc::new(QmUser {
+                id: Arc::from("1"),
+                username: Arc::from("alice"),
+                email: Arc::from("alice@example.com"),
+                firstname: Arc::from("Alice"),
+                lastname: Arc::from("Doe"),
+                enabled: true,
+            }),
+            context: None,
+            access: None,
+            group: None,
+        };
+        let list: QmUserList = paginate_query(vec![user], None, 1);
+        assert_eq!(list.items[0].user.username.as_ref(), "alice");
+        assert_eq!(list.limit, None);
+        assert_eq!(list.total, Some(1));
+        assert_eq!(list.page, Some(0));
+    }
+
+    #[test]
+    fn test_user_event_by_context() {
+        let institution = InfraContext::Institution((1, 2, 3).into());
+        let customer = InfraContext::Customer(1.into());
+        let events = QmUserEvent::by_context([
+            ("u1".to_string(), Some(institution)),
+            ("u2".to_string(), None),
+            ("u3".to_string(), Some(institution)),
+            ("u4".to_string(), Some(customer)),
+        ]);
+        let payloads: Vec<_> = events
+            .iter()
+            .map(|event| serde_json::to_value(event).unwrap())
+            .collect();
+        assert_eq!(
+            payloads,
+            vec![
+                serde_json::json!({ "ids": ["u2"], "context": null }),
+                serde_json::json!({ "ids": ["u4"], "context": customer.to_string() }),
+                serde_json::json!({ "ids": ["u1", "u3"], "context": institution.to_string() }),
+            ]
+        );
+    }
+}
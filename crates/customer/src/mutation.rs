@@ -1,5 +1,6 @@
 use crate::model::*;
 use qm_entity::ids::InfraId;
+use qm_entity::ids::{InstitutionId, InstitutionIds};
 use sqlx::types::Uuid;
 use sqlx::PgPool;
 use std::sync::Arc;
@@ -242,6 +243,71 @@ pub async fn create_organization(
     }
 }
 
+/// Validates the name and type of every organization input and splits them into the name and
+/// type columns of a multi-row insert.
+fn organization_columns(
+    inputs: &[(String, Option<String>)],
+) -> anyhow::Result<(Vec<&str>, Vec<&str>)> {
+    check_max_size_input_slice("Organization inputs", inputs)?;
+    let mut names = Vec::with_capacity(inputs.len());
+    let mut tys = Vec::with_capacity(inputs.len());
+    for (name, ty) in inputs {
+        check_max_size("Organization name", Some(name), NAME_MAX_LEN)?;
+        check_max_size("Organization ty", ty.as_deref(), TY_MAX_LEN)?;
+        names.push(name.as_str());
+        tys.push(ty.as_deref().unwrap_or(DEFAULT_TYPE));
+    }
+    Ok((names, tys))
+}
+
+/// Creates all organizations of `inputs` (name and optional type) under the customer with a
+/// single insert.
+pub async fn create_organizations(
+    pool: &PgPool,
+    customer_id: InfraId,
+    inputs: &[(String, Option<String>)],
+    created_by: &Uuid,
+) -> anyhow::Result<Vec<QmOrganization>> {
+    let (names, tys) = organization_columns(inputs)?;
+    if names.is_empty() {
+        return Ok(vec![]);
+    }
+    let recs = sqlx::query!(
+        r#"
+    INSERT INTO organizations ( name, ty, customer_id, created_by )
+    SELECT name, ty, $3, $4 FROM UNNEST($1::varchar[], $2::varchar[]) AS input ( name, ty )
+    RETURNING
+        id,
+        customer_id,
+        name,
+        ty,
+        created_by,
+        created_at,
+        updated_by,
+        updated_at
+    "#,
+        &names as &[&str],
+        &tys as &[&str],
+        customer_id.as_ref(),
+        created_by
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(recs
+        .into_iter()
+        .map(|rec| QmOrganization {
+            id: rec.id.into(),
+            customer_id: rec.customer_id.into(),
+            name: Arc::from(rec.name),
+            ty: Arc::from(rec.ty),
+            created_by: rec.created_by,
+            created_at: rec.created_at,
+            updated_by: rec.updated_by,
+            updated_at: rec.updated_at,
+        })
+        .collect())
+}
+
 pub async fn update_organization(
     pool: &PgPool,
     id: InfraId,
@@ -304,7 +370,7 @@ pub async fn remove_organizations(pool: &PgPool, ids: &[i64]) -> anyhow::Result<
 }
 
 pub async fn create_institution(
-    pool: &PgPool,
+    pool: impl sqlx::PgExecutor<'_>,
     id: Option<i64>,
     name: &str,
     ty: Option<&str>,
@@ -390,6 +456,68 @@ RETURNING
     }
 }
 
+/// Creates the institution and an organization unit named `unit_name` with the new institution
+/// as its only member in one transaction.
+pub async fn create_institution_with_unit(
+    pool: &PgPool,
+    institution: InstitutionData,
+    unit_name: &str,
+    created_by: &Uuid,
+) -> anyhow::Result<(QmInstitution, QmOrganizationUnit)> {
+    check_max_size("Organization unit name", Some(unit_name), NAME_MAX_LEN)?;
+    let InstitutionData(organization_id, name, ty, id) = institution;
+    let (cid, oid) = organization_id.unzip();
+    let mut tx = pool.begin().await?;
+    let institution = create_institution(
+        &mut *tx,
+        id,
+        &name,
+        ty.as_deref(),
+        cid.into(),
+        oid.into(),
+        created_by,
+    )
+    .await?;
+    let rec = sqlx::query!(
+        r#"
+INSERT INTO organization_units ( customer_id, organization_id, name, ty, created_by )
+VALUES ( $1, $2, $3, $4, $5 )
+RETURNING
+    id,
+    created_at
+"#,
+        institution.customer_id.as_ref(),
+        institution.organization_id.as_ref(),
+        unit_name,
+        DEFAULT_TYPE,
+        created_by
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    let unit = QmOrganizationUnit::for_institution(
+        rec.id.into(),
+        Arc::from(unit_name),
+        Arc::from(DEFAULT_TYPE),
+        *created_by,
+        rec.created_at,
+        &institution,
+    );
+    sqlx::query!(
+        r#"
+INSERT INTO organization_unit_members ( organization_unit_id, customer_id, organization_id, institution_id )
+VALUES ( $1, $2, $3, $4 )
+"#,
+        unit.id.as_ref(),
+        institution.customer_id.as_ref(),
+        institution.organization_id.as_ref(),
+        institution.id.as_ref()
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok((institution, unit))
+}
+
 pub async fn update_institution(
     pool: &PgPool,
     id: InfraId,
@@ -453,3 +581,257 @@ pub async fn remove_institutions(pool: &PgPool, ids: &[i64]) -> anyhow::Result<u
     .rows_affected() as u64;
     Ok(result)
 }
+
+pub async fn remove_organization_units(pool: &PgPool, ids: &[i64]) -> anyhow::Result<u64> {
+    check_max_size_input_slice("Organization unit ids", ids)?;
+    let result = sqlx::query!(
+        "DELETE FROM organization_units WHERE id IN (SELECT UNNEST($1::int8[]))",
+        &ids[..] as &[i64]
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    Ok(result)
+}
+
+fn institution_ids(members: &[InstitutionId]) -> anyhow::Result<Vec<i64>> {
+    let ids: Vec<i64> = members.iter().map(InstitutionId::id).collect();
+    check_max_size_input_slice("Institution ids", &ids)?;
+    Ok(ids)
+}
+
+/// Removes the given institutions from the organization unit in one statement.
+///
+/// Institutions which are no member of the unit are ignored, returns the number of removed members.
+pub async fn remove_organization_unit_members(
+    pool: impl sqlx::PgExecutor<'_>,
+    organization_unit_id: i64,
+    members: InstitutionIds,
+) -> anyhow::Result<u64> {
+    let ids = institution_ids(&members)?;
+    if ids.is_empty() {
+        return Ok(0);
+    }
+    let result = sqlx::query!(
+        "DELETE FROM organization_unit_members WHERE organization_unit_id = $1 AND institution_id IN (SELECT UNNEST($2::int8[]))",
+        organization_unit_id,
+        &ids[..] as &[i64]
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    Ok(result)
+}
+
+/// Replaces the members of the organization unit with `members` in one transaction without
+/// touching its name, only the difference to the current members is applied.
+///
+/// `updated_by` and `updated_at` of the unit are only set if the members changed.
+pub async fn set_organization_unit_members(
+    pool: &PgPool,
+    id: InfraId,
+    members: InstitutionIds,
+    updated_by: &Uuid,
+) -> anyhow::Result<OrganizationUnitMemberChanges> {
+    check_max_size_input_slice("Institution ids", &members)?;
+    let mut tx = pool.begin().await?;
+    let unit = sqlx::query_scalar!(
+        r#"
+SELECT id FROM organization_units WHERE id = $1 FOR UPDATE
+"#,
+        id.as_ref()
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    if unit.is_none() {
+        anyhow::bail!("organization unit '{id}' does not exist");
+    }
+    let current: Vec<InstitutionId> = sqlx::query!(
+        r#"
+SELECT customer_id, organization_id, institution_id
+FROM organization_unit_members
+WHERE organization_unit_id = $1
+"#,
+        id.as_ref()
+    )
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .map(|rec| (rec.customer_id, rec.organization_id, rec.institution_id).into())
+    .collect();
+    let changes = OrganizationUnitMemberChanges::diff(&current, &members);
+    if changes.is_empty() {
+        return Ok(changes);
+    }
+    if !changes.removed.is_empty() {
+        remove_organization_unit_members(&mut *tx, *id.as_ref(), changes.removed.as_slice().into())
+            .await?;
+    }
+    if !changes.added.is_empty() {
+        let (cids, (oids, iids)): (Vec<i64>, (Vec<i64>, Vec<i64>)) = changes
+            .added
+            .iter()
+            .map(|v| (v.cid, (v.oid, v.iid)))
+            .unzip();
+        sqlx::query!(
+            r#"
+INSERT INTO organization_unit_members ( organization_unit_id, customer_id, organization_id, institution_id )
+SELECT $1, * FROM UNNEST($2::int8[], $3::int8[], $4::int8[])
+"#,
+            id.as_ref(),
+            &cids[..] as &[i64],
+            &oids[..] as &[i64],
+            &iids[..] as &[i64]
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    sqlx::query!(
+        r#"
+UPDATE organization_units SET updated_by = $2, updated_at = NOW() WHERE id = $1
+"#,
+        id.as_ref(),
+        updated_by
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::seed_institutions;
+    use sqlx::types::time::PrimitiveDateTime;
+
+    #[test]
+    fn test_organization_columns() {
+        let inputs: Vec<(String, Option<String>)> = (0..100)
+            .map(|i| {
+                (
+                    format!("Organization {i}"),
+                    (i % 2 == 0).then(|| "team".into()),
+                )
+            })
+            .collect();
+        let (names, tys) = organization_columns(&inputs).unwrap();
+        assert_eq!(names.len(), 100);
+        assert_eq!(names[42], "Organization 42");
+        assert_eq!(tys[42], "team");
+        assert_eq!(tys[43], DEFAULT_TYPE);
+
+        let too_long = vec![
+            ("Organization".to_string(), None),
+            ("Organization".to_string(), Some("x".repeat(TY_MAX_LEN + 1))),
+        ];
+        assert!(organization_columns(&too_long).is_err());
+        let too_long = vec![("x".repeat(NAME_MAX_LEN + 1), None)];
+        assert!(organization_columns(&too_long).is_err());
+    }
+
+    #[test]
+    fn test_institution_ids() {
+        let members: InstitutionIds = Arc::from([(1, 2, 3).into(), (1, 4, 5).into()]);
+        assert_eq!(institution_ids(&members).unwrap(), vec![3, 5]);
+        assert!(institution_ids(&[]).unwrap().is_empty());
+    }
+
+    /// Creates an organization unit with three member institutions.
+    async fn unit_with_members(pool: &PgPool) -> (InfraId, Vec<InstitutionId>) {
+        let seeded = seed_institutions(pool, 3).await;
+        let institutions: Vec<InstitutionId> = seeded
+            .institutions
+            .iter()
+            .map(InstitutionId::from)
+            .collect();
+        let unit_id = seeded.units[0].id;
+        set_organization_unit_members(pool, unit_id, Arc::from(institutions.clone()), &Uuid::nil())
+            .await
+            .unwrap();
+        (unit_id, institutions)
+    }
+
+    /// Institution ids of the members of the organization unit, ordered.
+    async fn member_ids(pool: &PgPool, id: InfraId) -> Vec<i64> {
+        sqlx::query_scalar(
+            "SELECT institution_id FROM organization_unit_members WHERE organization_unit_id = $1 ORDER BY institution_id",
+        )
+        .bind(id.as_ref())
+        .fetch_all(pool)
+        .await
+        .unwrap()
+    }
+
+    async fn unit_updated_at(pool: &PgPool, id: InfraId) -> Option<PrimitiveDateTime> {
+        sqlx::query_scalar("SELECT updated_at FROM organization_units WHERE id = $1")
+            .bind(id.as_ref())
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a postgres database configured by PG_*"]
+    async fn test_remove_organization_unit_members() {
+        let db = crate::testing::customer_db().await;
+        let pool = db.pool();
+        let (unit_id, institutions) = unit_with_members(pool).await;
+        let unknown = InstitutionId::from((1, 1, i64::MAX));
+        let removed =
+            remove_organization_unit_members(pool, *unit_id, Arc::from([institutions[0], unknown]))
+                .await
+                .unwrap();
+        assert_eq!(removed, 1);
+        let removed = remove_organization_unit_members(pool, *unit_id, Arc::from([]))
+            .await
+            .unwrap();
+        assert_eq!(removed, 0);
+        let removed = remove_organization_unit_members(pool, *unit_id, Arc::from(institutions))
+            .await
+            .unwrap();
+        assert_eq!(removed, 2);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a postgres database configured by PG_*"]
+    async fn test_set_organization_unit_members() {
+        let db = crate::testing::customer_db().await;
+        let pool = db.pool();
+        let user = Uuid::from_u128(1);
+        let seeded = seed_institutions(pool, 3).await;
+        let [a, b, c] = [0, 1, 2].map(|i| InstitutionId::from(&seeded.institutions[i]));
+        let unit_id = seeded.units[0].id;
+        assert_eq!(member_ids(pool, unit_id).await, vec![a.iid]);
+        assert!(unit_updated_at(pool, unit_id).await.is_none());
+
+        let changes = set_organization_unit_members(pool, unit_id, Arc::from([a, b, c]), &user)
+            .await
+            .unwrap();
+        assert_eq!(changes.added, vec![b, c]);
+        assert!(changes.removed.is_empty());
+        assert_eq!(member_ids(pool, unit_id).await, vec![a.iid, b.iid, c.iid]);
+        let updated_at = unit_updated_at(pool, unit_id).await;
+        assert!(updated_at.is_some());
+
+        let changes = set_organization_unit_members(pool, unit_id, Arc::from([c]), &user)
+            .await
+            .unwrap();
+        assert!(changes.added.is_empty());
+        assert_eq!(changes.removed, vec![a, b]);
+        assert_eq!(member_ids(pool, unit_id).await, vec![c.iid]);
+        assert!(unit_updated_at(pool, unit_id).await >= updated_at);
+
+        let updated_at = unit_updated_at(pool, unit_id).await;
+        let changes = set_organization_unit_members(pool, unit_id, Arc::from([c, c]), &user)
+            .await
+            .unwrap();
+        assert!(changes.is_empty());
+        assert_eq!(member_ids(pool, unit_id).await, vec![c.iid]);
+        assert_eq!(unit_updated_at(pool, unit_id).await, updated_at);
+
+        let unknown = set_organization_unit_members(pool, i64::MAX.into(), Arc::from([c]), &user);
+        assert!(unknown.await.is_err());
+        remove_customer(pool, seeded.customer.id).await.unwrap();
+    }
+}
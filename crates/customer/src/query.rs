@@ -1,6 +1,14 @@
 use crate::model::*;
+use qm_entity::ids::InfraContext;
+use qm_entity::ids::InfraId;
+use qm_entity::ids::InstitutionId;
+use qm_entity::model::Pagination;
 use qm_pg::DB;
 use sqlx::query_as;
+use sqlx::types::time::PrimitiveDateTime;
+use sqlx::types::Uuid;
+use std::collections::HashMap;
+use std::sync::Arc;
 
 pub async fn fetch_users(
     db: &DB,
@@ -238,3 +246,740 @@ FROM institutions;"#
     .fetch_all(db.pool())
     .await?)
 }
+
+pub async fn fetch_customers_by_ids(db: &DB, ids: &[i64]) -> anyhow::Result<Vec<QmCustomer>> {
+    Ok(query_as!(
+        QmCustomer,
+        r#"
+SELECT
+    id,
+    name,
+    ty,
+    created_by,
+    created_at,
+    updated_by,
+    updated_at
+FROM customers
+WHERE id = ANY($1);"#,
+        ids
+    )
+    .fetch_all(db.pool())
+    .await?)
+}
+
+/// Customers created or updated after `since`, used to refresh the cache incrementally.
+pub async fn fetch_customers_modified_since(
+    db: &DB,
+    since: PrimitiveDateTime,
+) -> anyhow::Result<Vec<QmCustomer>> {
+    Ok(query_as!(
+        QmCustomer,
+        r#"
+SELECT
+    id,
+    name,
+    ty,
+    created_by,
+    created_at,
+    updated_by,
+    updated_at
+FROM customers
+WHERE updated_at > $1 OR created_at > $1;"#,
+        since
+    )
+    .fetch_all(db.pool())
+    .await?)
+}
+
+/// Organizations created or updated after `since`, used to refresh the cache incrementally.
+pub async fn fetch_organizations_modified_since(
+    db: &DB,
+    since: PrimitiveDateTime,
+) -> anyhow::Result<Vec<QmOrganization>> {
+    Ok(query_as!(
+        QmOrganization,
+        r#"
+SELECT
+    id,
+    name,
+    ty,
+    customer_id,
+    created_by,
+    created_at,
+    updated_by,
+    updated_at
+FROM organizations
+WHERE updated_at > $1 OR created_at > $1;"#,
+        since
+    )
+    .fetch_all(db.pool())
+    .await?)
+}
+
+/// Institutions created or updated after `since`, used to refresh the cache incrementally.
+pub async fn fetch_institutions_modified_since(
+    db: &DB,
+    since: PrimitiveDateTime,
+) -> anyhow::Result<Vec<QmInstitution>> {
+    Ok(query_as!(
+        QmInstitution,
+        r#"
+SELECT
+    id,
+    name,
+    ty,
+    customer_id,
+    organization_id,
+    created_by,
+    created_at,
+    updated_by,
+    updated_at
+FROM institutions
+WHERE updated_at > $1 OR created_at > $1;"#,
+        since
+    )
+    .fetch_all(db.pool())
+    .await?)
+}
+
+/// Ids of all customers, organizations and institutions.
+///
+/// Deleted rows leave no trace to filter on, so an incremental refresh compares the cached
+/// ids against these to drop them.
+pub async fn fetch_infra_ids(db: &DB) -> anyhow::Result<(Vec<i64>, Vec<i64>, Vec<i64>)> {
+    let cids = sqlx::query_scalar!("SELECT id FROM customers;")
+        .fetch_all(db.pool())
+        .await?;
+    let oids = sqlx::query_scalar!("SELECT id FROM organizations;")
+        .fetch_all(db.pool())
+        .await?;
+    let iids = sqlx::query_scalar!("SELECT id FROM institutions;")
+        .fetch_all(db.pool())
+        .await?;
+    Ok((cids, oids, iids))
+}
+
+/// Current time of the database, in the same time zone as the `created_at` and
+/// `updated_at` columns.
+pub async fn fetch_database_time(db: &DB) -> anyhow::Result<PrimitiveDateTime> {
+    Ok(sqlx::query_scalar!(r#"SELECT LOCALTIMESTAMP AS "now!";"#)
+        .fetch_one(db.pool())
+        .await?)
+}
+
+struct CustomerWithTotal {
+    id: i64,
+    name: String,
+    ty: String,
+    created_by: Uuid,
+    created_at: PrimitiveDateTime,
+    updated_by: Option<Uuid>,
+    updated_at: Option<PrimitiveDateTime>,
+    total: i64,
+}
+
+/// Customers created between `from` and `to`, both inclusive, ordered by creation time.
+///
+/// Returns the requested page and the total number of matching customers. The total is
+/// counted with a window function, so it is `0` if the page is beyond the last one.
+pub async fn list_customers_created_between(
+    db: &DB,
+    from: PrimitiveDateTime,
+    to: PrimitiveDateTime,
+    pagination: Pagination,
+) -> anyhow::Result<(Vec<QmCustomer>, i64)> {
+    if from > to {
+        return Ok((vec![], 0));
+    }
+    let rows = query_as!(
+        CustomerWithTotal,
+        r#"
+SELECT
+    id,
+    name,
+    ty,
+    created_by,
+    created_at,
+    updated_by,
+    updated_at,
+    COUNT(*) OVER() AS "total!"
+FROM customers
+WHERE created_at BETWEEN $1 AND $2
+ORDER BY created_at, id
+LIMIT $3 OFFSET $4;"#,
+        from,
+        to,
+        pagination.limit(),
+        pagination.offset()
+    )
+    .fetch_all(db.pool())
+    .await?;
+    let total = rows.first().map(|row| row.total).unwrap_or(0);
+    let customers = rows
+        .into_iter()
+        .map(|row| QmCustomer {
+            id: row.id.into(),
+            name: row.name.into(),
+            ty: row.ty.into(),
+            created_by: row.created_by,
+            created_at: row.created_at,
+            updated_by: row.updated_by,
+            updated_at: row.updated_at,
+        })
+        .collect();
+    Ok((customers, total))
+}
+
+pub async fn fetch_organizations_by_ids(
+    db: &DB,
+    ids: &[i64],
+) -> anyhow::Result<Vec<QmOrganization>> {
+    Ok(query_as!(
+        QmOrganization,
+        r#"
+SELECT
+    id,
+    name,
+    ty,
+    customer_id,
+    created_by,
+    created_at,
+    updated_by,
+    updated_at
+FROM organizations
+WHERE id = ANY($1);"#,
+        ids
+    )
+    .fetch_all(db.pool())
+    .await?)
+}
+
+pub async fn fetch_institutions_by_ids(db: &DB, ids: &[i64]) -> anyhow::Result<Vec<QmInstitution>> {
+    Ok(query_as!(
+        QmInstitution,
+        r#"
+SELECT
+    id,
+    name,
+    ty,
+    customer_id,
+    organization_id,
+    created_by,
+    created_at,
+    updated_by,
+    updated_at
+FROM institutions
+WHERE id = ANY($1);"#,
+        ids
+    )
+    .fetch_all(db.pool())
+    .await?)
+}
+
+/// Institutions with the given ids in a single query, in the order of `ids`.
+///
+/// Ids without an institution are skipped, duplicate ids yield the institution once.
+pub async fn institutions_by_ids(db: &DB, ids: &[InfraId]) -> anyhow::Result<Vec<QmInstitution>> {
+    if ids.is_empty() {
+        return Ok(vec![]);
+    }
+    let ids: Vec<i64> = ids.iter().map(|id| *id.as_ref()).collect();
+    let institutions = fetch_institutions_by_ids(db, &ids).await?;
+    Ok(order_by_ids(&ids, institutions, |v| *v.id.as_ref()))
+}
+
+fn order_by_ids<T>(ids: &[i64], items: Vec<T>, id: impl Fn(&T) -> i64) -> Vec<T> {
+    let mut items: HashMap<i64, T> = items.into_iter().map(|v| (id(&v), v)).collect();
+    ids.iter().filter_map(|id| items.remove(id)).collect()
+}
+
+/// Customer with its organizations, institutions and organization units.
+///
+/// A customer without organizations yields an empty tree, an unknown customer is an error.
+pub async fn customer_tree(db: &DB, customer_id: i64) -> anyhow::Result<CustomerTree> {
+    let customer = fetch_customers_by_ids(db, &[customer_id])
+        .await?
+        .pop()
+        .ok_or_else(|| anyhow::anyhow!("customer '{customer_id}' not found"))?;
+    let organizations = query_as!(
+        QmOrganization,
+        r#"
+SELECT
+    id,
+    name,
+    ty,
+    customer_id,
+    created_by,
+    created_at,
+    updated_by,
+    updated_at
+FROM organizations
+WHERE customer_id = $1
+ORDER BY id;"#,
+        customer_id
+    )
+    .fetch_all(db.pool())
+    .await?;
+    let institutions = query_as!(
+        QmInstitution,
+        r#"
+SELECT
+    id,
+    name,
+    ty,
+    customer_id,
+    organization_id,
+    created_by,
+    created_at,
+    updated_by,
+    updated_at
+FROM institutions
+WHERE customer_id = $1
+ORDER BY id;"#,
+        customer_id
+    )
+    .fetch_all(db.pool())
+    .await?;
+    let organization_units = sqlx::query!(
+        r#"
+SELECT
+    id,
+    organization_id,
+    name,
+    ty
+FROM organization_units
+WHERE customer_id = $1
+ORDER BY id;"#,
+        customer_id
+    )
+    .fetch_all(db.pool())
+    .await?
+    .into_iter()
+    .map(|row| {
+        (
+            row.organization_id.map(InfraId::from),
+            OrganizationUnitNode {
+                id: row.id.into(),
+                name: row.name.into(),
+                ty: row.ty.into(),
+            },
+        )
+    })
+    .collect();
+    Ok(CustomerTree::assemble(
+        customer,
+        organizations,
+        institutions,
+        organization_units,
+    ))
+}
+
+/// Number of organization units of the organization.
+pub async fn count_organization_units(db: &DB, organization_id: i64) -> anyhow::Result<i64> {
+    Ok(sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM organization_units WHERE organization_id = $1;"#,
+        organization_id
+    )
+    .fetch_one(db.pool())
+    .await?)
+}
+
+/// Resolves the type and owning customer of all entities with the id.
+///
+/// Each table has its own id sequence, so the id of an organization or institution usually also
+/// exists as customer id. Use [resolve_context_id] for ids in the prefixed form.
+pub async fn resolve_infra_id(db: &DB, id: InfraId) -> anyhow::Result<Vec<ResolvedEntity>> {
+    sqlx::query!(
+        r#"
+SELECT kind AS "kind!", id AS "id!", customer_id AS "customer_id!", organization_id
+FROM (
+    SELECT 0 AS kind, id, id AS customer_id, NULL::int8 AS organization_id FROM customers WHERE id = $1
+    UNION ALL
+    SELECT 1, id, customer_id, id FROM organizations WHERE id = $1
+    UNION ALL
+    SELECT 2, id, customer_id, organization_id FROM institutions WHERE id = $1
+    UNION ALL
+    SELECT 3, id, customer_id, organization_id FROM organization_units WHERE id = $1
+) AS entities
+ORDER BY kind;
+"#,
+        id.as_ref()
+    )
+    .fetch_all(db.pool())
+    .await?
+    .into_iter()
+    .map(|row| ResolvedEntity::from_row(row.kind, row.id, row.customer_id, row.organization_id))
+    .collect()
+}
+
+/// Resolves an id in the prefixed form of [InfraContext] (`V`, `T` or `R`), returns `None` if
+/// the entity does not exist.
+pub async fn resolve_context_id(db: &DB, id: &str) -> anyhow::Result<Option<ResolvedEntity>> {
+    let context = InfraContext::parse(id)?;
+    let id = context
+        .institution_id()
+        .or_else(|| context.organization_id())
+        .unwrap_or_else(|| context.customer_id());
+    Ok(ResolvedEntity::find_context(
+        resolve_infra_id(db, id).await?,
+        &context,
+    ))
+}
+
+/// Organization units the institution is a member of, with all their members.
+pub async fn organization_units_for_institution(
+    db: &DB,
+    institution_id: InfraId,
+) -> anyhow::Result<Vec<QmOrganizationUnit>> {
+    let rows = sqlx::query!(
+        r#"
+SELECT
+    u.id,
+    u.customer_id,
+    u.organization_id,
+    u.name,
+    u.ty,
+    u.created_by,
+    u.created_at,
+    u.updated_by,
+    u.updated_at,
+    m.customer_id AS member_customer_id,
+    m.organization_id AS member_organization_id,
+    m.institution_id AS member_institution_id
+FROM organization_units AS u
+JOIN organization_unit_members AS m ON m.organization_unit_id = u.id
+WHERE EXISTS (
+    SELECT 1 FROM organization_unit_members AS i
+    WHERE i.organization_unit_id = u.id AND i.institution_id = $1
+)
+ORDER BY u.id, m.institution_id;"#,
+        institution_id.as_ref()
+    )
+    .fetch_all(db.pool())
+    .await?
+    .into_iter()
+    .map(|row| {
+        (
+            QmOrganizationUnit {
+                id: row.id.into(),
+                customer_id: row.customer_id.into(),
+                organization_id: row.organization_id.map(InfraId::from),
+                name: row.name.into(),
+                ty: row.ty.into(),
+                created_by: row.created_by,
+                created_at: row.created_at,
+                updated_by: row.updated_by,
+                updated_at: row.updated_at,
+                members: Arc::from([]),
+            },
+            InstitutionId::from((
+                row.member_customer_id,
+                row.member_organization_id,
+                row.member_institution_id,
+            )),
+        )
+    });
+    Ok(QmOrganizationUnit::collect_with_members(rows))
+}
+
+/// Organization units of `unit_ids` without any member institution.
+pub async fn fetch_empty_organization_units(
+    db: &DB,
+    unit_ids: &[i64],
+) -> anyhow::Result<Vec<OrganizationUnitNode>> {
+    Ok(query_as!(
+        OrganizationUnitNode,
+        r#"
+SELECT u.id, u.name, u.ty
+FROM organization_units AS u
+WHERE u.id = ANY($1)
+    AND NOT EXISTS (SELECT 1 FROM organization_unit_members AS m WHERE m.organization_unit_id = u.id)
+ORDER BY u.id;"#,
+        unit_ids
+    )
+    .fetch_all(db.pool())
+    .await?)
+}
+
+/// Ids of the organization units with member institutions of the organizations.
+pub async fn fetch_organization_unit_ids_with_members_of(
+    db: &DB,
+    organization_ids: &[i64],
+) -> anyhow::Result<Vec<i64>> {
+    Ok(sqlx::query_scalar!(
+        r#"
+SELECT DISTINCT organization_unit_id
+FROM organization_unit_members
+WHERE organization_id = ANY($1)
+ORDER BY organization_unit_id;"#,
+        organization_ids
+    )
+    .fetch_all(db.pool())
+    .await?)
+}
+
+/// Distinct `ty` values of all customers, sorted.
+pub async fn distinct_customer_types(db: &DB) -> anyhow::Result<Vec<Arc<str>>> {
+    let types = sqlx::query_scalar!("SELECT DISTINCT ty FROM customers;")
+        .fetch_all(db.pool())
+        .await?;
+    Ok(sorted_types(types))
+}
+
+/// Distinct `ty` values of all organizations, sorted.
+pub async fn distinct_organization_types(db: &DB) -> anyhow::Result<Vec<Arc<str>>> {
+    let types = sqlx::query_scalar!("SELECT DISTINCT ty FROM organizations;")
+        .fetch_all(db.pool())
+        .await?;
+    Ok(sorted_types(types))
+}
+
+/// Distinct `ty` values of all institutions, sorted.
+pub async fn distinct_institution_types(db: &DB) -> anyhow::Result<Vec<Arc<str>>> {
+    let types = sqlx::query_scalar!("SELECT DISTINCT ty FROM institutions;")
+        .fetch_all(db.pool())
+        .await?;
+    Ok(sorted_types(types))
+}
+
+/// Distinct `ty` values of all organization units, sorted.
+pub async fn distinct_organization_unit_types(db: &DB) -> anyhow::Result<Vec<Arc<str>>> {
+    let types = sqlx::query_scalar!("SELECT DISTINCT ty FROM organization_units;")
+        .fetch_all(db.pool())
+        .await?;
+    Ok(sorted_types(types))
+}
+
+fn sorted_types(mut types: Vec<String>) -> Vec<Arc<str>> {
+    types.sort_unstable();
+    types.dedup();
+    types.into_iter().map(Arc::from).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mutation::{
+        create_customer, create_institution, create_organization, remove_customer,
+        remove_organization_unit_members, set_organization_unit_members,
+    };
+    use crate::testing::{customer_db, seed_institutions};
+
+    #[test]
+    fn test_order_by_ids() {
+        let items = vec![(1, "a"), (3, "c"), (2, "b")];
+        let ordered = order_by_ids(&[3, 4, 1, 2, 3], items, |v| v.0);
+        assert_eq!(ordered, vec![(3, "c"), (1, "a"), (2, "b")]);
+    }
+
+    #[test]
+    fn test_sorted_types() {
+        let types = sorted_types(vec![
+            "school".to_string(),
+            "clinic".to_string(),
+            "university".to_string(),
+            "clinic".to_string(),
+        ]);
+        assert_eq!(
+            types,
+            vec![
+                Arc::from("clinic"),
+                Arc::from("school"),
+                Arc::from("university")
+            ]
+        );
+        assert!(sorted_types(vec![]).is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a postgres database configured by PG_*"]
+    async fn test_organization_units_for_institution() {
+        let db = customer_db().await;
+        let pool = db.pool();
+        let seeded = seed_institutions(pool, 3).await;
+        let [a, b, c] = [0, 1, 2].map(|i| InstitutionId::from(&seeded.institutions[i]));
+        let units: Vec<InfraId> = seeded.units.iter().map(|u| u.id).collect();
+        // a is in its own unit and the one of c, b is in none
+        set_organization_unit_members(pool, units[2], Arc::from([a, c]), &Uuid::nil())
+            .await
+            .unwrap();
+        remove_organization_unit_members(pool, *units[1], Arc::from([b]))
+            .await
+            .unwrap();
+        let unit_ids =
+            |units: &[QmOrganizationUnit]| units.iter().map(|u| u.id).collect::<Vec<_>>();
+
+        let none = organization_units_for_institution(&db, b.iid.into())
+            .await
+            .unwrap();
+        assert!(none.is_empty());
+
+        let one = organization_units_for_institution(&db, c.iid.into())
+            .await
+            .unwrap();
+        assert_eq!(unit_ids(&one), vec![units[2]]);
+        assert_eq!(one[0].members.as_ref(), &[a, c]);
+
+        let many = organization_units_for_institution(&db, a.iid.into())
+            .await
+            .unwrap();
+        assert_eq!(unit_ids(&many), vec![units[0], units[2]]);
+        assert_eq!(many[0].members.as_ref(), &[a]);
+        assert_eq!(many[1].members.as_ref(), &[a, c]);
+
+        remove_customer(pool, seeded.customer.id).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a postgres database configured by PG_*"]
+    async fn test_list_customers_created_between() {
+        let db = customer_db().await;
+        let pool = db.pool();
+        let prefix = Uuid::new_v4();
+        // a random instant far in the past, so rows of other tests do not fall into the ranges
+        let base = time::macros::datetime!(1900-01-01 0:00)
+            + time::Duration::seconds((prefix.as_u128() % 1_000_000) as i64 * 10);
+        let second = time::Duration::SECOND;
+        let mut ids = vec![];
+        for i in 0..3 {
+            let customer =
+                create_customer(pool, None, &format!("{prefix} {i}"), None, &Uuid::nil())
+                    .await
+                    .unwrap();
+            sqlx::query("UPDATE customers SET created_at = $2 WHERE id = $1")
+                .bind(customer.id.as_ref())
+                .bind(base + second * i)
+                .execute(pool)
+                .await
+                .unwrap();
+            ids.push(customer.id);
+        }
+        let db = &db;
+        let list = |from, to, pagination| async move {
+            let (customers, total) = list_customers_created_between(db, from, to, pagination)
+                .await
+                .unwrap();
+            (customers.iter().map(|c| c.id).collect::<Vec<_>>(), total)
+        };
+
+        // both boundaries are inclusive
+        assert_eq!(
+            list(base, base + second, Pagination::default()).await,
+            (vec![ids[0], ids[1]], 2)
+        );
+        assert_eq!(
+            list(base + second, base + second, Pagination::default()).await,
+            (vec![ids[1]], 1)
+        );
+        assert_eq!(
+            list(base, base + second * 2, Pagination::new(1, 2)).await,
+            (vec![ids[2]], 3)
+        );
+        // empty ranges
+        assert_eq!(
+            list(
+                base + second / 2,
+                base + second * 9 / 10,
+                Pagination::default()
+            )
+            .await,
+            (vec![], 0)
+        );
+        assert_eq!(
+            list(base + second, base, Pagination::default()).await,
+            (vec![], 0)
+        );
+
+        for id in ids {
+            remove_customer(pool, id).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a postgres database configured by PG_*"]
+    async fn test_distinct_types() {
+        let db = customer_db().await;
+        let pool = db.pool();
+        let user = Uuid::nil();
+        let name = Uuid::new_v4().to_string();
+        // types are at most 16 characters, a random tag keeps them apart from other rows
+        let tag = &name[..8];
+        let ty = |suffix: &str| format!("{tag}-{suffix}");
+        let mut customers = vec![];
+        for (i, suffix) in ["b", "a", "c", "a"].into_iter().enumerate() {
+            let customer =
+                create_customer(pool, None, &format!("{name} {i}"), Some(&ty(suffix)), &user)
+                    .await
+                    .unwrap();
+            customers.push(customer.id);
+        }
+        let cid = customers[0];
+        for (i, suffix) in ["z", "y", "z"].into_iter().enumerate() {
+            let organization = create_organization(
+                pool,
+                None,
+                &format!("{name} {i}"),
+                Some(&ty(suffix)),
+                cid,
+                &user,
+            )
+            .await
+            .unwrap();
+            create_institution(
+                pool,
+                None,
+                &format!("{name} {i}"),
+                Some(&ty(suffix)),
+                cid,
+                organization.id,
+                &user,
+            )
+            .await
+            .unwrap();
+        }
+        let tagged = |types: Vec<Arc<str>>| {
+            assert!(types.windows(2).all(|w| w[0] < w[1]), "{types:?}");
+            types
+                .into_iter()
+                .filter(|ty| ty.starts_with(tag))
+                .map(|ty| ty.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let types = tagged(distinct_customer_types(&db).await.unwrap());
+        assert_eq!(types, vec![ty("a"), ty("b"), ty("c")]);
+        let types = tagged(distinct_organization_types(&db).await.unwrap());
+        assert_eq!(types, vec![ty("y"), ty("z")]);
+        let types = tagged(distinct_institution_types(&db).await.unwrap());
+        assert_eq!(types, vec![ty("y"), ty("z")]);
+
+        for id in customers {
+            remove_customer(pool, id).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a postgres database configured by PG_*"]
+    async fn test_institutions_by_ids() {
+        let db = customer_db().await;
+        let seeded = seed_institutions(db.pool(), 3).await;
+        let id = |i: usize| seeded.institutions[i].id;
+        let missing = InfraId::from(i64::MAX);
+
+        assert!(institutions_by_ids(&db, &[]).await.unwrap().is_empty());
+        assert!(institutions_by_ids(&db, &[missing])
+            .await
+            .unwrap()
+            .is_empty());
+        let institutions = institutions_by_ids(&db, &[id(2), missing, id(0), id(1)])
+            .await
+            .unwrap();
+        let ids: Vec<InfraId> = institutions.iter().map(|v| v.id).collect();
+        assert_eq!(ids, vec![id(2), id(0), id(1)]);
+        assert_eq!(institutions[0].name, seeded.institutions[2].name);
+
+        remove_customer(db.pool(), seeded.customer.id)
+            .await
+            .unwrap();
+    }
+}
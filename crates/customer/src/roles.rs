@@ -1,6 +1,16 @@
+use std::collections::BTreeSet;
+
+use qm_entity::ids::InfraContext;
+use qm_kafka::producer::EventNs;
+use qm_kafka::producer::MoveEvent;
+use qm_kafka::producer::Producer;
 use qm_keycloak::Keycloak;
 use qm_keycloak::KeycloakError;
 use qm_keycloak::RoleRepresentation;
+use qm_role::AccessLevel;
+
+use crate::cache::CacheDB;
+use crate::context::RelatedStorage;
 
 // pub const DEFAULT_COLLECTION: &str = "roles";
 
@@ -18,6 +28,13 @@ use qm_keycloak::RoleRepresentation;
 //     }
 // }
 
+/// Canonical access role name of the entity with `id` on the given level.
+pub fn access_string_for(level: AccessLevel, id: &impl std::fmt::Display) -> String {
+    qm_role::Access::new(level)
+        .with_fmt_id(Some(id))
+        .to_string()
+}
+
 pub async fn ensure(
     keycloak: &Keycloak,
     role_set: impl Iterator<Item = String>,
@@ -51,3 +68,393 @@ pub async fn ensure(
     }
     Ok(roles)
 }
+
+/// Creates the given roles in keycloak and adds them to the role cache.
+///
+/// Counterpart of [crate::cleanup::cleanup_roles], existing roles are reused.
+pub async fn provision_roles(
+    keycloak: &Keycloak,
+    cache_db: &CacheDB,
+    roles: BTreeSet<String>,
+) -> anyhow::Result<Vec<RoleRepresentation>> {
+    if roles.is_empty() {
+        return Ok(vec![]);
+    }
+    let roles = ensure(keycloak, roles.into_iter()).await?;
+    cache_db.user().new_roles(roles.clone()).await;
+    Ok(roles)
+}
+
+/// Access role name of the entity of the context.
+pub fn context_access_string(context: &InfraContext) -> String {
+    match context {
+        InfraContext::Customer(id) => access_string_for(AccessLevel::Customer, id),
+        InfraContext::Organization(id) => access_string_for(AccessLevel::Organization, id),
+        InfraContext::Institution(id) => access_string_for(AccessLevel::Institution, id),
+    }
+}
+
+fn owner_access_roles(from: &InfraContext, to: &InfraContext) -> anyhow::Result<(String, String)> {
+    if from.level() != to.level() {
+        anyhow::bail!("unable to rewrite owner '{from}' to '{to}' on a different level");
+    }
+    if from == to {
+        anyhow::bail!("owner '{from}' must not be rewritten to itself");
+    }
+    Ok((context_access_string(from), context_access_string(to)))
+}
+
+/// Moves all users with the access role of `from` to the access role of `to`, e.g. after the
+/// entity was restored with a new id. Returns the number of moved users.
+///
+/// `to` has to exist and be on the same level as `from`, a move event is emitted for each moved
+/// user.
+pub async fn rewrite_owner<Store>(
+    store: &Store,
+    from: InfraContext,
+    to: InfraContext,
+) -> anyhow::Result<u64>
+where
+    Store: RelatedStorage,
+{
+    rewrite_owner_with(
+        store.keycloak(),
+        store.cache_db(),
+        store.mutation_event_producer(),
+        from,
+        to,
+    )
+    .await
+}
+
+async fn rewrite_owner_with(
+    keycloak: &Keycloak,
+    cache_db: &CacheDB,
+    producer: Option<&Producer>,
+    from: InfraContext,
+    to: InfraContext,
+) -> anyhow::Result<u64> {
+    let (from_role, to_role) = owner_access_roles(&from, &to)?;
+    if !cache_db.context_exists(&to).await {
+        anyhow::bail!("new owner '{to}' does not exist");
+    }
+    let realm = keycloak.config().realm();
+    let users = match keycloak.all_role_members(realm, &from_role).await {
+        Ok(users) => users,
+        Err(KeycloakError::HttpFailure { status: 404, .. }) => return Ok(0),
+        Err(err) => Err(err)?,
+    };
+    if users.is_empty() {
+        return Ok(0);
+    }
+    let from_role = keycloak.realm_role_by_name(realm, &from_role).await?;
+    let to_role = provision_roles(keycloak, cache_db, BTreeSet::from([to_role]))
+        .await?
+        .pop()
+        .ok_or_else(|| anyhow::anyhow!("unable to provision access role of '{to}'"))?;
+    let (Some(from_role_id), Some(to_role_id)) = (from_role.id.clone(), to_role.id.clone()) else {
+        anyhow::bail!("access roles of '{from}' and '{to}' must have ids");
+    };
+    let mut rewritten = 0;
+    for user_id in users.into_iter().filter_map(|user| user.id) {
+        keycloak
+            .add_user_role(realm, &user_id, to_role.clone())
+            .await?;
+        if let Err(err) = keycloak
+            .remove_user_role(realm, &user_id, from_role.clone())
+            .await
+        {
+            // the user must not end up with both owners
+            if let Err(rollback_err) = keycloak
+                .remove_user_role(realm, &user_id, to_role.clone())
+                .await
+            {
+                tracing::error!(
+                    "unable to roll back the owner of user '{user_id}' to '{from}': {rollback_err:#}"
+                );
+            }
+            return Err(err.into());
+        }
+        cache_db
+            .user()
+            .replace_user_role(&user_id, &from_role_id, &to_role_id)
+            .await;
+        rewritten += 1;
+        if let Some(producer) = producer {
+            producer
+                .move_event(
+                    &EventNs::User,
+                    "user",
+                    "sys",
+                    MoveEvent {
+                        from: from.to_string(),
+                        to: to.to_string(),
+                        id: user_id,
+                    },
+                )
+                .await?;
+        }
+    }
+    Ok(rewritten)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::infra::InfraDB;
+    use crate::cache::user::UserDB;
+    use crate::model::QmUser;
+    use crate::testing::institution;
+    use qm_entity::ids::{CustomerId, InstitutionId, OrganizationId};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_access_string_for() {
+        let cid = CustomerId::from(1);
+        let oid = OrganizationId::from((1, 2));
+        let iid = InstitutionId::from((1, 2, 3));
+        assert_eq!(
+            access_string_for(AccessLevel::Customer, &cid),
+            qm_role::Access::new(AccessLevel::Customer)
+                .with_fmt_id(Some(&cid))
+                .to_string()
+        );
+        assert_eq!(
+            access_string_for(AccessLevel::Organization, &oid),
+            qm_role::Access::new(AccessLevel::Organization)
+                .with_fmt_id(Some(&oid))
+                .to_string()
+        );
+        assert_eq!(
+            access_string_for(AccessLevel::Institution, &iid),
+            qm_role::Access::new(AccessLevel::Institution)
+                .with_fmt_id(Some(&iid))
+                .to_string()
+        );
+        assert_eq!(
+            access_string_for(AccessLevel::Customer, &cid),
+            format!("customer:access@{cid}")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rewrite_owner_validation() {
+        let from = InfraContext::Institution((1, 2, 3).into());
+        let to = InfraContext::Institution((1, 2, 4).into());
+        let (from_role, to_role) = owner_access_roles(&from, &to).unwrap();
+        assert_eq!(
+            from_role,
+            access_string_for(AccessLevel::Institution, &InstitutionId::from((1, 2, 3)))
+        );
+        assert_eq!(
+            to_role,
+            access_string_for(AccessLevel::Institution, &InstitutionId::from((1, 2, 4)))
+        );
+        assert!(owner_access_roles(&from, &from).is_err());
+        assert!(owner_access_roles(&from, &InfraContext::Organization((1, 2).into())).is_err());
+
+        let infra = InfraDB::default();
+        infra.new_institution(Arc::new(institution(1, 2, 4))).await;
+        let cache = CacheDB::from_parts(infra, UserDB::default());
+        assert!(cache.context_exists(&to).await);
+        assert!(!cache.context_exists(&from).await);
+        assert!(
+            !cache
+                .context_exists(&InfraContext::Institution((1, 5, 4).into()))
+                .await
+        );
+    }
+    fn owner_keycloak(
+        from_role: String,
+        to_role: String,
+        failing_user: Option<&'static str>,
+    ) -> impl FnMut(&str, &str) -> Option<String> + Send + 'static {
+        move |route, body| {
+            let route = route.replace("%3A", ":").replace("%40", "@");
+            let users = format!("GET /admin/realms/rmp/roles/{from_role}/users?");
+            if route.starts_with(&users) {
+                return Some(if route.ends_with("first=0&max=1000") {
+                    r#"[{"id":"u1"},{"id":"u2"}]"#.to_string()
+                } else {
+                    "[]".to_string()
+                });
+            }
+            if route == format!("GET /admin/realms/rmp/roles/{from_role}") {
+                return Some(serde_json::json!({ "id": "r1", "name": from_role }).to_string());
+            }
+            if route == format!("GET /admin/realms/rmp/roles/{to_role}") {
+                return Some(serde_json::json!({ "id": "r2", "name": to_role }).to_string());
+            }
+            if let Some(user) = route
+                .strip_prefix("DELETE /admin/realms/rmp/users/")
+                .and_then(|r| r.strip_suffix("/role-mappings/realm"))
+            {
+                if Some(user) == failing_user && body.contains(r#""id":"r1""#) {
+                    return None;
+                }
+            }
+            Some(String::new())
+        }
+    }
+
+    async fn owner_cache(from_role: &str) -> CacheDB {
+        let infra = InfraDB::default();
+        infra.new_institution(Arc::new(institution(1, 2, 4))).await;
+        let cache = CacheDB::from_parts(infra, UserDB::default());
+        cache
+            .user()
+            .new_roles(vec![RoleRepresentation {
+                id: Some("r1".to_string()),
+                name: Some(from_role.to_string()),
+                ..RoleRepresentation::default()
+            }])
+            .await;
+        for id in ["u1", "u2"] {
+            cache
+                .user()
+                .new_user(Arc::new(QmUser {
+                    id: Arc::from(id),
+                    username: Arc::from(id),
+                    email: Arc::from(format!("{id}@test.local")),
+                    firstname: Arc::from(id),
+                    lastname: Arc::from(id),
+                    enabled: true,
+                }))
+                .await;
+            cache
+                .user()
+                .user_roles
+                .write()
+                .await
+                .insert(Arc::from(id), Arc::from("r1"));
+        }
+        cache
+    }
+
+    #[tokio::test]
+    async fn test_rewrite_owner() {
+        let from = InfraContext::Institution((1, 2, 3).into());
+        let to = InfraContext::Institution((1, 2, 4).into());
+        let (from_role, to_role) = owner_access_roles(&from, &to).unwrap();
+        let requests = Arc::new(std::sync::Mutex::new(vec![]));
+        let recorded = requests.clone();
+        let mut handler = owner_keycloak(from_role.clone(), to_role.clone(), None);
+        let keycloak = qm_keycloak::testing::mock_keycloak_with(move |route, body| {
+            recorded.lock().unwrap().push(route.to_string());
+            handler(route, body)
+        })
+        .await;
+        let cache = owner_cache(&from_role).await;
+
+        let rewritten = rewrite_owner_with(&keycloak, &cache, None, from, to)
+            .await
+            .unwrap();
+        assert_eq!(rewritten, 2);
+        for id in ["u1", "u2"] {
+            let details = cache.user_details_by_id(id).await.unwrap();
+            assert_eq!(details.context, Some(to));
+            assert_eq!(
+                details.access.map(|access| access.to_string()),
+                Some(to_role.clone())
+            );
+        }
+        let requests = requests.lock().unwrap().clone();
+        for id in ["u1", "u2"] {
+            let mapping = format!("/admin/realms/rmp/users/{id}/role-mappings/realm");
+            assert!(requests.contains(&format!("POST {mapping}")));
+            assert!(requests.contains(&format!("DELETE {mapping}")));
+        }
+
+        let keycloak = qm_keycloak::testing::mock_keycloak_with(owner_keycloak(
+            from_role.clone(),
+            to_role,
+            None,
+        ))
+        .await;
+        assert!(rewrite_owner_with(&keycloak, &cache, None, from, from)
+            .await
+            .is_err());
+        let missing = InfraContext::Institution((1, 2, 5).into());
+        assert!(rewrite_owner_with(&keycloak, &cache, None, from, missing)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rewrite_owner_rollback() {
+        let from = InfraContext::Institution((1, 2, 3).into());
+        let to = InfraContext::Institution((1, 2, 4).into());
+        let (from_role, to_role) = owner_access_roles(&from, &to).unwrap();
+        let requests = Arc::new(std::sync::Mutex::new(vec![]));
+        let recorded = requests.clone();
+        let mut handler = owner_keycloak(from_role.clone(), to_role, Some("u2"));
+        let keycloak = qm_keycloak::testing::mock_keycloak_with(move |route, body| {
+            recorded.lock().unwrap().push(format!("{route} {body}"));
+            handler(route, body)
+        })
+        .await;
+        let cache = owner_cache(&from_role).await;
+
+        assert!(rewrite_owner_with(&keycloak, &cache, None, from, to)
+            .await
+            .is_err());
+        // the new access role of u2 is removed again after the old one could not be removed
+        let requests = requests.lock().unwrap().clone();
+        assert!(requests.iter().any(|r| {
+            r.starts_with("DELETE /admin/realms/rmp/users/u2/role-mappings/realm ")
+                && r.contains(r#""id":"r2""#)
+        }));
+        assert_eq!(
+            cache.user_details_by_id("u1").await.unwrap().context,
+            Some(to)
+        );
+        assert_eq!(
+            cache.user_details_by_id("u2").await.unwrap().context,
+            Some(from)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_provision_roles() {
+        let role = context_access_string(&InfraContext::Institution((1, 2, 3).into()));
+        let role_route = format!("GET /admin/realms/rmp/roles/{role}");
+        let representation = serde_json::json!({ "id": "r1", "name": role }).to_string();
+        let requests = Arc::new(std::sync::Mutex::new(vec![]));
+        let recorded = requests.clone();
+        let keycloak = qm_keycloak::testing::mock_keycloak_with(move |route, body| {
+            recorded.lock().unwrap().push(format!("{route} {body}"));
+            match route {
+                "POST /admin/realms/rmp/roles" => Some(String::new()),
+                route if route == role_route => Some(representation.clone()),
+                _ => None,
+            }
+        })
+        .await;
+        let cache = CacheDB::from_parts(InfraDB::default(), UserDB::default());
+
+        let roles = provision_roles(&keycloak, &cache, BTreeSet::new())
+            .await
+            .unwrap();
+        assert!(roles.is_empty());
+        assert!(requests.lock().unwrap().is_empty());
+
+        let roles = provision_roles(&keycloak, &cache, BTreeSet::from([role.clone()]))
+            .await
+            .unwrap();
+        assert_eq!(roles.len(), 1);
+        assert_eq!(roles[0].id.as_deref(), Some("r1"));
+        let requests = requests.lock().unwrap().clone();
+        let created = requests
+            .iter()
+            .find_map(|r| r.strip_prefix("POST /admin/realms/rmp/roles "))
+            .unwrap();
+        let created: serde_json::Value = serde_json::from_str(created).unwrap();
+        assert_eq!(created["name"], role.as_str());
+        let cached = cache.role_by_name(&role).await.unwrap();
+        assert_eq!(cached.id.as_ref(), "r1");
+        assert_eq!(
+            cached.context,
+            Some(InfraContext::Institution((1, 2, 3).into()))
+        );
+    }
+}
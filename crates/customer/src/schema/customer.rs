@@ -1,3 +1,4 @@
+use std::collections::BTreeSet;
 use std::sync::Arc;
 
 use async_graphql::{Context, Object, ResultExt};
@@ -13,8 +14,9 @@ use qm_entity::ids::InfraId;
 use qm_entity::model::ListFilter;
 use qm_mongodb::bson::doc;
 use qm_role::AccessLevel;
-use sqlx::types::Uuid;
 
+use crate::cache::CacheDB;
+use crate::cleanup::max_ids_per_task;
 use crate::cleanup::CleanupTask;
 use crate::cleanup::CleanupTaskType;
 use crate::context::RelatedStorage;
@@ -26,6 +28,7 @@ use crate::model::QmCreateCustomerInput;
 use crate::model::QmCustomer;
 use crate::model::QmCustomerList;
 use crate::model::QmUpdateCustomerInput;
+use crate::model::QmUserDetails;
 use crate::mutation::remove_customers;
 use crate::mutation::update_customer;
 use crate::roles;
@@ -37,6 +40,26 @@ impl QmCustomer {
     async fn id(&self) -> async_graphql::FieldResult<CustomerId> {
         Ok(self.into())
     }
+
+    async fn created_by_user(&self, ctx: &Context<'_>) -> Option<QmUserDetails> {
+        let cache = ctx.data::<CacheDB>().ok();
+        if cache.is_none() {
+            tracing::warn!("qm::customer::cache::CacheDB is not installed in schema context");
+            return None;
+        }
+        let cache = cache.unwrap();
+        cache.user_details_by_uuid(&self.created_by).await
+    }
+
+    async fn updated_by_user(&self, ctx: &Context<'_>) -> Option<QmUserDetails> {
+        let cache = ctx.data::<CacheDB>().ok();
+        if cache.is_none() {
+            tracing::warn!("qm::customer::cache::CacheDB is not installed in schema context");
+            return None;
+        }
+        let cache = cache.unwrap();
+        cache.user_details_by_uuid(self.updated_by.as_ref()?).await
+    }
 }
 
 pub struct Ctx<'a, Auth, Store, Resource, Permission>(
@@ -86,12 +109,13 @@ where
                     )
                     .await?;
                     let id: CustomerId = (&result).into();
-                    let access = qm_role::Access::new(AccessLevel::Customer)
-                        .with_fmt_id(Some(&id))
-                        .to_string();
-                    let roles =
-                        roles::ensure(self.0.store.keycloak(), Some(access).into_iter()).await?;
-                    self.0.store.cache_db().user().new_roles(roles).await;
+                    let access = roles::access_string_for(AccessLevel::Customer, &id);
+                    roles::provision_roles(
+                        self.0.store.keycloak(),
+                        self.0.store.cache_db(),
+                        BTreeSet::from([access]),
+                    )
+                    .await?;
                     if let Some(producer) = self.0.store.mutation_event_producer() {
                         producer
                             .create_event(
@@ -146,16 +170,15 @@ where
         let v: Vec<i64> = ids.iter().map(CustomerId::unzip).collect();
         let delete_count = remove_customers(self.0.store.customer_db().pool(), &v).await?;
         if delete_count != 0 {
-            let id = Uuid::new_v4();
-            self.0
-                .store
-                .cleanup_task_producer()
-                .add_item(&CleanupTask {
-                    id,
-                    ty: CleanupTaskType::Customers(ids),
-                })
-                .await?;
-            tracing::debug!("emit cleanup task {}", id.to_string());
+            let tasks = CleanupTask::new(CleanupTaskType::Customers(ids)).split(max_ids_per_task());
+            for task in tasks {
+                self.0
+                    .store
+                    .cleanup_task_producer()
+                    .produce_confirmed(&task)
+                    .await?;
+                tracing::debug!("emit cleanup task {}", task.id.to_string());
+            }
             return Ok(delete_count);
         }
         Ok(0)
@@ -297,3 +320,59 @@ where
         .extend()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::infra::InfraDB;
+    use crate::cache::user::UserDB;
+    use crate::model::QmUser;
+    use crate::testing::customer;
+    use async_graphql::{EmptyMutation, EmptySubscription, Schema};
+    use sqlx::types::Uuid;
+
+    struct Query(QmCustomer);
+
+    #[Object]
+    impl Query {
+        async fn customer(&self) -> &QmCustomer {
+            &self.0
+        }
+    }
+
+    #[tokio::test]
+    async fn test_created_by_user() {
+        let created_by = Uuid::from_u128(1);
+        let user = UserDB::default();
+        user.users.write().await.new_user(Arc::new(QmUser {
+            id: Arc::from(created_by.to_string()),
+            username: Arc::from("alice"),
+            email: Arc::from("alice@example.com"),
+            firstname: Arc::from("Alice"),
+            lastname: Arc::from("Doe"),
+            enabled: true,
+        }));
+        let cache = CacheDB::from_parts(InfraDB::default(), user);
+        let customer = QmCustomer {
+            created_by,
+            updated_by: Some(Uuid::from_u128(2)),
+            ..customer(1)
+        };
+        let schema = Schema::build(Query(customer), EmptyMutation, EmptySubscription)
+            .data(cache)
+            .finish();
+        let response = schema
+            .execute("{ customer { createdByUser { username } updatedByUser { username } } }")
+            .await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        assert_eq!(
+            response.data.into_json().unwrap(),
+            serde_json::json!({
+                "customer": {
+                    "createdByUser": { "username": "alice" },
+                    "updatedByUser": null,
+                }
+            })
+        );
+    }
+}
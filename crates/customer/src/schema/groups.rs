@@ -15,7 +15,7 @@ use crate::query::fetch_group_by_id;
 use crate::schema::auth::AuthGuard;
 use sqlx::types::Uuid;
 
-use crate::groups::RelatedBuiltInGroup;
+use crate::groups::{GroupPath, RelatedBuiltInGroup};
 use crate::marker::Marker;
 use crate::model::{Group, GroupDetail, Role, UserGroup};
 use qm_role::AccessLevel;
@@ -157,7 +157,7 @@ impl Groups {
         context: InfraContext,
     ) -> FieldResult<Vec<UserGroup>> {
         let cache = ctx.data_unchecked::<CacheDB>();
-        let parent = format!("custom@{context}");
+        let parent = GroupPath::parent_name(&context);
         let groups = cache.groups_by_parent(&parent).await;
         Ok(futures::stream::iter(groups)
             .filter_map(|g| async move {
@@ -194,10 +194,7 @@ where
         allowed_types: HashSet<String>,
         roles: HashSet<qm_role::Role<Resource, Permission>>,
     ) -> async_graphql::FieldResult<Arc<UserGroup>> {
-        let path = format!(
-            "/custom@{context}/{}",
-            inflector::cases::snakecase::to_snake_case(name.replace('/', "").trim())
-        );
+        let path = GroupPath::new(context, &name).to_string();
         if self
             .0
             .store
@@ -1,3 +1,4 @@
+use std::collections::BTreeSet;
 use std::sync::Arc;
 
 use async_graphql::ResultExt;
@@ -13,11 +14,10 @@ use qm_entity::model::ListFilter;
 use qm_entity::{err, exerr};
 use qm_mongodb::bson::doc;
 use qm_role::AccessLevel;
-use sqlx::types::Uuid;
 
 use crate::cache::CacheDB;
 
-use crate::cleanup::{CleanupTask, CleanupTaskType};
+use crate::cleanup::{max_ids_per_task, CleanupTask, CleanupTaskType};
 use crate::context::RelatedAuth;
 use crate::context::RelatedPermission;
 use crate::context::RelatedResource;
@@ -136,12 +136,13 @@ where
                     )
                     .await?;
                     let id: InstitutionId = (&result).into();
-                    let access = qm_role::Access::new(AccessLevel::Institution)
-                        .with_fmt_id(Some(&id))
-                        .to_string();
-                    let roles =
-                        roles::ensure(self.0.store.keycloak(), Some(access).into_iter()).await?;
-                    self.0.store.cache_db().user().new_roles(roles).await;
+                    let access = roles::access_string_for(AccessLevel::Institution, &id);
+                    roles::provision_roles(
+                        self.0.store.keycloak(),
+                        self.0.store.cache_db(),
+                        BTreeSet::from([access]),
+                    )
+                    .await?;
                     if let Some(producer) = self.0.store.mutation_event_producer() {
                         producer
                             .create_event(
@@ -197,16 +198,16 @@ where
         let v: Vec<i64> = ids.iter().map(InstitutionId::id).collect();
         let delete_count = remove_institutions(self.0.store.customer_db().pool(), &v).await?;
         if delete_count != 0 {
-            let id = Uuid::new_v4();
-            self.0
-                .store
-                .cleanup_task_producer()
-                .add_item(&CleanupTask {
-                    id,
-                    ty: CleanupTaskType::Institutions(ids),
-                })
-                .await?;
-            tracing::debug!("emit cleanup task {}", id.to_string());
+            let tasks =
+                CleanupTask::new(CleanupTaskType::Institutions(ids)).split(max_ids_per_task());
+            for task in tasks {
+                self.0
+                    .store
+                    .cleanup_task_producer()
+                    .produce_confirmed(&task)
+                    .await?;
+                tracing::debug!("emit cleanup task {}", task.id.to_string());
+            }
             return Ok(delete_count);
         }
         Ok(0)
@@ -1,3 +1,4 @@
+use std::collections::BTreeSet;
 use std::sync::Arc;
 
 use async_graphql::ComplexObject;
@@ -18,10 +19,10 @@ use qm_entity::err;
 use qm_entity::model::ListFilter;
 use qm_mongodb::bson::doc;
 use qm_role::AccessLevel;
-use sqlx::types::Uuid;
 
 use crate::cache::CacheDB;
 
+use crate::cleanup::max_ids_per_task;
 use crate::cleanup::CleanupTask;
 use crate::cleanup::CleanupTaskType;
 use crate::context::RelatedAuth;
@@ -38,6 +39,7 @@ use crate::model::QmOrganizationList;
 use crate::model::UpdateOrganizationInput;
 use crate::mutation::remove_organizations;
 use crate::mutation::update_organization;
+use crate::query::fetch_organization_unit_ids_with_members_of;
 use crate::roles;
 use crate::schema::auth::AuthCtx;
 
@@ -132,12 +134,13 @@ where
                     )
                     .await?;
                     let id: OrganizationId = (&result).into();
-                    let access = qm_role::Access::new(AccessLevel::Organization)
-                        .with_fmt_id(Some(&id))
-                        .to_string();
-                    let roles =
-                        roles::ensure(self.0.store.keycloak(), Some(access).into_iter()).await?;
-                    self.0.store.cache_db().user().new_roles(roles).await;
+                    let access = roles::access_string_for(AccessLevel::Organization, &id);
+                    roles::provision_roles(
+                        self.0.store.keycloak(),
+                        self.0.store.cache_db(),
+                        BTreeSet::from([access]),
+                    )
+                    .await?;
                     if let Some(producer) = self.0.store.mutation_event_producer() {
                         producer
                             .create_event(
@@ -197,18 +200,22 @@ where
 
     pub async fn remove(&self, ids: OrganizationIds) -> EntityResult<u64> {
         let v: Vec<i64> = ids.iter().map(OrganizationId::id).collect();
+        // the memberships cascade away with the organizations
+        let unit_ids =
+            fetch_organization_unit_ids_with_members_of(self.0.store.customer_db(), &v).await?;
         let delete_count = remove_organizations(self.0.store.customer_db().pool(), &v).await?;
         if delete_count != 0 {
-            let id = Uuid::new_v4();
-            self.0
-                .store
-                .cleanup_task_producer()
-                .add_item(&CleanupTask {
-                    id,
-                    ty: CleanupTaskType::Organizations(ids),
-                })
-                .await?;
-            tracing::debug!("emit cleanup task {}", id.to_string());
+            let tasks = CleanupTask::new(CleanupTaskType::Organizations(ids))
+                .with_organization_unit_ids(unit_ids)
+                .split(max_ids_per_task());
+            for task in tasks {
+                self.0
+                    .store
+                    .cleanup_task_producer()
+                    .produce_confirmed(&task)
+                    .await?;
+                tracing::debug!("emit cleanup task {}", task.id.to_string());
+            }
             return Ok(delete_count);
         }
         Ok(0)
@@ -15,6 +15,7 @@ use crate::config::SchemaConfig;
 use crate::groups::RelatedBuiltInGroup;
 use crate::marker::Marker;
 use crate::model::QmUser;
+use crate::model::QmUserEvent;
 use crate::model::QmUserList;
 use crate::model::{CreateUserPayload, QmInstitution, QmOrganization, QmUserDetails};
 use crate::model::{Group, QmRequiredUserAction, Role, UserGroup};
@@ -22,7 +23,7 @@ use crate::model::{QmCreateUserInput, QmCustomer};
 use qm_entity::err;
 use qm_entity::error::EntityError;
 use qm_entity::error::EntityResult;
-use qm_keycloak::CredentialRepresentation;
+use qm_keycloak::password_credential;
 use qm_keycloak::Keycloak;
 use qm_keycloak::KeycloakError;
 use qm_keycloak::UserRepresentation;
@@ -120,21 +121,12 @@ pub async fn create_keycloak_user(
     );
 
     // Set the credential
-    keycloak_user.credentials = Some(vec![CredentialRepresentation {
-        created_date: None,
-        credential_data: None,
-        id: None,
-        priority: None,
-        secret_data: None,
-        temporary: user
-            .required_actions
+    keycloak_user.credentials = Some(vec![password_credential(
+        &user.password,
+        user.required_actions
             .as_ref()
-            .map(|actions| actions.contains(&QmRequiredUserAction::UpdatePassword)),
-        type_: Some("password".to_string()),
-        user_label: None,
-        value: Some(user.password),
-        ..Default::default()
-    }]);
+            .is_some_and(|actions| actions.contains(&QmRequiredUserAction::UpdatePassword)),
+    )]);
 
     let result = keycloak.create_user(realm, keycloak_user).await;
     let exists = match result {
@@ -308,7 +300,7 @@ where
             user: mut user_input,
             access,
             group_id,
-            context: _,
+            context,
         } = input;
         let mut conflict_fields = Vec::new();
         let user_exists_by_username = self
@@ -406,23 +398,63 @@ where
             enabled: user_input.enabled.unwrap(),
         });
         cache.user().new_user(user.clone()).await;
+        // the user exists at this point, a failed event must not fail the mutation
+        if let Some(producer) = self.0.store.mutation_event_producer() {
+            if let Err(err) = producer
+                .create_event(
+                    &qm_kafka::producer::EventNs::User,
+                    "user",
+                    "sys",
+                    QmUserEvent::new(vec![user.id.to_string()], context.as_ref()),
+                )
+                .await
+            {
+                tracing::error!(
+                    "unable to emit create event for user '{}': {err:#}",
+                    user.id
+                );
+            }
+        }
         Ok(user)
     }
 
     pub async fn remove(&self, ids: Arc<[Arc<str>]>) -> EntityResult<u64> {
         let keycloak = self.0.store.keycloak();
+        let cache = self.0.store.cache_db();
         let mut user_ids = Vec::default();
+        let mut removed = vec![];
         for id in ids.iter() {
+            let context = cache
+                .user_details_by_id(id.as_ref())
+                .await
+                .and_then(|u| u.context);
             match keycloak
                 .remove_user(keycloak.config().realm(), id.as_ref())
                 .await
             {
-                Ok(_) => user_ids.push(id.as_ref()),
+                Ok(_) => {
+                    user_ids.push(id.as_ref());
+                    removed.push((id.to_string(), context));
+                }
                 Err(err) => {
                     tracing::error!("{err:#?}");
                 }
             }
         }
+        // the users are removed at this point, a failed event must not fail the mutation
+        if let Some(producer) = self.0.store.mutation_event_producer() {
+            for event in QmUserEvent::by_context(removed) {
+                if let Err(err) = producer
+                    .delete_event(&qm_kafka::producer::EventNs::User, "user", "sys", &event)
+                    .await
+                {
+                    tracing::error!(
+                        "unable to emit delete event for users {:?}: {err:#}",
+                        event.ids
+                    );
+                }
+            }
+        }
         if !user_ids.is_empty() {
             return Ok(user_ids.len() as u64);
         }
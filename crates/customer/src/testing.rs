@@ -0,0 +1,111 @@
+//! Helpers shared by the tests of this crate.
+
+use crate::model::InstitutionData;
+use crate::model::QmCustomer;
+use crate::model::QmInstitution;
+use crate::model::QmOrganization;
+use crate::model::QmOrganizationUnit;
+use crate::mutation::{create_customer, create_institution_with_unit, create_organization};
+use qm_entity::ids::OrganizationId;
+use sqlx::types::time::PrimitiveDateTime;
+use sqlx::types::Uuid;
+use sqlx::PgPool;
+use std::sync::Arc;
+
+/// Customer database configured by the `PG_*` environment variables with the customer
+/// migrations applied. Tests using it are ignored by default as they need a running Postgres.
+pub async fn customer_db() -> qm_pg::DB {
+    let cfg = qm_pg::DbConfig::new().unwrap();
+    let db = qm_pg::DB::new("qm-customer-test", &cfg).await.unwrap();
+    let mut migrator = sqlx::migrate!("./migrations/customer");
+    migrator.set_ignore_missing(true);
+    migrator.run(db.pool()).await.unwrap();
+    db
+}
+
+/// Mongo database configured by the `MONGODB_*` environment variables. Tests using it are
+/// ignored by default as they need a running MongoDB.
+pub async fn mongo_db() -> qm_mongodb::DB {
+    let cfg = qm_mongodb::DbConfig::new().unwrap();
+    qm_mongodb::DB::new("qm-customer-test", &cfg).await.unwrap()
+}
+
+/// Rows created by [seed_institutions].
+pub struct Seeded {
+    pub customer: QmCustomer,
+    pub institutions: Vec<QmInstitution>,
+    /// Organization unit of each institution, in the order of `institutions`.
+    pub units: Vec<QmOrganizationUnit>,
+}
+
+/// Creates a customer with a unique name, one organization and `count` institutions, each
+/// with an organization unit that has the institution as its only member.
+pub async fn seed_institutions(pool: &PgPool, count: usize) -> Seeded {
+    let user = Uuid::nil();
+    let name = Uuid::new_v4().to_string();
+    let customer = create_customer(pool, None, &name, None, &user)
+        .await
+        .unwrap();
+    let organization = create_organization(pool, None, &name, None, customer.id, &user)
+        .await
+        .unwrap();
+    let organization_id: OrganizationId = (*customer.id, *organization.id).into();
+    let mut institutions = vec![];
+    let mut units = vec![];
+    for i in 0..count {
+        let data = InstitutionData(organization_id, format!("{name} {i}"), None, None);
+        let (institution, unit) =
+            create_institution_with_unit(pool, data, &format!("{name} {i}"), &user)
+                .await
+                .unwrap();
+        institutions.push(institution);
+        units.push(unit);
+    }
+    Seeded {
+        customer,
+        institutions,
+        units,
+    }
+}
+
+/// Customer `id` named `customer {id}`, created by the nil user and never updated.
+pub fn customer(id: i64) -> QmCustomer {
+    QmCustomer {
+        id: id.into(),
+        name: Arc::from(format!("customer {id}")),
+        ty: Arc::from("none"),
+        created_by: Uuid::nil(),
+        created_at: PrimitiveDateTime::MIN,
+        updated_by: None,
+        updated_at: None,
+    }
+}
+
+/// Organization `oid` of customer `cid` named `organization {oid}`.
+pub fn organization(cid: i64, oid: i64) -> QmOrganization {
+    QmOrganization {
+        id: oid.into(),
+        customer_id: cid.into(),
+        name: Arc::from(format!("organization {oid}")),
+        ty: Arc::from("none"),
+        created_by: Uuid::nil(),
+        created_at: PrimitiveDateTime::MIN,
+        updated_by: None,
+        updated_at: None,
+    }
+}
+
+/// Institution `iid` of organization `oid` and customer `cid` named `institution {iid}`.
+pub fn institution(cid: i64, oid: i64, iid: i64) -> QmInstitution {
+    QmInstitution {
+        id: iid.into(),
+        customer_id: cid.into(),
+        organization_id: oid.into(),
+        name: Arc::from(format!("institution {iid}")),
+        ty: Arc::from("none"),
+        created_by: Uuid::nil(),
+        created_at: PrimitiveDateTime::MIN,
+        updated_by: None,
+        updated_at: None,
+    }
+}
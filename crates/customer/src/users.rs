@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+
+use futures::TryStreamExt as _;
+use qm_entity::ids::{Owner, OwnerId};
+use qm_mongodb::bson::{doc, to_bson, Document};
+use qm_mongodb::DB;
+use serde::Deserialize;
+
+/// Mongo collection of the user documents, each stores its [Owner] in `owner`.
+pub const USERS_COLLECTION: &str = "users";
+
+#[derive(Debug, Deserialize)]
+struct OwnerCount {
+    #[serde(rename = "_id")]
+    owner: OwnerId,
+    count: i64,
+}
+
+/// Counts the users of each owner without loading them, owners without users are counted as
+/// zero.
+///
+/// The counts are keyed by the id of the owner, an owner of type `None` is keyed by
+/// [OwnerId::default] and counts the users without owner. Owners are matched exactly, a
+/// customer does not include the users of its organizations or institutions.
+pub async fn user_counts_by_owner<Store>(
+    store: &Store,
+    owners: &[Owner],
+) -> anyhow::Result<HashMap<OwnerId, u64>>
+where
+    Store: AsRef<DB>,
+{
+    let mut counts: HashMap<OwnerId, u64> = owners
+        .iter()
+        .map(|owner| (owner.as_owner_id().copied().unwrap_or_default(), 0))
+        .collect();
+    if owners.is_empty() {
+        return Ok(counts);
+    }
+    let ids: Vec<&OwnerId> = owners.iter().filter_map(Owner::as_owner_id).collect();
+    let mut matches = vec![doc! { "owner.id": { "$in": to_bson(&ids)? } }];
+    if ids.len() < owners.len() {
+        matches.push(doc! { "owner.id": { "$exists": false } });
+    }
+    let result = store
+        .as_ref()
+        .get()
+        .collection::<Document>(USERS_COLLECTION)
+        .aggregate([
+            doc! { "$match": { "$or": matches } },
+            doc! { "$group": {
+                "_id": { "$ifNull": ["$owner.id", {}] },
+                "count": { "$sum": 1 },
+            } },
+        ])
+        .with_type::<OwnerCount>()
+        .await?
+        .try_collect::<Vec<_>>()
+        .await?;
+    for OwnerCount { owner, count } in result {
+        counts.insert(owner, count.max(0) as u64);
+    }
+    Ok(counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::mongo_db;
+    use qm_entity::ids::{CustomerId, InfraContext, InstitutionId, OrganizationId};
+    use qm_mongodb::bson::oid::ObjectId;
+    use sqlx::types::Uuid;
+
+    struct Store(DB);
+
+    impl AsRef<DB> for Store {
+        fn as_ref(&self) -> &DB {
+            &self.0
+        }
+    }
+
+    fn user(owner: &Owner) -> Document {
+        doc! { "_id": ObjectId::new(), "owner": to_bson(owner).unwrap() }
+    }
+
+    /// The counts match and group on `owner.id`, the field the owner id is serialized to.
+    #[test]
+    fn test_owner_id_field() {
+        let owner = Owner::from(InfraContext::Institution(InstitutionId::from((1, 2, 3))));
+        let owner = to_bson(&owner).unwrap();
+        assert_eq!(
+            owner.as_document().unwrap().get_document("id").unwrap(),
+            &doc! { "cid": 1_i64, "oid": 2_i64, "iid": 3_i64 }
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a mongodb server configured by MONGODB_*"]
+    async fn test_user_counts_by_owner() {
+        let store = Store(mongo_db().await);
+        let users = store.0.get().collection::<Document>(USERS_COLLECTION);
+        // a random customer keeps the seeded users apart from other documents
+        let cid = (Uuid::new_v4().as_u128() % 1_000_000_000) as i64 + 1_000_000_000;
+        let customer = Owner::from(InfraContext::Customer(CustomerId::from(cid)));
+        let organization = Owner::from(InfraContext::Organization(OrganizationId::from((cid, 1))));
+        let a = Owner::from(InfraContext::Institution(InstitutionId::from((cid, 1, 1))));
+        let b = Owner::from(InfraContext::Institution(InstitutionId::from((cid, 1, 2))));
+        let none = Owner::default();
+        let seeded = [user(&a), user(&a), user(&b), user(&none)];
+        users.insert_many(&seeded).await.unwrap();
+
+        let counts = user_counts_by_owner(&store, &[a.clone(), b.clone(), customer.clone()])
+            .await
+            .unwrap();
+        assert_eq!(counts.len(), 3);
+        assert_eq!(counts[a.as_owner_id().unwrap()], 2);
+        assert_eq!(counts[b.as_owner_id().unwrap()], 1);
+        assert_eq!(counts[customer.as_owner_id().unwrap()], 0);
+
+        let counts = user_counts_by_owner(&store, &[organization.clone(), none])
+            .await
+            .unwrap();
+        assert_eq!(counts[organization.as_owner_id().unwrap()], 0);
+        assert!(counts[&OwnerId::default()] >= 1);
+        assert!(user_counts_by_owner(&store, &[]).await.unwrap().is_empty());
+
+        let ids: Vec<_> = seeded.iter().map(|user| user.get("_id").unwrap()).collect();
+        users
+            .delete_many(doc! { "_id": { "$in": ids } })
+            .await
+            .unwrap();
+    }
+}
@@ -1,15 +1,34 @@
+use crate::cache::user::roles::access_id;
+use crate::cache::user::roles::parse_context;
+use crate::cleanup::apply_empty_unit_policy;
 use crate::cleanup::cleanup_api_clients;
 use crate::cleanup::cleanup_roles;
+use crate::cleanup::organization_owner_query;
 use crate::cleanup::CleanupTaskType;
+use crate::cleanup::EmptyUnitPolicy;
+use crate::cleanup::RoleCleanupBatch;
+use crate::cleanup::UserCleanupMode;
+use crate::cleanup::ROLE_CLEANUP_BATCH_SIZE;
+use crate::cleanup::USER_DISABLED_REASON_ATTRIBUTE;
+use crate::cleanup::USER_DISABLED_REASON_CLEANUP;
+use crate::context::InMemoryCache;
 use crate::context::RelatedAuth;
 use crate::context::RelatedPermission;
 use crate::context::RelatedResource;
 use crate::context::RelatedStorage;
 use crate::marker::Marker;
+use crate::model::QmUserEvent;
+use crate::model::Role;
+use crate::roles::access_string_for;
+use crate::users::USERS_COLLECTION;
 
+use std::collections::BTreeMap;
 use std::collections::BTreeSet;
+use std::collections::HashMap;
 use std::sync::Arc;
 
+use prometheus_client::metrics::counter::Counter;
+
 use crate::cleanup::CleanupTask;
 use qm_entity::ids::CustomerId;
 use qm_entity::ids::CustomerIds;
@@ -31,8 +50,10 @@ use qm_mongodb::DB;
 use qm_role::AccessLevel;
 use sqlx::types::Uuid;
 
+use qm_redis::redis::AsyncCommands;
 use qm_redis::AsyncWorker;
 pub use qm_redis::Producer;
+use qm_redis::Redis;
 use qm_redis::Work;
 use qm_redis::WorkerContext;
 use qm_redis::Workers;
@@ -43,6 +64,120 @@ lazy_static::lazy_static! {
     };
 }
 
+/// Key of the redis marker of a cancelled cleanup task.
+fn cancelled_task_key(task_id: Uuid) -> String {
+    format!("{}:cancelled:{task_id}", PREFIX.as_str())
+}
+
+/// Time after which a cancellation expires, so the markers of tasks which are never run do
+/// not pile up in redis.
+const CANCELLED_TASK_TTL_SECS: u64 = 7 * 24 * 60 * 60;
+
+/// Requests the cancellation of the cleanup task with `task_id`.
+///
+/// The worker stops after the collection it currently cleans up and skips the remaining
+/// collections, api clients and roles. Data which was already deleted is not restored.
+/// The request is dropped once the task finishes, or after seven days if the task is never
+/// run.
+pub async fn cancel_cleanup(redis: &Redis, task_id: Uuid) -> anyhow::Result<()> {
+    let mut con = redis.connect().await?;
+    con.set_ex::<_, _, ()>(cancelled_task_key(task_id), 1, CANCELLED_TASK_TTL_SECS)
+        .await?;
+    Ok(())
+}
+
+/// Drops a cancellation request of the cleanup task with `task_id`.
+async fn clear_cancellation(redis: &Redis, task_id: Uuid) -> anyhow::Result<()> {
+    let mut con = redis.connect().await?;
+    con.del::<_, ()>(cancelled_task_key(task_id)).await?;
+    Ok(())
+}
+
+/// Tells whether a cleanup task was cancelled with [cancel_cleanup].
+#[async_trait::async_trait]
+pub trait CleanupCancellation: Send + Sync {
+    async fn is_cancelled(&self, task_id: Uuid) -> anyhow::Result<bool>;
+}
+
+#[async_trait::async_trait]
+impl CleanupCancellation for Redis {
+    async fn is_cancelled(&self, task_id: Uuid) -> anyhow::Result<bool> {
+        let mut con = self.connect().await?;
+        Ok(con.exists(cancelled_task_key(task_id)).await?)
+    }
+}
+
+/// Completes a cleanup task, implemented by the [WorkerContext] of the cleanup worker.
+#[async_trait::async_trait]
+pub trait CleanupCompletion: Send + Sync {
+    async fn complete(&self) -> anyhow::Result<()>;
+}
+
+#[async_trait::async_trait]
+impl<Ctx> CleanupCompletion for WorkerContext<Ctx>
+where
+    Ctx: Clone + Send + Sync + 'static,
+{
+    async fn complete(&self) -> anyhow::Result<()> {
+        WorkerContext::complete(self).await
+    }
+}
+
+/// Completes the task without removing anything if it has no ids, as an empty id set is almost
+/// always a bug of the caller. Returns `true` if the task was skipped.
+async fn skip_empty_task(
+    completion: &dyn CleanupCompletion,
+    ty: &str,
+    id: Uuid,
+    kind: &str,
+    is_empty: bool,
+) -> anyhow::Result<bool> {
+    if !is_empty {
+        return Ok(false);
+    }
+    tracing::warn!("skip cleanup task '{ty}' with id '{id}': no {kind} ids given");
+    completion.complete().await?;
+    Ok(true)
+}
+
+/// Collections of a cleanup task, checking for a cancellation before each one.
+struct CancellableCollections<'a> {
+    cancellation: &'a dyn CleanupCancellation,
+    task_id: Uuid,
+    collections: std::vec::IntoIter<String>,
+    cancelled: bool,
+}
+
+impl<'a> CancellableCollections<'a> {
+    fn new(
+        cancellation: &'a dyn CleanupCancellation,
+        task_id: Uuid,
+        collections: Vec<String>,
+    ) -> Self {
+        Self {
+            cancellation,
+            task_id,
+            collections: collections.into_iter(),
+            cancelled: false,
+        }
+    }
+
+    async fn next(&mut self) -> anyhow::Result<Option<String>> {
+        if self.cancelled || self.collections.len() == 0 {
+            return Ok(None);
+        }
+        if self.cancellation.is_cancelled(self.task_id).await? {
+            self.cancelled = true;
+            return Ok(None);
+        }
+        Ok(self.collections.next())
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled
+    }
+}
+
 pub trait CleanupTaskProducer {
     fn cleanup_task_producer(&self) -> &qm_redis::Producer;
 }
@@ -66,8 +201,37 @@ impl AsRef<Producer> for CleanupProducer {
     }
 }
 
+/// Runs for a collection before the documents of a cleanup task are removed from it.
+#[async_trait::async_trait]
+pub trait CollectionCleanupHandler: Send + Sync {
+    /// `query` matches the documents which are removed afterwards.
+    async fn before_remove(&self, collection: &str, query: &Document) -> anyhow::Result<()>;
+}
+
+type CollectionCleanupHandlers = HashMap<Arc<str>, Arc<dyn CollectionCleanupHandler>>;
+
+/// Runs the handler registered for `collection`, then `remove`.
+async fn remove_with_handler<F>(
+    handlers: &CollectionCleanupHandlers,
+    collection: &str,
+    query: &Document,
+    remove: F,
+) -> anyhow::Result<u64>
+where
+    F: std::future::Future<Output = anyhow::Result<u64>>,
+{
+    if let Some(handler) = handlers.get(collection) {
+        tracing::debug!("run cleanup handler of collection {collection}");
+        handler.before_remove(collection, query).await?;
+    }
+    remove.await
+}
+
 pub struct CleanupWorkerCtx<Auth, Store, Resource, Permission> {
     pub store: Store,
+    cleanup_noop_total: Counter,
+    cleanup_cancelled_total: Counter,
+    collection_handlers: Arc<CollectionCleanupHandlers>,
     _marker: Marker<Auth, Store, Resource, Permission, ()>,
 }
 
@@ -75,9 +239,39 @@ impl<Auth, Store, Resource, Permission> CleanupWorkerCtx<Auth, Store, Resource,
     pub fn new(store: Store) -> Self {
         Self {
             store,
+            cleanup_noop_total: Counter::default(),
+            cleanup_cancelled_total: Counter::default(),
+            collection_handlers: Arc::default(),
             _marker: std::marker::PhantomData,
         }
     }
+
+    /// Registers a handler which runs before documents are removed from `collection`.
+    ///
+    /// Collections without a handler are only cleaned up by removing the documents.
+    pub fn with_collection_handler(
+        mut self,
+        collection: impl Into<Arc<str>>,
+        handler: impl CollectionCleanupHandler + 'static,
+    ) -> Self {
+        Arc::make_mut(&mut self.collection_handlers).insert(collection.into(), Arc::new(handler));
+        self
+    }
+
+    /// Number of processed cleanup tasks of type `none`.
+    pub fn cleanup_noop_total(&self) -> &Counter {
+        &self.cleanup_noop_total
+    }
+
+    /// Number of cleanup tasks stopped by [cancel_cleanup].
+    pub fn cleanup_cancelled_total(&self) -> &Counter {
+        &self.cleanup_cancelled_total
+    }
+
+    fn record_noop(&self, id: Uuid) {
+        tracing::warn!("cleanup task with id '{id}' has type 'none', nothing to clean up");
+        self.cleanup_noop_total.inc();
+    }
 }
 
 impl<Auth, Store, Resource, Permission> Clone
@@ -88,11 +282,18 @@ where
     fn clone(&self) -> Self {
         Self {
             store: self.store.clone(),
+            cleanup_noop_total: self.cleanup_noop_total.clone(),
+            cleanup_cancelled_total: self.cleanup_cancelled_total.clone(),
+            collection_handlers: self.collection_handlers.clone(),
             _marker: self._marker,
         }
     }
 }
 
+/// Deletes the matching documents of the collection.
+///
+/// The session does not start a transaction, so the deletion is applied once this returns.
+/// The in-memory cache is not involved, it follows the Postgres notifications.
 async fn remove_documents(
     db: &DB,
     session: &mut ClientSession,
@@ -108,11 +309,103 @@ async fn remove_documents(
     Ok(result.deleted_count)
 }
 
+/// Disables the matching user documents, see [USERS_COLLECTION].
+async fn disable_user_documents(
+    db: &DB,
+    session: &mut ClientSession,
+    query: &Document,
+) -> anyhow::Result<u64> {
+    let result = db
+        .get()
+        .collection::<Document>(USERS_COLLECTION)
+        .update_many(
+            query.clone(),
+            doc! {
+                "$set": {
+                    "enabled": false,
+                    USER_DISABLED_REASON_ATTRIBUTE: USER_DISABLED_REASON_CLEANUP,
+                }
+            },
+        )
+        .session(session)
+        .await?;
+    Ok(result.modified_count)
+}
+
+/// `query` on the owner of user documents, which store the owner id below `owner.id`.
+fn user_owner_query(query: &Document) -> Document {
+    query
+        .iter()
+        .map(|(key, value)| {
+            let key = match key.strip_prefix("owner.") {
+                Some(field) => format!("owner.id.{field}"),
+                None => key.clone(),
+            };
+            (key, value.clone())
+        })
+        .collect()
+}
+
+/// Cleans up the documents of `collection` matching the owner `query`.
+///
+/// User documents are only disabled with [UserCleanupMode::Disable], all other documents are
+/// removed.
+async fn cleanup_collection(
+    handlers: &CollectionCleanupHandlers,
+    db: &DB,
+    session: &mut ClientSession,
+    collection: &str,
+    query: &Document,
+    user_cleanup_mode: UserCleanupMode,
+) -> anyhow::Result<u64> {
+    if collection != USERS_COLLECTION {
+        return remove_with_handler(
+            handlers,
+            collection,
+            query,
+            remove_documents(db, session, collection, query),
+        )
+        .await;
+    }
+    let query = user_owner_query(query);
+    match user_cleanup_mode {
+        UserCleanupMode::Delete => {
+            remove_with_handler(
+                handlers,
+                collection,
+                &query,
+                remove_documents(db, session, collection, &query),
+            )
+            .await
+        }
+        UserCleanupMode::Disable => disable_user_documents(db, session, &query).await,
+    }
+}
+
+/// Finishes a cancelled cleanup task without running the remaining steps.
+async fn cancel<Auth, Store, Resource, Permission>(
+    worker_ctx: &WorkerContext<CleanupWorkerCtx<Auth, Store, Resource, Permission>>,
+    ty: &str,
+    id: Uuid,
+) -> anyhow::Result<()>
+where
+    Auth: RelatedAuth<Resource, Permission>,
+    Store: RelatedStorage,
+    Resource: RelatedResource,
+    Permission: RelatedPermission,
+{
+    tracing::warn!("cancelled cleanup task '{ty}' with id '{id}'");
+    worker_ctx.ctx().cleanup_cancelled_total.inc();
+    worker_ctx.complete().await?;
+    Ok(())
+}
+
 async fn cleanup_customers<Auth, Store, Resource, Permission>(
     worker_ctx: WorkerContext<CleanupWorkerCtx<Auth, Store, Resource, Permission>>,
     ty: &str,
     id: Uuid,
-    cids: &CustomerIds,
+    customer_ids: &CustomerIds,
+    user_cleanup_mode: UserCleanupMode,
 ) -> anyhow::Result<()>
 where
     Auth: RelatedAuth<Resource, Permission>,
@@ -120,54 +413,50 @@ where
     Resource: RelatedResource,
     Permission: RelatedPermission,
 {
+    if skip_empty_task(&worker_ctx, ty, id, "customer", customer_ids.is_empty()).await? {
+        return Ok(());
+    }
     let store: &Store = &worker_ctx.ctx().store;
     let db: &DB = store.as_ref();
     let mut session = db.session().await?;
-    let mut roles = BTreeSet::new();
-    let existing_roles = store.cache_db().roles().await;
-    let access_roles: Vec<&str> = existing_roles
-        .iter()
-        .filter(|k| k.name.contains("access@"))
-        .map(|v| v.name.as_ref())
-        .collect();
-    let mut client_ids = Vec::with_capacity(cids.len());
-    for cid in cids.iter() {
-        client_ids.push(cid.to_string());
-        roles.insert(
-            qm_role::Access::new(AccessLevel::Customer)
-                .with_fmt_id(Some(cid))
-                .to_string(),
-        );
-        extend_roles_with_children(
-            cid,
-            &[INSTITUTION_ID_PREFIX, ORGANIZATION_ID_PREFIX],
-            &access_roles,
-            &mut roles,
-        );
-    }
-    let cids: Vec<i64> = cids.iter().map(CustomerId::unzip).collect();
+    let client_ids = customer_ids.iter().map(CustomerId::to_string).collect();
+    let cids: Vec<i64> = customer_ids.iter().map(CustomerId::unzip).collect();
     let query = doc! {
         "owner.cid": {
             "$in": &cids
         },
     };
-    for collection in db
+    let collections = db
         .get()
         .list_collection_names()
         .session(&mut session)
-        .await?
-    {
+        .await?;
+    let mut collections = CancellableCollections::new(store.redis(), id, collections);
+    while let Some(collection) = collections.next().await? {
         tracing::debug!("remove all organization related resources from db {collection}");
-        remove_documents(db, &mut session, &collection, &query).await?;
+        cleanup_collection(
+            &worker_ctx.ctx().collection_handlers,
+            db,
+            &mut session,
+            &collection,
+            &query,
+            user_cleanup_mode,
+        )
+        .await?;
+    }
+    if collections.is_cancelled() {
+        return cancel(&worker_ctx, ty, id).await;
     }
     tracing::debug!("cleanup api clients");
     cleanup_api_clients(store.keycloak(), client_ids).await?;
     tracing::debug!("cleanup roles");
-    cleanup_roles(store.keycloak(), roles).await?;
+    let task = CleanupTaskType::Customers(customer_ids.clone());
+    let removed_users = remove_task_roles(store, &task, user_cleanup_mode).await?;
     // Emit the Kafka event
     if let Some(producer) = store.mutation_event_producer() {
+        emit_user_events(producer, user_cleanup_mode, removed_users).await;
         producer
-            .delete_event(&EventNs::Customer, "customer", "sys", cids)
+            .delete_events(&EventNs::Customer, "customer", "sys", &cids)
             .await?;
     }
     worker_ctx.complete().await?;
@@ -175,31 +464,144 @@ where
     Ok(())
 }
 
-fn extend_roles_with_children(
-    v: &impl std::fmt::Display,
-    allowed_prefixes: &[char],
-    access_roles: &[&str],
-    roles: &mut BTreeSet<String>,
+/// Emits the user events of a cleanup, failures are logged as the users are already removed.
+async fn emit_user_events(
+    producer: &qm_kafka::producer::Producer,
+    user_cleanup_mode: UserCleanupMode,
+    affected_users: BTreeMap<String, Vec<String>>,
 ) {
-    let id = v.to_string();
-    for role in access_roles.iter() {
-        if let Some((_, role_id)) = role.rsplit_once("access@") {
-            if !role_id.is_empty()
-                && !id.is_empty()
-                && allowed_prefixes.iter().any(|v| role_id.starts_with(*v))
-                && role_id[1..].starts_with(&id[1..])
-            {
-                roles.insert(role.to_string());
+    for (role, user_ids) in affected_users {
+        let event = user_event(&role, user_ids);
+        let result = match user_cleanup_mode {
+            UserCleanupMode::Delete => {
+                producer
+                    .delete_event(&EventNs::User, "user", "sys", &event)
+                    .await
             }
+            UserCleanupMode::Disable => {
+                producer
+                    .update_event(&EventNs::User, "user", "sys", &event)
+                    .await
+            }
+        };
+        if let Err(err) = result {
+            tracing::error!("unable to emit user events for role '{role}': {err:#}");
         }
     }
 }
 
+fn user_event(role: &str, user_ids: Vec<String>) -> QmUserEvent {
+    QmUserEvent::new(user_ids, parse_context(role).as_ref())
+}
+
+/// Access roles removed by the cleanup of `task`, including the roles of all children which
+/// are part of `access_roles`.
+///
+/// The roles are generated lazily so that they can be streamed into a [`RoleCleanupBatch`],
+/// a role may be yielded more than once.
+pub fn cleanup_role_iter<'a, I>(
+    task: &'a CleanupTaskType,
+    access_roles: I,
+) -> Box<dyn Iterator<Item = String> + Send + 'a>
+where
+    I: Iterator<Item = &'a str> + Clone + Send + 'a,
+{
+    match task {
+        CleanupTaskType::Customers(ids) => Box::new(ids.iter().flat_map(move |cid| {
+            std::iter::once(access_string_for(AccessLevel::Customer, cid)).chain(child_roles(
+                cid,
+                &[INSTITUTION_ID_PREFIX, ORGANIZATION_ID_PREFIX],
+                access_roles.clone(),
+            ))
+        })),
+        CleanupTaskType::Organizations(ids) => {
+            Box::new(ids.iter().flat_map(move |oid| {
+                std::iter::once(access_string_for(AccessLevel::Organization, oid)).chain(
+                    child_roles(oid, &[INSTITUTION_ID_PREFIX], access_roles.clone()),
+                )
+            }))
+        }
+        CleanupTaskType::Institutions(ids) => Box::new(
+            ids.iter()
+                .map(|iid| access_string_for(AccessLevel::Institution, iid)),
+        ),
+        CleanupTaskType::None => Box::new(std::iter::empty()),
+    }
+}
+
+/// Access roles removed by the cleanup of `task` as a set, see [`cleanup_role_iter`].
+pub fn cleanup_role_set(task: &CleanupTaskType, access_roles: &[&str]) -> BTreeSet<String> {
+    cleanup_role_iter(task, access_roles.iter().copied()).collect()
+}
+
+/// Names of the cached roles, without copying them.
+fn role_names(roles: &[Arc<Role>]) -> impl Iterator<Item = &str> + Clone + Send {
+    roles.iter().map(|v| v.name.as_ref())
+}
+
+/// Roles which the cleanup of `task` removes, based on the cached roles of the realm.
+///
+/// Used to preview a cleanup before it is queued. The roles are taken from the in memory
+/// cache, so unlike the document cleanup no database session is involved. The worker
+/// streams the same roles with [`cleanup_role_iter`] instead of collecting them.
+pub async fn compute_cleanup_roles<Store>(
+    store: &Store,
+    task: &CleanupTaskType,
+) -> anyhow::Result<BTreeSet<String>>
+where
+    Store: InMemoryCache,
+{
+    let existing_roles = store.cache_db().roles().await;
+    Ok(cleanup_role_iter(task, role_names(&existing_roles)).collect())
+}
+
+/// Removes the roles of `task` in batches of [`ROLE_CLEANUP_BATCH_SIZE`] and returns the
+/// affected users grouped by role.
+async fn remove_task_roles<Store>(
+    store: &Store,
+    task: &CleanupTaskType,
+    user_cleanup_mode: UserCleanupMode,
+) -> anyhow::Result<BTreeMap<String, Vec<String>>>
+where
+    Store: RelatedStorage,
+{
+    let keycloak = store.keycloak().clone();
+    let mut role_batch = RoleCleanupBatch::new(ROLE_CLEANUP_BATCH_SIZE, |roles| {
+        let keycloak = keycloak.clone();
+        async move { cleanup_roles(&keycloak, roles, user_cleanup_mode).await }
+    });
+    let existing_roles = store.cache_db().roles().await;
+    role_batch
+        .extend(cleanup_role_iter(task, role_names(&existing_roles)))
+        .await?;
+    role_batch.finish().await
+}
+
+/// Access roles in `access_roles` which belong to a child of `v`, other roles are skipped.
+fn child_roles<'a>(
+    v: &impl std::fmt::Display,
+    allowed_prefixes: &'a [char],
+    access_roles: impl Iterator<Item = &'a str> + Send + 'a,
+) -> impl Iterator<Item = String> + Send + 'a {
+    let id = v.to_string();
+    access_roles.filter_map(move |role| {
+        let role_id = access_id(role)?;
+        (!role_id.is_empty()
+            && !id.is_empty()
+            && allowed_prefixes.iter().any(|v| role_id.starts_with(*v))
+            && role_id[1..].starts_with(&id[1..]))
+        .then(|| role.to_string())
+    })
+}
+
 async fn cleanup_organizations<Auth, Store, Resource, Permission>(
     worker_ctx: WorkerContext<CleanupWorkerCtx<Auth, Store, Resource, Permission>>,
     ty: &str,
     id: Uuid,
     strict_oids: &OrganizationIds,
+    user_cleanup_mode: UserCleanupMode,
+    empty_unit_policy: EmptyUnitPolicy,
+    organization_unit_ids: &[i64],
 ) -> anyhow::Result<()>
 where
     Auth: RelatedAuth<Resource, Permission>,
@@ -207,54 +609,49 @@ where
     Resource: RelatedResource,
     Permission: RelatedPermission,
 {
+    if skip_empty_task(&worker_ctx, ty, id, "organization", strict_oids.is_empty()).await? {
+        return Ok(());
+    }
     let store: &Store = &worker_ctx.ctx().store;
     let db: &DB = store.as_ref();
     let mut session = db.session().await?;
-    let mut roles = BTreeSet::new();
-    let existing_roles = store.cache_db().roles().await;
-    let access_roles: Vec<&str> = existing_roles
-        .iter()
-        .filter(|k| k.name.contains("access@"))
-        .map(|v| v.name.as_ref())
-        .collect();
-    let mut client_ids = Vec::with_capacity(strict_oids.len());
-    for v in strict_oids.iter() {
-        client_ids.push(v.to_string());
-        roles.insert(
-            qm_role::Access::new(AccessLevel::Organization)
-                .with_fmt_id(Some(&v))
-                .to_string(),
-        );
-        extend_roles_with_children(v, &[INSTITUTION_ID_PREFIX], &access_roles, &mut roles);
-    }
-    let (cids, oids): (Vec<i64>, Vec<i64>) = strict_oids.iter().map(OrganizationId::unzip).unzip();
-    let query = doc! {
-        "owner.cid": {
-            "$in": &cids
-        },
-        "owner.oid": {
-            "$in": &oids
-        }
-    };
-    for collection in db
+    let client_ids = strict_oids.iter().map(OrganizationId::to_string).collect();
+    let query = organization_owner_query(strict_oids.iter());
+    let collections = db
         .get()
         .list_collection_names()
         .session(&mut session)
-        .await?
-    {
+        .await?;
+    let mut collections = CancellableCollections::new(store.redis(), id, collections);
+    while let Some(collection) = collections.next().await? {
         tracing::debug!("remove all organization related resources from db {collection}");
-        remove_documents(db, &mut session, &collection, &query).await?;
+        cleanup_collection(
+            &worker_ctx.ctx().collection_handlers,
+            db,
+            &mut session,
+            &collection,
+            &query,
+            user_cleanup_mode,
+        )
+        .await?;
+    }
+    if collections.is_cancelled() {
+        return cancel(&worker_ctx, ty, id).await;
     }
     tracing::debug!("cleanup api clients");
     cleanup_api_clients(store.keycloak(), client_ids).await?;
     tracing::debug!("cleanup roles");
-    cleanup_roles(store.keycloak(), roles).await?;
+    let task = CleanupTaskType::Organizations(strict_oids.clone());
+    let removed_users = remove_task_roles(store, &task, user_cleanup_mode).await?;
     // // Emit the Kafka event
     if let Some(producer) = store.mutation_event_producer() {
+        emit_user_events(producer, user_cleanup_mode, removed_users).await;
         producer
-            .delete_event(&EventNs::Organization, "organization", "sys", strict_oids)
+            .delete_events(&EventNs::Organization, "organization", "sys", strict_oids)
             .await?;
     }
+    tracing::debug!("check organization units");
+    apply_empty_unit_policy(store, organization_unit_ids, empty_unit_policy).await?;
     worker_ctx.complete().await?;
     tracing::debug!("finished cleanup task '{ty}' with id '{id}'");
     Ok(())
@@ -265,6 +662,7 @@ async fn cleanup_institutions<Auth, Store, Resource, Permission>(
     ty: &str,
     id: Uuid,
     strict_iids: &InstitutionIds,
+    user_cleanup_mode: UserCleanupMode,
 ) -> anyhow::Result<()>
 where
     Auth: RelatedAuth<Resource, Permission>,
@@ -272,19 +670,13 @@ where
     Resource: RelatedResource,
     Permission: RelatedPermission,
 {
+    if skip_empty_task(&worker_ctx, ty, id, "institution", strict_iids.is_empty()).await? {
+        return Ok(());
+    }
     let store: &Store = &worker_ctx.ctx().store;
     let db = store.as_ref();
     let mut session = db.session().await?;
-    let mut roles = BTreeSet::new();
-    let mut client_ids = Vec::with_capacity(strict_iids.len());
-    for id in strict_iids.iter() {
-        client_ids.push(id.to_string());
-        roles.insert(
-            qm_role::Access::new(AccessLevel::Institution)
-                .with_fmt_id(Some(&id))
-                .to_string(),
-        );
-    }
+    let client_ids = strict_iids.iter().map(InstitutionId::to_string).collect();
     let (cids, (oids, iids)): (Vec<i64>, (Vec<i64>, Vec<i64>)) =
         strict_iids.iter().map(InstitutionId::untuple).unzip();
     let query = doc! {
@@ -298,23 +690,37 @@ where
             "$in": &iids
         }
     };
-    for collection in db
+    let collections = db
         .get()
         .list_collection_names()
         .session(&mut session)
-        .await?
-    {
+        .await?;
+    let mut collections = CancellableCollections::new(store.redis(), id, collections);
+    while let Some(collection) = collections.next().await? {
         tracing::debug!("remove all organization related resources from db {collection}");
-        remove_documents(db, &mut session, &collection, &query).await?;
+        cleanup_collection(
+            &worker_ctx.ctx().collection_handlers,
+            db,
+            &mut session,
+            &collection,
+            &query,
+            user_cleanup_mode,
+        )
+        .await?;
+    }
+    if collections.is_cancelled() {
+        return cancel(&worker_ctx, ty, id).await;
     }
     tracing::debug!("cleanup api clients");
     cleanup_api_clients(store.keycloak(), client_ids).await?;
     tracing::debug!("cleanup roles");
-    cleanup_roles(store.keycloak(), roles).await?;
+    let task = CleanupTaskType::Institutions(strict_iids.clone());
+    let removed_users = remove_task_roles(store, &task, user_cleanup_mode).await?;
     // // Emit the Kafka event
     if let Some(producer) = store.mutation_event_producer() {
+        emit_user_events(producer, user_cleanup_mode, removed_users).await;
         producer
-            .delete_event(&EventNs::Institution, "institution", "sys", strict_iids)
+            .delete_events(&EventNs::Institution, "institution", "sys", strict_iids)
             .await?;
     }
     worker_ctx.complete().await?;
@@ -343,20 +749,35 @@ where
             item.ty.as_ref(),
             item.id
         );
+        let redis = ctx.ctx().store.redis().clone();
         match &item.ty {
             CleanupTaskType::Customers(ids) => {
-                cleanup_customers(ctx, item.ty.as_ref(), item.id, ids).await?;
+                cleanup_customers(ctx, item.ty.as_ref(), item.id, ids, item.user_cleanup_mode)
+                    .await?;
             }
             CleanupTaskType::Organizations(ids) => {
-                cleanup_organizations(ctx, item.ty.as_ref(), item.id, ids).await?;
+                cleanup_organizations(
+                    ctx,
+                    item.ty.as_ref(),
+                    item.id,
+                    ids,
+                    item.user_cleanup_mode,
+                    item.empty_unit_policy,
+                    &item.organization_unit_ids,
+                )
+                .await?;
             }
             CleanupTaskType::Institutions(ids) => {
-                cleanup_institutions(ctx, item.ty.as_ref(), item.id, ids).await?;
+                cleanup_institutions(ctx, item.ty.as_ref(), item.id, ids, item.user_cleanup_mode)
+                    .await?;
             }
             CleanupTaskType::None => {
+                ctx.ctx().record_noop(item.id);
                 ctx.complete().await?;
             }
         }
+        // the task is done, a cancellation requested after its last check has no effect
+        clear_cancellation(&redis, item.id).await?;
         Ok(())
     }
 }
@@ -382,3 +803,292 @@ where
         .await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use qm_entity::ids::InfraContext;
+
+    fn customer(cid: i64) -> String {
+        access_string_for(AccessLevel::Customer, &CustomerId::from(cid))
+    }
+
+    fn organization(cid: i64, oid: i64) -> String {
+        access_string_for(AccessLevel::Organization, &OrganizationId::from((cid, oid)))
+    }
+
+    fn institution(cid: i64, oid: i64, iid: i64) -> String {
+        access_string_for(
+            AccessLevel::Institution,
+            &InstitutionId::from((cid, oid, iid)),
+        )
+    }
+
+    #[test]
+    fn test_user_event() {
+        let context = InfraContext::Institution((1, 2, 3).into());
+        let event = user_event(
+            &institution(1, 2, 3),
+            vec!["u1".to_string(), "u2".to_string()],
+        );
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            serde_json::json!({ "ids": ["u1", "u2"], "context": context.to_string() })
+        );
+        let event = user_event(&customer(4), vec!["u3".to_string()]);
+        assert_eq!(
+            event.context,
+            Some(InfraContext::Customer(4.into()).to_string())
+        );
+        let event = user_event("no-access-role", vec!["u4".to_string()]);
+        assert_eq!(event.context, None);
+    }
+
+    #[test]
+    fn test_cleanup_role_set() {
+        let access_roles = [
+            customer(1),
+            organization(1, 2),
+            organization(1, 3),
+            institution(1, 2, 4),
+            institution(1, 2, 5),
+            institution(1, 3, 6),
+            customer(7),
+            organization(7, 8),
+            institution(7, 8, 9),
+        ];
+        let access_roles: Vec<&str> = access_roles.iter().map(String::as_str).collect();
+        let role_set = |task: CleanupTaskType| cleanup_role_set(&task, &access_roles);
+
+        assert_eq!(
+            role_set(CleanupTaskType::Customers(Arc::from([1.into()]))),
+            BTreeSet::from([
+                customer(1),
+                organization(1, 2),
+                organization(1, 3),
+                institution(1, 2, 4),
+                institution(1, 2, 5),
+                institution(1, 3, 6),
+            ])
+        );
+        assert_eq!(
+            role_set(CleanupTaskType::Organizations(Arc::from([(1, 2).into()]))),
+            BTreeSet::from([
+                organization(1, 2),
+                institution(1, 2, 4),
+                institution(1, 2, 5),
+            ])
+        );
+        assert_eq!(
+            role_set(CleanupTaskType::Institutions(Arc::from([(1, 3, 6).into()]))),
+            BTreeSet::from([institution(1, 3, 6)])
+        );
+        assert!(role_set(CleanupTaskType::None).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_role_iter_batches() {
+        let access_roles = [
+            customer(1),
+            organization(1, 2),
+            institution(1, 2, 3),
+            institution(1, 2, 4),
+        ];
+        let access_roles: Vec<&str> = access_roles.iter().map(String::as_str).collect();
+        let task = CleanupTaskType::Customers(Arc::from([1.into()]));
+        let batches = std::sync::Mutex::new(vec![]);
+        let mut batch = RoleCleanupBatch::new(2, |roles: BTreeSet<String>| {
+            batches.lock().unwrap().push(roles.len());
+            async { Ok(BTreeMap::new()) }
+        });
+        batch
+            .extend(cleanup_role_iter(&task, access_roles.iter().copied()))
+            .await
+            .unwrap();
+        batch.finish().await.unwrap();
+        assert_eq!(*batches.lock().unwrap(), vec![2, 2]);
+    }
+
+    #[test]
+    fn test_record_noop() {
+        let ctx = CleanupWorkerCtx::<(), (), (), ()>::new(());
+        let counter = ctx.cleanup_noop_total().clone();
+        ctx.record_noop(Uuid::nil());
+        ctx.record_noop(Uuid::nil());
+        assert_eq!(counter.get(), 2);
+    }
+
+    struct RecordingHandler(Arc<std::sync::Mutex<Vec<String>>>);
+
+    #[async_trait::async_trait]
+    impl CollectionCleanupHandler for RecordingHandler {
+        async fn before_remove(&self, collection: &str, _: &Document) -> anyhow::Result<()> {
+            self.0.lock().unwrap().push(format!("handler {collection}"));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_collection_handler() {
+        let calls = Arc::new(std::sync::Mutex::new(vec![]));
+        let ctx = CleanupWorkerCtx::<(), (), (), ()>::new(())
+            .with_collection_handler("assets", RecordingHandler(calls.clone()));
+        let query = doc! { "owner.cid": { "$in": [1] } };
+        for collection in ["assets", "documents"] {
+            let calls = calls.clone();
+            remove_with_handler(&ctx.collection_handlers, collection, &query, async move {
+                calls.lock().unwrap().push(format!("remove {collection}"));
+                Ok(1)
+            })
+            .await
+            .unwrap();
+        }
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec!["handler assets", "remove assets", "remove documents"]
+        );
+    }
+
+    #[test]
+    fn test_user_owner_query() {
+        let query = organization_owner_query([&OrganizationId::from((1, 2))]);
+        assert_eq!(
+            user_owner_query(&query),
+            doc! { "owner.id.cid": { "$in": [1_i64] }, "owner.id.oid": { "$in": [2_i64] } }
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a mongodb server configured by MONGODB_*"]
+    async fn test_cleanup_user_documents() {
+        use futures::TryStreamExt as _;
+        use qm_entity::ids::Owner;
+        use qm_mongodb::bson::to_bson;
+
+        let db = crate::testing::mongo_db().await;
+        let users = db.get().collection::<Document>(USERS_COLLECTION);
+        // a random customer keeps the seeded users apart from other documents
+        let cid = (Uuid::new_v4().as_u128() % 1_000_000_000) as i64 + 1_000_000_000;
+        let owner = Owner::from(InfraContext::Institution((cid, 1, 1).into()));
+        let seeded = [
+            doc! { "owner": to_bson(&owner).unwrap(), "enabled": true },
+            doc! { "owner": to_bson(&owner).unwrap(), "enabled": true },
+        ];
+        users.insert_many(&seeded).await.unwrap();
+        let filter = doc! { "owner.id.cid": cid };
+        let query = doc! { "owner.cid": { "$in": [cid] } };
+        let handlers = CollectionCleanupHandlers::default();
+        let mut session = db.session().await.unwrap();
+
+        cleanup_collection(
+            &handlers,
+            &db,
+            &mut session,
+            USERS_COLLECTION,
+            &query,
+            UserCleanupMode::Disable,
+        )
+        .await
+        .unwrap();
+        let disabled: Vec<Document> = users
+            .find(filter.clone())
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(disabled.len(), 2);
+        for user in disabled {
+            assert!(!user.get_bool("enabled").unwrap());
+            assert_eq!(
+                user.get_str(USER_DISABLED_REASON_ATTRIBUTE).unwrap(),
+                USER_DISABLED_REASON_CLEANUP
+            );
+        }
+
+        let removed = cleanup_collection(
+            &handlers,
+            &db,
+            &mut session,
+            USERS_COLLECTION,
+            &query,
+            UserCleanupMode::Delete,
+        )
+        .await
+        .unwrap();
+        assert_eq!(removed, 2);
+        assert_eq!(users.count_documents(filter).await.unwrap(), 0);
+    }
+
+    #[derive(Default)]
+    struct CountCompletions(std::sync::atomic::AtomicUsize);
+
+    #[async_trait::async_trait]
+    impl CleanupCompletion for CountCompletions {
+        async fn complete(&self) -> anyhow::Result<()> {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_skip_empty_task() {
+        let completion = CountCompletions::default();
+        let skipped = skip_empty_task(&completion, "customers", Uuid::nil(), "customer", false)
+            .await
+            .unwrap();
+        assert!(!skipped);
+        assert_eq!(completion.0.load(std::sync::atomic::Ordering::SeqCst), 0);
+        let skipped = skip_empty_task(&completion, "customers", Uuid::nil(), "customer", true)
+            .await
+            .unwrap();
+        assert!(skipped);
+        assert_eq!(completion.0.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    struct CancelAfter(std::sync::atomic::AtomicUsize);
+
+    #[async_trait::async_trait]
+    impl CleanupCancellation for CancelAfter {
+        async fn is_cancelled(&self, _: Uuid) -> anyhow::Result<bool> {
+            Ok(self.0.fetch_sub(1, std::sync::atomic::Ordering::SeqCst) == 0)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cancellable_collections() {
+        let names = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let cancellation = CancelAfter(1.into());
+        let mut collections =
+            CancellableCollections::new(&cancellation, Uuid::nil(), names.clone());
+        let mut removed = vec![];
+        while let Some(collection) = collections.next().await.unwrap() {
+            removed.push(collection);
+        }
+        assert_eq!(removed, vec!["a"]);
+        assert!(collections.is_cancelled());
+
+        let cancellation = CancelAfter(usize::MAX.into());
+        let mut collections = CancellableCollections::new(&cancellation, Uuid::nil(), names);
+        let mut removed = vec![];
+        while let Some(collection) = collections.next().await.unwrap() {
+            removed.push(collection);
+        }
+        assert_eq!(removed, vec!["a", "b", "c"]);
+        assert!(!collections.is_cancelled());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a redis server configured by REDIS_*"]
+    async fn test_cancel_cleanup_expires() {
+        let redis = Redis::new().unwrap();
+        let task_id = Uuid::new_v4();
+        cancel_cleanup(&redis, task_id).await.unwrap();
+        assert!(redis.is_cancelled(task_id).await.unwrap());
+        let mut con = redis.connect().await.unwrap();
+        let ttl: i64 = con.ttl(cancelled_task_key(task_id)).await.unwrap();
+        assert!(ttl > 0 && ttl as u64 <= CANCELLED_TASK_TTL_SECS);
+        clear_cancellation(&redis, task_id).await.unwrap();
+        assert!(!redis.is_cancelled(task_id).await.unwrap());
+    }
+}
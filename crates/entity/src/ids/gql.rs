@@ -9,6 +9,7 @@ use async_graphql::Value;
 
 use crate::ids::CustomerId;
 use crate::ids::CustomerResourceId;
+use crate::ids::InfraContext;
 use crate::ids::InstitutionId;
 use crate::ids::InstitutionResourceId;
 use crate::ids::OrganizationId;
@@ -50,16 +51,117 @@ pub enum CustomerOrOrganization {
     Organization(OrganizationId),
 }
 
-#[derive(OneofObject, serde::Serialize, serde::Deserialize)]
-#[serde(tag = "t", content = "c")]
+/// Serialized as the flat id string, e.g. `"T0102"` or `"R010203"`.
+///
+/// The previous tagged form `{"t": "Organization", "c": {..}}` is still accepted when
+/// deserializing.
+#[derive(OneofObject, Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(into = "String", try_from = "OrganizationOrInstitutionRepr")]
 pub enum OrganizationOrInstitution {
     Organization(OrganizationId),
     Institution(InstitutionId),
 }
 
+impl std::fmt::Display for OrganizationOrInstitution {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Organization(v) => v.fmt(f),
+            Self::Institution(v) => v.fmt(f),
+        }
+    }
+}
+
+impl std::str::FromStr for OrganizationOrInstitution {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match InfraContext::parse(s)? {
+            InfraContext::Organization(v) => Ok(Self::Organization(v)),
+            InfraContext::Institution(v) => Ok(Self::Institution(v)),
+            InfraContext::Customer(v) => {
+                anyhow::bail!("'{v}' is neither an organization nor an institution")
+            }
+        }
+    }
+}
+
+impl From<OrganizationOrInstitution> for String {
+    fn from(value: OrganizationOrInstitution) -> Self {
+        value.to_string()
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum OrganizationOrInstitutionRepr {
+    Flat(String),
+    Tagged(TaggedOrganizationOrInstitution),
+}
+
+#[derive(serde::Deserialize)]
+#[serde(tag = "t", content = "c")]
+enum TaggedOrganizationOrInstitution {
+    Organization(OrganizationId),
+    Institution(InstitutionId),
+}
+
+impl TryFrom<OrganizationOrInstitutionRepr> for OrganizationOrInstitution {
+    type Error = anyhow::Error;
+
+    fn try_from(value: OrganizationOrInstitutionRepr) -> Result<Self, Self::Error> {
+        match value {
+            OrganizationOrInstitutionRepr::Flat(s) => s.parse(),
+            OrganizationOrInstitutionRepr::Tagged(
+                TaggedOrganizationOrInstitution::Organization(v),
+            ) => Ok(Self::Organization(v)),
+            OrganizationOrInstitutionRepr::Tagged(
+                TaggedOrganizationOrInstitution::Institution(v),
+            ) => Ok(Self::Institution(v)),
+        }
+    }
+}
+
 pub type CustomerIds = Arc<[CustomerId]>;
 pub type CustomerResourceIds = Arc<[CustomerResourceId]>;
 pub type OrganizationIds = Arc<[OrganizationId]>;
 pub type OrganizationResourceIds = Arc<[OrganizationResourceId]>;
 pub type InstitutionIds = Arc<[InstitutionId]>;
 pub type InstitutionResourceIds = Arc<[InstitutionResourceId]>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use qm_mongodb::bson::{self, Bson};
+
+    #[test]
+    fn test_organization_or_institution_serde() {
+        let organization = OrganizationOrInstitution::Organization((1, 2).into());
+        let institution = OrganizationOrInstitution::Institution((1, 2, 3).into());
+        assert_eq!(serde_json::to_string(&organization).unwrap(), r#""T0102""#);
+        assert_eq!(serde_json::to_string(&institution).unwrap(), r#""R010203""#);
+        assert_eq!(
+            bson::to_bson(&institution).unwrap(),
+            Bson::String("R010203".into())
+        );
+        assert_eq!(
+            serde_json::from_str::<OrganizationOrInstitution>(r#""R010203""#).unwrap(),
+            institution
+        );
+        assert_eq!(
+            bson::from_bson::<OrganizationOrInstitution>(Bson::String("T0102".into())).unwrap(),
+            organization
+        );
+        assert!(serde_json::from_str::<OrganizationOrInstitution>(r#""V01""#).is_err());
+
+        let tagged = r#"{"t":"Organization","c":{"cid":1,"oid":2}}"#;
+        assert_eq!(
+            serde_json::from_str::<OrganizationOrInstitution>(tagged).unwrap(),
+            organization
+        );
+        let tagged = r#"{"t":"Institution","c":{"cid":1,"oid":2,"iid":3}}"#;
+        assert_eq!(
+            serde_json::from_str::<OrganizationOrInstitution>(tagged).unwrap(),
+            institution
+        );
+    }
+}
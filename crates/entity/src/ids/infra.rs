@@ -13,6 +13,8 @@
 
 use async_graphql::OneofObject;
 
+use qm_mongodb::bson::Bson;
+
 use sqlx::postgres::PgArgumentBuffer;
 use sqlx::Encode;
 use sqlx::Postgres;
@@ -67,6 +69,31 @@ impl From<InfraId> for i64 {
     }
 }
 
+impl FromStr for InfraId {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(Self)
+    }
+}
+impl std::fmt::Display for InfraId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl InfraId {
+    pub fn as_bson(&self) -> Bson {
+        Bson::Int64(self.0)
+    }
+}
+
+impl From<InfraId> for Bson {
+    fn from(val: InfraId) -> Self {
+        val.as_bson()
+    }
+}
+
 impl<'q> Encode<'q, Postgres> for InfraId {
     fn encode_by_ref(
         &self,
@@ -802,6 +829,14 @@ impl_institution_resource_id_from_ty_tuple!(i16);
 impl_institution_resource_id_from_ty_tuple!(u8);
 impl_institution_resource_id_from_ty_tuple!(i8);
 
+/// Level of an [InfraContext] in the customer / organization / institution hierarchy.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum InfraLevel {
+    Customer,
+    Organization,
+    Institution,
+}
+
 #[derive(Debug, Clone, Copy, OneofObject, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub enum InfraContext {
     Customer(CustomerId),
@@ -834,6 +869,54 @@ impl InfraContext {
         }
     }
 
+    pub fn level(&self) -> InfraLevel {
+        match self {
+            InfraContext::Customer(_) => InfraLevel::Customer,
+            InfraContext::Organization(_) => InfraLevel::Organization,
+            InfraContext::Institution(_) => InfraLevel::Institution,
+        }
+    }
+
+    /// Returns the parent context at `level`, or `None` if the context is above `level`.
+    pub fn truncate_to(&self, level: InfraLevel) -> Option<InfraContext> {
+        if level > self.level() {
+            return None;
+        }
+        Some(match level {
+            InfraLevel::Customer => InfraContext::Customer(match self {
+                InfraContext::Customer(v) => *v,
+                InfraContext::Organization(v) => v.root(),
+                InfraContext::Institution(v) => v.root(),
+            }),
+            InfraLevel::Organization => {
+                InfraContext::Organization(self.try_as_organization_id().ok()?)
+            }
+            InfraLevel::Institution => *self,
+        })
+    }
+
+    pub fn try_as_organization_id(&self) -> anyhow::Result<OrganizationId> {
+        match self {
+            InfraContext::Customer(v) => {
+                anyhow::bail!("context '{v}' is a customer and has no organization segment")
+            }
+            InfraContext::Organization(v) => Ok(*v),
+            InfraContext::Institution(v) => Ok(v.parent()),
+        }
+    }
+
+    pub fn try_as_institution_id(&self) -> anyhow::Result<InstitutionId> {
+        match self {
+            InfraContext::Customer(v) => anyhow::bail!(
+                "context '{v}' is a customer and has no organization and institution segment"
+            ),
+            InfraContext::Organization(v) => {
+                anyhow::bail!("context '{v}' is an organization and has no institution segment")
+            }
+            InfraContext::Institution(v) => Ok(*v),
+        }
+    }
+
     pub fn is_customer(&self) -> bool {
         match self {
             InfraContext::Customer(_) => true,
@@ -888,6 +971,15 @@ impl InfraContext {
         }
     }
 
+    /// Returns `true` if `other` is this context or one of its descendants.
+    pub fn contains(&self, other: &InfraContext) -> bool {
+        match self {
+            InfraContext::Customer(v) => other.has_customer(v),
+            InfraContext::Organization(v) => other.has_organization(v),
+            InfraContext::Institution(v) => other.has_institution(v),
+        }
+    }
+
     // Call from user context
     pub fn combine(self, query_context: Self) -> Self {
         match &self {
@@ -932,6 +1024,15 @@ impl InfraContext {
     }
 }
 
+/// Truncates the context id `s` to its parent id at `level`.
+pub fn truncate_context_str(s: &str, level: InfraLevel) -> anyhow::Result<String> {
+    let context = InfraContext::parse(s)?;
+    context
+        .truncate_to(level)
+        .map(|v| v.to_string())
+        .ok_or_else(|| anyhow::anyhow!("context '{context}' has no {level:?} segment"))
+}
+
 impl From<CustomerId> for InfraContext {
     fn from(value: CustomerId) -> Self {
         InfraContext::Customer(value)
@@ -1128,6 +1229,63 @@ impl<'a, const N: usize> Iterator for StringParser<'a, N> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_infra_id_bson() {
+        let id = InfraId::from(0x6000);
+        assert_eq!(id.as_bson(), Bson::Int64(0x6000));
+        assert_eq!(Bson::from(&id), Bson::Int64(*id));
+        assert_eq!(Bson::from(id), Bson::Int64(0x6000));
+        assert_eq!(qm_mongodb::bson::doc! { "owner.cid": id.as_bson() }, qm_mongodb::bson::doc! { "owner.cid": 0x6000_i64 });
+    }
+
+    #[test]
+    fn test_infra_id_str_and_json() {
+        let id: InfraId = "24576".parse().unwrap();
+        assert_eq!(id, InfraId::from(0x6000));
+        assert_eq!(id.to_string(), "24576");
+        assert!("V01".parse::<InfraId>().is_err());
+        assert_eq!(serde_json::to_string(&id).unwrap(), "24576");
+        assert_eq!(serde_json::from_str::<InfraId>("24576").unwrap(), id);
+    }
+
+    #[test]
+    fn test_infra_context_try_as() {
+        let customer = InfraContext::parse("V01").unwrap();
+        let organization = InfraContext::parse("T0102").unwrap();
+        let institution = InfraContext::parse("R010203").unwrap();
+        assert_eq!(customer.try_as_organization_id().unwrap_err().to_string(), "context 'V01' is a customer and has no organization segment");
+        assert_eq!(customer.try_as_institution_id().unwrap_err().to_string(), "context 'V01' is a customer and has no organization and institution segment");
+        assert_eq!(organization.try_as_organization_id().unwrap(), OrganizationId { cid: 1, oid: 2 });
+        assert_eq!(organization.try_as_institution_id().unwrap_err().to_string(), "context 'T0102' is an organization and has no institution segment");
+        assert_eq!(institution.try_as_organization_id().unwrap(), OrganizationId { cid: 1, oid: 2 });
+        assert_eq!(institution.try_as_institution_id().unwrap(), InstitutionId { cid: 1, oid: 2, iid: 3 });
+    }
+
+    #[test]
+    fn test_infra_context_truncate_to() {
+        let institution = InfraContext::parse("R010203").unwrap();
+        assert_eq!(institution.truncate_to(InfraLevel::Customer), Some(InfraContext::Customer(CustomerId { cid: 1 })));
+        assert_eq!(institution.truncate_to(InfraLevel::Organization), Some(InfraContext::Organization(OrganizationId { cid: 1, oid: 2 })));
+        assert_eq!(institution.truncate_to(InfraLevel::Institution), Some(institution));
+        let customer = InfraContext::parse("V01").unwrap();
+        assert_eq!(customer.truncate_to(InfraLevel::Organization), None);
+        assert_eq!(truncate_context_str("R010203", InfraLevel::Customer).unwrap(), "V01");
+        assert_eq!(truncate_context_str("T0102", InfraLevel::Organization).unwrap(), "T0102");
+        assert!(truncate_context_str("V01", InfraLevel::Institution).is_err());
+    }
+
+    #[test]
+    fn test_infra_context_contains() {
+        let customer = InfraContext::parse("V01").unwrap();
+        let organization = InfraContext::parse("T0102").unwrap();
+        let institution = InfraContext::parse("R010203").unwrap();
+        assert!(customer.contains(&institution));
+        assert!(organization.contains(&institution));
+        assert!(institution.contains(&institution));
+        assert!(!institution.contains(&organization));
+        assert!(!organization.contains(&InfraContext::parse("R010303").unwrap()));
+    }
+
     #[test]
     fn test_string_parser() {
         let mut parser = StringParser::<3>::new("010101");
@@ -167,15 +167,10 @@ where
         filter: Option<ListFilter>,
     ) -> qm_mongodb::error::Result<ListResult<T>> {
         let query = query.unwrap_or_default();
-        let limit = filter
-            .as_ref()
-            .and_then(|filter| filter.limit.as_ref().copied())
-            .unwrap_or(1000) as i64;
-        let page = filter
-            .as_ref()
-            .and_then(|filter| filter.page.as_ref().copied())
-            .unwrap_or(0);
-        let offset = page as u64 * limit as u64;
+        let pagination = filter.unwrap_or_default().pagination(1000);
+        let limit = pagination.limit();
+        let page = pagination.page as usize;
+        let offset = pagination.offset() as u64;
         let total = self.as_ref().count_documents(query.clone()).await?;
         let options = FindOptions::builder().limit(limit).skip(offset).build();
 
@@ -1,13 +1,29 @@
 use qm_mongodb::bson::Document;
 use serde::de::DeserializeOwned;
 
-use crate::{error::EntityResult, model::ListResult};
+use crate::{
+    error::EntityResult,
+    model::{ListResult, Pagination},
+};
 
-pub trait NewList<T>
+pub trait NewList<T> {
+    fn new(items: Vec<T>, limit: Option<i64>, total: Option<i64>, page: Option<i64>) -> Self;
+}
+
+/// Builds the list type `L` from one page of `items` and the `total` number of matching
+/// rows, as returned by the sqlx list queries.
+///
+/// `limit` is `None` if the query was not paginated.
+pub fn paginate_query<T, L>(items: Vec<T>, pagination: Option<&Pagination>, total: i64) -> L
 where
-    T: DeserializeOwned + Send + Sync + Unpin + 'static,
+    L: NewList<T>,
 {
-    fn new(items: Vec<T>, limit: Option<i64>, total: Option<i64>, page: Option<i64>) -> Self;
+    L::new(
+        items,
+        pagination.map(Pagination::limit),
+        Some(total),
+        Some(pagination.map_or(0, |pagination| pagination.page)),
+    )
 }
 
 pub struct ListCtx<T>
@@ -28,9 +28,105 @@ pub struct ListFilter {
     pub limit: Option<usize>,
 }
 
+/// Items per page of the list APIs if no limit was requested.
+pub const DEFAULT_PER_PAGE: i64 = 100;
+
+impl ListFilter {
+    /// Pagination requested by the filter, with `default_per_page` items per page if no
+    /// limit was given.
+    pub fn pagination(&self, default_per_page: i64) -> Pagination {
+        Pagination {
+            page: self.page.map_or(0, saturating_i64),
+            per_page: self.limit.map_or(default_per_page, saturating_i64),
+        }
+    }
+}
+
+fn saturating_i64(v: usize) -> i64 {
+    i64::try_from(v).unwrap_or(i64::MAX)
+}
+
+/// One page of a list, `page` is zero based.
+///
+/// The list APIs convert their input into this type, so the page to offset math lives in
+/// one place.
+#[derive(Debug, Clone, Copy, InputObject, Serialize, Deserialize, Eq, PartialEq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub struct Pagination {
+    pub page: i64,
+    pub per_page: i64,
+}
+
+impl Default for Pagination {
+    fn default() -> Self {
+        Self {
+            page: 0,
+            per_page: DEFAULT_PER_PAGE,
+        }
+    }
+}
+
+impl Pagination {
+    pub fn new(page: i64, per_page: i64) -> Self {
+        Self { page, per_page }
+    }
+
+    /// Number of items to skip to reach the page, negative pages are read as the first one.
+    pub fn offset(&self) -> i64 {
+        self.page.max(0).saturating_mul(self.limit())
+    }
+
+    /// Number of items on the page, never negative.
+    pub fn limit(&self) -> i64 {
+        self.per_page.max(0)
+    }
+}
+
 pub struct ListResult<T> {
     pub items: Vec<T>,
     pub limit: Option<i64>,
     pub total: Option<i64>,
     pub page: Option<i64>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pagination_offset() {
+        assert_eq!(Pagination::default().offset(), 0);
+        assert_eq!(Pagination::default().limit(), DEFAULT_PER_PAGE);
+        assert_eq!(Pagination::new(0, 10).offset(), 0);
+        assert_eq!(Pagination::new(1, 10).offset(), 10);
+        assert_eq!(Pagination::new(3, 25).offset(), 75);
+        assert_eq!(Pagination::new(-1, 10).offset(), 0);
+        assert_eq!(Pagination::new(2, -5).limit(), 0);
+        assert_eq!(Pagination::new(i64::MAX, 2).offset(), i64::MAX);
+    }
+
+    #[test]
+    fn test_list_filter_pagination() {
+        let filter = ListFilter::default();
+        assert_eq!(filter.pagination(100), Pagination::new(0, 100));
+
+        let filter = ListFilter {
+            page: Some(1),
+            limit: Some(10),
+        };
+        assert_eq!(filter.pagination(100), Pagination::new(1, 10));
+        assert_eq!(filter.pagination(100).offset(), 10);
+
+        let filter = ListFilter {
+            page: Some(3),
+            limit: None,
+        };
+        assert_eq!(filter.pagination(25).offset(), 75);
+
+        let filter = ListFilter {
+            page: Some(usize::MAX),
+            limit: Some(2),
+        };
+        assert_eq!(filter.pagination(100).offset(), i64::MAX);
+    }
+}
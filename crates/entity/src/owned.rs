@@ -1,4 +1,4 @@
-use std::{borrow::Cow, str::FromStr, sync::Arc};
+use std::{borrow::Cow, collections::HashMap, str::FromStr, sync::Arc};
 
 use async_graphql::{Description, InputValueError, InputValueResult, Scalar, ScalarType, Value};
 use chrono::{DateTime, Utc};
@@ -331,10 +331,10 @@ impl TryFrom<ListFilter> for PageInfo {
     type Error = EntityError;
 
     fn try_from(value: ListFilter) -> Result<Self, Self::Error> {
-        let limit = value.limit.map(|l| l as i64).unwrap_or(DEFAULT_PAGE_LIMIT);
+        let pagination = value.pagination(DEFAULT_PAGE_LIMIT);
         Ok(Self {
-            skip: value.page.map(|page| limit as u64 * page as u64),
-            limit: Some(limit),
+            skip: value.page.map(|_| pagination.offset() as u64),
+            limit: Some(pagination.limit()),
         })
     }
 }
@@ -596,6 +596,27 @@ where
         Ok(result.deleted_count as i32)
     }
 
+    /// Counts the entities of each owner; owners without entities are counted as zero.
+    ///
+    /// Owners are matched exactly, a customer owner does not include the entities of its
+    /// organizations or institutions.
+    pub async fn count_by_owner(
+        db: &Database,
+        owners: &[OwnerId],
+    ) -> Result<HashMap<OwnerId, u64>, EntityError> {
+        let owners_bson = to_bson(owners).map_err(|err| EntityError::Bson(err.to_string()))?;
+        let counts = T::mongo_collection::<Document>(db)
+            .aggregate([
+                doc! { "$match": { "owner": { "$in": owners_bson } } },
+                doc! { "$group": { "_id": "$owner", "count": { "$sum": 1 } } },
+            ])
+            .with_type::<OwnerCount>()
+            .await?
+            .try_collect::<Vec<_>>()
+            .await?;
+        Ok(owner_counts(owners, counts))
+    }
+
     pub async fn page_filter(
         db: &Database,
         filter: Document,
@@ -643,6 +664,24 @@ where
     }
 }
 
+#[derive(Debug, Deserialize)]
+struct OwnerCount {
+    #[serde(rename = "_id")]
+    owner: OwnerId,
+    count: i64,
+}
+
+fn owner_counts(
+    owners: &[OwnerId],
+    counts: impl IntoIterator<Item = OwnerCount>,
+) -> HashMap<OwnerId, u64> {
+    let mut result: HashMap<OwnerId, u64> = owners.iter().map(|owner| (*owner, 0)).collect();
+    for OwnerCount { owner, count } in counts {
+        result.insert(owner, count.max(0) as u64);
+    }
+    result
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Defaults {
     pub created: UserModification,
@@ -710,3 +749,26 @@ pub trait MongoCollection {
         db.collection(Self::COLLECTION)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_owner_counts() {
+        let a = OwnerId::from(InstitutionId::from((1, 2, 3)));
+        let b = OwnerId::from(InstitutionId::from((1, 2, 4)));
+        let empty = OwnerId::from(OrganizationId::from((1, 2)));
+        let counts = owner_counts(
+            &[a, b, empty],
+            [
+                OwnerCount { owner: a, count: 2 },
+                OwnerCount { owner: b, count: 1 },
+            ],
+        );
+        assert_eq!(counts.len(), 3);
+        assert_eq!(counts[&a], 2);
+        assert_eq!(counts[&b], 1);
+        assert_eq!(counts[&empty], 0);
+    }
+}
@@ -8,6 +8,7 @@ pub struct Config {
     address: Option<Arc<str>>,
     topic_mutation_events: Option<Arc<str>>,
     consumer_group_mutation_events_prefix: Option<Arc<str>>,
+    max_event_items: Option<usize>,
 }
 
 impl Config {
@@ -32,6 +33,11 @@ impl Config {
             .as_deref()
             .unwrap()
     }
+
+    /// Maximum number of items in a single batched event, defaults to 1000.
+    pub fn max_event_items(&self) -> usize {
+        self.max_event_items.unwrap_or(1000)
+    }
 }
 
 #[derive(Default)]
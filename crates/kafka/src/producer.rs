@@ -63,6 +63,16 @@ pub enum EventType {
     Assign,
     Unassign,
     Link,
+    /// The owner of an object changed, the object is a [MoveEvent]
+    Move,
+}
+
+/// Payload of a [EventType::Move] event carrying the old and the new owner.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct MoveEvent<O, I> {
+    pub from: O,
+    pub to: O,
+    pub id: I,
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -73,6 +83,20 @@ pub struct Event {
     pub object: serde_json::Value,
 }
 
+impl Event {
+    pub fn new<O>(event: EventType, ty: &str, cty: &str, object: O) -> serde_json::Result<Self>
+    where
+        O: serde::ser::Serialize,
+    {
+        Ok(Self {
+            event,
+            ty: ty.to_string(),
+            cty: cty.to_string(),
+            object: serde_json::to_value(object)?,
+        })
+    }
+}
+
 pub struct Inner {
     config: Config,
     producer: FutureProducer,
@@ -162,6 +186,25 @@ impl Producer {
             .await
     }
 
+    /// Emits delete events for `items`, split into chunks of at most
+    /// [Config::max_event_items] items each. An empty slice emits one event without items.
+    pub async fn delete_events<T>(
+        &self,
+        event_ns: &EventNs,
+        ty: &str,
+        cty: &str,
+        items: &[T],
+    ) -> anyhow::Result<()>
+    where
+        T: serde::ser::Serialize,
+    {
+        for chunk in chunk_items(items, self.inner.config.max_event_items()) {
+            self.produce_event("delete", EventType::Delete, event_ns, ty, cty, chunk)
+                .await?;
+        }
+        Ok(())
+    }
+
     pub async fn link_event<O>(
         &self,
         event_ns: &EventNs,
@@ -176,6 +219,21 @@ impl Producer {
             .await
     }
 
+    pub async fn move_event<O, I>(
+        &self,
+        event_ns: &EventNs,
+        ty: &str,
+        cty: &str,
+        object: MoveEvent<O, I>,
+    ) -> anyhow::Result<()>
+    where
+        O: serde::ser::Serialize,
+        I: serde::ser::Serialize,
+    {
+        self.produce_event("move", EventType::Move, event_ns, ty, cty, object)
+            .await
+    }
+
     async fn produce_event<N, O>(
         &self,
         event_name: &'static str,
@@ -190,14 +248,7 @@ impl Producer {
         O: serde::ser::Serialize,
     {
         tracing::debug!("{event_name} event for type: {ty}");
-        let object = serde_json::to_value(object)?;
-        let event = Event {
-            event,
-            ty: ty.to_string(),
-            cty: cty.to_string(),
-            object,
-        };
-        let event = serde_json::to_string(&event)?;
+        let event = serde_json::to_string(&Event::new(event, ty, cty, object)?)?;
         let (a, b) = self
             .inner
             .producer
@@ -229,14 +280,7 @@ impl Producer {
         O: serde::ser::Serialize,
     {
         tracing::debug!("{event:?} event for type: {ty}");
-        let object = serde_json::to_value(object)?;
-        let event = Event {
-            event,
-            ty: ty.to_string(),
-            cty: cty.to_string(),
-            object,
-        };
-        let event = serde_json::to_string(&event)?;
+        let event = serde_json::to_string(&Event::new(event, ty, cty, object)?)?;
         let (a, b) = self
             .inner
             .producer
@@ -254,6 +298,14 @@ impl Producer {
     }
 }
 
+/// Splits `items` into chunks of at most `max_items` items. An empty slice gives one empty
+/// chunk, so [Producer::delete_events] emits the same event as [Producer::delete_event].
+fn chunk_items<T>(items: &[T], max_items: usize) -> impl Iterator<Item = &[T]> {
+    items
+        .chunks(max_items.max(1))
+        .chain(items.is_empty().then_some(items))
+}
+
 fn now() -> i64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -262,3 +314,101 @@ fn now() -> i64 {
         .try_into()
         .unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunk_items() {
+        let ids: Vec<i64> = (0..2500).collect();
+        let chunks = chunk_items(&ids, 1000)
+            .map(|chunk| serde_json::to_value(chunk).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(chunks.len(), 3);
+        assert!(chunks
+            .iter()
+            .all(|chunk| chunk.as_array().unwrap().len() <= 1000));
+        let produced: Vec<i64> = chunks
+            .iter()
+            .flat_map(|chunk| serde_json::from_value::<Vec<i64>>(chunk.clone()).unwrap())
+            .collect();
+        assert_eq!(produced, ids);
+        assert_eq!(chunk_items(&ids, 0).count(), 2500);
+        let empty: Vec<&[i64]> = chunk_items::<i64>(&[], 1000).collect();
+        assert_eq!(empty, vec![&[] as &[i64]]);
+    }
+
+    #[tokio::test]
+    async fn test_delete_events() {
+        use rdkafka::consumer::{BaseConsumer, Consumer};
+        use rdkafka::Message;
+
+        let cluster = rdkafka::mocking::MockCluster::new(1).unwrap();
+        cluster.create_topic("qm_mutation_events", 1, 1).unwrap();
+        std::env::set_var("QM_KAFKA_TEST_ADDRESS", cluster.bootstrap_servers());
+        std::env::set_var("QM_KAFKA_TEST_MAX_EVENT_ITEMS", "1000");
+        let producer = ProducerBuilder::default()
+            .with_env_prefix("QM_KAFKA_TEST_")
+            .build()
+            .unwrap();
+        let ids: Vec<i64> = (0..2500).collect();
+        producer
+            .delete_events(&EventNs::Customer, "customer", "sys", &ids)
+            .await
+            .unwrap();
+        producer
+            .delete_events::<i64>(&EventNs::Customer, "customer", "sys", &[])
+            .await
+            .unwrap();
+
+        let consumer: BaseConsumer = ClientConfig::new()
+            .set("bootstrap.servers", cluster.bootstrap_servers())
+            .set("group.id", "test_delete_events")
+            .set("auto.offset.reset", "earliest")
+            .create()
+            .unwrap();
+        let mut partitions = rdkafka::TopicPartitionList::new();
+        partitions
+            .add_partition_offset("qm_mutation_events", 0, rdkafka::Offset::Beginning)
+            .unwrap();
+        consumer.assign(&partitions).unwrap();
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(30);
+        let mut events = vec![];
+        while events.len() < 4 && std::time::Instant::now() < deadline {
+            let Some(message) = consumer.poll(std::time::Duration::from_millis(100)) else {
+                continue;
+            };
+            let message = message.unwrap();
+            assert_eq!(message.key(), Some("customer".as_bytes()));
+            let event: Event = serde_json::from_slice(message.payload().unwrap()).unwrap();
+            assert!(matches!(event.event, EventType::Delete));
+            events.push(serde_json::from_value::<Vec<i64>>(event.object).unwrap());
+        }
+        let sizes: Vec<usize> = events.iter().map(Vec::len).collect();
+        assert_eq!(sizes, vec![1000, 1000, 500, 0]);
+        assert_eq!(events.concat(), ids);
+    }
+
+    #[test]
+    fn test_move_event() {
+        let event = Event::new(
+            EventType::Move,
+            "user",
+            "institution",
+            MoveEvent {
+                from: "1A2B3C",
+                to: "1A2B3D",
+                id: "user-1",
+            },
+        )
+        .unwrap();
+        let value = serde_json::to_value(&event).unwrap();
+        assert_eq!(value["event"], "Move");
+        assert_eq!(value["object"]["from"], "1A2B3C");
+        assert_eq!(value["object"]["to"], "1A2B3D");
+        assert_eq!(value["object"]["id"], "user-1");
+        let parsed: Event = serde_json::from_value(value).unwrap();
+        assert!(matches!(parsed.event, EventType::Move));
+    }
+}
@@ -1,18 +1,28 @@
-use std::{borrow::Cow, sync::Arc};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
 
 pub use keycloak::{
     types::{
         AuthenticationExecutionInfoRepresentation, AuthenticationFlowRepresentation,
         AuthenticatorConfigRepresentation, ClientRepresentation, CredentialRepresentation,
-        GroupRepresentation, RealmRepresentation, RoleRepresentation, TypeMap, UserRepresentation,
+        GroupRepresentation, ProtocolMapperRepresentation, RealmRepresentation, RoleRepresentation,
+        TypeMap, UserRepresentation, UserSessionRepresentation,
     },
     KeycloakAdmin, KeycloakError, KeycloakTokenSupplier,
 };
 use serde_json::Value;
 
-use crate::session::{KeycloakSession, KeycloakSessionClient};
+use crate::schema::RequiredUserAction;
+use crate::session::{
+    AdminGrantType, KeycloakSession, KeycloakSessionClient, KeycloakSessionError, ParsedAccessToken,
+};
 
 pub use crate::config::Config as KeycloakConfig;
+use crate::config::TokenLifespans;
 
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 pub struct ServerInfo {
@@ -28,6 +38,17 @@ pub struct RealmInfo {
     pub public_key: Option<String>,
 }
 
+/// Password credential for [Keycloak::update_password] and new users, `temporary` requires the
+/// user to change it on the next login.
+pub fn password_credential(value: &str, temporary: bool) -> CredentialRepresentation {
+    CredentialRepresentation {
+        type_: Some("password".to_string()),
+        value: Some(value.to_string()),
+        temporary: Some(temporary),
+        ..CredentialRepresentation::default()
+    }
+}
+
 async fn error_check(response: reqwest::Response) -> Result<reqwest::Response, KeycloakError> {
     if !response.status().is_success() {
         let status = response.status().into();
@@ -42,6 +63,75 @@ async fn error_check(response: reqwest::Response) -> Result<reqwest::Response, K
     Ok(response)
 }
 
+#[derive(serde::Deserialize)]
+struct ExchangedToken {
+    access_token: String,
+}
+
+async fn exchange_token(
+    client: &reqwest::Client,
+    url: &str,
+    realm: &str,
+    client_id: &str,
+    client_secret: &str,
+    subject_token: &str,
+    requested_subject: &str,
+) -> Result<String, KeycloakError> {
+    let response = client
+        .post(format!("{url}realms/{realm}/protocol/openid-connect/token"))
+        .form(&serde_json::json!({
+            "grant_type": "urn:ietf:params:oauth:grant-type:token-exchange",
+            "client_id": client_id,
+            "client_secret": client_secret,
+            "subject_token": subject_token,
+            "requested_subject": requested_subject,
+            "requested_token_type": "urn:ietf:params:oauth:token-type:access_token",
+        }))
+        .send()
+        .await?;
+    Ok(error_check(response)
+        .await?
+        .json::<ExchangedToken>()
+        .await?
+        .access_token)
+}
+
+fn merge_attributes(
+    target: &mut Option<TypeMap<String, Vec<String>>>,
+    attributes: HashMap<String, Vec<String>>,
+) {
+    target
+        .get_or_insert_with(Default::default)
+        .extend(attributes);
+}
+
+async fn expand_composites<F, Fut>(
+    composites: Vec<RoleRepresentation>,
+    fetch_composites: F,
+) -> Result<Vec<RoleRepresentation>, KeycloakError>
+where
+    F: Fn(String) -> Fut,
+    Fut: std::future::Future<Output = Result<Vec<RoleRepresentation>, KeycloakError>>,
+{
+    let mut visited = std::collections::HashSet::new();
+    let mut leafs = vec![];
+    let mut queue = composites;
+    while let Some(role) = queue.pop() {
+        let Some(id) = role.id.clone() else {
+            continue;
+        };
+        if !visited.insert(id.clone()) {
+            continue;
+        }
+        if role.composite.unwrap_or(false) {
+            queue.extend(fetch_composites(id).await?);
+        } else {
+            leafs.push(role);
+        }
+    }
+    Ok(leafs)
+}
+
 struct Inner {
     url: Arc<str>,
     config: KeycloakConfig,
@@ -50,13 +140,152 @@ struct Inner {
     admin: KeycloakAdmin<KeycloakSession>,
 }
 
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_GROUP_DEPTH: usize = 32;
+const DEFAULT_STARTUP_ATTEMPTS: usize = 5;
+const DEFAULT_STARTUP_BACKOFF: Duration = Duration::from_secs(1);
+const SESSION_PAGE_SIZE: i32 = 100;
+
 #[derive(Default)]
 pub struct KeycloakBuilder {
     no_refresh: bool,
     env_prefix: Option<&'static str>,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    pool_max_idle_per_host: Option<usize>,
+    admin_realm: Option<String>,
+    admin_client_id: Option<String>,
+    grant_type: AdminGrantType,
+    startup_retry: Option<(usize, Duration)>,
+    http_client: Option<reqwest::Client>,
 }
 
 impl KeycloakBuilder {
+    /// Total timeout of a single request, defaults to 30 seconds.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = Some(connect_timeout);
+        self
+    }
+
+    pub fn with_pool_max_idle_per_host(mut self, max: usize) -> Self {
+        self.pool_max_idle_per_host = Some(max);
+        self
+    }
+
+    /// Realm of the admin session, defaults to `master`.
+    pub fn with_admin_realm(mut self, realm: impl Into<String>) -> Self {
+        self.admin_realm = Some(realm.into());
+        self
+    }
+
+    /// Client of the admin session, defaults to `admin-cli`.
+    pub fn with_admin_client_id(mut self, client_id: impl Into<String>) -> Self {
+        self.admin_client_id = Some(client_id.into());
+        self
+    }
+
+    /// [AdminGrantType::ClientCredentials] uses `KEYCLOAK_ADMIN_CLIENT_SECRET` instead of
+    /// username and password.
+    pub fn with_grant_type(mut self, grant_type: AdminGrantType) -> Self {
+        self.grant_type = grant_type;
+        self
+    }
+
+    /// Retries the initial login up to `max_attempts` times, doubling `backoff` after each
+    /// failure. Defaults to 5 attempts starting with 1 second.
+    pub fn with_startup_retry(mut self, max_attempts: usize, backoff: Duration) -> Self {
+        self.startup_retry = Some((max_attempts, backoff));
+        self
+    }
+
+    /// Reuses `client` for the session and admin requests instead of creating a new one.
+    /// Timeout and pool settings of the builder are ignored in that case.
+    pub fn with_http_client(mut self, client: reqwest::Client) -> Self {
+        self.http_client = Some(client);
+        self
+    }
+
+    async fn startup_session(
+        &self,
+        config: &KeycloakConfig,
+        client: reqwest::Client,
+    ) -> anyhow::Result<KeycloakSession> {
+        let (max_attempts, mut backoff) = self
+            .startup_retry
+            .unwrap_or((DEFAULT_STARTUP_ATTEMPTS, DEFAULT_STARTUP_BACKOFF));
+        let mut attempt = 1;
+        loop {
+            match self.session(config, client.clone()).await {
+                Ok(session) => return Ok(session),
+                Err(err)
+                    if attempt < max_attempts
+                        && err.downcast_ref::<KeycloakSessionError>().is_some() =>
+                {
+                    tracing::warn!(
+                        "keycloak login failed (attempt {attempt}/{max_attempts}), retry in {backoff:?}: {err:#}"
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    async fn session(
+        &self,
+        config: &KeycloakConfig,
+        client: reqwest::Client,
+    ) -> anyhow::Result<KeycloakSession> {
+        let admin_client_id = self.admin_client_id.as_deref().unwrap_or("admin-cli");
+        let session_client = KeycloakSessionClient::new_with_client(
+            config.address(),
+            self.admin_realm.as_deref().unwrap_or("master"),
+            admin_client_id,
+            client,
+        );
+        let (username, password) = match self.grant_type {
+            AdminGrantType::Password => (config.username(), config.password()),
+            AdminGrantType::ClientCredentials => (
+                admin_client_id,
+                config.admin_client_secret().ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "'KEYCLOAK_ADMIN_CLIENT_SECRET' is required for the client credentials grant"
+                    )
+                })?,
+            ),
+        };
+        KeycloakSession::new_with_grant_type(
+            session_client,
+            self.grant_type,
+            username,
+            password,
+            !self.no_refresh,
+        )
+        .await
+    }
+
+    fn http_client(&self) -> anyhow::Result<reqwest::Client> {
+        if let Some(client) = &self.http_client {
+            return Ok(client.clone());
+        }
+        let mut builder =
+            reqwest::Client::builder().timeout(self.timeout.unwrap_or(DEFAULT_TIMEOUT));
+        if let Some(connect_timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(connect_timeout);
+        }
+        if let Some(max) = self.pool_max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max);
+        }
+        Ok(builder.build()?)
+    }
+
     pub fn with_no_refresh(mut self) -> Self {
         self.no_refresh = true;
         self
@@ -73,15 +302,16 @@ impl KeycloakBuilder {
             config_builder = config_builder.with_prefix(prefix);
         }
         let config = config_builder.build()?;
-        let refresh_token_enabled = !self.no_refresh;
+        self.build_with_config(config).await
+    }
+
+    pub(crate) async fn build_with_config(
+        self,
+        config: KeycloakConfig,
+    ) -> anyhow::Result<Keycloak> {
         let url: Arc<str> = Arc::from(config.address().to_string());
-        let username: Arc<str> = Arc::from(config.username().to_string());
-        let password: Arc<str> = Arc::from(config.password().to_string());
-        let client = reqwest::Client::new();
-        let session_client = KeycloakSessionClient::new(config.address(), "master", "admin-cli");
-        let session =
-            KeycloakSession::new(session_client, &username, &password, refresh_token_enabled)
-                .await?;
+        let client = self.http_client()?;
+        let session = self.startup_session(&config, client.clone()).await?;
         Ok(Keycloak {
             inner: Arc::new(Inner {
                 url: url.clone(),
@@ -120,6 +350,40 @@ impl Keycloak {
         &self.inner.config
     }
 
+    /// Decoded claims of the admin session token.
+    pub async fn admin_token_claims(&self) -> Option<ParsedAccessToken> {
+        self.inner.session.current_claims().await
+    }
+
+    /// Checks with the claims of the admin session token whether it may manage `realm`, see
+    /// [ParsedAccessToken::can_manage_realm].
+    pub async fn admin_can_manage_realm(&self, realm: &str) -> anyhow::Result<bool> {
+        let claims = self
+            .admin_token_claims()
+            .await
+            .ok_or_else(|| anyhow::anyhow!("unable to decode the admin session token"))?;
+        Ok(claims.can_manage_realm(realm))
+    }
+
+    /// Discards the admin session token and acquires a new one with the configured credentials.
+    pub async fn reauthenticate(&self) -> anyhow::Result<()> {
+        self.inner.session.reauthenticate().await
+    }
+
+    /// Starts the background refresh of the admin session, e.g. after building with
+    /// [KeycloakBuilder::with_no_refresh]. Returns `false` if it is already running.
+    ///
+    /// See [KeycloakSession::start_refresh] for `interval` and `threshold`.
+    pub fn start_refresh(&self, interval: Option<Duration>, threshold: Duration) -> bool {
+        self.inner.session.start_refresh(interval, threshold)
+    }
+
+    /// Expiry of the admin session token, for callers that schedule their own refresh.
+    pub async fn token_expires_at(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        let exp = self.admin_token_claims().await?.exp();
+        chrono::DateTime::from_timestamp(i64::try_from(exp).ok()?, 0)
+    }
+
     pub async fn users(
         &self,
         realm: &str,
@@ -225,6 +489,11 @@ impl Keycloak {
     }
 
     pub async fn realms(&self) -> Result<Vec<String>, KeycloakError> {
+        self.realms_all(false).await
+    }
+
+    /// Lists the names of all realms, `master` only if `include_master` is set.
+    pub async fn realms_all(&self, include_master: bool) -> Result<Vec<String>, KeycloakError> {
         let builder = self
             .inner
             .client
@@ -242,16 +511,8 @@ impl Keycloak {
             .json::<Vec<ServerInfo>>()
             .await?
             .into_iter()
-            .filter_map(|r| {
-                if let Some(r) = r.realm {
-                    match r.as_str() {
-                        "master" => None,
-                        _ => Some(r),
-                    }
-                } else {
-                    None
-                }
-            })
+            .filter_map(|r| r.realm)
+            .filter(|r| include_master || r != "master")
             .collect())
     }
 
@@ -293,6 +554,11 @@ impl Keycloak {
         })
     }
 
+    /// Alias of the browser flow bound to the realm.
+    pub async fn realm_browser_flow(&self, realm: &str) -> Result<Option<String>, KeycloakError> {
+        Ok(self.realm_by_name(realm).await?.browser_flow)
+    }
+
     pub async fn update_realm_by_name(
         &self,
         realm: &str,
@@ -304,6 +570,17 @@ impl Keycloak {
         })
     }
 
+    /// Sets the given token and session lifespans on the realm, unset values are left untouched.
+    pub async fn set_token_lifespans(
+        &self,
+        realm: &str,
+        lifespans: &TokenLifespans,
+    ) -> Result<(), KeycloakError> {
+        let mut rep = self.realm_by_name(realm).await?;
+        lifespans.apply(&mut rep);
+        self.update_realm_by_name(realm, rep).await
+    }
+
     pub async fn roles(&self, realm: &str) -> Result<Vec<RoleRepresentation>, KeycloakError> {
         self.inner
             .admin
@@ -316,6 +593,22 @@ impl Keycloak {
     }
 
     pub async fn all_roles(&self, realm: &str) -> Result<Vec<RoleRepresentation>, KeycloakError> {
+        self.realm_roles_paged(realm, true).await
+    }
+
+    /// Returns all realm roles including their attributes.
+    pub async fn all_roles_full(
+        &self,
+        realm: &str,
+    ) -> Result<Vec<RoleRepresentation>, KeycloakError> {
+        self.realm_roles_paged(realm, false).await
+    }
+
+    async fn realm_roles_paged(
+        &self,
+        realm: &str,
+        brief_representation: bool,
+    ) -> Result<Vec<RoleRepresentation>, KeycloakError> {
         let page_offset = 1000;
         let mut offset = 0;
         let mut roles = vec![];
@@ -323,7 +616,13 @@ impl Keycloak {
             let result = self
                 .inner
                 .admin
-                .realm_roles_get(realm, Some(true), Some(offset), Some(page_offset), None)
+                .realm_roles_get(
+                    realm,
+                    Some(brief_representation),
+                    Some(offset),
+                    Some(page_offset),
+                    None,
+                )
                 .await
                 .map_err(|e| {
                     tracing::error!("{e:#?}");
@@ -338,6 +637,53 @@ impl Keycloak {
         Ok(roles)
     }
 
+    /// Returns all realm roles sorted by name, roles without a name first.
+    pub async fn all_roles_sorted(
+        &self,
+        realm: &str,
+    ) -> Result<Vec<RoleRepresentation>, KeycloakError> {
+        let mut roles = self.all_roles(realm).await?;
+        roles.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(roles)
+    }
+
+    /// Returns all top level groups of the realm sorted by path, groups without a path first.
+    pub async fn all_groups_sorted(
+        &self,
+        realm: &str,
+    ) -> Result<Vec<GroupRepresentation>, KeycloakError> {
+        let page_offset = 1000;
+        let mut offset = 0;
+        let mut groups = vec![];
+        loop {
+            let result = self
+                .inner
+                .admin
+                .realm_groups_get(
+                    realm,
+                    None,
+                    None,
+                    Some(offset),
+                    Some(page_offset),
+                    None,
+                    None,
+                    None,
+                )
+                .await
+                .map_err(|e| {
+                    tracing::error!("{e:#?}");
+                    e
+                })?;
+            if result.is_empty() {
+                break;
+            }
+            offset += page_offset;
+            groups.extend(result);
+        }
+        groups.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(groups)
+    }
+
     pub async fn realm_role_by_name(
         &self,
         realm: &str,
@@ -353,6 +699,49 @@ impl Keycloak {
             })
     }
 
+    pub async fn role_composites(
+        &self,
+        realm: &str,
+        role_name: &str,
+    ) -> Result<Vec<RoleRepresentation>, KeycloakError> {
+        self.inner
+            .admin
+            .realm_roles_with_role_name_composites_get(realm, role_name)
+            .await
+            .map_err(|e| {
+                tracing::error!("{e:#?}");
+                e
+            })
+    }
+
+    async fn role_composites_by_id(
+        &self,
+        realm: &str,
+        role_id: &str,
+    ) -> Result<Vec<RoleRepresentation>, KeycloakError> {
+        self.inner
+            .admin
+            .realm_roles_by_id_with_role_id_composites_get(realm, role_id, None, None, None)
+            .await
+            .map_err(|e| {
+                tracing::error!("{e:#?}");
+                e
+            })
+    }
+
+    /// Resolves a composite role transitively into the set of non-composite roles it grants.
+    pub async fn expand_role(
+        &self,
+        realm: &str,
+        role_name: &str,
+    ) -> Result<Vec<RoleRepresentation>, KeycloakError> {
+        let composites = self.role_composites(realm, role_name).await?;
+        expand_composites(composites, |id| async move {
+            self.role_composites_by_id(realm, &id).await
+        })
+        .await
+    }
+
     pub async fn create_role(
         &self,
         realm: &str,
@@ -398,72 +787,506 @@ impl Keycloak {
             })
     }
 
-    pub async fn role_members(
+    /// Returns the group at `path`, creating it first if it does not exist.
+    ///
+    /// Missing parent groups are created with their name only, `rep` is used for the group at
+    /// `path`, its name is taken from the last path segment.
+    pub async fn ensure_group(
         &self,
         realm: &str,
-        role_name: &str,
-    ) -> Result<Vec<UserRepresentation>, KeycloakError> {
-        self.inner
-            .admin
-            .realm_roles_with_role_name_users_get(realm, role_name, None, None, None)
+        path: &str,
+        rep: GroupRepresentation,
+    ) -> anyhow::Result<GroupRepresentation> {
+        let segments: Vec<&str> = path
+            .split('/')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .collect();
+        let Some((name, parents)) = segments.split_last() else {
+            anyhow::bail!("invalid group path '{path}'");
+        };
+        let path = format!("/{}", segments.join("/"));
+        if let Some(group) = self.find_group_by_path(realm, &path).await? {
+            return Ok(group);
+        }
+        let mut parent: Option<GroupRepresentation> = None;
+        let mut parent_path = String::new();
+        for parent_name in parents {
+            parent_path = format!("{parent_path}/{parent_name}");
+            let group = match self.find_group_by_path(realm, &parent_path).await? {
+                Some(group) => group,
+                None => {
+                    tracing::info!("create missing parent group '{parent_path}'");
+                    let rep = GroupRepresentation {
+                        name: Some(parent_name.to_string()),
+                        ..Default::default()
+                    };
+                    self.create_group_at(realm, parent.as_ref(), rep, &parent_path)
+                        .await?
+                }
+            };
+            parent = Some(group);
+        }
+        let rep = GroupRepresentation {
+            name: Some(name.to_string()),
+            ..rep
+        };
+        self.create_group_at(realm, parent.as_ref(), rep, &path)
             .await
-            .map_err(|e| {
-                tracing::error!("{e:#?}");
-                e
-            })
     }
 
-    pub async fn create_sub_group_with_id(
+    async fn find_group_by_path(
         &self,
         realm: &str,
-        parent_id: &str,
-        rep: GroupRepresentation,
-    ) -> Result<(), KeycloakError> {
-        self.inner
+        path: &str,
+    ) -> Result<Option<GroupRepresentation>, KeycloakError> {
+        match self
+            .inner
             .admin
-            .realm_groups_with_group_id_children_post(realm, parent_id, rep)
+            .realm_group_by_path_with_path_get(realm, path)
             .await
-            .map_err(|e| {
+        {
+            Ok(group) => Ok(Some(group)),
+            Err(KeycloakError::HttpFailure { status: 404, .. }) => Ok(None),
+            Err(e) => {
                 tracing::error!("{e:#?}");
-                e
-            })?;
-        Ok(())
+                Err(e)
+            }
+        }
     }
 
-    pub async fn create_realm_role_mappings_by_group_id(
+    async fn create_group_at(
         &self,
         realm: &str,
-        id: &str,
-        roles: Vec<RoleRepresentation>,
-    ) -> Result<Option<String>, KeycloakError> {
-        self.inner
+        parent: Option<&GroupRepresentation>,
+        rep: GroupRepresentation,
+        path: &str,
+    ) -> anyhow::Result<GroupRepresentation> {
+        match parent {
+            Some(parent) => {
+                let parent_id = parent
+                    .id
+                    .as_deref()
+                    .ok_or_else(|| anyhow::anyhow!("parent group of '{path}' has no id"))?;
+                self.create_sub_group_with_id(realm, parent_id, rep).await?;
+            }
+            None => {
+                self.create_group(realm, rep).await?;
+            }
+        }
+        self.find_group_by_path(realm, path)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("group '{path}' not found after creation"))
+    }
+
+    /// Direct children of the group, fetched page by page.
+    pub async fn group_children(
+        &self,
+        realm: &str,
+        group_id: &str,
+    ) -> Result<Vec<GroupRepresentation>, KeycloakError> {
+        let page_offset = 1000;
+        let mut offset = 0;
+        let mut groups = vec![];
+        loop {
+            let result = self
+                .inner
+                .admin
+                .realm_groups_with_group_id_children_get(
+                    realm,
+                    group_id,
+                    None,
+                    None,
+                    Some(offset),
+                    Some(page_offset),
+                    None,
+                )
+                .await
+                .map_err(|e| {
+                    tracing::error!("{e:#?}");
+                    e
+                })?;
+            if result.is_empty() {
+                break;
+            }
+            offset += page_offset;
+            groups.extend(result);
+        }
+        Ok(groups)
+    }
+
+    /// The group with `sub_groups` populated recursively, up to a depth of 32 levels.
+    pub async fn group_subtree(
+        &self,
+        realm: &str,
+        group_id: &str,
+    ) -> Result<GroupRepresentation, KeycloakError> {
+        let mut group = self
+            .inner
             .admin
-            .realm_groups_with_group_id_role_mappings_realm_post(realm, id, roles)
+            .realm_groups_with_group_id_get(realm, group_id)
             .await
             .map_err(|e| {
                 tracing::error!("{e:#?}");
                 e
-            })
+            })?;
+        self.fill_sub_groups(realm, &mut group, 1).await?;
+        Ok(group)
     }
 
-    pub async fn user_by_id(
+    fn fill_sub_groups<'a>(
+        &'a self,
+        realm: &'a str,
+        group: &'a mut GroupRepresentation,
+        depth: usize,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), KeycloakError>> + Send + 'a>>
+    {
+        Box::pin(async move {
+            let Some(group_id) = group.id.as_deref() else {
+                return Ok(());
+            };
+            if depth >= MAX_GROUP_DEPTH {
+                tracing::warn!("group '{group_id}' exceeds the max depth of {MAX_GROUP_DEPTH}");
+                return Ok(());
+            }
+            let mut children = self.group_children(realm, group_id).await?;
+            for child in children.iter_mut() {
+                self.fill_sub_groups(realm, child, depth + 1).await?;
+            }
+            group.sub_groups = Some(children);
+            Ok(())
+        })
+    }
+
+    /// Realm roles mapped to the group.
+    pub async fn group_realm_role_mappings(
         &self,
         realm: &str,
-        id: &str,
-    ) -> Result<Option<UserRepresentation>, KeycloakError> {
-        Ok(self
-            .inner
+        group_id: &str,
+    ) -> Result<Vec<RoleRepresentation>, KeycloakError> {
+        self.inner
             .admin
-            .realm_users_with_user_id_get(realm, id, Some(true))
+            .realm_groups_with_group_id_role_mappings_realm_get(realm, group_id)
             .await
             .map_err(|e| {
                 tracing::error!("{e:#?}");
                 e
             })
-            .ok())
     }
 
-    pub async fn user_by_role(
+    fn fill_group_realm_roles<'a>(
+        &'a self,
+        realm: &'a str,
+        group: &'a mut GroupRepresentation,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), KeycloakError>> + Send + 'a>>
+    {
+        Box::pin(async move {
+            if let Some(group_id) = group.id.as_deref() {
+                let roles = self.group_realm_role_mappings(realm, group_id).await?;
+                group.realm_roles = Some(roles.into_iter().filter_map(|r| r.name).collect());
+            }
+            for sub_group in group.sub_groups.iter_mut().flatten() {
+                self.fill_group_realm_roles(realm, sub_group).await?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Copies the realm roles and the group tree including the realm role mappings of the
+    /// groups from `from_realm` to `to_realm`. Users and composites of roles are not copied.
+    ///
+    /// Roles and groups which already exist in `to_realm` are skipped.
+    pub async fn clone_realm_structure(
+        &self,
+        from_realm: &str,
+        to_realm: &str,
+    ) -> Result<(), KeycloakError> {
+        let default_roles = format!("default-roles-{from_realm}");
+        let roles: Vec<RoleRepresentation> = self
+            .all_roles_full(from_realm)
+            .await?
+            .into_iter()
+            .filter(|r| r.name.as_ref().is_some_and(|name| *name != default_roles))
+            .map(|r| RoleRepresentation {
+                name: r.name,
+                description: r.description,
+                attributes: r.attributes,
+                ..Default::default()
+            })
+            .collect();
+        let mut groups = vec![];
+        for group in self.all_groups_sorted(from_realm).await? {
+            let Some(group_id) = group.id.as_deref() else {
+                continue;
+            };
+            let mut group = self.group_subtree(from_realm, group_id).await?;
+            self.fill_group_realm_roles(from_realm, &mut group).await?;
+            groups.push(copy_group(group));
+        }
+        let body = serde_json::json!({
+            "ifResourceExists": "SKIP",
+            "roles": { "realm": roles },
+            "groups": groups,
+        });
+        // the generated `realm_partial_import_post` sends the body as json string
+        let response = self
+            .inner
+            .client
+            .post(format!(
+                "{}admin/realms/{to_realm}/partialImport",
+                &self.inner.url
+            ))
+            .json(&body)
+            .bearer_auth(self.inner.session.get(&self.inner.url).await?)
+            .send()
+            .await
+            .map_err(|e| {
+                tracing::error!("{e:#?}");
+                e
+            })?;
+        error_check(response).await?;
+        Ok(())
+    }
+
+    /// Sets the given attributes on the group, attributes not mentioned keep their values.
+    pub async fn set_group_attributes(
+        &self,
+        realm: &str,
+        group_id: &str,
+        attributes: HashMap<String, Vec<String>>,
+    ) -> Result<(), KeycloakError> {
+        let mut group = self
+            .inner
+            .admin
+            .realm_groups_with_group_id_get(realm, group_id)
+            .await
+            .map_err(|e| {
+                tracing::error!("{e:#?}");
+                e
+            })?;
+        merge_attributes(&mut group.attributes, attributes);
+        self.inner
+            .admin
+            .realm_groups_with_group_id_put(realm, group_id, group)
+            .await
+            .map_err(|e| {
+                tracing::error!("{e:#?}");
+                e
+            })
+    }
+
+    pub async fn role_members(
+        &self,
+        realm: &str,
+        role_name: &str,
+    ) -> Result<Vec<UserRepresentation>, KeycloakError> {
+        self.inner
+            .admin
+            .realm_roles_with_role_name_users_get(realm, role_name, None, None, None)
+            .await
+            .map_err(|e| {
+                tracing::error!("{e:#?}");
+                e
+            })
+    }
+
+    /// All users which have the realm role `role_name` mapped directly, fetched page by page.
+    pub async fn all_role_members(
+        &self,
+        realm: &str,
+        role_name: &str,
+    ) -> Result<Vec<UserRepresentation>, KeycloakError> {
+        let page_offset = 1000;
+        let mut offset = 0;
+        let mut users = vec![];
+        loop {
+            let result = self
+                .inner
+                .admin
+                .realm_roles_with_role_name_users_get(
+                    realm,
+                    role_name,
+                    Some(true),
+                    Some(offset),
+                    Some(page_offset),
+                )
+                .await
+                .map_err(|e| {
+                    tracing::error!("{e:#?}");
+                    e
+                })?;
+            if result.is_empty() {
+                break;
+            }
+            offset += page_offset;
+            users.extend(result);
+        }
+        Ok(users)
+    }
+
+    /// One page of direct members of the group.
+    pub async fn group_members(
+        &self,
+        realm: &str,
+        group_id: &str,
+        first: Option<i32>,
+        max: Option<i32>,
+        brief_representation: Option<bool>,
+    ) -> Result<Vec<UserRepresentation>, KeycloakError> {
+        self.inner
+            .admin
+            .realm_groups_with_group_id_members_get(
+                realm,
+                group_id,
+                brief_representation,
+                first,
+                max,
+            )
+            .await
+            .map_err(|e| {
+                tracing::error!("{e:#?}");
+                e
+            })
+    }
+
+    /// All direct members of the group, fetched page by page.
+    pub async fn all_group_members(
+        &self,
+        realm: &str,
+        group_id: &str,
+    ) -> Result<Vec<UserRepresentation>, KeycloakError> {
+        let page_offset = 1000;
+        let mut offset = 0;
+        let mut users = vec![];
+        loop {
+            let result = self
+                .group_members(realm, group_id, Some(offset), Some(page_offset), Some(true))
+                .await?;
+            if result.is_empty() {
+                break;
+            }
+            offset += page_offset;
+            users.extend(result);
+        }
+        Ok(users)
+    }
+
+    /// Adds every user holding the realm role `role_name` to the group.
+    ///
+    /// Users which are already members are skipped. Returns the ids of the added users.
+    pub async fn add_role_holders_to_group(
+        &self,
+        realm: &str,
+        role_name: &str,
+        group_id: &str,
+    ) -> Result<Vec<String>, KeycloakError> {
+        let mut members: HashSet<String> = self
+            .all_group_members(realm, group_id)
+            .await?
+            .into_iter()
+            .filter_map(|user| user.id)
+            .collect();
+        let mut added = vec![];
+        for user_id in self
+            .all_role_members(realm, role_name)
+            .await?
+            .into_iter()
+            .filter_map(|user| user.id)
+        {
+            if !members.insert(user_id.clone()) {
+                continue;
+            }
+            self.add_user_to_group(realm, &user_id, group_id).await?;
+            added.push(user_id);
+        }
+        Ok(added)
+    }
+
+    /// Groups which have the realm role `role_name` mapped directly.
+    pub async fn groups_with_role(
+        &self,
+        realm: &str,
+        role_name: &str,
+    ) -> Result<Vec<GroupRepresentation>, KeycloakError> {
+        let page_offset = 1000;
+        let mut offset = 0;
+        let mut groups = vec![];
+        loop {
+            let result = self
+                .inner
+                .admin
+                .realm_roles_with_role_name_groups_get(
+                    realm,
+                    role_name,
+                    None,
+                    Some(offset),
+                    Some(page_offset),
+                )
+                .await
+                .map_err(|e| {
+                    tracing::error!("{e:#?}");
+                    e
+                })?;
+            if result.is_empty() {
+                break;
+            }
+            offset += page_offset;
+            groups.extend(result);
+        }
+        Ok(groups)
+    }
+
+    pub async fn create_sub_group_with_id(
+        &self,
+        realm: &str,
+        parent_id: &str,
+        rep: GroupRepresentation,
+    ) -> Result<(), KeycloakError> {
+        self.inner
+            .admin
+            .realm_groups_with_group_id_children_post(realm, parent_id, rep)
+            .await
+            .map_err(|e| {
+                tracing::error!("{e:#?}");
+                e
+            })?;
+        Ok(())
+    }
+
+    pub async fn create_realm_role_mappings_by_group_id(
+        &self,
+        realm: &str,
+        id: &str,
+        roles: Vec<RoleRepresentation>,
+    ) -> Result<Option<String>, KeycloakError> {
+        self.inner
+            .admin
+            .realm_groups_with_group_id_role_mappings_realm_post(realm, id, roles)
+            .await
+            .map_err(|e| {
+                tracing::error!("{e:#?}");
+                e
+            })
+    }
+
+    pub async fn user_by_id(
+        &self,
+        realm: &str,
+        id: &str,
+    ) -> Result<Option<UserRepresentation>, KeycloakError> {
+        Ok(self
+            .inner
+            .admin
+            .realm_users_with_user_id_get(realm, id, Some(true))
+            .await
+            .map_err(|e| {
+                tracing::error!("{e:#?}");
+                e
+            })
+            .ok())
+    }
+
+    pub async fn user_by_role(
         &self,
         realm: &str,
         role_name: &str,
@@ -487,6 +1310,136 @@ impl Keycloak {
             }))
     }
 
+    /// Finds the user linked to `federated_user_id` of the identity provider `provider`.
+    pub async fn user_by_federated_identity(
+        &self,
+        realm: &str,
+        provider: &str,
+        federated_user_id: &str,
+    ) -> Result<Option<UserRepresentation>, KeycloakError> {
+        let users = self
+            .inner
+            .admin
+            .realm_users_get(
+                realm,
+                Some(false),
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                Some(provider.to_string()),
+                Some(federated_user_id.to_string()),
+                None,
+                None,
+                None,
+                None,
+                None,
+            )
+            .await
+            .map_err(|e| {
+                tracing::error!("{e:#?}");
+                e
+            })?;
+        Ok(users.into_iter().next())
+    }
+
+    /// Removes the link between the user and the identity provider, a missing link is not an error.
+    pub async fn remove_federated_identity(
+        &self,
+        realm: &str,
+        user_id: &str,
+        provider: &str,
+    ) -> Result<(), KeycloakError> {
+        match self
+            .inner
+            .admin
+            .realm_users_with_user_id_federated_identity_with_provider_delete(
+                realm, user_id, provider,
+            )
+            .await
+        {
+            Ok(()) | Err(KeycloakError::HttpFailure { status: 404, .. }) => Ok(()),
+            Err(e) => {
+                tracing::error!("{e:#?}");
+                Err(e)
+            }
+        }
+    }
+
+    /// Checks if a user with `username` exists, usernames are compared case-insensitively.
+    pub async fn username_exists(
+        &self,
+        realm: &str,
+        username: &str,
+    ) -> Result<bool, KeycloakError> {
+        let users = self
+            .inner
+            .admin
+            .realm_users_get(
+                realm,
+                Some(true),
+                None,
+                None,
+                None,
+                Some(true),
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                Some(username.to_string()),
+            )
+            .await
+            .map_err(|e| {
+                tracing::error!("{e:#?}");
+                e
+            })?;
+        Ok(users.iter().any(|u| {
+            u.username
+                .as_deref()
+                .is_some_and(|v| v.eq_ignore_ascii_case(username))
+        }))
+    }
+
+    /// Checks if a user with `email` exists, emails are compared case-insensitively.
+    pub async fn email_exists(&self, realm: &str, email: &str) -> Result<bool, KeycloakError> {
+        let users = self
+            .inner
+            .admin
+            .realm_users_get(
+                realm,
+                Some(true),
+                Some(email.to_string()),
+                None,
+                None,
+                Some(true),
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+            )
+            .await
+            .map_err(|e| {
+                tracing::error!("{e:#?}");
+                e
+            })?;
+        Ok(users.iter().any(|u| {
+            u.email
+                .as_deref()
+                .is_some_and(|v| v.eq_ignore_ascii_case(email))
+        }))
+    }
+
     pub async fn user_by_username(
         &self,
         realm: &str,
@@ -527,6 +1480,78 @@ impl Keycloak {
             }))
     }
 
+    /// Same as [`Self::user_by_username`], but re-fetches the user by id so the
+    /// full representation including the attributes is returned.
+    pub async fn user_by_username_full(
+        &self,
+        realm: &str,
+        username: String,
+    ) -> Result<Option<UserRepresentation>, KeycloakError> {
+        let Some(user) = self.user_by_username(realm, username).await? else {
+            return Ok(None);
+        };
+        match user.id.as_deref() {
+            Some(id) => self.user_by_id(realm, id).await,
+            None => Ok(Some(user)),
+        }
+    }
+
+    /// Exchanges the admin session token for an access token of the user
+    /// `user_id` in `realm`.
+    ///
+    /// Requires `KEYCLOAK_IMPERSONATION_ENABLED=true` and a confidential client with token
+    /// exchange permission in `realm`, configured with `KEYCLOAK_IMPERSONATION_CLIENT_ID` and
+    /// `KEYCLOAK_IMPERSONATION_CLIENT_SECRET`. The token is exchanged at the realm which issued
+    /// the admin session token, so the admin session has to belong to `realm`, see
+    /// [KeycloakBuilder::with_admin_realm].
+    pub async fn impersonate(&self, realm: &str, user_id: &str) -> anyhow::Result<String> {
+        let config = &self.inner.config;
+        if !config.impersonation_enabled() {
+            anyhow::bail!(
+                "impersonation is disabled, set 'KEYCLOAK_IMPERSONATION_ENABLED=true' to enable it"
+            );
+        }
+        let (Some(client_id), Some(client_secret)) = (
+            config.impersonation_client_id(),
+            config.impersonation_client_secret(),
+        ) else {
+            anyhow::bail!(
+                "impersonation requires 'KEYCLOAK_IMPERSONATION_CLIENT_ID' and 'KEYCLOAK_IMPERSONATION_CLIENT_SECRET'"
+            );
+        };
+        let claims = self
+            .admin_token_claims()
+            .await
+            .ok_or_else(|| anyhow::anyhow!("unable to decode the admin session token"))?;
+        let token_realm = claims
+            .realm()
+            .ok_or_else(|| anyhow::anyhow!("the admin session token has no issuer"))?;
+        if token_realm != realm {
+            anyhow::bail!(
+                "unable to impersonate users of realm '{realm}' with an admin session of realm '{token_realm}'"
+            );
+        }
+        tracing::warn!(
+            "audit: '{}' impersonates user '{user_id}' in realm '{realm}'",
+            config.username()
+        );
+        let subject_token = self.inner.session.get(&self.inner.url).await?;
+        Ok(exchange_token(
+            &self.inner.client,
+            &self.inner.url,
+            token_realm,
+            client_id,
+            client_secret,
+            &subject_token,
+            user_id,
+        )
+        .await
+        .map_err(|e| {
+            tracing::error!("{e:#?}");
+            e
+        })?)
+    }
+
     pub async fn info(&self, realm: &str) -> Result<RealmInfo, KeycloakError> {
         let builder = self
             .inner
@@ -592,6 +1617,41 @@ impl Keycloak {
             .pop())
     }
 
+    /// Returns every client of the realm, including the built-in ones, fetched page by page.
+    pub async fn all_clients(
+        &self,
+        realm: &str,
+    ) -> Result<Vec<ClientRepresentation>, KeycloakError> {
+        self.clients(realm).await
+    }
+
+    /// Looks up a client by its exact `clientId` (not the internal uuid).
+    pub async fn client_by_client_id(
+        &self,
+        realm: &str,
+        client_id: &str,
+    ) -> Result<Option<ClientRepresentation>, KeycloakError> {
+        Ok(self
+            .inner
+            .admin
+            .realm_clients_get(
+                realm,
+                Some(client_id.to_owned()),
+                None,
+                None,
+                None,
+                Some(false),
+                Some(false),
+            )
+            .await
+            .map_err(|e| {
+                tracing::error!("{e:#?}");
+                e
+            })?
+            .into_iter()
+            .find(|c| c.client_id.as_deref() == Some(client_id)))
+    }
+
     pub async fn get_client_service_account(
         &self,
         realm: &str,
@@ -599,7 +1659,126 @@ impl Keycloak {
     ) -> Result<UserRepresentation, KeycloakError> {
         self.inner
             .admin
-            .realm_clients_with_client_uuid_service_account_user_get(realm, client_uuid)
+            .realm_clients_with_client_uuid_service_account_user_get(realm, client_uuid)
+            .await
+            .map_err(|e| {
+                tracing::error!("{e:#?}");
+                e
+            })
+    }
+
+    pub async fn create_client(
+        &self,
+        realm: &str,
+        rep: ClientRepresentation,
+    ) -> Result<(), KeycloakError> {
+        self.inner
+            .admin
+            .realm_clients_post(realm, rep)
+            .await
+            .map_err(|e| {
+                tracing::error!("{e:#?}");
+                e
+            })?;
+        Ok(())
+    }
+
+    pub async fn remove_client(&self, realm: &str, client_id: &str) -> Result<(), KeycloakError> {
+        let client = self
+            .get_client_by_id(realm, client_id)
+            .await?
+            .ok_or_else(|| KeycloakError::HttpFailure {
+                status: 404,
+                body: None,
+                text: format!("client with id: '{client_id}' not found"),
+            })?;
+        self.inner
+            .admin
+            .realm_clients_with_client_uuid_delete(realm, &client.id.unwrap())
+            .await
+            .map_err(|e| {
+                tracing::error!("{e:#?}");
+                e
+            })?;
+        Ok(())
+    }
+
+    pub async fn remove_client_with_uuid(
+        &self,
+        realm: &str,
+        client_uuid: &str,
+    ) -> Result<(), KeycloakError> {
+        self.inner
+            .admin
+            .realm_clients_with_client_uuid_delete(realm, client_uuid)
+            .await
+            .map_err(|e| {
+                tracing::error!("{e:#?}");
+                e
+            })?;
+        Ok(())
+    }
+
+    /// Returns all offline sessions of the client with the internal id `client_uuid`.
+    pub async fn client_offline_sessions(
+        &self,
+        realm: &str,
+        client_uuid: &str,
+    ) -> Result<Vec<UserSessionRepresentation>, KeycloakError> {
+        let mut sessions = vec![];
+        loop {
+            let page = self
+                .inner
+                .admin
+                .realm_clients_with_client_uuid_offline_sessions_get(
+                    realm,
+                    client_uuid,
+                    Some(sessions.len() as i32),
+                    Some(SESSION_PAGE_SIZE),
+                )
+                .await
+                .map_err(|e| {
+                    tracing::error!("{e:#?}");
+                    e
+                })?;
+            let done = page.len() < SESSION_PAGE_SIZE as usize;
+            sessions.extend(page);
+            if done {
+                return Ok(sessions);
+            }
+        }
+    }
+
+    /// Removes all offline sessions of the client with the internal id `client_uuid`, returns
+    /// the number of removed sessions.
+    pub async fn revoke_client_sessions(
+        &self,
+        realm: &str,
+        client_uuid: &str,
+    ) -> Result<usize, KeycloakError> {
+        let sessions = self.client_offline_sessions(realm, client_uuid).await?;
+        for session_id in sessions.iter().filter_map(|s| s.id.as_deref()) {
+            self.inner
+                .admin
+                .realm_sessions_with_session_delete(realm, session_id, Some(true))
+                .await
+                .map_err(|e| {
+                    tracing::error!("{e:#?}");
+                    e
+                })?;
+        }
+        Ok(sessions.len())
+    }
+
+    pub async fn update_client(
+        &self,
+        realm: &str,
+        id: &str,
+        rep: ClientRepresentation,
+    ) -> Result<(), KeycloakError> {
+        self.inner
+            .admin
+            .realm_clients_with_client_uuid_put(realm, id, rep)
             .await
             .map_err(|e| {
                 tracing::error!("{e:#?}");
@@ -607,14 +1786,15 @@ impl Keycloak {
             })
     }
 
-    pub async fn create_client(
+    pub async fn create_client_protocol_mapper(
         &self,
         realm: &str,
-        rep: ClientRepresentation,
+        client_uuid: &str,
+        rep: ProtocolMapperRepresentation,
     ) -> Result<(), KeycloakError> {
         self.inner
             .admin
-            .realm_clients_post(realm, rep)
+            .realm_clients_with_client_uuid_protocol_mappers_models_post(realm, client_uuid, rep)
             .await
             .map_err(|e| {
                 tracing::error!("{e:#?}");
@@ -623,18 +1803,14 @@ impl Keycloak {
         Ok(())
     }
 
-    pub async fn remove_client(&self, realm: &str, client_id: &str) -> Result<(), KeycloakError> {
-        let client = self
-            .get_client_by_id(realm, client_id)
-            .await?
-            .ok_or_else(|| KeycloakError::HttpFailure {
-                status: 404,
-                body: None,
-                text: format!("client with id: '{client_id}' not found"),
-            })?;
+    pub async fn create_user(
+        &self,
+        realm: &str,
+        user: UserRepresentation,
+    ) -> Result<(), KeycloakError> {
         self.inner
             .admin
-            .realm_clients_with_client_uuid_delete(realm, &client.id.unwrap())
+            .realm_users_post(realm, user)
             .await
             .map_err(|e| {
                 tracing::error!("{e:#?}");
@@ -643,14 +1819,15 @@ impl Keycloak {
         Ok(())
     }
 
-    pub async fn remove_client_with_uuid(
+    pub async fn update_password(
         &self,
         realm: &str,
-        client_uuid: &str,
+        user_id: &str,
+        credential: CredentialRepresentation,
     ) -> Result<(), KeycloakError> {
         self.inner
             .admin
-            .realm_clients_with_client_uuid_delete(realm, client_uuid)
+            .realm_users_with_user_id_reset_password_put(realm, user_id, credential)
             .await
             .map_err(|e| {
                 tracing::error!("{e:#?}");
@@ -659,30 +1836,26 @@ impl Keycloak {
         Ok(())
     }
 
-    pub async fn update_client(
+    /// Sets a password which the user has to change on the next login.
+    pub async fn set_temporary_password(
         &self,
         realm: &str,
-        id: &str,
-        rep: ClientRepresentation,
+        user_id: &str,
+        password: &str,
     ) -> Result<(), KeycloakError> {
-        self.inner
-            .admin
-            .realm_clients_with_client_uuid_put(realm, id, rep)
+        self.update_password(realm, user_id, password_credential(password, true))
             .await
-            .map_err(|e| {
-                tracing::error!("{e:#?}");
-                e
-            })
     }
 
-    pub async fn create_user(
+    pub async fn update_user(
         &self,
         realm: &str,
-        user: UserRepresentation,
+        user_id: &str,
+        user: &UserRepresentation,
     ) -> Result<(), KeycloakError> {
         self.inner
             .admin
-            .realm_users_post(realm, user)
+            .realm_users_with_user_id_put(realm, user_id, user.to_owned())
             .await
             .map_err(|e| {
                 tracing::error!("{e:#?}");
@@ -691,32 +1864,84 @@ impl Keycloak {
         Ok(())
     }
 
-    pub async fn update_password(
+    pub async fn user_credentials(
         &self,
         realm: &str,
         user_id: &str,
-        credential: CredentialRepresentation,
-    ) -> Result<(), KeycloakError> {
+    ) -> Result<Vec<CredentialRepresentation>, KeycloakError> {
         self.inner
             .admin
-            .realm_users_with_user_id_reset_password_put(realm, user_id, credential)
+            .realm_users_with_user_id_credentials_get(realm, user_id)
             .await
             .map_err(|e| {
                 tracing::error!("{e:#?}");
                 e
-            })?;
+            })
+    }
+
+    /// Removes all OTP credentials of the user, so a new device can be configured.
+    pub async fn remove_totp(&self, realm: &str, user_id: &str) -> Result<(), KeycloakError> {
+        let credentials = self.user_credentials(realm, user_id).await?;
+        for credential in credentials {
+            if credential.type_.as_deref() != Some("otp") {
+                continue;
+            }
+            let Some(credential_id) = credential.id.as_deref() else {
+                continue;
+            };
+            self.inner
+                .admin
+                .realm_users_with_user_id_credentials_with_credential_id_delete(
+                    realm,
+                    user_id,
+                    credential_id,
+                )
+                .await
+                .map_err(|e| {
+                    tracing::error!("{e:#?}");
+                    e
+                })?;
+        }
         Ok(())
     }
 
-    pub async fn update_user(
+    /// Replaces the required actions of the user.
+    pub async fn set_required_actions(
         &self,
         realm: &str,
         user_id: &str,
-        user: &UserRepresentation,
+        actions: Vec<RequiredUserAction>,
+    ) -> Result<(), KeycloakError> {
+        let user = UserRepresentation {
+            required_actions: Some(actions.iter().map(ToString::to_string).collect()),
+            ..UserRepresentation::default()
+        };
+        self.update_user(realm, user_id, &user).await
+    }
+
+    /// Sets the `emailVerified` flag of the user without touching other fields.
+    pub async fn set_email_verified(
+        &self,
+        realm: &str,
+        user_id: &str,
+        verified: bool,
+    ) -> Result<(), KeycloakError> {
+        let user = UserRepresentation {
+            email_verified: Some(verified),
+            ..UserRepresentation::default()
+        };
+        self.update_user(realm, user_id, &user).await
+    }
+
+    pub async fn add_user_to_group(
+        &self,
+        realm: &str,
+        user_id: &str,
+        group_id: &str,
     ) -> Result<(), KeycloakError> {
         self.inner
             .admin
-            .realm_users_with_user_id_put(realm, user_id, user.to_owned())
+            .realm_users_with_user_id_groups_with_group_id_put(realm, user_id, group_id)
             .await
             .map_err(|e| {
                 tracing::error!("{e:#?}");
@@ -725,21 +1950,20 @@ impl Keycloak {
         Ok(())
     }
 
-    pub async fn add_user_to_group(
+    /// Groups the user is a direct member of.
+    pub async fn user_groups(
         &self,
         realm: &str,
         user_id: &str,
-        group_id: &str,
-    ) -> Result<(), KeycloakError> {
+    ) -> Result<Vec<GroupRepresentation>, KeycloakError> {
         self.inner
             .admin
-            .realm_users_with_user_id_groups_with_group_id_put(realm, user_id, group_id)
+            .realm_users_with_user_id_groups_get(realm, user_id, Some(false), None, None, None)
             .await
             .map_err(|e| {
                 tracing::error!("{e:#?}");
                 e
-            })?;
-        Ok(())
+            })
     }
 
     pub async fn add_user_role(
@@ -758,6 +1982,22 @@ impl Keycloak {
             })
     }
 
+    pub async fn remove_user_role(
+        &self,
+        realm: &str,
+        user_id: &str,
+        role: RoleRepresentation,
+    ) -> Result<(), KeycloakError> {
+        self.inner
+            .admin
+            .realm_users_with_user_id_role_mappings_realm_delete(realm, user_id, vec![role])
+            .await
+            .map_err(|e| {
+                tracing::error!("{e:#?}");
+                e
+            })
+    }
+
     pub async fn remove_user_from_group(
         &self,
         realm: &str,
@@ -1014,3 +2254,1142 @@ impl Keycloak {
         Ok(())
     }
 }
+
+/// The group without ids, so it can be imported into another realm.
+fn copy_group(group: GroupRepresentation) -> GroupRepresentation {
+    GroupRepresentation {
+        name: group.name,
+        path: group.path,
+        attributes: group.attributes,
+        realm_roles: group.realm_roles,
+        sub_groups: group
+            .sub_groups
+            .map(|sub_groups| sub_groups.into_iter().map(copy_group).collect()),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    fn role(id: &str, composite: bool) -> RoleRepresentation {
+        RoleRepresentation {
+            id: Some(id.to_string()),
+            name: Some(id.to_string()),
+            composite: Some(composite),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_expand_composites() {
+        let composites = HashMap::from([
+            (
+                "b",
+                vec![role("c", false), role("d", false), role("a", true)],
+            ),
+            ("a", vec![role("b", true), role("c", false)]),
+        ]);
+        let mut roles = expand_composites(vec![role("b", true), role("e", false)], |id| {
+            let result = composites.get(id.as_str()).cloned().unwrap_or_default();
+            async move { Ok(result) }
+        })
+        .await
+        .unwrap()
+        .into_iter()
+        .filter_map(|r| r.name)
+        .collect::<Vec<_>>();
+        roles.sort();
+        assert_eq!(roles, vec!["c", "d", "e"]);
+    }
+
+    #[tokio::test]
+    async fn test_builder_timeout() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            // accept connections but never answer
+            let mut sockets = vec![];
+            while let Ok((socket, _)) = listener.accept().await {
+                sockets.push(socket);
+            }
+        });
+        let client = KeycloakBuilder::default()
+            .with_timeout(Duration::from_millis(100))
+            .with_connect_timeout(Duration::from_millis(100))
+            .with_pool_max_idle_per_host(1)
+            .http_client()
+            .unwrap();
+        let err = client
+            .get(format!("http://{addr}/"))
+            .send()
+            .await
+            .unwrap_err();
+        assert!(err.is_timeout());
+    }
+
+    #[tokio::test]
+    async fn test_exchange_token() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let request = read_request(&mut socket).await;
+            respond(&mut socket, "200 OK", r#"{"access_token":"user-token"}"#).await;
+            request
+        });
+        let token = exchange_token(
+            &reqwest::Client::new(),
+            &format!("http://{addr}/"),
+            "test",
+            "impersonator",
+            "secret",
+            "admin-token",
+            "user-id",
+        )
+        .await
+        .unwrap();
+        assert_eq!(token, "user-token");
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /realms/test/protocol/openid-connect/token "));
+        let (_, body) = request.split_once("\r\n\r\n").unwrap();
+        let params: HashMap<&str, &str> =
+            body.split('&').filter_map(|p| p.split_once('=')).collect();
+        assert_eq!(
+            params.get("grant_type"),
+            Some(&"urn%3Aietf%3Aparams%3Aoauth%3Agrant-type%3Atoken-exchange")
+        );
+        assert_eq!(params.get("client_id"), Some(&"impersonator"));
+        assert_eq!(params.get("client_secret"), Some(&"secret"));
+        assert_eq!(params.get("subject_token"), Some(&"admin-token"));
+        assert_eq!(params.get("requested_subject"), Some(&"user-id"));
+    }
+
+    #[tokio::test]
+    async fn test_impersonate() {
+        let requests = Arc::new(std::sync::Mutex::new(vec![]));
+        let recorded = requests.clone();
+        let config = serde_json::json!({
+            "impersonation_enabled": true,
+            "impersonation_client_id": "impersonator",
+            "impersonation_client_secret": "secret",
+        });
+        let keycloak = mock_keycloak_with_config(config, move |route, body| {
+            recorded
+                .lock()
+                .unwrap()
+                .push((route.to_string(), body.to_string()));
+            Some(r#"{"access_token":"user-token"}"#.to_string())
+        })
+        .await;
+        // the admin session of the mock is issued by the master realm
+        assert_eq!(
+            keycloak.impersonate("master", "user-id").await.unwrap(),
+            "user-token"
+        );
+        assert!(keycloak.impersonate("test", "user-id").await.is_err());
+        let requests = requests.lock().unwrap().clone();
+        assert_eq!(requests.len(), 1);
+        let (route, body) = &requests[0];
+        assert_eq!(route, "POST /realms/master/protocol/openid-connect/token");
+        let params: HashMap<&str, &str> =
+            body.split('&').filter_map(|p| p.split_once('=')).collect();
+        assert_eq!(params.get("client_id"), Some(&"impersonator"));
+        assert_eq!(params.get("client_secret"), Some(&"secret"));
+        assert_eq!(params.get("requested_subject"), Some(&"user-id"));
+
+        let keycloak = mock_keycloak_with_config(
+            serde_json::json!({
+                "impersonation_enabled": true,
+                "impersonation_client_id": "admin-cli",
+            }),
+            |_, _| None,
+        )
+        .await;
+        assert!(keycloak.impersonate("master", "user-id").await.is_err());
+        let keycloak = mock_keycloak(HashMap::new()).await;
+        assert!(keycloak.impersonate("master", "user-id").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_groups_with_role() {
+        let keycloak = mock_keycloak(HashMap::from([
+            (
+                "GET /admin/realms/test/roles/deprecated/groups?first=0&max=1000",
+                r#"[{"id":"1","name":"a"},{"id":"3","name":"c"}]"#,
+            ),
+            (
+                "GET /admin/realms/test/roles/deprecated/groups?first=1000&max=1000",
+                "[]",
+            ),
+            (
+                "GET /admin/realms/test/roles/other/groups?first=0&max=1000",
+                r#"[{"id":"2","name":"b"}]"#,
+            ),
+        ]))
+        .await;
+        let groups = keycloak
+            .groups_with_role("test", "deprecated")
+            .await
+            .unwrap()
+            .into_iter()
+            .filter_map(|g| g.name)
+            .collect::<Vec<_>>();
+        assert_eq!(groups, vec!["a", "c"]);
+    }
+
+    #[tokio::test]
+    async fn test_all_roles_and_groups_sorted() {
+        let keycloak = mock_keycloak(HashMap::from([
+            (
+                "GET /admin/realms/test/roles?briefRepresentation=true&first=0&max=1000",
+                r#"[{"id":"3","name":"c"},{"id":"1","name":"a"},{"id":"2","name":"b"}]"#,
+            ),
+            (
+                "GET /admin/realms/test/roles?briefRepresentation=true&first=1000&max=1000",
+                "[]",
+            ),
+            (
+                "GET /admin/realms/test/groups?first=0&max=1000",
+                r#"[{"id":"2","name":"b","path":"/b"},{"id":"1","name":"a","path":"/a"}]"#,
+            ),
+            ("GET /admin/realms/test/groups?first=1000&max=1000", "[]"),
+        ]))
+        .await;
+        let roles = keycloak
+            .all_roles_sorted("test")
+            .await
+            .unwrap()
+            .into_iter()
+            .filter_map(|r| r.name)
+            .collect::<Vec<_>>();
+        assert_eq!(roles, vec!["a", "b", "c"]);
+        let groups = keycloak
+            .all_groups_sorted("test")
+            .await
+            .unwrap()
+            .into_iter()
+            .filter_map(|g| g.path)
+            .collect::<Vec<_>>();
+        assert_eq!(groups, vec!["/a", "/b"]);
+    }
+
+    #[tokio::test]
+    async fn test_add_role_holders_to_group() {
+        let (keycloak, requests) = mock_keycloak_recorded(HashMap::from([
+            (
+                "GET /admin/realms/test/groups/g/members?briefRepresentation=true&first=0&max=1000",
+                r#"[{"id":"1"}]"#,
+            ),
+            (
+                "GET /admin/realms/test/groups/g/members?briefRepresentation=true&first=1000&max=1000",
+                "[]",
+            ),
+            (
+                "GET /admin/realms/test/roles/access/users?briefRepresentation=true&first=0&max=1000",
+                r#"[{"id":"1"},{"id":"2"}]"#,
+            ),
+            (
+                "GET /admin/realms/test/roles/access/users?briefRepresentation=true&first=1000&max=1000",
+                r#"[{"id":"3"}]"#,
+            ),
+            (
+                "GET /admin/realms/test/roles/access/users?briefRepresentation=true&first=2000&max=1000",
+                "[]",
+            ),
+            ("PUT /admin/realms/test/users/2/groups/g", ""),
+            ("PUT /admin/realms/test/users/3/groups/g", ""),
+        ]))
+        .await;
+        let added = keycloak
+            .add_role_holders_to_group("test", "access", "g")
+            .await
+            .unwrap();
+        assert_eq!(added, vec!["2", "3"]);
+        let puts = requests
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|r| r.starts_with("PUT"))
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(
+            puts,
+            vec![
+                "PUT /admin/realms/test/users/2/groups/g",
+                "PUT /admin/realms/test/users/3/groups/g"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_remove_federated_identity() {
+        let (keycloak, requests) = mock_keycloak_recorded(HashMap::from([(
+            "DELETE /admin/realms/test/users/1/federated-identity/google",
+            "",
+        )]))
+        .await;
+        keycloak
+            .remove_federated_identity("test", "1", "google")
+            .await
+            .unwrap();
+        keycloak
+            .remove_federated_identity("test", "1", "github")
+            .await
+            .unwrap();
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![
+                "DELETE /admin/realms/test/users/1/federated-identity/google",
+                "DELETE /admin/realms/test/users/1/federated-identity/github",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_group_subtree() {
+        let keycloak = mock_keycloak(HashMap::from([
+            (
+                "GET /admin/realms/test/groups/a",
+                r#"{"id":"a","path":"/a"}"#,
+            ),
+            (
+                "GET /admin/realms/test/groups/a/children?first=0&max=1000",
+                r#"[{"id":"b","path":"/a/b"}]"#,
+            ),
+            (
+                "GET /admin/realms/test/groups/a/children?first=1000&max=1000",
+                "[]",
+            ),
+            (
+                "GET /admin/realms/test/groups/b/children?first=0&max=1000",
+                r#"[{"id":"c","path":"/a/b/c"}]"#,
+            ),
+            (
+                "GET /admin/realms/test/groups/b/children?first=1000&max=1000",
+                "[]",
+            ),
+            (
+                "GET /admin/realms/test/groups/c/children?first=0&max=1000",
+                "[]",
+            ),
+        ]))
+        .await;
+        let a = keycloak.group_subtree("test", "a").await.unwrap();
+        let b = &a.sub_groups.as_ref().unwrap()[0];
+        assert_eq!(b.path.as_deref(), Some("/a/b"));
+        let c = &b.sub_groups.as_ref().unwrap()[0];
+        assert_eq!(c.path.as_deref(), Some("/a/b/c"));
+        assert_eq!(c.sub_groups.as_deref(), Some(&[][..]));
+    }
+
+    #[tokio::test]
+    async fn test_clone_realm_structure() {
+        let (keycloak, requests) = mock_keycloak_recorded(HashMap::from([
+            (
+                "GET /admin/realms/from/roles?briefRepresentation=false&first=0&max=1000",
+                r#"[{"id":"1","name":"default-roles-from"},{"id":"2","name":"reader","description":"Reader","attributes":{"scope":["app"]}}]"#,
+            ),
+            (
+                "GET /admin/realms/from/roles?briefRepresentation=false&first=1000&max=1000",
+                "[]",
+            ),
+            (
+                "GET /admin/realms/from/groups?first=0&max=1000",
+                r#"[{"id":"a","name":"app","path":"/app"}]"#,
+            ),
+            ("GET /admin/realms/from/groups?first=1000&max=1000", "[]"),
+            (
+                "GET /admin/realms/from/groups/a",
+                r#"{"id":"a","name":"app","path":"/app"}"#,
+            ),
+            (
+                "GET /admin/realms/from/groups/a/children?first=0&max=1000",
+                r#"[{"id":"b","name":"readers","path":"/app/readers"}]"#,
+            ),
+            (
+                "GET /admin/realms/from/groups/a/children?first=1000&max=1000",
+                "[]",
+            ),
+            (
+                "GET /admin/realms/from/groups/b/children?first=0&max=1000",
+                "[]",
+            ),
+            ("GET /admin/realms/from/groups/a/role-mappings/realm", "[]"),
+            (
+                "GET /admin/realms/from/groups/b/role-mappings/realm",
+                r#"[{"id":"2","name":"reader"}]"#,
+            ),
+            ("POST /admin/realms/to/partialImport", "{}"),
+        ]))
+        .await;
+        keycloak.clone_realm_structure("from", "to").await.unwrap();
+        let requests = requests.lock().unwrap();
+        let body = requests
+            .iter()
+            .find_map(|r| r.strip_prefix("POST /admin/realms/to/partialImport "))
+            .unwrap();
+        let body: Value = serde_json::from_str(body).unwrap();
+        assert_eq!(body["ifResourceExists"], "SKIP");
+        assert_eq!(
+            body["roles"]["realm"],
+            serde_json::json!([{
+                "name": "reader",
+                "description": "Reader",
+                "attributes": { "scope": ["app"] },
+            }])
+        );
+        assert_eq!(
+            body["groups"],
+            serde_json::json!([{
+                "name": "app",
+                "path": "/app",
+                "realmRoles": [],
+                "subGroups": [{
+                    "name": "readers",
+                    "path": "/app/readers",
+                    "realmRoles": ["reader"],
+                    "subGroups": [],
+                }],
+            }])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_all_group_members() {
+        let keycloak = mock_keycloak(HashMap::from([
+            (
+                "GET /admin/realms/test/groups/g/members?briefRepresentation=true&first=0&max=1000",
+                r#"[{"id":"1"},{"id":"2"}]"#,
+            ),
+            (
+                "GET /admin/realms/test/groups/g/members?briefRepresentation=true&first=1000&max=1000",
+                r#"[{"id":"3"}]"#,
+            ),
+            (
+                "GET /admin/realms/test/groups/g/members?briefRepresentation=true&first=2000&max=1000",
+                "[]",
+            ),
+        ]))
+        .await;
+        let members = keycloak
+            .all_group_members("test", "g")
+            .await
+            .unwrap()
+            .into_iter()
+            .filter_map(|u| u.id)
+            .collect::<Vec<_>>();
+        assert_eq!(members, vec!["1", "2", "3"]);
+    }
+
+    #[tokio::test]
+    async fn test_realm_browser_flow() {
+        let keycloak = mock_keycloak(HashMap::from([
+            (
+                "GET /admin/realms/test",
+                r#"{"realm":"test","browserFlow":"browser_email_otp"}"#,
+            ),
+            ("GET /admin/realms/other", r#"{"realm":"other"}"#),
+        ]))
+        .await;
+        assert_eq!(
+            keycloak
+                .realm_browser_flow("test")
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("browser_email_otp")
+        );
+        assert_eq!(keycloak.realm_browser_flow("other").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_realms_all() {
+        let keycloak = mock_keycloak(HashMap::from([(
+            "GET /admin/realms",
+            r#"[{"realm":"master"},{"realm":"test"},{}]"#,
+        )]))
+        .await;
+        assert_eq!(keycloak.realms().await.unwrap(), vec!["test"]);
+        assert_eq!(keycloak.realms_all(false).await.unwrap(), vec!["test"]);
+        assert_eq!(
+            keycloak.realms_all(true).await.unwrap(),
+            vec!["master", "test"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_username_and_email_exists() {
+        let keycloak = mock_keycloak(HashMap::from([
+            (
+                "GET /admin/realms/test/users?briefRepresentation=true&exact=true&username=alice",
+                r#"[{"id":"1","username":"alice","email":"alice@example.com"}]"#,
+            ),
+            (
+                "GET /admin/realms/test/users?briefRepresentation=true&exact=true&username=bob",
+                "[]",
+            ),
+            (
+                "GET /admin/realms/test/users?briefRepresentation=true&email=Alice%40Example.com&exact=true",
+                r#"[{"id":"1","username":"alice","email":"alice@example.com"}]"#,
+            ),
+            (
+                "GET /admin/realms/test/users?briefRepresentation=true&email=bob%40example.com&exact=true",
+                "[]",
+            ),
+        ]))
+        .await;
+        assert!(keycloak.username_exists("test", "alice").await.unwrap());
+        assert!(!keycloak.username_exists("test", "bob").await.unwrap());
+        assert!(keycloak
+            .email_exists("test", "Alice@Example.com")
+            .await
+            .unwrap());
+        assert!(!keycloak
+            .email_exists("test", "bob@example.com")
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_user_by_username_full() {
+        let keycloak = mock_keycloak(HashMap::from([
+            (
+                "GET /admin/realms/test/users?briefRepresentation=false&exact=true&username=alice",
+                r#"[{"id":"1","username":"alice"}]"#,
+            ),
+            (
+                "GET /admin/realms/test/users?briefRepresentation=false&exact=true&username=bob",
+                "[]",
+            ),
+            (
+                "GET /admin/realms/test/users/1?userProfileMetadata=true",
+                r#"{"id":"1","username":"alice","attributes":{"locale":["de"]}}"#,
+            ),
+        ]))
+        .await;
+        let brief = keycloak
+            .user_by_username("test", "alice".to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(brief.attributes.is_none());
+        let full = keycloak
+            .user_by_username_full("test", "alice".to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(full.attributes.unwrap()["locale"], vec!["de".to_string()]);
+        assert!(keycloak
+            .user_by_username_full("test", "bob".to_string())
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_admin_token_claims() {
+        let keycloak = mock_keycloak(HashMap::new()).await;
+        let claims = keycloak.admin_token_claims().await.unwrap();
+        assert!(claims.exp() > chrono::Utc::now().timestamp() as usize);
+        assert_eq!(claims.iss(), Some("http://127.0.0.1/realms/master"));
+        assert_eq!(claims.preferred_username(), Some("admin"));
+        assert_eq!(claims.roles(), ["admin", "create-realm"]);
+    }
+
+    #[tokio::test]
+    async fn test_admin_can_manage_realm() {
+        let keycloak = mock_keycloak(HashMap::new()).await;
+        assert!(keycloak.admin_can_manage_realm("test").await.unwrap());
+
+        let claims = |iss: &str, claims: serde_json::Value| -> ParsedAccessToken {
+            let mut token = serde_json::json!({ "exp": 0, "iat": 0, "iss": iss });
+            token
+                .as_object_mut()
+                .unwrap()
+                .extend(claims.as_object().unwrap().clone());
+            serde_json::from_value(token).unwrap()
+        };
+        let master = "http://127.0.0.1/realms/master";
+        let no_admin = claims(
+            master,
+            serde_json::json!({ "realm_access": { "roles": ["create-realm"] } }),
+        );
+        assert!(!no_admin.can_manage_realm("test"));
+        let test_realm_client = claims(
+            master,
+            serde_json::json!({ "resource_access": { "test-realm": { "roles": ["manage-realm"] } } }),
+        );
+        assert!(test_realm_client.can_manage_realm("test"));
+        assert!(!test_realm_client.can_manage_realm("other"));
+        let realm_admin = claims(
+            "http://127.0.0.1/realms/test",
+            serde_json::json!({ "resource_access": { "realm-management": { "roles": ["view-users", "realm-admin"] } } }),
+        );
+        assert!(realm_admin.can_manage_realm("test"));
+        assert!(!realm_admin.can_manage_realm("other"));
+        let viewer = claims(
+            "http://127.0.0.1/realms/test",
+            serde_json::json!({ "resource_access": { "realm-management": { "roles": ["view-users"] } } }),
+        );
+        assert!(!viewer.can_manage_realm("test"));
+    }
+
+    #[tokio::test]
+    async fn test_token_expires_at() {
+        let keycloak = mock_keycloak(HashMap::new()).await;
+        let exp = keycloak.admin_token_claims().await.unwrap().exp();
+        let expires_at = keycloak.token_expires_at().await.unwrap();
+        assert_eq!(expires_at.timestamp(), exp as i64);
+        assert!(expires_at > chrono::Utc::now());
+    }
+
+    #[tokio::test]
+    async fn test_reauthenticate() {
+        let keycloak = mock_keycloak(HashMap::new()).await;
+        keycloak.inner.session.invalidate().await;
+        assert!(keycloak.admin_token_claims().await.is_none());
+        keycloak.reauthenticate().await.unwrap();
+        assert_ne!(
+            keycloak.inner.session.access_token().await.as_ref(),
+            "invalid"
+        );
+        let claims = keycloak.admin_token_claims().await.unwrap();
+        assert_eq!(claims.preferred_username(), Some("admin"));
+    }
+
+    #[tokio::test]
+    async fn test_builder_client_credentials() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let request = read_request(&mut socket).await;
+            respond(&mut socket, "200 OK", &admin_token()).await;
+            request
+        });
+        let config: KeycloakConfig = serde_json::from_value(serde_json::json!({
+            "address": format!("http://{addr}"),
+            "admin_client_secret": "secret",
+        }))
+        .unwrap();
+        let builder = KeycloakBuilder::default()
+            .with_no_refresh()
+            .with_admin_realm("service")
+            .with_admin_client_id("qm-admin")
+            .with_grant_type(AdminGrantType::ClientCredentials);
+        builder
+            .session(&config, reqwest::Client::new())
+            .await
+            .unwrap();
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /realms/service/protocol/openid-connect/token "));
+        let (_, body) = request.split_once("\r\n\r\n").unwrap();
+        let params: HashMap<&str, &str> =
+            body.split('&').filter_map(|p| p.split_once('=')).collect();
+        assert_eq!(params.get("grant_type"), Some(&"client_credentials"));
+        assert_eq!(params.get("client_id"), Some(&"qm-admin"));
+        assert_eq!(params.get("client_secret"), Some(&"secret"));
+        assert_eq!(params.get("username"), None);
+        assert_eq!(params.get("password"), None);
+    }
+
+    #[tokio::test]
+    async fn test_builder_startup_retry() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            read_request(&mut socket).await;
+            respond(&mut socket, "503 Service Unavailable", "{}").await;
+            let (mut socket, _) = listener.accept().await.unwrap();
+            read_request(&mut socket).await;
+            respond(&mut socket, "200 OK", &admin_token()).await;
+        });
+        let config: KeycloakConfig = serde_json::from_value(serde_json::json!({
+            "address": format!("http://{addr}"),
+        }))
+        .unwrap();
+        let builder = KeycloakBuilder::default()
+            .with_no_refresh()
+            .with_startup_retry(2, Duration::from_millis(1));
+        builder
+            .startup_session(&config, reqwest::Client::new())
+            .await
+            .unwrap();
+        server.await.unwrap();
+
+        let builder = KeycloakBuilder::default().with_startup_retry(1, Duration::from_millis(1));
+        assert!(builder
+            .startup_session(&config, reqwest::Client::new())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_password_credential() {
+        let credential = password_credential("secret", false);
+        assert_eq!(credential.type_.as_deref(), Some("password"));
+        assert_eq!(credential.value.as_deref(), Some("secret"));
+        assert_eq!(credential.temporary, Some(false));
+        assert_eq!(password_credential("secret", true).temporary, Some(true));
+
+        let (keycloak, requests) = mock_keycloak_recorded(HashMap::from([(
+            "PUT /admin/realms/test/users/u1/reset-password",
+            "{}",
+        )]))
+        .await;
+        keycloak
+            .set_temporary_password("test", "u1", "secret")
+            .await
+            .unwrap();
+        let requests = requests.lock().unwrap();
+        let body = requests[0]
+            .strip_prefix("PUT /admin/realms/test/users/u1/reset-password ")
+            .unwrap();
+        let credential: CredentialRepresentation = serde_json::from_str(body).unwrap();
+        assert_eq!(credential.type_.as_deref(), Some("password"));
+        assert_eq!(credential.temporary, Some(true));
+    }
+
+    #[tokio::test]
+    async fn test_set_token_lifespans() {
+        let (keycloak, requests) = mock_keycloak_recorded(HashMap::from([
+            (
+                "GET /admin/realms/test",
+                r#"{"realm":"test","accessTokenLifespan":60,"ssoSessionIdleTimeout":1800}"#,
+            ),
+            ("PUT /admin/realms/test", "{}"),
+        ]))
+        .await;
+        let lifespans = TokenLifespans {
+            access_token_lifespan: Some(300),
+            offline_session_max_lifespan: Some(86400),
+            ..TokenLifespans::default()
+        };
+        keycloak
+            .set_token_lifespans("test", &lifespans)
+            .await
+            .unwrap();
+        let requests = requests.lock().unwrap();
+        let body = requests[1].strip_prefix("PUT /admin/realms/test ").unwrap();
+        let rep: RealmRepresentation = serde_json::from_str(body).unwrap();
+        assert_eq!(rep.access_token_lifespan, Some(300));
+        assert_eq!(rep.offline_session_max_lifespan, Some(86400));
+        assert_eq!(rep.sso_session_idle_timeout, Some(1800));
+    }
+
+    #[tokio::test]
+    async fn test_clients() {
+        let keycloak = mock_keycloak(HashMap::from([
+            (
+                "GET /admin/realms/test/clients?first=0&max=1000",
+                r#"[{"id":"1","clientId":"spa"},{"id":"2","clientId":"admin-cli"},{"id":"3","clientId":"api"}]"#,
+            ),
+            ("GET /admin/realms/test/clients?first=1000&max=1000", "[]"),
+        ]))
+        .await;
+        let clients = keycloak.clients("test").await.unwrap();
+        let ids: Vec<_> = clients
+            .iter()
+            .filter_map(|c| c.client_id.as_deref())
+            .collect();
+        assert_eq!(ids, vec!["spa", "admin-cli", "api"]);
+    }
+
+    #[tokio::test]
+    async fn test_all_clients() {
+        let keycloak = mock_keycloak(HashMap::from([
+            (
+                "GET /admin/realms/test/clients?first=0&max=1000",
+                r#"[{"id":"1","clientId":"spa"},{"id":"2","clientId":"admin-cli"}]"#,
+            ),
+            (
+                "GET /admin/realms/test/clients?first=1000&max=1000",
+                r#"[{"id":"3","clientId":"api"}]"#,
+            ),
+            ("GET /admin/realms/test/clients?first=2000&max=1000", "[]"),
+        ]))
+        .await;
+        let clients = keycloak.all_clients("test").await.unwrap();
+        let ids: Vec<_> = clients
+            .iter()
+            .filter_map(|c| c.client_id.as_deref())
+            .collect();
+        assert_eq!(ids, vec!["spa", "admin-cli", "api"]);
+    }
+
+    #[tokio::test]
+    async fn test_client_by_client_id() {
+        let keycloak = mock_keycloak(HashMap::from([
+            (
+                "GET /admin/realms/test/clients?clientId=spa&search=false&viewableOnly=false",
+                r#"[{"id":"1","clientId":"spa-admin"},{"id":"2","clientId":"spa"},{"id":"3","clientId":"spa-test"}]"#,
+            ),
+            (
+                "GET /admin/realms/test/clients?clientId=api&search=false&viewableOnly=false",
+                r#"[{"id":"4","clientId":"api-gateway"}]"#,
+            ),
+        ]))
+        .await;
+        let client = keycloak.client_by_client_id("test", "spa").await.unwrap();
+        assert_eq!(client.and_then(|c| c.id).as_deref(), Some("2"));
+        let client = keycloak.client_by_client_id("test", "api").await.unwrap();
+        assert!(client.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_get_client_by_id() {
+        let keycloak = mock_keycloak(HashMap::from([
+            (
+                "GET /admin/realms/test/clients?clientId=spa&search=true&viewableOnly=false",
+                r#"[{"id":"1","clientId":"spa"}]"#,
+            ),
+            (
+                "GET /admin/realms/test/clients?clientId=unknown&search=true&viewableOnly=false",
+                "[]",
+            ),
+        ]))
+        .await;
+        let client = keycloak.get_client_by_id("test", "spa").await.unwrap();
+        assert_eq!(client.and_then(|c| c.id).as_deref(), Some("1"));
+        let client = keycloak.get_client_by_id("test", "unknown").await.unwrap();
+        assert!(client.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_client_offline_sessions() {
+        let (keycloak, requests) = mock_keycloak_recorded(HashMap::from([
+            (
+                "GET /admin/realms/test/clients/c1/offline-sessions?first=0&max=100",
+                r#"[{"id":"s1","userId":"u1"},{"id":"s2","userId":"u2"}]"#,
+            ),
+            ("DELETE /admin/realms/test/sessions/s1?isOffline=true", "{}"),
+            ("DELETE /admin/realms/test/sessions/s2?isOffline=true", "{}"),
+        ]))
+        .await;
+        let sessions = keycloak
+            .client_offline_sessions("test", "c1")
+            .await
+            .unwrap();
+        let ids: Vec<_> = sessions.iter().filter_map(|s| s.id.as_deref()).collect();
+        assert_eq!(ids, vec!["s1", "s2"]);
+        assert!(keycloak
+            .client_offline_sessions("test", "c2")
+            .await
+            .is_err());
+
+        requests.lock().unwrap().clear();
+        assert_eq!(
+            keycloak.revoke_client_sessions("test", "c1").await.unwrap(),
+            2
+        );
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![
+                "GET /admin/realms/test/clients/c1/offline-sessions?first=0&max=100",
+                "DELETE /admin/realms/test/sessions/s1?isOffline=true",
+                "DELETE /admin/realms/test/sessions/s2?isOffline=true",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_user_groups() {
+        let keycloak = mock_keycloak(HashMap::from([(
+            "GET /admin/realms/test/users/u1/groups?briefRepresentation=false",
+            r#"[{"id":"g1","path":"/admin"},{"id":"g2","path":"/custom@V01/staff"}]"#,
+        )]))
+        .await;
+        let groups = keycloak.user_groups("test", "u1").await.unwrap();
+        let paths: Vec<_> = groups.iter().filter_map(|g| g.path.as_deref()).collect();
+        assert_eq!(paths, vec!["/admin", "/custom@V01/staff"]);
+        assert!(keycloak.user_groups("test", "u2").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_remove_user_role() {
+        let (keycloak, requests) = mock_keycloak_recorded(HashMap::from([(
+            "DELETE /admin/realms/test/users/u1/role-mappings/realm",
+            "",
+        )]))
+        .await;
+        let role = RoleRepresentation {
+            id: Some("r1".to_string()),
+            name: Some("customer:access@V01".to_string()),
+            ..Default::default()
+        };
+        keycloak
+            .remove_user_role("test", "u1", role.clone())
+            .await
+            .unwrap();
+        assert!(keycloak.remove_user_role("test", "u2", role).await.is_err());
+        let requests = requests.lock().unwrap();
+        assert!(requests[0].starts_with("DELETE /admin/realms/test/users/u1/role-mappings/realm"));
+        assert!(requests[0].contains(r#""name":"customer:access@V01""#));
+    }
+
+    /// Keycloak mock keeping the created groups, the id of a group is its path.
+    async fn mock_group_keycloak(
+        existing: &[&str],
+    ) -> (Keycloak, Arc<std::sync::Mutex<Vec<String>>>) {
+        let mut groups: HashSet<String> = existing.iter().map(|s| s.to_string()).collect();
+        let created = Arc::new(std::sync::Mutex::new(vec![]));
+        let recorded = created.clone();
+        let keycloak = mock_keycloak_with(move |route, body| {
+            if let Some(path) = route.strip_prefix("GET /admin/realms/test/group-by-path/") {
+                let path = path.replace("%2F", "/");
+                return groups
+                    .contains(&path)
+                    .then(|| serde_json::json!({ "id": path, "path": path }).to_string());
+            }
+            let parent = if route == "POST /admin/realms/test/groups" {
+                String::new()
+            } else {
+                let id = route
+                    .strip_prefix("POST /admin/realms/test/groups/")?
+                    .strip_suffix("/children")?;
+                id.replace("%2F", "/")
+            };
+            let rep: GroupRepresentation = serde_json::from_str(body).ok()?;
+            let path = format!("{parent}/{}", rep.name.unwrap_or_default());
+            recorded.lock().unwrap().push(path.clone());
+            groups.insert(path);
+            Some(String::new())
+        })
+        .await;
+        (keycloak, created)
+    }
+
+    #[tokio::test]
+    async fn test_ensure_group() {
+        let (keycloak, created) = mock_group_keycloak(&["/a", "/a/b"]).await;
+        let group = keycloak
+            .ensure_group("test", "/a/b", GroupRepresentation::default())
+            .await
+            .unwrap();
+        assert_eq!(group.path.as_deref(), Some("/a/b"));
+        assert!(created.lock().unwrap().is_empty());
+
+        let group = keycloak
+            .ensure_group("test", "c", GroupRepresentation::default())
+            .await
+            .unwrap();
+        assert_eq!(group.path.as_deref(), Some("/c"));
+        assert_eq!(*created.lock().unwrap(), vec!["/c"]);
+
+        created.lock().unwrap().clear();
+        let group = keycloak
+            .ensure_group("test", "/a/x/y/z", GroupRepresentation::default())
+            .await
+            .unwrap();
+        assert_eq!(group.id.as_deref(), Some("/a/x/y/z"));
+        assert_eq!(*created.lock().unwrap(), vec!["/a/x", "/a/x/y", "/a/x/y/z"]);
+
+        assert!(keycloak
+            .ensure_group("test", "/", GroupRepresentation::default())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_start_refresh() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (refresh_tx, mut refresh_rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let request = read_request(&mut socket).await;
+                if request.contains("grant_type=refresh_token") {
+                    refresh_tx.send(()).ok();
+                }
+                respond(&mut socket, "200 OK", &admin_token()).await;
+            }
+        });
+        let config: KeycloakConfig = serde_json::from_value(serde_json::json!({
+            "address": format!("http://{addr}"),
+        }))
+        .unwrap();
+        let keycloak = KeycloakBuilder::default()
+            .with_no_refresh()
+            .build_with_config(config)
+            .await
+            .unwrap();
+        assert!(keycloak.start_refresh(Some(Duration::from_millis(10)), Duration::ZERO));
+        assert!(!keycloak.start_refresh(Some(Duration::from_millis(10)), Duration::ZERO));
+        tokio::time::timeout(Duration::from_secs(5), refresh_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        keycloak.inner.session.stop().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_builder_with_http_client() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let login = read_request(&mut socket).await;
+            respond(&mut socket, "200 OK", &admin_token()).await;
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let request = read_request(&mut socket).await;
+            respond(&mut socket, "200 OK", "{}").await;
+            (login, request)
+        });
+        let config: KeycloakConfig = serde_json::from_value(serde_json::json!({
+            "address": format!("http://{addr}"),
+        }))
+        .unwrap();
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("x-shared-client", "1".parse().unwrap());
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .pool_max_idle_per_host(0)
+            .build()
+            .unwrap();
+        let keycloak = KeycloakBuilder::default()
+            .with_no_refresh()
+            .with_timeout(Duration::from_millis(1))
+            .with_http_client(client)
+            .build_with_config(config)
+            .await
+            .unwrap();
+        keycloak
+            .http_client()
+            .get(format!("http://{addr}/"))
+            .send()
+            .await
+            .unwrap();
+        let (login, request) = server.await.unwrap();
+        assert!(login.contains("x-shared-client: 1"));
+        assert!(request.contains("x-shared-client: 1"));
+    }
+
+    #[tokio::test]
+    async fn test_builder_client_credentials_requires_secret() {
+        let config: KeycloakConfig = serde_json::from_str("{}").unwrap();
+        let Err(err) = KeycloakBuilder::default()
+            .with_grant_type(AdminGrantType::ClientCredentials)
+            .session(&config, reqwest::Client::new())
+            .await
+        else {
+            panic!("session without client secret must fail");
+        };
+        assert!(err.to_string().contains("KEYCLOAK_ADMIN_CLIENT_SECRET"));
+    }
+
+    #[tokio::test]
+    async fn test_user_by_federated_identity() {
+        let keycloak = mock_keycloak(HashMap::from([
+            (
+                "GET /admin/realms/test/users?briefRepresentation=false&idpAlias=google&idpUserId=123",
+                r#"[{"id":"1","username":"alice"}]"#,
+            ),
+            (
+                "GET /admin/realms/test/users?briefRepresentation=false&idpAlias=google&idpUserId=456",
+                "[]",
+            ),
+        ]))
+        .await;
+        let user = keycloak
+            .user_by_federated_identity("test", "google", "123")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.username.as_deref(), Some("alice"));
+        assert!(keycloak
+            .user_by_federated_identity("test", "google", "456")
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_remove_totp() {
+        let (keycloak, requests) = mock_keycloak_recorded(HashMap::from([
+            (
+                "GET /admin/realms/test/users/1/credentials",
+                r#"[{"id":"c1","type":"password"},{"id":"c2","type":"otp"}]"#,
+            ),
+            ("DELETE /admin/realms/test/users/1/credentials/c2", ""),
+        ]))
+        .await;
+        keycloak.remove_totp("test", "1").await.unwrap();
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![
+                "GET /admin/realms/test/users/1/credentials",
+                "DELETE /admin/realms/test/users/1/credentials/c2",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_set_required_actions() {
+        let (keycloak, requests) =
+            mock_keycloak_recorded(HashMap::from([("PUT /admin/realms/test/users/1", "")])).await;
+        keycloak
+            .set_required_actions(
+                "test",
+                "1",
+                vec![
+                    RequiredUserAction::ConfigureTotp,
+                    RequiredUserAction::UpdatePassword,
+                ],
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![
+                r#"PUT /admin/realms/test/users/1 {"requiredActions":["CONFIGURE_TOTP","UPDATE_PASSWORD"]}"#
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_set_email_verified() {
+        let (keycloak, requests) =
+            mock_keycloak_recorded(HashMap::from([("PUT /admin/realms/test/users/1", "")])).await;
+        keycloak
+            .set_email_verified("test", "1", true)
+            .await
+            .unwrap();
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![r#"PUT /admin/realms/test/users/1 {"emailVerified":true}"#]
+        );
+    }
+
+    #[test]
+    fn test_merge_attributes() {
+        let mut attributes = Some(TypeMap::from([(
+            "built_in".to_string(),
+            vec!["true".to_string()],
+        )]));
+        merge_attributes(
+            &mut attributes,
+            HashMap::from([(
+                "display_name".to_string(),
+                vec!["Administrators".to_string()],
+            )]),
+        );
+        let attributes = attributes.unwrap();
+        assert_eq!(attributes.len(), 2);
+        assert_eq!(attributes["built_in"], vec!["true".to_string()]);
+        assert_eq!(
+            attributes["display_name"],
+            vec!["Administrators".to_string()]
+        );
+
+        let mut attributes = None;
+        merge_attributes(
+            &mut attributes,
+            HashMap::from([("description".to_string(), vec![])]),
+        );
+        assert_eq!(attributes.unwrap().len(), 1);
+    }
+}
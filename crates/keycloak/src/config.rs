@@ -1,5 +1,14 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
+use crate::{ClientRepresentation, ProtocolMapperRepresentation, RealmRepresentation};
+
+/// Password policy of new realms and of realms without any policy.
+pub const DEFAULT_PASSWORD_POLICY: &str = "length(8) and specialChars(1) and upperCase(1) and lowerCase(1) and digits(1) and notUsername(undefined) and notEmail(undefined)";
+
+/// Range of code lengths accepted by the email OTP authenticator.
+pub const AUTHENTICATOR_OTP_LENGTH_RANGE: std::ops::RangeInclusive<u8> = 4..=10;
+
 #[derive(Default)]
 pub struct ConfigBuilder<'a> {
     prefix: Option<&'a str>,
@@ -62,6 +71,8 @@ impl<'a> ConfigBuilder<'a> {
         if cfg.authenticator_email_subject.is_none() {
             cfg.authenticator_email_subject = Some("Temporary Authentication Code".into());
         }
+        cfg.clients()
+            .map_err(|e| envy::Error::Custom(format!("invalid clients: {e}")))?;
 
         Ok(cfg)
     }
@@ -74,6 +85,7 @@ pub struct Config {
     password: Option<Arc<str>>,
     theme: Option<Arc<str>>,
     email_theme: Option<Arc<str>>,
+    available_themes: Option<Vec<Arc<str>>>,
     realm_admin_email: Option<Arc<str>>,
     realm_admin_username: Option<Arc<str>>,
     realm_admin_password: Option<Arc<str>>,
@@ -89,8 +101,24 @@ pub struct Config {
     smtp_from: Option<Arc<str>>,
     smtp_from_display_name: Option<Arc<str>>,
     smtp_ssl: Option<bool>,
+    smtp_verify_connection: Option<bool>,
     browser_flow: Option<Arc<str>>,
     authenticator_email_subject: Option<Arc<str>>,
+    authenticator_otp_length: Option<u8>,
+    authenticator_otp_ttl: Option<u32>,
+    authenticator_otp_max_retries: Option<u32>,
+    impersonation_enabled: Option<bool>,
+    impersonation_client_id: Option<Arc<str>>,
+    impersonation_client_secret: Option<Arc<str>>,
+    spa_audience: Option<Arc<str>>,
+    spa_attributes: Option<Vec<Arc<str>>>,
+    clients: Option<Arc<str>>,
+    admin_client_secret: Option<Arc<str>>,
+    access_token_lifespan: Option<i32>,
+    sso_session_idle_timeout: Option<i32>,
+    sso_session_max_lifespan: Option<i32>,
+    offline_session_idle_timeout: Option<i32>,
+    offline_session_max_lifespan: Option<i32>,
 }
 
 impl Config {
@@ -114,6 +142,20 @@ impl Config {
         self.email_theme.as_deref().unwrap_or("qm")
     }
 
+    /// Themes accepted on a realm: the configured login and email themes plus
+    /// `KEYCLOAK_AVAILABLE_THEMES` (comma separated).
+    pub fn available_themes(&self) -> Vec<&str> {
+        let mut themes = vec![self.theme(), self.email_theme()];
+        themes.extend(self.available_themes.iter().flatten().map(AsRef::as_ref));
+        themes.sort_unstable();
+        themes.dedup();
+        themes
+    }
+
+    pub fn is_available_theme(&self, theme: &str) -> bool {
+        self.available_themes().contains(&theme)
+    }
+
     pub fn realm_admin_username(&self) -> &str {
         self.realm_admin_username.as_deref().unwrap_or("admin")
     }
@@ -174,6 +216,11 @@ impl Config {
         self.smtp_ssl.as_ref()
     }
 
+    /// Reachability of the SMTP server is only checked with `KEYCLOAK_SMTP_VERIFY_CONNECTION=true`.
+    pub fn smtp_verify_connection(&self) -> bool {
+        self.smtp_verify_connection.unwrap_or(false)
+    }
+
     pub fn browser_flow(&self) -> &str {
         self.browser_flow.as_deref().unwrap_or("browser")
     }
@@ -181,4 +228,246 @@ impl Config {
     pub fn authenticator_email_subject(&self) -> Option<&str> {
         self.authenticator_email_subject.as_deref()
     }
+
+    /// Length of the email OTP code, clamped to [AUTHENTICATOR_OTP_LENGTH_RANGE] (default `6`).
+    pub fn authenticator_otp_length(&self) -> u8 {
+        let length = self.authenticator_otp_length.unwrap_or(6);
+        if !AUTHENTICATOR_OTP_LENGTH_RANGE.contains(&length) {
+            tracing::warn!(
+                "authenticator otp length {length} is not within {AUTHENTICATOR_OTP_LENGTH_RANGE:?}"
+            );
+        }
+        length.clamp(
+            *AUTHENTICATOR_OTP_LENGTH_RANGE.start(),
+            *AUTHENTICATOR_OTP_LENGTH_RANGE.end(),
+        )
+    }
+
+    /// Seconds the email OTP code stays valid (default `300`).
+    pub fn authenticator_otp_ttl(&self) -> u32 {
+        self.authenticator_otp_ttl.unwrap_or(300)
+    }
+
+    /// Attempts allowed to enter the email OTP code (default `3`).
+    pub fn authenticator_otp_max_retries(&self) -> u32 {
+        self.authenticator_otp_max_retries.unwrap_or(3)
+    }
+
+    /// Impersonation of users is disabled unless `KEYCLOAK_IMPERSONATION_ENABLED=true`.
+    pub fn impersonation_enabled(&self) -> bool {
+        self.impersonation_enabled.unwrap_or(false)
+    }
+
+    /// Confidential client with token exchange permission used for impersonation.
+    pub fn impersonation_client_id(&self) -> Option<&str> {
+        self.impersonation_client_id.as_deref()
+    }
+
+    pub fn impersonation_client_secret(&self) -> Option<&str> {
+        self.impersonation_client_secret.as_deref()
+    }
+
+    /// Secret of the admin client when the admin session uses `client_credentials`.
+    pub fn admin_client_secret(&self) -> Option<&str> {
+        self.admin_client_secret.as_deref()
+    }
+
+    /// Token and session lifespans of the realm, unset values keep the Keycloak defaults.
+    pub fn token_lifespans(&self) -> TokenLifespans {
+        TokenLifespans {
+            access_token_lifespan: self.access_token_lifespan,
+            sso_session_idle_timeout: self.sso_session_idle_timeout,
+            sso_session_max_lifespan: self.sso_session_max_lifespan,
+            offline_session_idle_timeout: self.offline_session_idle_timeout,
+            offline_session_max_lifespan: self.offline_session_max_lifespan,
+        }
+    }
+
+    pub fn spa_audience(&self) -> &str {
+        self.spa_audience.as_deref().unwrap_or("spa")
+    }
+
+    /// Additional default attributes of the `spa` client from
+    /// `KEYCLOAK_SPA_ATTRIBUTES` (comma separated `key=value` pairs).
+    pub fn spa_attributes(&self) -> Vec<(&str, &str)> {
+        self.spa_attributes
+            .iter()
+            .flatten()
+            .filter_map(|attribute| attribute.split_once('='))
+            .map(|(key, value)| (key.trim(), value.trim()))
+            .filter(|(key, _)| !key.is_empty())
+            .collect()
+    }
+
+    /// Clients which are validated in addition to `spa`, from `KEYCLOAK_CLIENTS` as a
+    /// JSON array of client representations. Only the fields which are set are validated.
+    pub fn clients(&self) -> serde_json::Result<Vec<ClientRepresentation>> {
+        let Some(clients) = self.clients.as_deref() else {
+            return Ok(vec![]);
+        };
+        let clients: Vec<ClientRepresentation> = serde_json::from_str(clients)?;
+        for client in &clients {
+            match client.client_id.as_deref() {
+                None | Some("") => {
+                    return Err(serde::de::Error::custom("every client requires a clientId"))
+                }
+                Some("spa") => {
+                    return Err(serde::de::Error::custom(
+                        "the client 'spa' is always validated",
+                    ))
+                }
+                _ => {}
+            }
+        }
+        Ok(clients)
+    }
+
+    /// Protocol mappers the `spa` client must have, matched by name.
+    pub fn spa_protocol_mappers(&self) -> Vec<ProtocolMapperRepresentation> {
+        vec![ProtocolMapperRepresentation {
+            name: Some("audience".to_string()),
+            protocol: Some("openid-connect".to_string()),
+            protocol_mapper: Some("oidc-audience-mapper".to_string()),
+            config: Some(HashMap::from_iter([
+                (
+                    "included.client.audience".to_string(),
+                    self.spa_audience().to_string(),
+                ),
+                ("access.token.claim".to_string(), "true".to_string()),
+                ("id.token.claim".to_string(), "false".to_string()),
+                ("introspection.token.claim".to_string(), "true".to_string()),
+            ])),
+            ..ProtocolMapperRepresentation::default()
+        }]
+    }
+
+    /// SMTP server settings for a realm, filled from the configured values
+    /// or the local development defaults.
+    pub fn smtp_server_defaults(&self) -> HashMap<String, String> {
+        let mut defaults: HashMap<String, String> = HashMap::new();
+        defaults.insert(
+            String::from("starttls"),
+            self.smtp_starttls().copied().unwrap_or(false).to_string(),
+        );
+        defaults.insert(
+            String::from("port"),
+            self.smtp_port().copied().unwrap_or(1025).to_string(),
+        );
+        defaults.insert(
+            String::from("host"),
+            self.smtp_host().unwrap_or("smtp").to_string(),
+        );
+        defaults.insert(
+            String::from("from"),
+            self.smtp_from().unwrap_or("noreply@qm.local").to_string(),
+        );
+        defaults.insert(
+            String::from("fromDisplayName"),
+            self.smtp_from_display_name().unwrap_or("qm").to_string(),
+        );
+        defaults.insert(
+            String::from("ssl"),
+            self.smtp_ssl().copied().unwrap_or(false).to_string(),
+        );
+        if let Some(reply_to) = self.smtp_reply_to() {
+            defaults.insert(String::from("replyTo"), reply_to.to_string());
+        }
+        if let Some(reply_to_display_name) = self.smtp_reply_to_display_name() {
+            defaults.insert(
+                String::from("replyToDisplayName"),
+                reply_to_display_name.to_string(),
+            );
+        }
+        defaults
+    }
+
+    /// Overwrites locale, themes, password policy and SMTP settings of `rep`
+    /// with the values the realm validator expects.
+    ///
+    /// The browser flow is left untouched because a custom flow only exists
+    /// after the realm has been configured.
+    pub fn apply_realm_defaults(&self, rep: &mut RealmRepresentation) {
+        rep.default_locale = Some("de".to_string());
+        rep.supported_locales = Some(vec!["de".to_string()]);
+        rep.internationalization_enabled = Some(true);
+        rep.login_theme = Some(self.theme().to_string());
+        rep.email_theme = Some(self.email_theme().to_string());
+        rep.password_policy = Some(DEFAULT_PASSWORD_POLICY.to_string());
+        rep.remember_me = Some(true);
+        rep.registration_allowed = Some(false);
+        rep.reset_password_allowed = Some(true);
+        rep.smtp_server = Some(self.smtp_server_defaults());
+        self.token_lifespans().apply(rep);
+    }
+
+    /// Representation for a new realm that already passes validation.
+    pub fn default_realm_representation(&self, realm_name: &str) -> RealmRepresentation {
+        let mut rep = RealmRepresentation {
+            realm: Some(realm_name.to_string()),
+            enabled: Some(true),
+            ..RealmRepresentation::default()
+        };
+        self.apply_realm_defaults(&mut rep);
+        rep.browser_flow = Some(self.browser_flow().to_string());
+        rep
+    }
+}
+
+/// Token and session lifespans of a realm in seconds, `None` leaves the value untouched.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TokenLifespans {
+    pub access_token_lifespan: Option<i32>,
+    pub sso_session_idle_timeout: Option<i32>,
+    pub sso_session_max_lifespan: Option<i32>,
+    pub offline_session_idle_timeout: Option<i32>,
+    pub offline_session_max_lifespan: Option<i32>,
+}
+
+impl TokenLifespans {
+    /// Sets the configured lifespans on `rep`.
+    pub fn apply(&self, rep: &mut RealmRepresentation) {
+        let targets = [
+            (self.access_token_lifespan, &mut rep.access_token_lifespan),
+            (
+                self.sso_session_idle_timeout,
+                &mut rep.sso_session_idle_timeout,
+            ),
+            (
+                self.sso_session_max_lifespan,
+                &mut rep.sso_session_max_lifespan,
+            ),
+            (
+                self.offline_session_idle_timeout,
+                &mut rep.offline_session_idle_timeout,
+            ),
+            (
+                self.offline_session_max_lifespan,
+                &mut rep.offline_session_max_lifespan,
+            ),
+        ];
+        for (lifespan, target) in targets {
+            if lifespan.is_some() {
+                *target = lifespan;
+            }
+        }
+    }
+
+    /// Whether all configured lifespans are set on `rep`.
+    pub fn is_applied(&self, rep: &RealmRepresentation) -> bool {
+        [
+            (self.access_token_lifespan, rep.access_token_lifespan),
+            (self.sso_session_idle_timeout, rep.sso_session_idle_timeout),
+            (self.sso_session_max_lifespan, rep.sso_session_max_lifespan),
+            (
+                self.offline_session_idle_timeout,
+                rep.offline_session_idle_timeout,
+            ),
+            (
+                self.offline_session_max_lifespan,
+                rep.offline_session_max_lifespan,
+            ),
+        ]
+        .into_iter()
+        .all(|(lifespan, current)| lifespan.is_none() || lifespan == current)
+    }
 }
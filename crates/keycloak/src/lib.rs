@@ -16,6 +16,9 @@ pub use client::*;
 pub mod config;
 pub mod realm;
 pub mod schema;
+pub mod smtp;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 pub mod token;
 pub mod validation;
 pub use token::store::JwtStore;
@@ -1,12 +1,9 @@
 use crate::schema::UserInput;
 use crate::validation::context::{Config, ValidationContext};
-use crate::validation::updater::{get_smtp_server_defaults, update_for_errors};
-use crate::validation::validator::validate_realm;
+use crate::validation::updater::validate_and_update_realm;
 use crate::Keycloak;
 use crate::KeycloakError;
-use crate::{
-    CredentialRepresentation, GroupRepresentation, RoleRepresentation, UserRepresentation,
-};
+use crate::{password_credential, GroupRepresentation, RoleRepresentation, UserRepresentation};
 use std::collections::{BTreeMap, BTreeSet, HashMap};
 
 use qm_role::Group;
@@ -42,15 +39,10 @@ pub async fn create(keycloak: &Keycloak) -> anyhow::Result<()> {
         client.root_url = Some(format!("{}/", &url));
         client.direct_access_grants_enabled = Some(true);
     }
-    let ctx = ValidationContext {
-        config: &Config {
-            realm,
-            keycloak: keycloak.config(),
-            public_url: url,
-        },
-        keycloak,
-    };
-    realm_representation.smtp_server = get_smtp_server_defaults(&ctx);
+    keycloak
+        .config()
+        .apply_realm_defaults(&mut realm_representation);
+    crate::smtp::verify_smtp_settings(keycloak.config()).await;
     tracing::info!("create keycloak realm '{realm}'");
     keycloak.create_realm(realm_representation).await?;
     Ok(())
@@ -64,7 +56,18 @@ where
     R: AsRef<str> + std::fmt::Debug + std::marker::Copy + Clone,
     P: AsRef<str> + std::fmt::Debug + std::marker::Copy + Clone,
 {
-    let realm = keycloak.config().realm();
+    configure(keycloak, keycloak.config().realm(), groups).await
+}
+
+async fn configure<R, P>(
+    keycloak: &Keycloak,
+    realm: &str,
+    groups: Vec<Group<R, P>>,
+) -> anyhow::Result<()>
+where
+    R: AsRef<str> + std::fmt::Debug + std::marker::Copy + Clone,
+    P: AsRef<str> + std::fmt::Debug + std::marker::Copy + Clone,
+{
     let url = APP_URL.as_str();
     let keycloak_config = keycloak.config();
     let ctx = ValidationContext {
@@ -75,28 +78,36 @@ where
         },
         keycloak,
     };
-    let max_tries = 5;
-    let mut current_try = 1;
-    while let Some(errors) = validate_realm(&ctx).await? {
-        if errors.is_empty() {
-            break;
-        } else {
-            for error in errors.iter() {
-                tracing::error!("{}", error.id);
-            }
-        }
-        tracing::info!(
-            "{current_try}. try time to update realm {} for errors {}",
-            realm,
-            errors.len()
-        );
-        update_for_errors(&ctx, errors.into_iter().map(From::from).collect()).await?;
-        current_try += 1;
-        if current_try > max_tries {
-            break;
+    validate_and_update_realm(&ctx).await?;
+    ensure_groups_with_roles(realm, keycloak, groups, true).await?;
+    Ok(())
+}
+
+/// Creates `realm` from the configured defaults, then sets up the `spa` client, roles and
+/// groups like [configure_realm].
+///
+/// If any step after the realm creation fails the realm is removed again.
+pub async fn create_default_realm<R, P>(
+    keycloak: &Keycloak,
+    realm: &str,
+    groups: Vec<Group<R, P>>,
+) -> anyhow::Result<()>
+where
+    R: AsRef<str> + std::fmt::Debug + std::marker::Copy + Clone,
+    P: AsRef<str> + std::fmt::Debug + std::marker::Copy + Clone,
+{
+    tracing::info!("create keycloak realm '{realm}' with defaults");
+    crate::smtp::verify_smtp_settings(keycloak.config()).await;
+    keycloak
+        .create_realm(keycloak.config().default_realm_representation(realm))
+        .await?;
+    if let Err(err) = configure(keycloak, realm, groups).await {
+        tracing::error!("provisioning realm '{realm}' failed, removing it: {err:#}");
+        if let Err(e) = keycloak.remove_realm(realm).await {
+            tracing::error!("unable to remove realm '{realm}': {e:#?}");
         }
+        return Err(err);
     }
-    ensure_groups_with_roles(realm, keycloak, groups, true).await?;
     Ok(())
 }
 
@@ -170,21 +181,12 @@ pub async fn create_keycloak_user(
     );
 
     // Set the credential
-    keycloak_user.credentials = Some(vec![CredentialRepresentation {
-        created_date: None,
-        credential_data: None,
-        id: None,
-        priority: None,
-        secret_data: None,
-        temporary: user
-            .required_actions
-            .as_ref()
-            .map(|actions| actions.contains(&crate::schema::RequiredUserAction::UpdatePassword)),
-        type_: Some("password".to_string()),
-        user_label: None,
-        value: Some(user.password),
-        ..Default::default()
-    }]);
+    keycloak_user.credentials = Some(vec![password_credential(
+        &user.password,
+        user.required_actions.as_ref().is_some_and(|actions| {
+            actions.contains(&crate::schema::RequiredUserAction::UpdatePassword)
+        }),
+    )]);
 
     let result = keycloak.create_user(realm, keycloak_user).await;
     let exists = match result {
@@ -523,3 +525,81 @@ where
         .await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::mock_keycloak_recorded;
+
+    #[tokio::test]
+    async fn test_create_default_realm_rollback() {
+        let (keycloak, requests) = mock_keycloak_recorded(HashMap::from([
+            ("POST /admin/realms", ""),
+            ("DELETE /admin/realms/tenant", ""),
+        ]))
+        .await;
+        let result = create_default_realm::<&str, &str>(&keycloak, "tenant", vec![]).await;
+        assert!(result.is_err());
+        let requests = requests.lock().unwrap();
+        assert!(requests[0].starts_with(r#"POST /admin/realms {"#));
+        assert!(requests[0].contains(r#""realm":"tenant""#));
+        assert_eq!(requests.last().unwrap(), "DELETE /admin/realms/tenant");
+    }
+
+    #[tokio::test]
+    async fn test_create_default_realm() {
+        let (keycloak, requests) = mock_keycloak_recorded(HashMap::from([
+            ("POST /admin/realms", ""),
+            ("GET /admin/realms/tenant", r#"{"realm":"tenant"}"#),
+            ("PUT /admin/realms/tenant", ""),
+            ("GET /admin/realms/tenant/authentication/flows", "[]"),
+            (
+                "GET /admin/realms/tenant/clients?clientId=spa&search=true&viewableOnly=false",
+                "[]",
+            ),
+            ("POST /admin/realms/tenant/clients", ""),
+            ("POST /admin/realms/tenant/roles", ""),
+            (
+                "GET /admin/realms/tenant/roles/user:view",
+                r#"{"id":"role-id","name":"user:view"}"#,
+            ),
+            ("POST /admin/realms/tenant/groups", ""),
+            (
+                "GET /admin/realms/tenant/group-by-path/%2Fadmin",
+                r#"{"id":"group-id","name":"admin","path":"/admin"}"#,
+            ),
+            (
+                "POST /admin/realms/tenant/groups/group-id/role-mappings/realm",
+                "",
+            ),
+        ]))
+        .await;
+        let groups = vec![Group::new(
+            "Admin".to_string(),
+            "/admin".to_string(),
+            vec![],
+            vec![],
+            vec![qm_role::Role::new("user", Some("view"))],
+        )];
+        create_default_realm::<&str, &str>(&keycloak, "tenant", groups)
+            .await
+            .unwrap();
+        let requests = requests.lock().unwrap();
+        assert!(requests[0].starts_with(r#"POST /admin/realms {"#));
+        assert!(requests[0].contains(r#""realm":"tenant""#));
+        assert!(requests.iter().any(|r| {
+            r.starts_with("POST /admin/realms/tenant/clients {")
+                && r.contains(r#""clientId":"spa""#)
+        }));
+        assert!(requests
+            .contains(&r#"POST /admin/realms/tenant/roles {"name":"user:view"}"#.to_string()));
+        assert!(
+            requests.contains(&r#"POST /admin/realms/tenant/groups {"name":"admin"}"#.to_string())
+        );
+        assert_eq!(
+            requests.last().unwrap(),
+            r#"POST /admin/realms/tenant/groups/group-id/role-mappings/realm [{"id":"role-id","name":"user:view"}]"#
+        );
+        assert!(!requests.iter().any(|r| r.starts_with("DELETE ")));
+    }
+}
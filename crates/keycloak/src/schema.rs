@@ -1,6 +1,10 @@
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum RequiredUserAction {
+    VerifyEmail,
+    UpdateProfile,
+    ConfigureTotp,
     UpdatePassword,
+    TermsAndConditions,
 }
 
 impl std::fmt::Display for RequiredUserAction {
@@ -9,7 +13,11 @@ impl std::fmt::Display for RequiredUserAction {
             f,
             "{}",
             match self {
+                RequiredUserAction::VerifyEmail => "VERIFY_EMAIL",
+                RequiredUserAction::UpdateProfile => "UPDATE_PROFILE",
+                RequiredUserAction::ConfigureTotp => "CONFIGURE_TOTP",
                 RequiredUserAction::UpdatePassword => "UPDATE_PASSWORD",
+                RequiredUserAction::TermsAndConditions => "TERMS_AND_CONDITIONS",
             }
         )
     }
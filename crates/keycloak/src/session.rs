@@ -1,10 +1,13 @@
 use keycloak::KeycloakError;
 use keycloak::KeycloakTokenSupplier;
-use std::{sync::Arc, time::Duration};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 use tokio::runtime::Builder;
 use tokio::sync::RwLock;
 use tokio::task::LocalSet;
 
+/// Time before the expiry of the token at which the background refresh renews it.
+pub const DEFAULT_REFRESH_THRESHOLD: Duration = Duration::from_secs(30);
+
 #[derive(Debug, Clone)]
 pub enum KeycloakSessionError {
     ReqwestFailure(Arc<reqwest::Error>),
@@ -47,7 +50,7 @@ async fn error(response: reqwest::Response) -> Result<reqwest::Response, Keycloa
     Ok(response)
 }
 
-#[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
 pub struct ParsedAccessToken {
     exp: usize,
     //:1677048774,
@@ -80,6 +83,92 @@ pub struct ParsedAccessToken {
     email_verified: bool,
     //:false,
     preferred_username: Option<String>, //:"admin"
+    realm_access: Option<ParsedRealmAccess>,
+    //:{"roles":["create-realm","admin"]}
+    #[serde(default)]
+    resource_access: HashMap<String, ParsedRealmAccess>,
+    //:{"realm-management":{"roles":["manage-realm"]}}
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize, serde::Serialize)]
+pub struct ParsedRealmAccess {
+    #[serde(default)]
+    roles: Vec<String>,
+}
+
+impl ParsedAccessToken {
+    /// Expiration time in seconds since the unix epoch.
+    pub fn exp(&self) -> usize {
+        self.exp
+    }
+
+    /// Issue time in seconds since the unix epoch.
+    pub fn iat(&self) -> usize {
+        self.iat
+    }
+
+    pub fn iss(&self) -> Option<&str> {
+        self.iss.as_deref()
+    }
+
+    /// Realm which issued the token, taken from `iss`.
+    pub fn realm(&self) -> Option<&str> {
+        self.iss()
+            .and_then(|iss| iss.rsplit_once("/realms/"))
+            .map(|(_, realm)| realm)
+    }
+
+    pub fn sub(&self) -> Option<&str> {
+        self.sub.as_deref()
+    }
+
+    pub fn azp(&self) -> Option<&str> {
+        self.azp.as_deref()
+    }
+
+    pub fn scope(&self) -> Option<&str> {
+        self.scope.as_deref()
+    }
+
+    pub fn preferred_username(&self) -> Option<&str> {
+        self.preferred_username.as_deref()
+    }
+
+    /// Realm roles of the token.
+    pub fn roles(&self) -> &[String] {
+        self.realm_access
+            .as_ref()
+            .map(|r| r.roles.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Roles of the token for the client with the client id `client_id`.
+    pub fn client_roles(&self, client_id: &str) -> &[String] {
+        self.resource_access
+            .get(client_id)
+            .map(|r| r.roles.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Returns `true` if the token grants managing `realm`.
+    ///
+    /// Tokens of the master realm need the `admin` realm role or `manage-realm` of the
+    /// `{realm}-realm` client, tokens of `realm` itself need `manage-realm` or `realm-admin` of
+    /// the `realm-management` client.
+    pub fn can_manage_realm(&self, realm: &str) -> bool {
+        let has = |roles: &[String], role: &str| roles.iter().any(|r| r == role);
+        match self.realm() {
+            Some("master") => {
+                has(self.roles(), "admin")
+                    || has(self.client_roles(&format!("{realm}-realm")), "manage-realm")
+            }
+            Some(token_realm) if token_realm == realm => {
+                let roles = self.client_roles("realm-management");
+                has(roles, "manage-realm") || has(roles, "realm-admin")
+            }
+            _ => false,
+        }
+    }
 }
 
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
@@ -89,6 +178,7 @@ pub struct KeycloakSessionToken {
     #[serde(rename = "not-before-policy")]
     not_before_policy: Option<usize>,
     refresh_expires_in: Option<usize>,
+    #[serde(default)]
     refresh_token: Arc<str>,
     scope: String,
     session_state: Option<String>,
@@ -148,6 +238,13 @@ pub struct KeycloakSessionClient {
 
 impl KeycloakSessionClient {
     pub fn new<T>(url: T, realm: T, client_id: T) -> Self
+    where
+        T: Into<String>,
+    {
+        Self::new_with_client(url, realm, client_id, reqwest::Client::default())
+    }
+
+    pub fn new_with_client<T>(url: T, realm: T, client_id: T, client: reqwest::Client) -> Self
     where
         T: Into<String>,
     {
@@ -156,7 +253,7 @@ impl KeycloakSessionClient {
                 url: Arc::from(url.into()),
                 realm: Arc::from(realm.into()),
                 client_id: Arc::from(client_id.into()),
-                client: reqwest::Client::default(),
+                client,
             }),
         }
     }
@@ -263,9 +360,33 @@ impl KeycloakSessionClient {
     }
 }
 
+/// Grant used to acquire the admin session token.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AdminGrantType {
+    /// `password` grant with username and password of an admin user.
+    #[default]
+    Password,
+    /// `client_credentials` grant with the secret of a service account client.
+    ClientCredentials,
+}
+
+async fn acquire_with_grant_type(
+    keycloak: &KeycloakSessionClient,
+    grant_type: AdminGrantType,
+    username: &str,
+    password: &str,
+) -> Result<KeycloakSessionToken, KeycloakSessionError> {
+    match grant_type {
+        AdminGrantType::Password => keycloak.acquire(username, password).await,
+        AdminGrantType::ClientCredentials => keycloak.acquire_with_secret(password).await,
+    }
+    .map(KeycloakSessionToken::parse_access_token)
+}
+
 async fn try_refresh(
     keycloak: &KeycloakSessionClient,
     refresh_token: &str,
+    grant_type: AdminGrantType,
     username: &str,
     password: &str,
 ) -> Result<KeycloakSessionToken, KeycloakSessionError> {
@@ -279,10 +400,7 @@ async fn try_refresh(
                         "refresh token expired try to acquire new token with credentials"
                     );
                     tracing::error!("{:#?}", err);
-                    keycloak
-                        .acquire(username, password)
-                        .await
-                        .map(KeycloakSessionToken::parse_access_token)
+                    acquire_with_grant_type(keycloak, grant_type, username, password).await
                 } else {
                     Err(err)
                 }
@@ -323,10 +441,13 @@ async fn try_refresh_with_secret(
 }
 
 struct KeycloakSessionInner {
+    client: KeycloakSessionClient,
+    grant_type: AdminGrantType,
     username: Arc<str>,
     password: Arc<str>,
     token: RwLock<KeycloakSessionToken>,
     stop_tx: tokio::sync::watch::Sender<bool>,
+    refresh_running: std::sync::atomic::AtomicBool,
 }
 
 #[derive(Clone)]
@@ -347,107 +468,149 @@ impl KeycloakSession {
         password: &str,
         refresh_enabled: bool,
     ) -> anyhow::Result<Self> {
-        let token = keycloak
-            .acquire(username, password)
-            .await
-            .map(KeycloakSessionToken::parse_access_token)?;
+        Self::new_with_grant_type(
+            keycloak,
+            AdminGrantType::Password,
+            username,
+            password,
+            refresh_enabled,
+        )
+        .await
+    }
+
+    /// Creates a session using `grant_type`.
+    ///
+    /// For [AdminGrantType::ClientCredentials] `password` is the client secret and `username`
+    /// is only used for logging.
+    pub async fn new_with_grant_type(
+        keycloak: KeycloakSessionClient,
+        grant_type: AdminGrantType,
+        username: &str,
+        password: &str,
+        refresh_enabled: bool,
+    ) -> anyhow::Result<Self> {
+        let token = acquire_with_grant_type(&keycloak, grant_type, username, password).await?;
         let username: Arc<str> = Arc::from(username.to_string());
         let password: Arc<str> = Arc::from(password.to_string());
-        let (stop_tx, stop_signal) = tokio::sync::watch::channel(true);
+        let (stop_tx, _) = tokio::sync::watch::channel(true);
         let result = KeycloakSession {
             inner: Arc::new(KeycloakSessionInner {
+                client: keycloak,
+                grant_type,
                 username,
                 password,
                 token: RwLock::new(token),
                 stop_tx,
+                refresh_running: Default::default(),
             }),
         };
         if refresh_enabled {
-            let keycloak = keycloak.clone();
-            let session = result.clone();
-            std::thread::spawn(move || {
-                let rt = Builder::new_current_thread().enable_all().build().unwrap();
-                let local = LocalSet::new();
-                local.spawn_local(async move {
-                    let username = &session.inner.username;
-                    let password = &session.inner.password;
-                    loop {
-                        let (expires_in, refresh_expires_in) = async {
-                            let r = session.inner.token.read().await;
-                            (r.expires_in, r.refresh_expires_in)
+            result.start_refresh(None, DEFAULT_REFRESH_THRESHOLD);
+        }
+        Ok(result)
+    }
+
+    /// Starts the background refresh of the token unless it is already running, e.g. for a
+    /// session created without refresh.
+    ///
+    /// The token is refreshed `threshold` before it expires, or after `interval` if that is
+    /// earlier. Returns `false` if the refresh was already running.
+    pub fn start_refresh(&self, interval: Option<Duration>, threshold: Duration) -> bool {
+        if self
+            .inner
+            .refresh_running
+            .swap(true, std::sync::atomic::Ordering::SeqCst)
+        {
+            return false;
+        }
+        let keycloak = self.inner.client.clone();
+        let session = self.clone();
+        let stop_signal = self.inner.stop_tx.subscribe();
+        std::thread::spawn(move || {
+            let rt = Builder::new_current_thread().enable_all().build().unwrap();
+            let local = LocalSet::new();
+            local.spawn_local(async move {
+                let grant_type = session.inner.grant_type;
+                let username = &session.inner.username;
+                let password = &session.inner.password;
+                loop {
+                    let (expires_in, refresh_expires_in) = async {
+                        let r = session.inner.token.read().await;
+                        (r.expires_in, r.refresh_expires_in)
+                    }
+                    .await;
+                    tracing::debug!("{expires_in} -> {refresh_expires_in:#?}");
+                    let refresh_future = async {
+                        let until_threshold = Duration::from_secs(expires_in as u64)
+                            .checked_sub(threshold)
+                            .ok_or(anyhow::anyhow!("unable to calculate refresh timeout"))?;
+                        tokio::time::sleep(
+                            interval.map_or(until_threshold, |v| v.min(until_threshold)),
+                        )
+                        .await;
+                        let next_token = async {
+                            try_refresh(
+                                &keycloak,
+                                &session.inner.token.read().await.refresh_token,
+                                grant_type,
+                                username,
+                                password,
+                            )
+                            .await
                         }
                         .await;
-                        tracing::debug!("{expires_in} -> {refresh_expires_in:#?}");
-                        let refresh_future = async {
-                            tokio::time::sleep(Duration::from_secs(
-                                expires_in
-                                    .checked_sub(30)
-                                    .ok_or(anyhow::anyhow!("unable to calculate refresh timeout"))?
-                                    as u64,
-                            ))
-                            .await;
-                            let next_token = async {
-                                try_refresh(
-                                    &keycloak,
-                                    &session.inner.token.read().await.refresh_token,
-                                    username,
-                                    password,
-                                )
-                                .await
+                        match next_token {
+                            Ok(next_token) => {
+                                *session.inner.token.write().await = next_token;
                             }
-                            .await;
-                            match next_token {
-                                Ok(next_token) => {
-                                    *session.inner.token.write().await = next_token;
-                                }
-                                Err(err) => {
-                                    tracing::error!("{err:#?}");
-                                    std::process::exit(1)
-                                }
+                            Err(err) => {
+                                tracing::error!("{err:#?}");
+                                std::process::exit(1)
                             }
-                            anyhow::Ok(true)
-                        };
-                        let stop_future = async {
-                            let mut stop_signal = stop_signal.clone();
-                            stop_signal.changed().await?;
-                            let result = *stop_signal.borrow_and_update();
-                            anyhow::Ok(result)
-                        };
-                        tokio::select! {
-                            result = refresh_future => {
-                                match result {
-                                    Ok(_) => {},
-                                    Err(_) => {
-                                        tracing::debug!("acquire new session");
-                                        match keycloak
-                                            .acquire(username, password)
-                                            .await
-                                            .map(KeycloakSessionToken::parse_access_token) {
-                                            Ok(next_token) => {
-                                                *session.inner.token.write().await = next_token;
-                                            },
-                                            Err(err) => {
-                                                tracing::error!("{err:#?}");
-                                                std::process::exit(1)
-                                            }
+                        }
+                        anyhow::Ok(true)
+                    };
+                    let stop_future = async {
+                        let mut stop_signal = stop_signal.clone();
+                        stop_signal.changed().await?;
+                        let result = *stop_signal.borrow_and_update();
+                        anyhow::Ok(result)
+                    };
+                    tokio::select! {
+                        result = refresh_future => {
+                            match result {
+                                Ok(_) => {},
+                                Err(_) => {
+                                    tracing::debug!("acquire new session");
+                                    match acquire_with_grant_type(&keycloak, grant_type, username, password).await {
+                                        Ok(next_token) => {
+                                            *session.inner.token.write().await = next_token;
+                                        },
+                                        Err(err) => {
+                                            tracing::error!("{err:#?}");
+                                            std::process::exit(1)
                                         }
                                     }
                                 }
                             }
-                            is_logged_in = stop_future => {
-                                if !is_logged_in.unwrap_or(false) {
-                                    break
-                                }
+                        }
+                        is_logged_in = stop_future => {
+                            if !is_logged_in.unwrap_or(false) {
+                                break
                             }
                         }
                     }
-                    tracing::debug!("session ends for user {username}");
-                    anyhow::Ok(())
-                });
-                rt.block_on(local);
+                }
+                session
+                    .inner
+                    .refresh_running
+                    .store(false, std::sync::atomic::Ordering::SeqCst);
+                tracing::debug!("session ends for user {username}");
+                anyhow::Ok(())
             });
-        }
-        Ok(result)
+            rt.block_on(local);
+        });
+        true
     }
 
     pub fn stop(&self) -> anyhow::Result<()> {
@@ -456,10 +619,31 @@ impl KeycloakSession {
         Ok(())
     }
 
+    /// Discards the current token and acquires a new one with the session credentials.
+    ///
+    /// Recovery path for a refresh token that was revoked out-of-band.
+    pub async fn reauthenticate(&self) -> anyhow::Result<()> {
+        tracing::debug!("reauthenticate session for {}", self.inner.username);
+        let token = acquire_with_grant_type(
+            &self.inner.client,
+            self.inner.grant_type,
+            &self.inner.username,
+            &self.inner.password,
+        )
+        .await?;
+        *self.inner.token.write().await = token;
+        Ok(())
+    }
+
     pub async fn access_token(&self) -> Arc<str> {
         self.inner.token.read().await.access_token.clone()
     }
 
+    /// Decoded claims of the current access token, `None` if it could not be decoded.
+    pub async fn current_claims(&self) -> Option<ParsedAccessToken> {
+        self.inner.token.read().await.parsed_access_token.clone()
+    }
+
     pub async fn token(&self) -> Arc<str> {
         self.inner
             .token
@@ -472,6 +656,17 @@ impl KeycloakSession {
     }
 }
 
+#[cfg(test)]
+impl KeycloakSession {
+    pub(crate) async fn invalidate(&self) {
+        let mut token = self.inner.token.write().await;
+        token.access_token = Arc::from("invalid");
+        token.refresh_token = Arc::from("revoked");
+        token.parsed_access_token = None;
+        token.client_token = None;
+    }
+}
+
 #[async_trait::async_trait]
 impl KeycloakTokenSupplier for KeycloakSession {
     async fn get(&self, _url: &str) -> Result<String, KeycloakError> {
@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::Context;
+
+use crate::KeycloakConfig;
+
+/// Timeout of the connection attempt in [check_smtp_reachable].
+pub const SMTP_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Connection settings of an SMTP server as stored in the `smtpServer` of a realm.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SmtpSettings<'a> {
+    pub host: &'a str,
+    pub port: u16,
+    pub ssl: bool,
+    pub starttls: bool,
+}
+
+impl<'a> SmtpSettings<'a> {
+    pub fn from_smtp_server(smtp_server: &'a HashMap<String, String>) -> anyhow::Result<Self> {
+        let flag = |key: &str| smtp_server.get(key).is_some_and(|v| v == "true");
+        let port = smtp_server.get("port").map(String::as_str).unwrap_or("25");
+        Ok(Self {
+            host: smtp_server.get("host").map(String::as_str).unwrap_or(""),
+            port: port
+                .parse()
+                .with_context(|| format!("invalid SMTP port '{port}'"))?,
+            ssl: flag("ssl"),
+            starttls: flag("starttls"),
+        })
+    }
+}
+
+/// Checks that the SMTP settings are coherent.
+pub fn validate_smtp_settings(settings: &SmtpSettings<'_>) -> anyhow::Result<()> {
+    if settings.ssl && settings.starttls {
+        anyhow::bail!("SMTP 'ssl' and 'starttls' must not be enabled at the same time");
+    }
+    if settings.host.trim().is_empty() {
+        anyhow::bail!("SMTP host is missing");
+    }
+    if settings.port == 0 {
+        anyhow::bail!("SMTP port must not be 0");
+    }
+    Ok(())
+}
+
+/// Opens a TCP connection to the SMTP server to check that it is reachable.
+pub async fn check_smtp_reachable(
+    settings: &SmtpSettings<'_>,
+    timeout: Duration,
+) -> anyhow::Result<()> {
+    let address = format!("{}:{}", settings.host, settings.port);
+    tokio::time::timeout(timeout, tokio::net::TcpStream::connect(&address))
+        .await
+        .with_context(|| format!("timed out connecting to SMTP server '{address}'"))?
+        .with_context(|| format!("unable to connect to SMTP server '{address}'"))?;
+    Ok(())
+}
+
+/// Logs a warning if the configured SMTP settings are not coherent or, with
+/// `KEYCLOAK_SMTP_VERIFY_CONNECTION=true`, if the server is not reachable.
+///
+/// Returns `false` if a warning was logged.
+pub async fn verify_smtp_settings(config: &KeycloakConfig) -> bool {
+    let smtp_server = config.smtp_server_defaults();
+    let result = async {
+        let settings = SmtpSettings::from_smtp_server(&smtp_server)?;
+        validate_smtp_settings(&settings)?;
+        if config.smtp_verify_connection() {
+            check_smtp_reachable(&settings, SMTP_CONNECT_TIMEOUT).await?;
+        }
+        anyhow::Ok(())
+    }
+    .await;
+    if let Err(err) = &result {
+        tracing::warn!("SMTP settings of realm '{}': {err:#}", config.realm());
+    }
+    result.is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(ssl: bool, starttls: bool) -> SmtpSettings<'static> {
+        SmtpSettings {
+            host: "smtp",
+            port: 1025,
+            ssl,
+            starttls,
+        }
+    }
+
+    #[test]
+    fn test_validate_smtp_settings() {
+        assert!(validate_smtp_settings(&settings(false, false)).is_ok());
+        assert!(validate_smtp_settings(&settings(true, false)).is_ok());
+        assert!(validate_smtp_settings(&settings(false, true)).is_ok());
+        assert!(validate_smtp_settings(&settings(true, true)).is_err());
+        assert!(validate_smtp_settings(&SmtpSettings {
+            host: " ",
+            ..settings(false, false)
+        })
+        .is_err());
+
+        let smtp_server = HashMap::from([
+            ("host".to_string(), "mail".to_string()),
+            ("port".to_string(), "587".to_string()),
+            ("starttls".to_string(), "true".to_string()),
+        ]);
+        assert_eq!(
+            SmtpSettings::from_smtp_server(&smtp_server).unwrap(),
+            SmtpSettings {
+                host: "mail",
+                port: 587,
+                ssl: false,
+                starttls: true,
+            }
+        );
+        let smtp_server = HashMap::from([("port".to_string(), "smtp".to_string())]);
+        assert!(SmtpSettings::from_smtp_server(&smtp_server).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_check_smtp_reachable() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let settings = SmtpSettings {
+            host: "127.0.0.1",
+            port,
+            ssl: false,
+            starttls: false,
+        };
+        check_smtp_reachable(&settings, SMTP_CONNECT_TIMEOUT)
+            .await
+            .unwrap();
+        drop(listener);
+        assert!(check_smtp_reachable(&settings, SMTP_CONNECT_TIMEOUT)
+            .await
+            .is_err());
+    }
+}
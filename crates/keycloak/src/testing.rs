@@ -0,0 +1,150 @@
+//! Mock Keycloak server for tests of this and dependent crates, enabled with the `testing`
+//! feature.
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::Config as KeycloakConfig;
+use crate::Keycloak;
+
+pub async fn read_request(socket: &mut tokio::net::TcpStream) -> String {
+    use tokio::io::AsyncReadExt;
+
+    let mut request = vec![];
+    let mut buf = [0u8; 1024];
+    loop {
+        let n = socket.read(&mut buf).await.unwrap();
+        request.extend_from_slice(&buf[..n]);
+        let text = String::from_utf8_lossy(&request);
+        if let Some((head, body)) = text.split_once("\r\n\r\n") {
+            let content_length = head
+                .lines()
+                .find_map(|l| {
+                    l.to_lowercase()
+                        .strip_prefix("content-length: ")
+                        .map(str::to_string)
+                })
+                .and_then(|l| l.trim().parse::<usize>().ok())
+                .unwrap_or(0);
+            if n == 0 || body.len() >= content_length {
+                break;
+            }
+        }
+    }
+    String::from_utf8(request).unwrap()
+}
+
+pub async fn respond(socket: &mut tokio::net::TcpStream, status: &str, body: &str) {
+    use tokio::io::AsyncWriteExt;
+
+    socket
+        .write_all(
+            format!(
+                "HTTP/1.1 {status}\r\ncontent-type: application/json\r\nconnection: close\r\ncontent-length: {}\r\n\r\n{body}",
+                body.len()
+            )
+            .as_bytes(),
+        )
+        .await
+        .unwrap();
+}
+
+pub fn admin_token() -> String {
+    use base64::engine::{general_purpose::STANDARD_NO_PAD, Engine};
+
+    let now = chrono::Utc::now().timestamp();
+    let claims = serde_json::json!({
+        "exp": now + 60,
+        "iat": now,
+        "iss": "http://127.0.0.1/realms/master",
+        "preferred_username": "admin",
+        "realm_access": { "roles": ["admin", "create-realm"] },
+    });
+    let access_token = format!(
+        "header.{}.signature",
+        STANDARD_NO_PAD.encode(claims.to_string())
+    );
+    serde_json::json!({
+        "access_token": access_token,
+        "expires_in": 60,
+        "refresh_token": "refresh-token",
+        "scope": "",
+        "token_type": "Bearer",
+    })
+    .to_string()
+}
+
+/// Starts a server answering `METHOD /path?query` requests from `routes`
+/// and returns a client logged in against it.
+pub async fn mock_keycloak(routes: HashMap<&'static str, &'static str>) -> Keycloak {
+    mock_keycloak_recorded(routes).await.0
+}
+
+/// Like [mock_keycloak] and additionally records all admin api requests.
+pub async fn mock_keycloak_recorded(
+    routes: HashMap<&'static str, &'static str>,
+) -> (Keycloak, Arc<std::sync::Mutex<Vec<String>>>) {
+    let requests = Arc::new(std::sync::Mutex::new(vec![]));
+    let recorded = requests.clone();
+    let keycloak = mock_keycloak_with(move |route, body| {
+        recorded
+            .lock()
+            .unwrap()
+            .push(format!("{route} {body}").trim_end().to_string());
+        routes.get(route).map(|body| body.to_string())
+    })
+    .await;
+    (keycloak, requests)
+}
+
+/// Like [mock_keycloak] but answers admin api requests with `handler`, which gets the
+/// route and the body of a request and returns the response body, `None` responds 404.
+pub async fn mock_keycloak_with<F>(handler: F) -> Keycloak
+where
+    F: FnMut(&str, &str) -> Option<String> + Send + 'static,
+{
+    mock_keycloak_with_config(serde_json::json!({}), handler).await
+}
+
+/// Like [mock_keycloak_with] with the client configured from the `KEYCLOAK_` variables in
+/// `config`, e.g. `{ "impersonation_enabled": true }`.
+pub async fn mock_keycloak_with_config<F>(mut config: serde_json::Value, mut handler: F) -> Keycloak
+where
+    F: FnMut(&str, &str) -> Option<String> + Send + 'static,
+{
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let request = read_request(&mut socket).await;
+            let request_line = request.lines().next().unwrap_or_default();
+            let route = request_line
+                .rsplit_once(' ')
+                .map(|(route, _)| route.replace("//", "/"))
+                .unwrap_or_default();
+            let body = request
+                .split_once("\r\n\r\n")
+                .map(|(_, body)| body)
+                .unwrap_or_default();
+            if route == "POST /realms/master/protocol/openid-connect/token"
+                && !body.contains("token-exchange")
+            {
+                respond(&mut socket, "200 OK", &admin_token()).await;
+                continue;
+            }
+            if let Some(body) = handler(&route, body) {
+                respond(&mut socket, "200 OK", &body).await;
+            } else {
+                respond(&mut socket, "404 Not Found", "{}").await;
+            }
+        }
+    });
+    config["address"] = serde_json::Value::from(format!("http://{addr}/"));
+    let config: KeycloakConfig = serde_json::from_value(config).unwrap();
+    Keycloak::builder()
+        .with_no_refresh()
+        .with_startup_retry(1, Duration::ZERO)
+        .build_with_config(config)
+        .await
+        .unwrap()
+}
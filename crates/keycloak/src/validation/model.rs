@@ -25,3 +25,13 @@ impl RealmConfigError {
         Self { id, key }
     }
 }
+
+/// Outcome of validating and updating a single realm.
+#[derive(Default, Debug, Serialize, Deserialize)]
+pub struct RealmValidationReport {
+    pub realm: String,
+    /// Errors which are still present after updating the realm
+    pub errors: Vec<RealmConfigError>,
+    /// Set if the realm could not be validated or updated
+    pub failure: Option<String>,
+}
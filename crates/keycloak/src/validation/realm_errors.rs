@@ -2,6 +2,7 @@ pub const REALM_PREFIX: &str = "realm-";
 pub const CLIENTS_CLIENT_PREFIX: &str = "clients-client-";
 pub const REALM_AUTHENTICATION_FLOW_2FAEMAIL_PREFIX: &str = "authentication_flow_2faemail-";
 pub const REALM_BROWSER_FLOW_PREFIX: &str = "browser_flow";
+pub const CLIENTS_CONFIGURED_PREFIX: &str = "clients-configured:";
 pub const REALM_DEFAULT_LOCALE_INVALID_ID: &str = "realm-default_locale-invalid";
 pub const REALM_DEFAULT_LOCALE_MISSING_ID: &str = "realm-default_locale-missing";
 pub const REALM_INTERNATIONALIZATION_ENABLED_ID: &str = "realm-internationalization_enabled";
@@ -18,10 +19,13 @@ pub const REALM_PASSWORD_POLICY_SYMBOL_ID: &str = "realm-password_policy-symbol"
 pub const REALM_PASSWORD_POLICY_UPPERCASE_ID: &str = "realm-password_policy-uppercase";
 pub const REALM_PASSWORD_POLICY_LOWERCASE_ID: &str = "realm-password_policy-lowercase";
 pub const REALM_PASSWORD_POLICY_DIGIT_ID: &str = "realm-password_policy-digit";
+pub const REALM_PASSWORD_POLICY_NOT_USERNAME_ID: &str = "realm-password_policy-not_username";
+pub const REALM_PASSWORD_POLICY_NOT_EMAIL_ID: &str = "realm-password_policy-not_email";
 pub const REALM_PASSWORD_POLICY_MISSING_ID: &str = "realm-password_policy-missing";
 pub const REALM_REMEMBER_ME_ID: &str = "realm-remember_me";
 pub const REALM_REGISTRATION_ALLOWED_ID: &str = "realm-registration_allowed";
 pub const REALM_RESET_PASSWORD_ALLOWED_ID: &str = "realm-reset_password_allowed";
+pub const REALM_TOKEN_LIFESPANS_MISMATCHED_ID: &str = "realm-token_lifespans-mismatched";
 pub const REALM_SUPPORTED_LOCALES_INVALID_ID: &str = "realm-supported_locales-invalid";
 pub const REALM_SUPPORTED_LOCALES_MISSING_ID: &str = "realm-supported_locales-missing";
 pub const REALM_SMTP_SERVER_MISSING_ID: &str = "realm-smtp_server-missing";
@@ -76,6 +80,10 @@ pub const CLIENTS_CLIENT_STANDARD_FLOW_ENABLED_ID: &str = "clients-client-standa
 pub const CLIENTS_CLIENT_MISSING_ID: &str = "clients-client-missing";
 pub const CLIENTS_CLIENT_FRONTCHANNEL_LOGOUT_ENABLED_ID: &str =
     "clients-client-frontchannel_logout_enabled";
+pub const CLIENTS_CLIENT_PROTOCOL_MAPPERS_MISSING_ID: &str =
+    "clients-client-protocol_mappers-missing";
+pub const CLIENTS_CLIENT_WEB_ORIGINS_MISSING_ID: &str = "clients-client-web_origins-missing";
+pub const CLIENTS_CONFIGURED_MISSING_FIELD: &str = "missing";
 pub const GROUPS_CUSTOMER_ID: &str = "groups-customer";
 pub const GROUPS_OWNER_ID: &str = "groups-owner";
 pub const ROLES_CUSTOMER_ID: &str = "roles-customer_id";
@@ -107,10 +115,13 @@ pub const REALM_PASSWORD_POLICY_SYMBOL_KEY: &str = "realm.password_policy.symbol
 pub const REALM_PASSWORD_POLICY_UPPERCASE_KEY: &str = "realm.password_policy.uppercase";
 pub const REALM_PASSWORD_POLICY_LOWERCASE_KEY: &str = "realm.password_policy.lowercase";
 pub const REALM_PASSWORD_POLICY_DIGIT_KEY: &str = "realm.password_policy.digit";
+pub const REALM_PASSWORD_POLICY_NOT_USERNAME_KEY: &str = "realm.password_policy.not_username";
+pub const REALM_PASSWORD_POLICY_NOT_EMAIL_KEY: &str = "realm.password_policy.not_email";
 pub const REALM_PASSWORD_POLICY_MISSING_KEY: &str = "realm.password_policy.missing";
 pub const REALM_REMEMBER_ME_KEY: &str = "realm.remember_me";
 pub const REALM_REGISTRATION_ALLOWED_KEY: &str = "realm.registration_allowed";
 pub const REALM_RESET_PASSWORD_ALLOWED_KEY: &str = "realm.reset_password_allowed";
+pub const REALM_TOKEN_LIFESPANS_MISMATCHED_KEY: &str = "realm.token_lifespans.mismatched";
 pub const REALM_SUPPORTED_LOCALES_INVALID_KEY: &str = "realm.supported_locales.invalid";
 pub const REALM_SUPPORTED_LOCALES_MISSING_KEY: &str = "realm.supported_locales.missing";
 pub const REALM_SMTP_SERVER_MISSING_KEY: &str = "realm.smtp_server.missing";
@@ -165,3 +176,18 @@ pub const CLIENTS_CLIENT_STANDARD_FLOW_ENABLED_KEY: &str = "clients.client.stand
 pub const CLIENTS_CLIENT_MISSING_KEY: &str = "clients.client.missing";
 pub const CLIENTS_CLIENT_FRONTCHANNEL_LOGOUT_ENABLED_KEY: &str =
     "clients.client.frontchannel_logout_enabled";
+pub const CLIENTS_CLIENT_PROTOCOL_MAPPERS_MISSING_KEY: &str =
+    "clients.client.protocol_mappers.missing";
+pub const CLIENTS_CLIENT_WEB_ORIGINS_MISSING_KEY: &str = "clients.client.web_origins.missing";
+pub const CLIENTS_CONFIGURED_MISSING_KEY: &str = "clients.configured.missing";
+pub const CLIENTS_CONFIGURED_INVALID_KEY: &str = "clients.configured.invalid";
+
+/// Prefix of the error ids of a client configured with `KEYCLOAK_CLIENTS`.
+pub fn configured_client_prefix(client_id: &str) -> String {
+    format!("{CLIENTS_CONFIGURED_PREFIX}{client_id}:")
+}
+
+/// Error id of a field of a client configured with `KEYCLOAK_CLIENTS`.
+pub fn configured_client_id(client_id: &str, field: &str) -> String {
+    format!("{}{field}", configured_client_prefix(client_id))
+}
@@ -7,15 +7,102 @@ use keycloak::types::{
 use keycloak::KeycloakError;
 use serde_json::Value;
 
-use crate::{ClientRepresentation, RealmRepresentation};
+use crate::config::DEFAULT_PASSWORD_POLICY;
+use crate::{ClientRepresentation, KeycloakConfig, RealmRepresentation};
 
-use crate::validation::context::ValidationContext as Ctx;
-use crate::validation::model::RealmConfigErrorInput;
+use crate::validation::context::{Config, ValidationContext as Ctx};
+use crate::validation::model::{RealmConfigError, RealmConfigErrorInput, RealmValidationReport};
 use crate::validation::realm_errors;
+use crate::validation::validator::{
+    missing_protocol_mappers, satisfies_password_rule, split_password_rule, validate_realm,
+};
+
+const MAX_UPDATE_TRIES: usize = 5;
+
+#[derive(thiserror::Error, Debug)]
+pub enum UpdaterError {
+    /// Errors for which no update exists, carrying the remaining actions
+    #[error("could not resolve all errors: {0:?}")]
+    UnresolvedErrors(Vec<RealmConfigErrorInput>),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+/// Validates the realm of the context and updates it until no errors are left.
+///
+/// Returns the errors which could not be resolved after the last try.
+pub async fn validate_and_update_realm(ctx: &Ctx<'_>) -> anyhow::Result<Vec<RealmConfigError>> {
+    let realm = ctx.cfg().realm();
+    let mut current_try = 1;
+    while let Some(errors) = validate_realm(ctx).await? {
+        if errors.is_empty() || current_try > MAX_UPDATE_TRIES {
+            return Ok(errors);
+        }
+        for error in errors.iter() {
+            tracing::error!("{}", error.id);
+        }
+        tracing::info!(
+            "{current_try}. try time to update realm {} for errors {}",
+            realm,
+            errors.len()
+        );
+        update_for_errors(ctx, errors.into_iter().map(From::from).collect()).await?;
+        current_try += 1;
+    }
+    Ok(vec![])
+}
+
+/// Validates and updates all realms except `master`, a failing realm does not abort the others.
+pub async fn validate_all_realms(ctx: &Ctx<'_>) -> anyhow::Result<Vec<RealmValidationReport>> {
+    let realms = ctx.keycloak().realms().await?;
+    Ok(for_each_realm(realms, |realm| async move {
+        let ctx = Ctx {
+            keycloak: ctx.keycloak(),
+            config: &Config {
+                realm: &realm,
+                keycloak: ctx.cfg().keycloak(),
+                public_url: ctx.cfg().public_url(),
+            },
+        };
+        validate_and_update_realm(&ctx).await
+    })
+    .await)
+}
+
+async fn for_each_realm<F, Fut>(realms: Vec<String>, f: F) -> Vec<RealmValidationReport>
+where
+    F: Fn(String) -> Fut,
+    Fut: std::future::Future<Output = anyhow::Result<Vec<RealmConfigError>>>,
+{
+    let mut reports = Vec::with_capacity(realms.len());
+    for realm in realms {
+        let report = match f(realm.clone()).await {
+            Ok(errors) => RealmValidationReport {
+                realm,
+                errors,
+                failure: None,
+            },
+            Err(err) => {
+                tracing::error!("unable to validate realm '{realm}': {err:#?}");
+                RealmValidationReport {
+                    realm,
+                    errors: vec![],
+                    failure: Some(err.to_string()),
+                }
+            }
+        };
+        reports.push(report);
+    }
+    reports
+}
+
+/// Updates the realm for the given errors.
+///
+/// Fails with [`UpdaterError::UnresolvedErrors`] if some of the errors are unknown to the updater.
 pub async fn update_for_errors(
     ctx: &Ctx<'_>,
     errors: Vec<RealmConfigErrorInput>,
-) -> anyhow::Result<()> {
+) -> Result<(), UpdaterError> {
     let realm = ctx.cfg().realm();
     let mut actions = errors;
     update_realm_settings(
@@ -45,6 +132,18 @@ pub async fn update_for_errors(
     .await?;
     actions.retain(|e| !e.id.starts_with(realm_errors::CLIENTS_CLIENT_PREFIX));
 
+    update_configured_clients(
+        ctx,
+        realm,
+        actions
+            .iter()
+            .filter(|e| e.id.starts_with(realm_errors::CLIENTS_CONFIGURED_PREFIX))
+            .cloned()
+            .collect(),
+    )
+    .await?;
+    actions.retain(|e| !e.id.starts_with(realm_errors::CLIENTS_CONFIGURED_PREFIX));
+
     update_authentication_flows(
         ctx,
         realm,
@@ -79,7 +178,7 @@ pub async fn update_for_errors(
             "Some unknown errors could not be resolved. Remaining: {:?}",
             actions
         );
-        return Err(anyhow::Error::msg("Could not resolve all errors"));
+        return Err(UpdaterError::UnresolvedErrors(actions));
     }
 
     Ok(())
@@ -111,74 +210,100 @@ async fn update_realm_settings(
             rep.internationalization_enabled = Some(true);
         }
         realm_errors::REALM_LOGIN_THEME_INVALID_ID | realm_errors::REALM_LOGIN_THEME_MISSING_ID => {
+            let theme = ctx.cfg().keycloak().theme();
+            if let Some(invalid) = rep.login_theme.as_deref() {
+                tracing::warn!(
+                    "login theme '{invalid}' of realm '{realm}' is not one of {:?}, falling back to '{theme}'",
+                    ctx.cfg().keycloak().available_themes()
+                );
+            }
             tracing::trace!("Setting 'login_theme' for realm '{}'", realm);
-            rep.login_theme = Some(ctx.cfg().keycloak().theme().to_string());
+            rep.login_theme = Some(theme.to_string());
         }
         realm_errors::REALM_EMAIL_THEME_INVALID_ID | realm_errors::REALM_EMAIL_THEME_MISSING_ID => {
+            let theme = ctx.cfg().keycloak().email_theme();
+            if let Some(invalid) = rep.email_theme.as_deref() {
+                tracing::warn!(
+                    "email theme '{invalid}' of realm '{realm}' is not one of {:?}, falling back to '{theme}'",
+                    ctx.cfg().keycloak().available_themes()
+                );
+            }
             tracing::trace!("Setting 'email_theme' for realm '{}'", realm);
-            rep.email_theme = Some(ctx.cfg().keycloak().email_theme().to_string());
+            rep.email_theme = Some(theme.to_string());
         }
         realm_errors::REALM_PASSWORD_POLICY_LENGTH_ID => {
             tracing::trace!(
                 "Adding 'password_policy' value 'length(8)' for realm '{}'",
                 realm
             );
-            let new_policy = match &rep.password_policy {
-                Some(s) => format!("{} and length(8)", s),
-                None => "length(8)".to_string(),
-            };
-            rep.password_policy = Some(new_policy)
+            rep.password_policy = Some(append_password_policy(
+                rep.password_policy.as_deref(),
+                "length(8)",
+            ))
         }
         realm_errors::REALM_PASSWORD_POLICY_SYMBOL_ID => {
             tracing::trace!(
                 "Adding 'password_policy' value 'specialChars(1)' for realm '{}'",
                 realm
             );
-            let new_policy = match &rep.password_policy {
-                Some(s) => format!("{} and specialChars(1)", s),
-                None => "specialChars(1)".to_string(),
-            };
-            rep.password_policy = Some(new_policy)
+            rep.password_policy = Some(append_password_policy(
+                rep.password_policy.as_deref(),
+                "specialChars(1)",
+            ))
         }
         realm_errors::REALM_PASSWORD_POLICY_UPPERCASE_ID => {
             tracing::trace!(
                 "Adding 'password_policy' value 'upperCase(1)' for realm '{}'",
                 realm
             );
-            let new_policy = match &rep.password_policy {
-                Some(s) => format!("{} and upperCase(1)", s),
-                None => "upperCase(1)".to_string(),
-            };
-            rep.password_policy = Some(new_policy)
+            rep.password_policy = Some(append_password_policy(
+                rep.password_policy.as_deref(),
+                "upperCase(1)",
+            ))
         }
         realm_errors::REALM_PASSWORD_POLICY_LOWERCASE_ID => {
             tracing::trace!(
                 "Adding 'password_policy' value 'lowerCase(1)' for realm '{}'",
                 realm
             );
-            let new_policy = match &rep.password_policy {
-                Some(s) => format!("{} and lowerCase(1)", s),
-                None => "lowerCase(1)".to_string(),
-            };
-            rep.password_policy = Some(new_policy)
+            rep.password_policy = Some(append_password_policy(
+                rep.password_policy.as_deref(),
+                "lowerCase(1)",
+            ))
         }
         realm_errors::REALM_PASSWORD_POLICY_DIGIT_ID => {
             tracing::trace!(
                 "Adding 'password_policy' value 'digits(1)' for realm '{}'",
                 realm
             );
-            let new_policy = match &rep.password_policy {
-                Some(s) => format!("{} and digits(1)", s),
-                None => "digits(1)".to_string(),
-            };
-            rep.password_policy = Some(new_policy)
+            rep.password_policy = Some(append_password_policy(
+                rep.password_policy.as_deref(),
+                "digits(1)",
+            ))
+        }
+        realm_errors::REALM_PASSWORD_POLICY_NOT_USERNAME_ID => {
+            tracing::trace!(
+                "Adding 'password_policy' value 'notUsername(undefined)' for realm '{}'",
+                realm
+            );
+            rep.password_policy = Some(append_password_policy(
+                rep.password_policy.as_deref(),
+                "notUsername(undefined)",
+            ))
+        }
+        realm_errors::REALM_PASSWORD_POLICY_NOT_EMAIL_ID => {
+            tracing::trace!(
+                "Adding 'password_policy' value 'notEmail(undefined)' for realm '{}'",
+                realm
+            );
+            rep.password_policy = Some(append_password_policy(
+                rep.password_policy.as_deref(),
+                "notEmail(undefined)",
+            ))
         }
         realm_errors::REALM_PASSWORD_POLICY_MISSING_ID => {
             tracing::trace!("Setting 'password_policy' for realm '{}'", realm);
-            rep.password_policy = Some(
-                "length(8) and specialChars(1) and upperCase(1) and lowerCase(1) and digits(1)"
-                    .to_string(),
-            )
+            rep.password_policy = Some(DEFAULT_PASSWORD_POLICY.to_string())
         }
         realm_errors::REALM_REMEMBER_ME_ID => {
             tracing::trace!("Setting 'remember_me' for realm '{}'", realm);
@@ -188,6 +313,10 @@ async fn update_realm_settings(
             tracing::trace!("Setting 'registration_allowed' for realm '{}'", realm);
             rep.registration_allowed = Some(false);
         }
+        realm_errors::REALM_TOKEN_LIFESPANS_MISMATCHED_ID => {
+            tracing::trace!("Setting token lifespans for realm '{}'", realm);
+            ctx.cfg().keycloak().token_lifespans().apply(&mut rep);
+        }
         realm_errors::REALM_RESET_PASSWORD_ALLOWED_ID => {
             tracing::trace!("Setting 'reset_password_allowed' for realm '{}'", realm);
             rep.reset_password_allowed = Some(true);
@@ -296,6 +425,26 @@ async fn update_realm_settings(
     Ok(())
 }
 
+/// Appends `rule` to the given password policy, replacing an existing rule of the same type
+/// because Keycloak rejects policies that contain a rule type twice. An existing rule which is
+/// stricter than `rule`, e.g. `length(12)` for `length(8)`, is kept.
+fn append_password_policy(policy: Option<&str>, rule: &str) -> String {
+    let (ty, _) = split_password_rule(rule);
+    let mut rules: Vec<String> = policy
+        .unwrap_or_default()
+        .split(" and ")
+        .map(str::trim)
+        .filter(|r| !r.is_empty())
+        .map(str::to_string)
+        .collect();
+    match rules.iter_mut().find(|r| split_password_rule(r).0 == ty) {
+        Some(existing) if satisfies_password_rule(existing, rule) => {}
+        Some(existing) => *existing = rule.to_string(),
+        None => rules.push(rule.to_string()),
+    }
+    rules.join(" and ")
+}
+
 async fn update_authentication_flows(
     ctx: &Ctx<'_>,
     realm: &str,
@@ -457,35 +606,48 @@ async fn create_email_otp_authenticator_execution(
     Ok(())
 }
 
-async fn add_configuration_to_browser_email_totp_execution(
-    ctx: &Ctx<'_>,
-    realm: &str,
-    executions: Vec<AuthenticationExecutionInfoRepresentation>,
-) -> anyhow::Result<()> {
+/// Builds the configuration of the email OTP authenticator from the keycloak config.
+fn email_otp_authenticator_config(keycloak: &KeycloakConfig) -> AuthenticatorConfigRepresentation {
     let mut config: HashMap<String, String> = HashMap::new();
     config.insert("default.reference.value".to_string(), "".to_string());
     config.insert("default.reference.maxAge".to_string(), "".to_string());
     config.insert("simulation".to_string(), "false".to_string());
     config.insert(
         "emailSubject".to_string(),
-        ctx.cfg()
-            .keycloak()
+        keycloak
             .authenticator_email_subject()
             .unwrap_or("Temporary Authentication Code")
             .to_string(),
     );
-    config.insert("length".to_string(), "6".to_string());
-    config.insert("ttl".to_string(), "300".to_string());
-    config.insert("maxRetries".to_string(), "3".to_string());
+    config.insert(
+        "length".to_string(),
+        keycloak.authenticator_otp_length().to_string(),
+    );
+    config.insert(
+        "ttl".to_string(),
+        keycloak.authenticator_otp_ttl().to_string(),
+    );
+    config.insert(
+        "maxRetries".to_string(),
+        keycloak.authenticator_otp_max_retries().to_string(),
+    );
     config.insert("allowUppercase".to_string(), "true".to_string());
     config.insert("allowLowercase".to_string(), "true".to_string());
     config.insert("allowNumbers".to_string(), "true".to_string());
 
-    let body_config = AuthenticatorConfigRepresentation {
+    AuthenticatorConfigRepresentation {
         alias: Some("email_otp_flow".to_string()),
         config: Some(config),
         ..AuthenticatorConfigRepresentation::default()
-    };
+    }
+}
+
+async fn add_configuration_to_browser_email_totp_execution(
+    ctx: &Ctx<'_>,
+    realm: &str,
+    executions: Vec<AuthenticationExecutionInfoRepresentation>,
+) -> anyhow::Result<()> {
+    let body_config = email_otp_authenticator_config(ctx.cfg().keycloak());
     let email_totp_auth_execution: AuthenticationExecutionInfoRepresentation = executions
         .iter()
         .find(|&execution| execution.display_name == Some("Email TOTP Authentication".to_string()))
@@ -545,21 +707,14 @@ async fn update_client_settings(
                 | realm_errors::CLIENTS_CLIENT_ATTRIBUTES_OAUTH2_DEVICE_AUTHORIZATION_GRANT_ENABLED_MISSING_ID
                 | realm_errors::CLIENTS_CLIENT_ATTRIBUTES_MISSING_ID
                 | realm_errors::CLIENTS_CLIENT_ATTRIBUTES_BACKCHANNEL_LOGOUT_DISABLED_ID => {
-                    if let Some(attributes) = rep.attributes.as_mut() {
-                        match e.id.as_str() {
-                            realm_errors::CLIENTS_CLIENT_ATTRIBUTES_BACKCHANNEL_LOGOUT_DISABLED_ID => {
-                                tracing::trace!("Setting attribute 'backchannel.logout.url' for client 'spa' in realm '{}'", realm);
-                                let backchannel_logout_url = env::var("BACKCHANNEL_LOGOUT_URL").unwrap_or("http://qm-backend:10220/api/logout".to_string());
-                                attributes.insert("backchannel.logout.url".to_string(), backchannel_logout_url.to_string());
-                            },
-                            _ => {
-                                tracing::trace!("Setting attribute 'oauth2.device.authorization.grant.enabled' for client 'spa' in realm '{}'", realm);
-                                attributes.insert("oauth2.device.authorization.grant.enabled".to_string(), "false".to_string());}
-                            }
-                    } else {
-                        rep.attributes = Some(HashMap::from_iter(vec![("oauth2.device.authorization.grant.enabled".to_string(), "false".to_string()),
-                        ("backchannel.logout.url".to_string(), "http://qm-backend:10220/api/logout".to_string())]))
-                    }
+                    let defaults = spa_client_default_attributes(ctx.cfg().keycloak());
+                    let key = match e.id.as_str() {
+                        realm_errors::CLIENTS_CLIENT_ATTRIBUTES_MISSING_ID => None,
+                        realm_errors::CLIENTS_CLIENT_ATTRIBUTES_BACKCHANNEL_LOGOUT_DISABLED_ID => Some(BACKCHANNEL_LOGOUT_URL_ATTRIBUTE),
+                        _ => Some(OAUTH2_DEVICE_AUTHORIZATION_GRANT_ENABLED_ATTRIBUTE),
+                    };
+                    tracing::trace!("Setting attribute '{}' for client 'spa' in realm '{}'", key.unwrap_or("*"), realm);
+                    merge_client_attributes(rep, defaults.into_iter().filter(|(k, _)| key.map_or(true, |key| key == k)));
                 }
                 realm_errors::CLIENTS_CLIENT_BASE_URL_INVALID_ID
                 | realm_errors::CLIENTS_CLIENT_BASE_URL_MISSING_ID => {
@@ -593,13 +748,11 @@ async fn update_client_settings(
                 realm_errors::CLIENTS_CLIENT_REDIRECT_URIS_INVALID_ID
                 | realm_errors::CLIENTS_CLIENT_REDIRECT_URIS_MISSING_ID => {
                     tracing::trace!("Adding 'redirect_uris' for configured value for client 'spa' in realm '{}'", realm);
-                    if let Some(uris) = rep.redirect_uris.as_mut() {
-                        uris.clear();
-                        uris.push(ctx.cfg().public_url().to_string());
-                        uris.push(format!("{}*", ctx.cfg().public_url()));
-                    } else {
-                        rep.redirect_uris = Some(vec![format!("{}*", ctx.cfg().public_url())]);
-                    }
+                    set_client_redirect_uris(rep, ctx.cfg().public_url());
+                }
+                realm_errors::CLIENTS_CLIENT_WEB_ORIGINS_MISSING_ID => {
+                    tracing::trace!("Setting 'web_origins' for client 'spa' in realm '{}'", realm);
+                    set_client_web_origins(rep, ctx.cfg().public_url());
                 }
                 realm_errors::CLIENTS_CLIENT_ROOT_URL_INVALID_ID
                 | realm_errors::CLIENTS_CLIENT_ROOT_URL_MISSING_ID => {
@@ -618,6 +771,8 @@ async fn update_client_settings(
                     tracing::trace!("Setting 'front_channel_logout' for client 'spa' in realm '{}'", realm);
                     rep.frontchannel_logout = Some(false);
                 }
+                // mappers are created separately after the client update
+                realm_errors::CLIENTS_CLIENT_PROTOCOL_MAPPERS_MISSING_ID => {}
                 _ => tracing::warn!("Unknown client error id '{}'. No action taken.", e.id),
             }
         });
@@ -630,32 +785,26 @@ async fn update_client_settings(
         ctx.keycloak()
             .update_client(realm, rep.id.as_ref().unwrap(), rep.clone())
             .await?;
+        if errors
+            .iter()
+            .any(|e| e.id == realm_errors::CLIENTS_CLIENT_PROTOCOL_MAPPERS_MISSING_ID)
+        {
+            for mapper in missing_protocol_mappers(
+                rep.protocol_mappers.as_deref(),
+                ctx.cfg().keycloak().spa_protocol_mappers(),
+            ) {
+                tracing::trace!(
+                    "Adding protocol mapper '{}' to client 'spa' in realm '{}'",
+                    mapper.name.as_deref().unwrap_or_default(),
+                    realm
+                );
+                ctx.keycloak()
+                    .create_client_protocol_mapper(realm, rep.id.as_ref().unwrap(), mapper)
+                    .await?;
+            }
+        }
     } else {
-        let rep = ClientRepresentation {
-            attributes: Some(HashMap::from_iter(vec![
-                (
-                    "oauth2.device.authorization.grant.enabled".to_string(),
-                    "false".to_string(),
-                ),
-                (
-                    "backchannel.logout.url".to_string(),
-                    "http://qm-backend:10220/api/logout".to_string(),
-                ),
-            ])),
-            base_url: Some(ctx.cfg().public_url().trim_end_matches('/').to_string()),
-            client_id: Some("spa".to_string()),
-            consent_required: Some(false),
-            direct_access_grants_enabled: Some(true),
-            enabled: Some(true),
-            implicit_flow_enabled: Some(false),
-            public_client: Some(true),
-            redirect_uris: Some(vec![format!("{}*", ctx.cfg().public_url())]),
-            root_url: Some(ctx.cfg().public_url().trim_end_matches('/').to_string()),
-            service_accounts_enabled: Some(false),
-            standard_flow_enabled: Some(true),
-            frontchannel_logout: Some(false),
-            ..ClientRepresentation::default()
-        };
+        let rep = spa_client_representation(ctx.cfg());
 
         tracing::info!(
             "Could not find required client 'spa' for realm '{}'. Creating with the following representation: {:?}",
@@ -667,39 +816,454 @@ async fn update_client_settings(
     Ok(())
 }
 
-pub fn get_smtp_server_defaults(ctx: &Ctx<'_>) -> Option<HashMap<String, String>> {
-    let mut defaults: HashMap<String, String> = HashMap::new();
+/// Creates or updates the clients configured with `KEYCLOAK_CLIENTS` which have errors.
+async fn update_configured_clients(
+    ctx: &Ctx<'_>,
+    realm: &str,
+    errors: Vec<RealmConfigErrorInput>,
+) -> anyhow::Result<()> {
+    if errors.is_empty() {
+        tracing::info!("No configured client errors in realm '{}'", realm);
+        return Ok(());
+    }
+    let clients = ctx.cfg().keycloak().clients()?;
+    for e in errors.iter().filter(|e| {
+        !clients.iter().any(|client| {
+            e.id.starts_with(&realm_errors::configured_client_prefix(
+                client.client_id.as_deref().unwrap_or_default(),
+            ))
+        })
+    }) {
+        tracing::warn!(
+            "Unknown configured client error id '{}'. No action taken.",
+            e.id
+        );
+    }
+    for expected in clients {
+        let client_id = expected.client_id.as_deref().unwrap_or_default();
+        let prefix = realm_errors::configured_client_prefix(client_id);
+        if !errors.iter().any(|e| e.id.starts_with(&prefix)) {
+            continue;
+        }
+        match ctx.keycloak().client_by_client_id(realm, client_id).await? {
+            Some(client) => {
+                let id = client.id.clone().unwrap_or_default();
+                let rep = apply_client_settings(client, &expected)?;
+                tracing::info!("Updating the client '{client_id}' for realm '{realm}'");
+                ctx.keycloak().update_client(realm, &id, rep).await?;
+            }
+            None => {
+                tracing::info!(
+                    "Could not find required client '{client_id}' for realm '{realm}'. Creating it."
+                );
+                ctx.keycloak().create_client(realm, expected).await?;
+            }
+        }
+    }
+    Ok(())
+}
 
-    if let Some(configured_starttls) = ctx.cfg().keycloak().smtp_starttls() {
-        defaults.insert(String::from("starttls"), configured_starttls.to_string());
-    } else {
-        defaults.insert(String::from("starttls"), "false".to_string());
+/// Overwrites the fields of `client` which are set in `settings`, attributes are merged.
+fn apply_client_settings(
+    client: ClientRepresentation,
+    settings: &ClientRepresentation,
+) -> serde_json::Result<ClientRepresentation> {
+    let mut client = serde_json::to_value(client)?;
+    let Value::Object(settings) = serde_json::to_value(settings)? else {
+        return serde_json::from_value(client);
+    };
+    for (field, value) in settings {
+        match (field.as_str(), value) {
+            ("id", _) => {}
+            ("attributes", Value::Object(attributes)) => {
+                let existing = &mut client["attributes"];
+                if !existing.is_object() {
+                    *existing = Value::Object(Default::default());
+                }
+                if let Value::Object(existing) = existing {
+                    existing.extend(attributes);
+                }
+            }
+            (_, value) => client[field] = value,
+        }
     }
-    if let Some(configured_port) = ctx.cfg().keycloak().smtp_port() {
-        defaults.insert(String::from("port"), configured_port.to_string());
-    } else {
-        defaults.insert(String::from("port"), "1025".to_string());
+    serde_json::from_value(client)
+}
+
+const OAUTH2_DEVICE_AUTHORIZATION_GRANT_ENABLED_ATTRIBUTE: &str =
+    "oauth2.device.authorization.grant.enabled";
+const BACKCHANNEL_LOGOUT_URL_ATTRIBUTE: &str = "backchannel.logout.url";
+
+/// Default attributes of the `spa` client, `KEYCLOAK_SPA_ATTRIBUTES` takes precedence.
+pub fn spa_client_default_attributes(config: &KeycloakConfig) -> HashMap<String, String> {
+    let backchannel_logout_url = env::var("BACKCHANNEL_LOGOUT_URL")
+        .unwrap_or_else(|_| "http://qm-backend:10220/api/logout".to_string());
+    let mut attributes = HashMap::from_iter([
+        (
+            OAUTH2_DEVICE_AUTHORIZATION_GRANT_ENABLED_ATTRIBUTE.to_string(),
+            "false".to_string(),
+        ),
+        (
+            BACKCHANNEL_LOGOUT_URL_ATTRIBUTE.to_string(),
+            backchannel_logout_url,
+        ),
+    ]);
+    attributes.extend(
+        config
+            .spa_attributes()
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value.to_string())),
+    );
+    attributes
+}
+
+/// Sets the given attributes on the client, attributes not managed here are kept.
+fn merge_client_attributes(
+    rep: &mut ClientRepresentation,
+    attributes: impl IntoIterator<Item = (String, String)>,
+) {
+    rep.attributes
+        .get_or_insert_with(HashMap::new)
+        .extend(attributes);
+}
+
+/// Returns the representation of the `spa` client which passes the validation.
+pub fn spa_client_representation(cfg: &Config<'_>) -> ClientRepresentation {
+    ClientRepresentation {
+        attributes: Some(spa_client_default_attributes(cfg.keycloak())),
+        base_url: Some(cfg.public_url().trim_end_matches('/').to_string()),
+        client_id: Some("spa".to_string()),
+        consent_required: Some(false),
+        direct_access_grants_enabled: Some(true),
+        enabled: Some(true),
+        implicit_flow_enabled: Some(false),
+        public_client: Some(true),
+        redirect_uris: Some(vec![format!("{}*", cfg.public_url())]),
+        web_origins: Some(vec![public_url_origin(cfg.public_url())]),
+        root_url: Some(cfg.public_url().trim_end_matches('/').to_string()),
+        service_accounts_enabled: Some(false),
+        standard_flow_enabled: Some(true),
+        frontchannel_logout: Some(false),
+        protocol_mappers: Some(cfg.keycloak().spa_protocol_mappers()),
+        ..ClientRepresentation::default()
     }
-    if let Some(configured_host) = ctx.cfg().keycloak().smtp_host() {
-        defaults.insert(String::from("host"), configured_host.to_string());
+}
+
+/// Re-runs the detection and returns the errors which are still present.
+///
+/// Used after [`update_for_errors`] to confirm that a realm has converged.
+pub async fn revalidate(ctx: &Ctx<'_>) -> anyhow::Result<Vec<RealmConfigErrorInput>> {
+    Ok(validate_realm(ctx)
+        .await?
+        .unwrap_or_default()
+        .into_iter()
+        .map(From::from)
+        .collect())
+}
+
+/// Returns the origin (`scheme://host[:port]`) of the public url.
+pub fn public_url_origin(public_url: &str) -> String {
+    reqwest::Url::parse(public_url)
+        .ok()
+        .map(|url| url.origin().ascii_serialization())
+        .filter(|origin| origin != "null")
+        .unwrap_or_else(|| public_url.trim_end_matches('/').to_string())
+}
+
+/// Sets the redirect uris of the client to the public url and a `*`-suffixed pattern of it.
+pub fn set_client_redirect_uris(rep: &mut ClientRepresentation, public_url: &str) {
+    if let Some(uris) = rep.redirect_uris.as_mut() {
+        uris.clear();
+        uris.push(public_url.to_string());
+        uris.push(format!("{public_url}*"));
     } else {
-        defaults.insert(String::from("host"), "smtp".to_string());
+        rep.redirect_uris = Some(vec![format!("{public_url}*")]);
     }
-    if let Some(configured_from) = ctx.cfg().keycloak().smtp_from() {
-        defaults.insert(String::from("from"), configured_from.to_string());
-    } else {
-        defaults.insert(String::from("from"), "noreply@qm.local".to_string());
+}
+
+/// Sets the web origins of the client to the origin of the public url.
+pub fn set_client_web_origins(rep: &mut ClientRepresentation, public_url: &str) {
+    rep.web_origins = Some(vec![public_url_origin(public_url)]);
+}
+
+pub fn get_smtp_server_defaults(ctx: &Ctx<'_>) -> Option<HashMap<String, String>> {
+    Some(ctx.cfg().keycloak().smtp_server_defaults())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{mock_keycloak, mock_keycloak_recorded};
+
+    #[tokio::test]
+    async fn test_update_for_errors_unresolved() {
+        let keycloak = mock_keycloak(HashMap::new()).await;
+        let config: KeycloakConfig = serde_json::from_str("{}").unwrap();
+        let ctx = Ctx {
+            keycloak: &keycloak,
+            config: &Config {
+                realm: "test",
+                keycloak: &config,
+                public_url: "http://localhost",
+            },
+        };
+        let errors = vec![
+            RealmConfigErrorInput {
+                id: "unknown.a".to_string(),
+            },
+            RealmConfigErrorInput {
+                id: "unknown.b".to_string(),
+            },
+        ];
+        match update_for_errors(&ctx, errors).await {
+            Err(UpdaterError::UnresolvedErrors(remaining)) => {
+                let ids: Vec<&str> = remaining.iter().map(|e| e.id.as_str()).collect();
+                assert_eq!(ids, vec!["unknown.a", "unknown.b"]);
+            }
+            other => panic!("unexpected result: {other:?}"),
+        }
     }
-    if let Some(configured_from) = ctx.cfg().keycloak().smtp_from_display_name() {
-        defaults.insert(String::from("fromDisplayName"), configured_from.to_string());
-    } else {
-        defaults.insert(String::from("fromDisplayName"), "qm".to_string());
+
+    #[tokio::test]
+    async fn test_for_each_realm() {
+        let reports = for_each_realm(vec!["a".to_string(), "b".to_string()], |realm| async move {
+            match realm.as_str() {
+                "a" => anyhow::bail!("realm '{realm}' not reachable"),
+                _ => Ok(vec![RealmConfigError::new(
+                    realm_errors::REALM_REMEMBER_ME_ID.to_string(),
+                    realm_errors::REALM_REMEMBER_ME_KEY.to_string(),
+                )]),
+            }
+        })
+        .await;
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].realm, "a");
+        assert_eq!(
+            reports[0].failure.as_deref(),
+            Some("realm 'a' not reachable")
+        );
+        assert_eq!(reports[1].realm, "b");
+        assert!(reports[1].failure.is_none());
+        assert_eq!(reports[1].errors[0].id, realm_errors::REALM_REMEMBER_ME_ID);
     }
-    if let Some(configured_ssl) = ctx.cfg().keycloak().smtp_ssl() {
-        defaults.insert(String::from("ssl"), configured_ssl.to_owned().to_string());
-    } else {
-        defaults.insert(String::from("ssl"), "false".to_string());
+
+    #[tokio::test]
+    async fn test_revalidate() {
+        let config: KeycloakConfig = serde_json::from_str("{}").unwrap();
+        let realm: &'static str =
+            serde_json::to_string(&config.default_realm_representation("test"))
+                .unwrap()
+                .leak();
+        let fixed = Config {
+            realm: "test",
+            keycloak: &config,
+            public_url: "http://localhost/",
+        };
+        let mut client = spa_client_representation(&fixed);
+        client.id = Some("1".to_string());
+        let clients: &'static str = serde_json::to_string(&[client]).unwrap().leak();
+        let keycloak = mock_keycloak(HashMap::from([
+            ("GET /admin/realms/test", realm),
+            ("GET /admin/realms/test/authentication/flows", "[]"),
+            (
+                "GET /admin/realms/test/clients?clientId=spa&search=true&viewableOnly=false",
+                clients,
+            ),
+        ]))
+        .await;
+        let ctx = Ctx {
+            keycloak: &keycloak,
+            config: &fixed,
+        };
+        let remaining = revalidate(&ctx).await.unwrap();
+        assert!(remaining.is_empty(), "{remaining:?}");
+    }
+
+    #[test]
+    fn test_set_client_redirect_uris_and_web_origins() {
+        let public_url = "https://app.example.com:8443/";
+        let mut rep = ClientRepresentation::default();
+        set_client_redirect_uris(&mut rep, public_url);
+        set_client_web_origins(&mut rep, public_url);
+        assert_eq!(
+            rep.redirect_uris.as_deref(),
+            Some(&["https://app.example.com:8443/*".to_string()][..])
+        );
+        assert_eq!(
+            rep.web_origins.as_deref(),
+            Some(&["https://app.example.com:8443".to_string()][..])
+        );
+
+        set_client_redirect_uris(&mut rep, public_url);
+        assert_eq!(
+            rep.redirect_uris.unwrap(),
+            vec![
+                "https://app.example.com:8443/".to_string(),
+                "https://app.example.com:8443/*".to_string()
+            ]
+        );
+        assert_eq!(public_url_origin("http://localhost/"), "http://localhost");
+    }
+
+    #[test]
+    fn test_merge_client_attributes_keeps_custom() {
+        let config: KeycloakConfig = serde_json::from_value(serde_json::json!({
+            "spa_attributes": ["pkce.code.challenge.method=S256", "invalid"],
+        }))
+        .unwrap();
+        let defaults = spa_client_default_attributes(&config);
+        assert_eq!(defaults["pkce.code.challenge.method"], "S256");
+        assert_eq!(
+            defaults[OAUTH2_DEVICE_AUTHORIZATION_GRANT_ENABLED_ATTRIBUTE],
+            "false"
+        );
+        assert!(!defaults.contains_key("invalid"));
+
+        let mut rep = ClientRepresentation {
+            attributes: Some(HashMap::from_iter([
+                ("custom.attribute".to_string(), "custom".to_string()),
+                (
+                    OAUTH2_DEVICE_AUTHORIZATION_GRANT_ENABLED_ATTRIBUTE.to_string(),
+                    "true".to_string(),
+                ),
+            ])),
+            ..ClientRepresentation::default()
+        };
+        merge_client_attributes(&mut rep, defaults);
+        let attributes = rep.attributes.unwrap();
+        assert_eq!(attributes["custom.attribute"], "custom");
+        assert_eq!(
+            attributes[OAUTH2_DEVICE_AUTHORIZATION_GRANT_ENABLED_ATTRIBUTE],
+            "false"
+        );
+        assert_eq!(attributes["pkce.code.challenge.method"], "S256");
+        assert!(attributes.contains_key(BACKCHANNEL_LOGOUT_URL_ATTRIBUTE));
     }
 
-    Some(defaults)
+    #[tokio::test]
+    async fn test_configured_clients() {
+        let config: KeycloakConfig = serde_json::from_value(serde_json::json!({
+            "clients": r#"[
+                {"clientId":"account-console","publicClient":true,"attributes":{"pkce.code.challenge.method":"S256"}},
+                {"clientId":"broker","enabled":true}
+            ]"#,
+        }))
+        .unwrap();
+        let (keycloak, requests) = mock_keycloak_recorded(HashMap::from([
+            (
+                "GET /admin/realms/test/clients?clientId=account-console&search=false&viewableOnly=false",
+                r#"[{"id":"1","clientId":"account-console","publicClient":false,"attributes":{"custom":"value"}}]"#,
+            ),
+            (
+                "GET /admin/realms/test/clients?clientId=broker&search=false&viewableOnly=false",
+                "[]",
+            ),
+            ("PUT /admin/realms/test/clients/1", ""),
+            ("POST /admin/realms/test/clients", ""),
+        ]))
+        .await;
+        let cfg = Config {
+            keycloak: &config,
+            public_url: "http://localhost/",
+            realm: "test",
+        };
+        let ctx = Ctx {
+            keycloak: &keycloak,
+            config: &cfg,
+        };
+        let mut errors = vec![];
+        crate::validation::validator::check_configured_clients(&ctx, "test", &mut errors)
+            .await
+            .unwrap();
+        let ids: Vec<_> = errors.iter().map(|e| e.id.as_str()).collect();
+        assert_eq!(
+            ids,
+            vec![
+                "clients-configured:account-console:attributes.pkce.code.challenge.method",
+                "clients-configured:account-console:publicClient",
+                "clients-configured:broker:missing",
+            ]
+        );
+
+        update_for_errors(&ctx, errors.into_iter().map(Into::into).collect())
+            .await
+            .unwrap();
+        let requests = requests.lock().unwrap();
+        let update = requests
+            .iter()
+            .find_map(|r| r.strip_prefix("PUT /admin/realms/test/clients/1 "))
+            .unwrap();
+        let update: ClientRepresentation = serde_json::from_str(update).unwrap();
+        assert_eq!(update.public_client, Some(true));
+        let attributes = update.attributes.unwrap();
+        assert_eq!(attributes["custom"], "value");
+        assert_eq!(attributes["pkce.code.challenge.method"], "S256");
+        let create = requests
+            .iter()
+            .find_map(|r| r.strip_prefix("POST /admin/realms/test/clients "))
+            .unwrap();
+        let create: ClientRepresentation = serde_json::from_str(create).unwrap();
+        assert_eq!(create.client_id.as_deref(), Some("broker"));
+        assert_eq!(create.enabled, Some(true));
+    }
+
+    #[test]
+    fn test_email_otp_authenticator_config() {
+        let config: KeycloakConfig = serde_json::from_value(serde_json::json!({
+            "authenticator_email_subject": "Your code",
+            "authenticator_otp_length": 8,
+            "authenticator_otp_ttl": 600,
+            "authenticator_otp_max_retries": 5,
+        }))
+        .unwrap();
+        let rep = email_otp_authenticator_config(&config);
+        let values = rep.config.unwrap();
+        assert_eq!(values["emailSubject"], "Your code");
+        assert_eq!(values["length"], "8");
+        assert_eq!(values["ttl"], "600");
+        assert_eq!(values["maxRetries"], "5");
+
+        let config: KeycloakConfig =
+            serde_json::from_value(serde_json::json!({ "authenticator_otp_length": 32 })).unwrap();
+        let values = email_otp_authenticator_config(&config).config.unwrap();
+        assert_eq!(values["length"], "10");
+        assert_eq!(values["ttl"], "300");
+        assert_eq!(values["maxRetries"], "3");
+    }
+
+    #[test]
+    fn test_append_password_policy() {
+        assert_eq!(append_password_policy(None, "length(8)"), "length(8)");
+        assert_eq!(append_password_policy(Some(""), "length(8)"), "length(8)");
+        assert_eq!(
+            append_password_policy(Some("length(8) and digits(1)"), "notUsername(undefined)"),
+            "length(8) and digits(1) and notUsername(undefined)"
+        );
+        assert_eq!(
+            append_password_policy(
+                Some("length(8) and notUsername(undefined)"),
+                "notEmail(undefined)"
+            ),
+            "length(8) and notUsername(undefined) and notEmail(undefined)"
+        );
+        assert_eq!(
+            append_password_policy(
+                Some("length(8) and notEmail(undefined)"),
+                "notEmail(undefined)"
+            ),
+            "length(8) and notEmail(undefined)"
+        );
+        assert_eq!(
+            append_password_policy(Some("length(12) and digits(1)"), "length(8)"),
+            "length(12) and digits(1)"
+        );
+        assert_eq!(
+            append_password_policy(Some("length(6) and digits(1)"), "length(8)"),
+            "length(8) and digits(1)"
+        );
+        assert_eq!(
+            append_password_policy(Some("length(12) and digits(2)"), "notEmail(undefined)"),
+            "length(12) and digits(2) and notEmail(undefined)"
+        );
+    }
 }
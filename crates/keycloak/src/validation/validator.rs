@@ -3,7 +3,9 @@ use std::collections::HashMap;
 use crate::validation::context::ValidationContext as Ctx;
 use crate::validation::model::RealmConfigError;
 use crate::validation::realm_errors;
-use crate::{ClientRepresentation, RealmRepresentation};
+use crate::{
+    ClientRepresentation, KeycloakConfig, ProtocolMapperRepresentation, RealmRepresentation,
+};
 
 pub async fn validate_realm(ctx: &Ctx<'_>) -> anyhow::Result<Option<Vec<RealmConfigError>>> {
     let mut errors = vec![];
@@ -11,6 +13,7 @@ pub async fn validate_realm(ctx: &Ctx<'_>) -> anyhow::Result<Option<Vec<RealmCon
     tracing::info!("validating realm '{realm}'");
     check_realm_settings(ctx, realm, &mut errors).await?;
     check_client(ctx, realm, &mut errors).await?;
+    check_configured_clients(ctx, realm, &mut errors).await?;
     Ok(Some(errors))
 }
 
@@ -21,6 +24,29 @@ async fn check_realm_settings(
 ) -> anyhow::Result<()> {
     let rep: RealmRepresentation = ctx.keycloak().realm_by_name(realm).await?;
 
+    check_realm_representation(ctx.cfg().keycloak(), &rep, errors);
+
+    let authentication_flows = ctx.keycloak().get_authentication_flows(realm).await?;
+    if ctx.cfg().keycloak().browser_flow() == "browser_email_otp"
+        && !authentication_flows
+            .iter()
+            .any(|flow| flow.alias.as_deref() == Some("browser_email_otp"))
+    {
+        add_error(
+            realm_errors::REALM_AUTHENTICATION_FLOW_2FAEMAIL_MISSING_ID,
+            realm_errors::REALM_AUTHENTICATION_FLOW_2FAEMAIL_MISSING_KEY,
+            errors,
+        );
+    }
+    Ok(())
+}
+
+/// Checks the settings of a realm representation against the configuration.
+fn check_realm_representation(
+    config: &KeycloakConfig,
+    rep: &RealmRepresentation,
+    errors: &mut Vec<RealmConfigError>,
+) {
     // default_locale must be `de`
     if let Some(locale) = &rep.default_locale {
         if locale != "de" {
@@ -45,9 +71,9 @@ async fn check_realm_settings(
             errors,
         );
     }
-    // login_theme must be `qm`
+    // login_theme must be one of the available themes
     if let Some(theme) = &rep.login_theme {
-        if theme != ctx.keycloak().config().theme() {
+        if !config.is_available_theme(theme) {
             add_error(
                 realm_errors::REALM_LOGIN_THEME_INVALID_ID,
                 realm_errors::REALM_LOGIN_THEME_INVALID_KEY,
@@ -61,9 +87,9 @@ async fn check_realm_settings(
             errors,
         );
     }
-    // email_theme must be `qm`
+    // email_theme must be one of the available themes
     if let Some(email_theme) = &rep.email_theme {
-        if email_theme != ctx.keycloak().config().email_theme() {
+        if !config.is_available_theme(email_theme) {
             add_error(
                 realm_errors::REALM_EMAIL_THEME_INVALID_ID,
                 realm_errors::REALM_EMAIL_THEME_INVALID_KEY,
@@ -78,42 +104,57 @@ async fn check_realm_settings(
         );
     }
     // password_policy must contain `length(8)`, `specialChars(1)`, `upperCase(1)`, `lowerCase(1)`, `digits(1)`
+    // or stricter values, `notUsername(undefined)`, `notEmail(undefined)`
     if let Some(policy) = &rep.password_policy {
-        if !policy.contains("length(8)") {
+        if !has_password_rule(policy, "length(8)") {
             add_error(
                 realm_errors::REALM_PASSWORD_POLICY_LENGTH_ID,
                 realm_errors::REALM_PASSWORD_POLICY_LENGTH_KEY,
                 errors,
             );
         }
-        if !policy.contains("specialChars(1)") {
+        if !has_password_rule(policy, "specialChars(1)") {
             add_error(
                 realm_errors::REALM_PASSWORD_POLICY_SYMBOL_ID,
                 realm_errors::REALM_PASSWORD_POLICY_SYMBOL_KEY,
                 errors,
             );
         }
-        if !policy.contains("upperCase(1)") {
+        if !has_password_rule(policy, "upperCase(1)") {
             add_error(
                 realm_errors::REALM_PASSWORD_POLICY_UPPERCASE_ID,
                 realm_errors::REALM_PASSWORD_POLICY_UPPERCASE_KEY,
                 errors,
             );
         }
-        if !policy.contains("lowerCase(1)") {
+        if !has_password_rule(policy, "lowerCase(1)") {
             add_error(
                 realm_errors::REALM_PASSWORD_POLICY_LOWERCASE_ID,
                 realm_errors::REALM_PASSWORD_POLICY_LOWERCASE_KEY,
                 errors,
             );
         }
-        if !policy.contains("digits(1)") {
+        if !has_password_rule(policy, "digits(1)") {
             add_error(
                 realm_errors::REALM_PASSWORD_POLICY_DIGIT_ID,
                 realm_errors::REALM_PASSWORD_POLICY_DIGIT_KEY,
                 errors,
             );
         }
+        if !policy.contains("notUsername(") {
+            add_error(
+                realm_errors::REALM_PASSWORD_POLICY_NOT_USERNAME_ID,
+                realm_errors::REALM_PASSWORD_POLICY_NOT_USERNAME_KEY,
+                errors,
+            );
+        }
+        if !policy.contains("notEmail(") {
+            add_error(
+                realm_errors::REALM_PASSWORD_POLICY_NOT_EMAIL_ID,
+                realm_errors::REALM_PASSWORD_POLICY_NOT_EMAIL_KEY,
+                errors,
+            );
+        }
     } else {
         add_error(
             realm_errors::REALM_PASSWORD_POLICY_MISSING_ID,
@@ -145,6 +186,14 @@ async fn check_realm_settings(
             errors,
         );
     }
+    // configured token and session lifespans must be set
+    if !config.token_lifespans().is_applied(rep) {
+        add_error(
+            realm_errors::REALM_TOKEN_LIFESPANS_MISMATCHED_ID,
+            realm_errors::REALM_TOKEN_LIFESPANS_MISMATCHED_KEY,
+            errors,
+        );
+    }
     // supported_locales must contain `de`
     if let Some(locales) = &rep.supported_locales {
         if !locales.contains(&"de".to_string()) {
@@ -163,7 +212,7 @@ async fn check_realm_settings(
     }
     // smtp_server must be configured
     if let Some(smtp_server) = &rep.smtp_server {
-        check_realm_smtp_settings(ctx, smtp_server, errors);
+        check_realm_smtp_settings(config, smtp_server, errors);
     } else {
         add_error(
             realm_errors::REALM_SMTP_SERVER_MISSING_ID,
@@ -172,22 +221,8 @@ async fn check_realm_settings(
         );
     }
 
-    let authentication_flows = ctx.keycloak().get_authentication_flows(realm).await?;
-    let browser_flow_config = ctx.keycloak().config().browser_flow();
-    if browser_flow_config == "browser_email_otp"
-        && !authentication_flows
-            .iter()
-            .any(|flow| flow.alias.as_deref() == Some("browser_email_otp"))
-    {
-        add_error(
-            realm_errors::REALM_AUTHENTICATION_FLOW_2FAEMAIL_MISSING_ID,
-            realm_errors::REALM_AUTHENTICATION_FLOW_2FAEMAIL_MISSING_KEY,
-            errors,
-        );
-    }
-
     if let Some(browser_flow) = &rep.browser_flow {
-        if browser_flow != browser_flow_config {
+        if browser_flow != config.browser_flow() {
             add_error(
                 realm_errors::REALM_BROWSER_FLOW_INVALID_ID,
                 realm_errors::REALM_BROWSER_FLOW_INVALID_KEY,
@@ -201,7 +236,6 @@ async fn check_realm_settings(
             errors,
         );
     }
-    Ok(())
 }
 
 async fn check_client(
@@ -376,6 +410,25 @@ async fn check_client(
                 errors,
             );
         }
+        // web_origins must allow the origin of the configured value
+        let origin = crate::validation::updater::public_url_origin(ctx.cfg().public_url());
+        if !client
+            .web_origins
+            .as_ref()
+            .is_some_and(|origins| origins.iter().any(|o| o == "+" || *o == origin))
+        {
+            tracing::info!(
+                "[{}]: Expected the 'web_origins' values '{:?}' to contain '{}'",
+                realm,
+                client.web_origins,
+                origin
+            );
+            add_error(
+                realm_errors::CLIENTS_CLIENT_WEB_ORIGINS_MISSING_ID,
+                realm_errors::CLIENTS_CLIENT_WEB_ORIGINS_MISSING_KEY,
+                errors,
+            );
+        }
         // service_accounts_enabled must be `false`
         if client.service_accounts_enabled.unwrap_or(false) {
             add_error(
@@ -400,6 +453,19 @@ async fn check_client(
                 errors,
             );
         }
+        // protocol_mappers must contain the configured mappers
+        if !missing_protocol_mappers(
+            client.protocol_mappers.as_deref(),
+            ctx.cfg().keycloak().spa_protocol_mappers(),
+        )
+        .is_empty()
+        {
+            add_error(
+                realm_errors::CLIENTS_CLIENT_PROTOCOL_MAPPERS_MISSING_ID,
+                realm_errors::CLIENTS_CLIENT_PROTOCOL_MAPPERS_MISSING_KEY,
+                errors,
+            );
+        }
     } else {
         add_error(
             realm_errors::CLIENTS_CLIENT_MISSING_ID,
@@ -410,6 +476,109 @@ async fn check_client(
     Ok(())
 }
 
+/// Checks the clients configured with `KEYCLOAK_CLIENTS`.
+pub(crate) async fn check_configured_clients(
+    ctx: &Ctx<'_>,
+    realm: &str,
+    errors: &mut Vec<RealmConfigError>,
+) -> anyhow::Result<()> {
+    for expected in ctx.cfg().keycloak().clients()? {
+        let client_id = expected.client_id.as_deref().unwrap_or_default();
+        match ctx.keycloak().client_by_client_id(realm, client_id).await? {
+            Some(client) => {
+                for field in mismatched_client_fields(&expected, &client)? {
+                    tracing::info!("[{realm}]: Client '{client_id}' has an unexpected '{field}'");
+                    add_error(
+                        realm_errors::configured_client_id(client_id, &field),
+                        realm_errors::CLIENTS_CONFIGURED_INVALID_KEY.to_string(),
+                        errors,
+                    );
+                }
+            }
+            None => add_error(
+                realm_errors::configured_client_id(
+                    client_id,
+                    realm_errors::CLIENTS_CONFIGURED_MISSING_FIELD,
+                ),
+                realm_errors::CLIENTS_CONFIGURED_MISSING_KEY.to_string(),
+                errors,
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Returns the fields set in `expected` which have another value in `client`.
+///
+/// Attributes are compared one by one and reported as `attributes.<name>`.
+pub(crate) fn mismatched_client_fields(
+    expected: &ClientRepresentation,
+    client: &ClientRepresentation,
+) -> serde_json::Result<Vec<String>> {
+    let serde_json::Value::Object(expected) = serde_json::to_value(expected)? else {
+        return Ok(vec![]);
+    };
+    let client = serde_json::to_value(client)?;
+    let mut fields = vec![];
+    for (field, value) in expected {
+        match field.as_str() {
+            "id" | "clientId" => {}
+            "attributes" => {
+                for (name, value) in value.as_object().into_iter().flatten() {
+                    if client["attributes"].get(name) != Some(value) {
+                        fields.push(format!("attributes.{name}"));
+                    }
+                }
+            }
+            _ => {
+                if client.get(&field) != Some(&value) {
+                    fields.push(field);
+                }
+            }
+        }
+    }
+    Ok(fields)
+}
+
+/// Returns the `desired` protocol mappers which have no mapper with the same name in `existing`.
+pub(crate) fn missing_protocol_mappers(
+    existing: Option<&[ProtocolMapperRepresentation]>,
+    desired: Vec<ProtocolMapperRepresentation>,
+) -> Vec<ProtocolMapperRepresentation> {
+    let existing = existing.unwrap_or_default();
+    desired
+        .into_iter()
+        .filter(|mapper| !existing.iter().any(|e| e.name == mapper.name))
+        .collect()
+}
+
+/// Splits a password policy rule like `length(8)` into its type and argument.
+pub(crate) fn split_password_rule(rule: &str) -> (&str, &str) {
+    let rule = rule.trim();
+    match rule.split_once('(') {
+        Some((ty, arg)) => (ty.trim(), arg.trim_end_matches(')')),
+        None => (rule, ""),
+    }
+}
+
+/// Whether `existing` is a password policy rule of the same type as `rule` which is at least
+/// as strict, e.g. `length(12)` satisfies `length(8)`.
+pub(crate) fn satisfies_password_rule(existing: &str, rule: &str) -> bool {
+    let (ty, arg) = split_password_rule(existing);
+    let (rule_ty, rule_arg) = split_password_rule(rule);
+    ty == rule_ty
+        && match (arg.parse::<u32>(), rule_arg.parse::<u32>()) {
+            (Ok(value), Ok(required)) => value >= required,
+            _ => arg == rule_arg,
+        }
+}
+
+fn has_password_rule(policy: &str, rule: &str) -> bool {
+    policy
+        .split(" and ")
+        .any(|existing| satisfies_password_rule(existing, rule))
+}
+
 fn add_error<S>(error_id: S, error_key: S, errors: &mut Vec<RealmConfigError>)
 where
     S: Into<String>,
@@ -418,13 +587,11 @@ where
 }
 
 fn check_realm_smtp_settings(
-    ctx: &Ctx<'_>,
+    config: &KeycloakConfig,
     smtp_server: &HashMap<String, String>,
     errors: &mut Vec<RealmConfigError>,
 ) {
-    if let Some(configured_reply_to_display_name) =
-        ctx.cfg().keycloak().smtp_reply_to_display_name()
-    {
+    if let Some(configured_reply_to_display_name) = config.smtp_reply_to_display_name() {
         // reply_to_display_name must be the configured value
         if let Some(reply_to_display_name) = smtp_server.get("replyToDisplayName") {
             if configured_reply_to_display_name != reply_to_display_name {
@@ -451,7 +618,7 @@ fn check_realm_smtp_settings(
     if let Some(starttls_value) = smtp_server.get("starttls") {
         // starttls must be the configured value or `false` if not configured
         let starttls = get_bool_from_string_value(starttls_value);
-        if let Some(configured_starttls) = ctx.cfg().keycloak().smtp_starttls() {
+        if let Some(configured_starttls) = config.smtp_starttls() {
             if configured_starttls != &starttls {
                 tracing::info!(
                     "The configured 'KEYCLOAK_SMTP_STARTTLS' '{}' does not match with the value from keycloak '{}'",
@@ -482,7 +649,7 @@ fn check_realm_smtp_settings(
     if let Some(port_value) = smtp_server.get("port") {
         // port must be the configured value or `1025` if not configured
         let port = get_u16_from_value(port_value);
-        if let Some(configured_port) = ctx.cfg().keycloak().smtp_port() {
+        if let Some(configured_port) = config.smtp_port() {
             if configured_port != &port {
                 tracing::info!(
                     "The configured 'KEYCLOAK_SMTP_PORT' '{}' does not match with the value from keycloak '{}'",
@@ -512,7 +679,7 @@ fn check_realm_smtp_settings(
 
     if let Some(host) = smtp_server.get("host") {
         // port must be the configured value or `smtp` if not configured
-        if let Some(configured_host) = ctx.cfg().keycloak().smtp_host() {
+        if let Some(configured_host) = config.smtp_host() {
             if configured_host != host {
                 tracing::info!(
                     "The configured 'KEYCLOAK_SMTP_HOST' '{}' does not match with the value from keycloak '{}'",
@@ -540,7 +707,7 @@ fn check_realm_smtp_settings(
         );
     }
 
-    if let Some(configured_reply_to) = ctx.cfg().keycloak().smtp_reply_to() {
+    if let Some(configured_reply_to) = config.smtp_reply_to() {
         // reply_to must be the configured value
         if let Some(reply_to) = smtp_server.get("replyTo") {
             if configured_reply_to != reply_to {
@@ -566,7 +733,7 @@ fn check_realm_smtp_settings(
 
     if let Some(from) = smtp_server.get("from") {
         // from must be the configured value or `noreply@qm.local` if not configured
-        if let Some(configured_from) = ctx.cfg().keycloak().smtp_from() {
+        if let Some(configured_from) = config.smtp_from() {
             if configured_from != from {
                 tracing::info!(
                     "The configured 'KEYCLOAK_SMTP_FROM' '{}' does not match with the value from keycloak '{}'",
@@ -594,7 +761,7 @@ fn check_realm_smtp_settings(
         );
     }
 
-    if let Some(configured_from_display_name) = ctx.cfg().keycloak().smtp_from_display_name() {
+    if let Some(configured_from_display_name) = config.smtp_from_display_name() {
         // reply_to_display_name must be the configured value
         if let Some(from_display_name) = smtp_server.get("fromDisplayName") {
             if configured_from_display_name != from_display_name {
@@ -621,7 +788,7 @@ fn check_realm_smtp_settings(
     if let Some(ssl_value) = smtp_server.get("ssl") {
         // ssl must be the configured value or `false` if not configured
         let ssl = get_bool_from_string_value(ssl_value);
-        if let Some(configured_ssl) = ctx.cfg().keycloak().smtp_ssl() {
+        if let Some(configured_ssl) = config.smtp_ssl() {
             if configured_ssl != &ssl {
                 tracing::info!(
                     "The configured 'KEYCLOAK_SMTP_SSL' '{}' does not match with the value from keycloak '{}'",
@@ -664,3 +831,113 @@ fn get_bool_from_string_value(value: &str) -> bool {
 fn get_u16_from_value(value: &str) -> u16 {
     value.parse::<u16>().unwrap_or(0)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_has_password_rule() {
+        let policy = "length(12) and digits(1) and notEmail(undefined)";
+        assert!(has_password_rule(policy, "length(8)"));
+        assert!(has_password_rule(policy, "length(12)"));
+        assert!(!has_password_rule(policy, "length(16)"));
+        assert!(has_password_rule(policy, "digits(1)"));
+        assert!(!has_password_rule(policy, "upperCase(1)"));
+        assert!(has_password_rule(policy, "notEmail(undefined)"));
+    }
+
+    #[test]
+    fn test_default_realm_representation_is_valid() {
+        let config: KeycloakConfig = serde_json::from_str("{}").unwrap();
+        let rep = config.default_realm_representation("test");
+        let mut errors = vec![];
+        check_realm_representation(&config, &rep, &mut errors);
+        assert!(errors.is_empty(), "{errors:?}");
+    }
+
+    #[test]
+    fn test_token_lifespans() {
+        let config: KeycloakConfig = serde_json::from_value(serde_json::json!({
+            "access_token_lifespan": 300,
+            "offline_session_idle_timeout": 86400,
+        }))
+        .unwrap();
+        let mut rep = config.default_realm_representation("test");
+        assert_eq!(rep.access_token_lifespan, Some(300));
+        assert_eq!(rep.offline_session_idle_timeout, Some(86400));
+        assert_eq!(rep.sso_session_idle_timeout, None);
+
+        rep.access_token_lifespan = Some(3600);
+        let mut errors = vec![];
+        check_realm_representation(&config, &rep, &mut errors);
+        let ids: Vec<_> = errors.iter().map(|e| e.id.as_str()).collect();
+        assert_eq!(ids, vec![realm_errors::REALM_TOKEN_LIFESPANS_MISMATCHED_ID]);
+    }
+
+    #[test]
+    fn test_missing_protocol_mappers() {
+        let config: KeycloakConfig = serde_json::from_str("{}").unwrap();
+        let missing = missing_protocol_mappers(None, config.spa_protocol_mappers());
+        assert_eq!(missing.len(), 1);
+        assert_eq!(missing[0].name.as_deref(), Some("audience"));
+
+        let existing = vec![
+            ProtocolMapperRepresentation {
+                name: Some("locale".to_string()),
+                ..Default::default()
+            },
+            ProtocolMapperRepresentation {
+                id: Some("1".to_string()),
+                name: Some("audience".to_string()),
+                ..Default::default()
+            },
+        ];
+        assert!(
+            missing_protocol_mappers(Some(&existing), config.spa_protocol_mappers()).is_empty()
+        );
+    }
+
+    #[test]
+    fn test_mismatched_client_fields() {
+        let expected: ClientRepresentation = serde_json::from_value(serde_json::json!({
+            "clientId": "account-console",
+            "publicClient": true,
+            "attributes": { "pkce.code.challenge.method": "S256" },
+        }))
+        .unwrap();
+        let client: ClientRepresentation = serde_json::from_value(serde_json::json!({
+            "id": "1",
+            "clientId": "account-console",
+            "publicClient": false,
+            "enabled": true,
+            "attributes": { "custom": "value" },
+        }))
+        .unwrap();
+        assert_eq!(
+            mismatched_client_fields(&expected, &client).unwrap(),
+            vec!["attributes.pkce.code.challenge.method", "publicClient"]
+        );
+        assert!(mismatched_client_fields(&expected, &expected)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_available_themes() {
+        let config: KeycloakConfig =
+            serde_json::from_value(serde_json::json!({ "available_themes": ["custom"] })).unwrap();
+        assert_eq!(config.available_themes(), vec!["custom", "qm"]);
+
+        let mut rep = config.default_realm_representation("test");
+        rep.login_theme = Some("custom".to_string());
+        let mut errors = vec![];
+        check_realm_representation(&config, &rep, &mut errors);
+        assert!(errors.is_empty(), "{errors:?}");
+
+        rep.email_theme = Some("unknown".to_string());
+        check_realm_representation(&config, &rep, &mut errors);
+        let ids = errors.iter().map(|e| e.id.as_str()).collect::<Vec<_>>();
+        assert_eq!(ids, vec![realm_errors::REALM_EMAIL_THEME_INVALID_ID]);
+    }
+}
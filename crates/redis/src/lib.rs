@@ -425,6 +425,65 @@ impl Producer {
         self.queue.add_item(&mut con, &item).await?;
         Ok(())
     }
+
+    /// Adds the item and confirms that it landed in the queue.
+    ///
+    /// Transient connection errors are retried with exponential backoff. The item id is
+    /// fixed across attempts and the enqueue is atomic per id, so a push that succeeded
+    /// before the connection dropped is not enqueued a second time.
+    pub async fn produce_confirmed<T>(&self, data: &T) -> anyhow::Result<()>
+    where
+        T: Serialize,
+    {
+        let item = Item::from_json_data(data)?;
+        retry_with_backoff(PRODUCE_ATTEMPTS, PRODUCE_BACKOFF, || async {
+            let mut con = self.client.get().await?;
+            Ok(self.queue.add_item_confirmed(&mut con, &item).await?)
+        })
+        .await?;
+        Ok(())
+    }
+}
+
+const PRODUCE_ATTEMPTS: usize = 5;
+const PRODUCE_BACKOFF: Duration = Duration::from_millis(100);
+
+fn is_transient(err: &anyhow::Error) -> bool {
+    if let Some(err) = err.downcast_ref::<redis::RedisError>() {
+        return err.is_io_error()
+            || err.is_connection_dropped()
+            || err.is_connection_refusal()
+            || err.is_timeout();
+    }
+    matches!(
+        err.downcast_ref::<deadpool_redis::PoolError>(),
+        Some(deadpool_redis::PoolError::Timeout(_) | deadpool_redis::PoolError::Backend(_))
+    )
+}
+
+async fn retry_with_backoff<F, Fut, R>(
+    attempts: usize,
+    base_delay: Duration,
+    mut f: F,
+) -> anyhow::Result<R>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = anyhow::Result<R>>,
+{
+    let mut delay = base_delay;
+    let mut attempt = 1;
+    loop {
+        match f().await {
+            Ok(result) => return Ok(result),
+            Err(err) if attempt < attempts && is_transient(&err) => {
+                tracing::warn!("transient redis error on attempt {attempt}: {err:#}");
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
 }
 
 pub struct AsyncWorker<Ctx, T>
@@ -497,3 +556,82 @@ where
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::sync::Mutex;
+
+    fn dropped() -> anyhow::Error {
+        redis::RedisError::from(std::io::Error::from(std::io::ErrorKind::ConnectionReset)).into()
+    }
+
+    #[tokio::test]
+    async fn test_retry_with_backoff_enqueues_once() -> anyhow::Result<()> {
+        let stored = Mutex::new(HashSet::new());
+        let queue = Mutex::new(Vec::new());
+        let attempts = Mutex::new(0);
+        let pushed = retry_with_backoff(3, Duration::from_millis(1), || async {
+            let attempt = {
+                let mut attempts = attempts.lock().unwrap();
+                *attempts += 1;
+                *attempts
+            };
+            if !stored.lock().unwrap().insert("item-1") {
+                return Ok(false);
+            }
+            queue.lock().unwrap().push("item-1");
+            if attempt == 1 {
+                return Err(dropped());
+            }
+            Ok(true)
+        })
+        .await?;
+        assert!(!pushed);
+        assert_eq!(*attempts.lock().unwrap(), 2);
+        assert_eq!(queue.lock().unwrap().as_slice(), ["item-1"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_retry_with_backoff_stops_on_permanent_error() {
+        let attempts = Mutex::new(0);
+        let result: anyhow::Result<()> =
+            retry_with_backoff(3, Duration::from_millis(1), || async {
+                *attempts.lock().unwrap() += 1;
+                anyhow::bail!("invalid item")
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(*attempts.lock().unwrap(), 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a redis server configured by REDIS_*"]
+    async fn test_add_item_confirmed_concurrent() -> anyhow::Result<()> {
+        let producer = Producer::new(
+            &RedisConfig::new()?,
+            format!("test:{}", uuid::Uuid::new_v4()),
+        )?;
+        let item = Item::from_string_data("payload".to_string());
+        let pushed = futures::future::try_join_all((0..16).map(|_| async {
+            let mut con = producer.client.get().await?;
+            anyhow::Ok(producer.queue.add_item_confirmed(&mut con, &item).await?)
+        }))
+        .await?;
+        assert_eq!(pushed.iter().filter(|pushed| **pushed).count(), 1);
+
+        let mut con = producer.client.get().await?;
+        assert_eq!(producer.queue.queue_len(&mut con).await?, 1);
+        let leased = producer
+            .queue
+            .lease(&mut con, Some(Duration::ZERO), Duration::from_secs(10))
+            .await?
+            .expect("item is queued");
+        assert!(producer.queue.complete(&mut con, &leased).await?);
+        assert!(!producer.queue.add_item_confirmed(&mut con, &item).await?);
+        assert_eq!(producer.queue.queue_len(&mut con).await?, 0);
+        Ok(())
+    }
+}
@@ -87,12 +87,29 @@ impl Item {
     }
 }
 
+/// Marks the item id as enqueued, then stores the data and pushes the id in one step.
+///
+/// The marker outlives the item data, so a retry after a worker completed the item
+/// does not push it a second time.
+const ADD_ITEM_ONCE_SCRIPT: &str = r#"
+  if redis.call("set", KEYS[1], 1, "ex", ARGV[3], "nx") then
+    redis.call("set", KEYS[2], ARGV[1])
+    return redis.call("lpush", KEYS[3], ARGV[2])
+  else
+    return 0
+  end
+"#;
+
+/// How long an enqueued item id is remembered by [`WorkQueue::add_item_confirmed`].
+const ENQUEUED_TTL_SECS: u64 = 24 * 60 * 60;
+
 pub struct WorkQueue {
     session: String,
     main_queue_key: String,
     processing_key: String,
     lease_key: KeyPrefix,
     item_data_key: KeyPrefix,
+    enqueued_key: KeyPrefix,
 }
 
 impl WorkQueue {
@@ -103,6 +120,7 @@ impl WorkQueue {
             processing_key: name.of(":processing"),
             lease_key: name.and(":leased_by_session:"),
             item_data_key: name.and(":item:"),
+            enqueued_key: name.and(":enqueued:"),
         }
     }
 
@@ -135,6 +153,28 @@ impl WorkQueue {
         pipeline.query_async(db).await
     }
 
+    /// Adds the item unless an item with the same id was enqueued before.
+    ///
+    /// The check and the push run as one Lua script, so concurrent callers cannot both
+    /// push the same id. Returns `true` if the item was pushed by this call and `false`
+    /// if it had already landed.
+    pub async fn add_item_confirmed<C: AsyncCommands>(
+        &self,
+        db: &mut C,
+        item: &Item,
+    ) -> RedisResult<bool> {
+        let len: usize = redis::Script::new(ADD_ITEM_ONCE_SCRIPT)
+            .key(self.enqueued_key.of(&item.id))
+            .key(self.item_data_key.of(&item.id))
+            .key(&self.main_queue_key)
+            .arg(item.data.as_ref())
+            .arg(&item.id)
+            .arg(ENQUEUED_TTL_SECS)
+            .invoke_async(db)
+            .await?;
+        Ok(len > 0)
+    }
+
     pub fn queue_len<'a, C: AsyncCommands>(
         &'a self,
         db: &'a mut C,
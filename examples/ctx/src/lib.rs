@@ -78,6 +78,7 @@ impl Storage {
             keycloak.config().realm_admin_username(),
         )
         .await?;
+        cache_db.warm_owner_cache(&customer_db).await?;
         let jwt_store = JwtStore::new(keycloak.config());
         let redis = Redis::new()?;
         // let cache = Cache::new("qm-example", keycloak.config().realm()).await?;